@@ -1,6 +1,5 @@
 use jsonvault::{RaftManager, Database, Command};
 use std::sync::Arc;
-use tokio;
 use serde_json::json;
 
 /// Example demonstrating JsonVault usage with Raft consensus
@@ -76,10 +76,30 @@ type PingCommand struct {
 // Response represents a server response
 type Response struct {
 	Ok    interface{} `json:"Ok,omitempty"`
-	Error string      `json:"Error,omitempty"`
+	Error *ErrorInfo  `json:"Error,omitempty"`
 	Pong  interface{} `json:"Pong,omitempty"`
 }
 
+// ErrorInfo represents a structured server error
+type ErrorInfo struct {
+	Code      string      `json:"code"`
+	Message   string      `json:"message"`
+	Details   interface{} `json:"details,omitempty"`
+	Retriable bool        `json:"retriable"`
+}
+
+// ServerError is returned when the server answers with an error response
+type ServerError struct {
+	Code      string
+	Message   string
+	Details   interface{}
+	Retriable bool
+}
+
+func (e *ServerError) Error() string {
+	return fmt.Sprintf("server error: %s: %s", e.Code, e.Message)
+}
+
 // Client represents a connection to the JSON database
 type Client struct {
 	conn   net.Conn
@@ -162,11 +182,19 @@ func parseResponse(resp interface{}) (value interface{}, err error) {
 		if okValue, exists := v["Ok"]; exists {
 			return okValue, nil
 		}
-		if errorMsg, exists := v["Error"]; exists {
-			if errStr, ok := errorMsg.(string); ok {
-				return nil, fmt.Errorf("server error: %s", errStr)
+		if errorValue, exists := v["Error"]; exists {
+			switch e := errorValue.(type) {
+			case string:
+				// Legacy plain-string error
+				return nil, &ServerError{Code: "UNKNOWN", Message: e}
+			case map[string]interface{}:
+				serverErr := &ServerError{Details: e["details"]}
+				serverErr.Code, _ = e["code"].(string)
+				serverErr.Message, _ = e["message"].(string)
+				serverErr.Retriable, _ = e["retriable"].(bool)
+				return nil, serverErr
 			}
-			return nil, fmt.Errorf("server error: %v", errorMsg)
+			return nil, fmt.Errorf("server error: %v", errorValue)
 		}
 		return nil, fmt.Errorf("unknown response format: %v", v)
 	default:
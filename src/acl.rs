@@ -0,0 +1,267 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+use crate::glob;
+use crate::protocol::Command;
+
+/// Class of commands a grant allows, each one including the previous ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    /// Commands that don't modify the keyspace
+    Read,
+    /// Writes, and messages published to channels
+    Write,
+    /// Admin commands, such as FLUSH or the ACL commands
+    Admin,
+}
+
+impl Permission {
+    /// Returns the permission needed to run a command
+    pub fn required_by(command: &Command) -> Self {
+        if command.is_admin() {
+            Permission::Admin
+        } else if command.is_write() || matches!(command, Command::Publish { .. }) {
+            Permission::Write
+        } else {
+            Permission::Read
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::Read => "read",
+            Permission::Write => "write",
+            Permission::Admin => "admin",
+        }
+    }
+}
+
+/// Allows the commands of a class on the keys matching a glob
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Grant {
+    pub permission: Permission,
+    /// Glob of the keys the grant applies to; commands without a key, such as
+    /// QSCAN or STATS, need a grant on every key (`*`)
+    #[serde(default = "all_keys")]
+    pub keys: String,
+}
+
+fn all_keys() -> String {
+    "*".to_string()
+}
+
+impl Grant {
+    pub fn new(permission: Permission, keys: &str) -> Self {
+        Self {
+            permission,
+            keys: keys.to_string(),
+        }
+    }
+
+    /// Returns true if the grant allows `permission` on `key`, or on every
+    /// key without one
+    fn allows(&self, permission: Permission, key: Option<&str>) -> bool {
+        self.permission >= permission
+            && match key {
+                Some(key) => glob::glob_match(&self.keys, key),
+                None => self.keys == "*",
+            }
+    }
+}
+
+impl fmt::Display for Grant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.permission.as_str(), self.keys)
+    }
+}
+
+impl FromStr for Grant {
+    type Err = String;
+
+    /// Parses `permission[:glob]`, e.g. `read:cache:*` (every key without a glob)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (permission, keys) = s.split_once(':').unwrap_or((s, "*"));
+        let permission = match permission {
+            "read" => Permission::Read,
+            "write" => Permission::Write,
+            "admin" => Permission::Admin,
+            other => {
+                return Err(format!(
+                    "Invalid permission '{}': expected read, write or admin",
+                    other
+                ))
+            }
+        };
+        Ok(Grant::new(permission, keys))
+    }
+}
+
+/// Grants of the authenticated identities.
+///
+/// Until ACLs are configured (loaded from a file or set with ACLSET) every
+/// identity is unrestricted. From then on access is denied by default: an
+/// identity without an entry, like one with an empty list of grants, can
+/// only run the connection commands.
+#[derive(Debug, Default)]
+pub struct AccessControl {
+    /// Grants by identity, None until ACLs are configured
+    grants: RwLock<Option<HashMap<String, Vec<Grant>>>>,
+}
+
+impl AccessControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the grants of an identity, configuring ACLs if they were not
+    pub fn set(&self, identity: &str, grants: Vec<Grant>) {
+        self.grants
+            .write()
+            .unwrap()
+            .get_or_insert_with(HashMap::new)
+            .insert(identity.to_string(), grants);
+    }
+
+    /// Remove the grants of an identity, leaving it without permissions;
+    /// returns false if it had none
+    pub fn remove(&self, identity: &str) -> bool {
+        self.grants
+            .write()
+            .unwrap()
+            .as_mut()
+            .is_some_and(|grants| grants.remove(identity).is_some())
+    }
+
+    /// Returns the grants of every identity that has some, by name
+    pub fn list(&self) -> BTreeMap<String, Vec<Grant>> {
+        self.grants
+            .read()
+            .unwrap()
+            .iter()
+            .flatten()
+            .map(|(identity, grants)| (identity.clone(), grants.clone()))
+            .collect()
+    }
+
+    /// Load grants from a JSON file mapping identity names to lists of grants
+    pub fn load(&self, path: &str) -> Result<(), String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read ACL '{}': {}", path, e))?;
+        let grants: HashMap<String, Vec<Grant>> =
+            serde_json::from_str(&content).map_err(|e| format!("Invalid ACL '{}': {}", path, e))?;
+        *self.grants.write().unwrap() = Some(grants);
+        Ok(())
+    }
+
+    /// Returns an error unless `identity` may run `command`
+    pub fn check(&self, identity: &str, command: &Command) -> Result<(), String> {
+        // Connection commands are open to every identity
+        if matches!(
+            command,
+            Command::Hello { .. }
+                | Command::Ping
+                | Command::Auth { .. }
+                | Command::Select { .. }
+                | Command::ClientSetName { .. }
+                | Command::Commands
+        ) {
+            return Ok(());
+        }
+        // Streamed results need the permissions of the streamed command
+        if let Command::Stream { command, .. } = command {
+            return self.check(identity, command);
+        }
+        let all = self.grants.read().unwrap();
+        let Some(all) = all.as_ref() else {
+            return Ok(());
+        };
+        let Some(grants) = all.get(identity) else {
+            return Err(format!("'{}' has no ACL entry", identity));
+        };
+        let permission = Permission::required_by(command);
+        let keys: Vec<Option<&str>> = match command {
+            Command::Eval { keys, .. } => keys.iter().map(|key| Some(key.as_str())).collect(),
+            command => vec![command.key()],
+        };
+        for key in keys {
+            if !grants.iter().any(|grant| grant.allows(permission, key)) {
+                return Err(match key {
+                    Some(key) => format!(
+                        "'{}' has no {} permission on key '{}'",
+                        identity,
+                        permission.as_str(),
+                        key
+                    ),
+                    None => format!(
+                        "'{}' has no {} permission on every key, needed by {}",
+                        identity,
+                        permission.as_str(),
+                        command.name()
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_check_grants() {
+        let acl = AccessControl::new();
+        // Every identity is unrestricted until ACLs are configured
+        assert!(acl
+            .check("anyone", &Command::Flush { prefix: None })
+            .is_ok());
+        acl.set(
+            "dashboard",
+            vec![
+                Grant::new(Permission::Read, "cache:*"),
+                Grant::new(Permission::Write, "session:*"),
+            ],
+        );
+        acl.set("ops", vec![Grant::new(Permission::Admin, "*")]);
+        let get = |key: &str| Command::Get {
+            key: key.to_string(),
+        };
+        let set = |key: &str| Command::Set {
+            key: key.to_string(),
+            value: json!(1),
+        };
+
+        assert!(acl.check("dashboard", &get("cache:a")).is_ok());
+        assert!(acl.check("dashboard", &set("cache:a")).is_err());
+        assert!(acl.check("dashboard", &get("session:a")).is_ok());
+        assert!(acl.check("dashboard", &set("session:a")).is_ok());
+        assert!(acl.check("dashboard", &get("user:1")).is_err());
+        assert!(acl.check("dashboard", &Command::Stats).is_err());
+        assert!(acl.check("ops", &Command::Flush { prefix: None }).is_ok());
+        // then identities without an entry are denied
+        assert!(acl
+            .check("anyone", &Command::Flush { prefix: None })
+            .is_err());
+        assert!(acl.check("anyone", &Command::Ping).is_ok());
+
+        assert_eq!(
+            "write:session:*".parse::<Grant>(),
+            Ok(Grant::new(Permission::Write, "session:*"))
+        );
+        assert_eq!(
+            "admin".parse::<Grant>(),
+            Ok(Grant::new(Permission::Admin, "*"))
+        );
+        assert!("owner:*".parse::<Grant>().is_err());
+
+        assert!(acl.remove("dashboard"));
+        assert!(acl.check("dashboard", &get("cache:a")).is_err());
+        assert!(!acl.remove("dashboard"));
+    }
+}
@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::canonical;
+use crate::glob;
+
+/// Records returned by AUDIT when the client doesn't choose
+pub const DEFAULT_AUDIT_LIMIT: usize = 1000;
+
+/// A mutating command, as recorded in the audit log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Time of the command in milliseconds since the UNIX epoch
+    pub at: u64,
+    /// Identity the command ran on behalf of (None for the embedding
+    /// application and unauthenticated servers)
+    pub identity: Option<String>,
+    pub namespace: String,
+    /// Protocol name of the command
+    pub command: String,
+    /// Key written by the command, if it targets one
+    pub key: Option<String>,
+    /// SHA-256 of the canonical value of the key before the command (None
+    /// when the key didn't exist)
+    pub before: Option<String>,
+    /// SHA-256 of the canonical value of the key after the command (None
+    /// once deleted)
+    pub after: Option<String>,
+    /// `ok`, or the error code of a failed command
+    pub outcome: String,
+}
+
+/// Append-only log of the mutating commands, one JSON record per line.
+///
+/// Records are written once commands complete, so before and after hashes
+/// may include concurrent writes to the same key.
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    /// Opens an audit log, appending to the records already in the file
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to open audit log {}: {}", path.display(), e))?;
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Appends a record, written to the file before returning
+    pub fn append(&self, record: &AuditRecord) -> Result<(), String> {
+        let mut line = serde_json::to_vec(record).map_err(|e| e.to_string())?;
+        line.push(b'\n');
+        self.file
+            .lock()
+            .unwrap()
+            .write_all(&line)
+            .map_err(|e| format!("Failed to write audit log {}: {}", self.path.display(), e))
+    }
+
+    /// Returns up to `limit` records since a time, oldest first, optionally
+    /// only those of an identity or of the keys matching a glob
+    pub fn query(
+        &self,
+        since: u64,
+        identity: Option<&str>,
+        namespace: Option<&str>,
+        key_pattern: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<AuditRecord>, String> {
+        let file = File::open(&self.path)
+            .map_err(|e| format!("Failed to read audit log {}: {}", self.path.display(), e))?;
+        let mut records = Vec::new();
+        for line in BufReader::new(file).lines() {
+            if records.len() >= limit {
+                break;
+            }
+            let line = line
+                .map_err(|e| format!("Failed to read audit log {}: {}", self.path.display(), e))?;
+            let record: AuditRecord = serde_json::from_str(&line)
+                .map_err(|e| format!("Invalid audit record in {}: {}", self.path.display(), e))?;
+            let matches = record.at >= since
+                && identity.is_none_or(|identity| record.identity.as_deref() == Some(identity))
+                && namespace.is_none_or(|namespace| record.namespace == namespace)
+                && key_pattern.is_none_or(|pattern| {
+                    record
+                        .key
+                        .as_deref()
+                        .is_some_and(|key| glob::glob_match(pattern, key))
+                });
+            if matches {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// Hash of a JSON value as recorded in the audit log
+pub(crate) fn value_hash(value: &Value) -> String {
+    bytes_hash(canonical::to_canonical_string(value).as_bytes())
+}
+
+/// Hash of a binary value as recorded in the audit log
+pub(crate) fn bytes_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_query() {
+        let path =
+            std::env::temp_dir().join(format!("jsonvault-audit-{}.jsonl", uuid::Uuid::new_v4()));
+        let record = |at: u64, identity: &str, key: &str| AuditRecord {
+            at,
+            identity: Some(identity.to_string()),
+            namespace: "default".to_string(),
+            command: "SET".to_string(),
+            key: Some(key.to_string()),
+            before: None,
+            after: Some(value_hash(&serde_json::json!({"b": 1, "a": 2}))),
+            outcome: "ok".to_string(),
+        };
+        let audit = AuditLog::open(&path).unwrap();
+        audit.append(&record(1, "ops", "user:1")).unwrap();
+        audit.append(&record(2, "dashboard", "user:2")).unwrap();
+        audit.append(&record(3, "ops", "session:1")).unwrap();
+
+        // Reopening appends to the existing records
+        let audit = AuditLog::open(&path).unwrap();
+        assert_eq!(audit.query(0, None, None, None, 10).unwrap().len(), 3);
+        assert_eq!(audit.query(2, None, None, None, 10).unwrap().len(), 2);
+        assert_eq!(
+            audit.query(0, Some("ops"), None, None, 10).unwrap().len(),
+            2
+        );
+        assert_eq!(
+            audit
+                .query(0, None, Some("default"), None, 10)
+                .unwrap()
+                .len(),
+            3
+        );
+        assert!(audit
+            .query(0, None, Some("acme"), None, 10)
+            .unwrap()
+            .is_empty());
+        let users = audit.query(0, None, None, Some("user:*"), 1).unwrap();
+        assert_eq!(users, vec![record(1, "ops", "user:1")]);
+        // Hashes don't depend on the order of object members
+        assert_eq!(
+            users[0].after,
+            Some(value_hash(&serde_json::json!({"a": 2, "b": 1})))
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// Identity authenticated by `--requirepass`
+pub const DEFAULT_IDENTITY: &str = "default";
+
+/// Secrets accepted by AUTH, each one authenticating an identity.
+///
+/// Only SHA-256 digests of the secrets are kept, and they are compared in
+/// constant time.
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    identities: Vec<Identity>,
+}
+
+#[derive(Debug, Clone)]
+struct Identity {
+    name: String,
+    digest: [u8; 32],
+    /// Namespace the identity is confined to, for tenants
+    namespace: Option<String>,
+}
+
+/// Entry of an API keys file: a key, or a key bound to a namespace
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ApiKey {
+    Key(String),
+    Tenant { key: String, namespace: String },
+}
+
+impl Credentials {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept `secret` as the password or API key of `name`
+    pub fn with_identity(mut self, name: &str, secret: &str) -> Self {
+        self.identities.push(Identity {
+            name: name.to_string(),
+            digest: digest(secret),
+            namespace: None,
+        });
+        self
+    }
+
+    /// Accept `secret` as the API key of the tenant `name`, confined to
+    /// `namespace`
+    pub fn with_tenant(mut self, name: &str, secret: &str, namespace: &str) -> Self {
+        self.identities.push(Identity {
+            name: name.to_string(),
+            digest: digest(secret),
+            namespace: Some(namespace.to_string()),
+        });
+        self
+    }
+
+    /// Load API keys from a JSON file mapping identity names to keys, or to
+    /// `{"key": ..., "namespace": ...}` objects for tenants
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read API keys '{}': {}", path, e))?;
+        let keys: BTreeMap<String, ApiKey> = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid API keys '{}': {}", path, e))?;
+        Ok(keys
+            .iter()
+            .fold(Self::new(), |credentials, (name, key)| match key {
+                ApiKey::Key(key) => credentials.with_identity(name, key),
+                ApiKey::Tenant { key, namespace } => credentials.with_tenant(name, key, namespace),
+            }))
+    }
+
+    /// Number of identities
+    pub fn len(&self) -> usize {
+        self.identities.len()
+    }
+
+    /// Returns true if no identity is configured
+    pub fn is_empty(&self) -> bool {
+        self.identities.is_empty()
+    }
+
+    /// Returns the identity authenticated by a secret: the identity named
+    /// `username`, or without one any identity with that secret
+    pub fn authenticate(&self, username: Option<&str>, secret: &str) -> Option<&str> {
+        let secret = digest(secret);
+        // Check every identity, so that timing doesn't tell which one matched
+        let mut authenticated = None;
+        for identity in &self.identities {
+            let matches = constant_time_eq(&identity.digest, &secret)
+                && username.is_none_or(|username| username == identity.name);
+            if matches && authenticated.is_none() {
+                authenticated = Some(identity.name.as_str());
+            }
+        }
+        authenticated
+    }
+
+    /// Returns the namespace a tenant identity is confined to
+    pub fn namespace_of(&self, name: &str) -> Option<&str> {
+        self.identities
+            .iter()
+            .find(|identity| identity.name == name)
+            .and_then(|identity| identity.namespace.as_deref())
+    }
+}
+
+fn digest(secret: &str) -> [u8; 32] {
+    Sha256::digest(secret.as_bytes()).into()
+}
+
+fn constant_time_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authenticate() {
+        let credentials = Credentials::new()
+            .with_identity(DEFAULT_IDENTITY, "s3cret")
+            .with_identity("dashboard", "key-1");
+        assert_eq!(credentials.authenticate(None, "s3cret"), Some("default"));
+        assert_eq!(credentials.authenticate(None, "key-1"), Some("dashboard"));
+        assert_eq!(
+            credentials.authenticate(Some("dashboard"), "key-1"),
+            Some("dashboard")
+        );
+        assert_eq!(credentials.authenticate(Some("default"), "key-1"), None);
+        assert_eq!(credentials.authenticate(None, "wrong"), None);
+        assert_eq!(Credentials::new().authenticate(None, ""), None);
+        assert_eq!(credentials.namespace_of("dashboard"), None);
+    }
+
+    #[test]
+    fn test_tenant_keys_file() {
+        let path =
+            std::env::temp_dir().join(format!("jsonvault-keys-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            r#"{"ops": "key-1", "acme": {"key": "key-2", "namespace": "acme"}}"#,
+        )
+        .unwrap();
+        let credentials = Credentials::from_file(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(credentials.authenticate(None, "key-2"), Some("acme"));
+        assert_eq!(credentials.namespace_of("acme"), Some("acme"));
+        assert_eq!(credentials.namespace_of("ops"), None);
+    }
+}
@@ -0,0 +1,131 @@
+//! Synchronous client, for programs that don't run an async runtime.
+//!
+//! Each client drives its connection on a runtime of its own, on the
+//! calling thread: its methods must not be called from async code, where
+//! blocking on the runtime panics.
+
+use std::io::BufRead;
+use tokio::runtime::{Builder, Runtime};
+
+use crate::network::{self, SocketOptions};
+use crate::protocol::{Command, Handshake, Response};
+
+/// Blocking TCP client for JSON database, wrapping `jsonvault::TcpClient`
+pub struct TcpClient {
+    // Dropped before the runtime that drives it
+    client: network::TcpClient,
+    runtime: Runtime,
+}
+
+impl TcpClient {
+    /// Connect to server
+    pub fn connect(address: &str) -> Result<Self, String> {
+        let runtime = runtime()?;
+        let client = runtime.block_on(network::TcpClient::connect(address))?;
+        Ok(Self { client, runtime })
+    }
+
+    /// Connect to server over TLS, verifying its certificate with `tls`
+    #[cfg(feature = "tls")]
+    pub fn connect_tls(address: &str, tls: &crate::tls::TlsConfig) -> Result<Self, String> {
+        let runtime = runtime()?;
+        let client = runtime.block_on(network::TcpClient::connect_tls(address, tls))?;
+        Ok(Self { client, runtime })
+    }
+
+    /// Tune the socket of the connection
+    pub fn with_socket_options(mut self, options: &SocketOptions) -> Result<Self, String> {
+        self.client = self.client.with_socket_options(options)?;
+        Ok(self)
+    }
+
+    /// Negotiate the protocol version, encoding and compression of this
+    /// connection
+    pub fn hello(&mut self) -> Result<Handshake, String> {
+        self.runtime.block_on(self.client.hello())
+    }
+
+    /// Authenticate the connection with a password or an API key, returning
+    /// the authenticated identity
+    pub fn auth(&mut self, username: Option<&str>, password: &str) -> Result<String, String> {
+        self.runtime.block_on(self.client.auth(username, password))
+    }
+
+    /// Switch this connection to a namespace
+    pub fn select(&mut self, namespace: &str) -> Result<(), String> {
+        self.runtime.block_on(self.client.select(namespace))
+    }
+
+    /// Send a command and receive the response
+    pub fn send_command(&mut self, command: Command) -> Result<Response, String> {
+        self.runtime.block_on(self.client.send_command(command))
+    }
+
+    /// Send several commands in a pipeline, returning their responses in
+    /// order
+    pub fn send_batch(&mut self, commands: Vec<Command>) -> Result<Vec<Response>, String> {
+        self.runtime.block_on(self.client.send_batch(commands))
+    }
+
+    /// Load NDJSON documents with BULKLOAD, returning the number of loaded
+    /// documents
+    pub fn bulk_load(&mut self, ndjson: impl BufRead, chunk_size: usize) -> Result<u64, String> {
+        self.runtime
+            .block_on(self.client.bulk_load(ndjson, chunk_size))
+    }
+
+    /// Close the connection
+    pub fn close(self) -> Result<(), String> {
+        self.runtime.block_on(self.client.close())
+    }
+}
+
+/// Runtime driving the connection of a client
+fn runtime() -> Result<Runtime, String> {
+    Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("Failed to start runtime: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use crate::network::TcpServer;
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_blocking_client() {
+        let server = TcpServer::new(Arc::new(Database::new()), "127.0.0.1:8110".to_string());
+        std::thread::spawn(move || {
+            Runtime::new().unwrap().block_on(async move {
+                let _ = server.start().await;
+            })
+        });
+        std::thread::sleep(Duration::from_millis(100));
+
+        let mut client = TcpClient::connect("127.0.0.1:8110").unwrap();
+        client.hello().unwrap();
+        let response = client
+            .send_command(Command::Set {
+                key: "k".to_string(),
+                value: json!(1),
+            })
+            .unwrap();
+        assert!(matches!(response, Response::Ok(None)));
+        let responses = client
+            .send_batch(vec![
+                Command::Get {
+                    key: "k".to_string(),
+                },
+                Command::Ping,
+            ])
+            .unwrap();
+        assert!(matches!(&responses[0], Response::Ok(Some(v)) if v == &json!(1)));
+        assert!(matches!(responses[1], Response::Pong));
+        client.close().unwrap();
+    }
+}
@@ -0,0 +1,84 @@
+use serde_json::{Number, Value};
+
+/// FNV-1a 64-bit offset basis
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+/// FNV-1a 64-bit prime
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Converts a value to its canonical form: object keys are sorted, floats
+/// without a fractional part become integers and negative zero becomes zero
+pub fn canonicalize(value: Value) -> Value {
+    match value {
+        Value::Number(n) => Value::Number(normalize_number(n)),
+        Value::Array(arr) => Value::Array(arr.into_iter().map(canonicalize).collect()),
+        Value::Object(map) => {
+            let mut entries: Vec<(String, Value)> = map.into_iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k, canonicalize(v)))
+                    .collect(),
+            )
+        }
+        other => other,
+    }
+}
+
+/// Serializes a value to its canonical JSON text
+pub fn to_canonical_string(value: &Value) -> String {
+    canonicalize(value.clone()).to_string()
+}
+
+/// Computes a stable 64-bit digest of the canonical JSON text of a value
+pub fn digest(value: &Value) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+    update_digest(&mut hash, to_canonical_string(value).as_bytes());
+    hash
+}
+
+/// Feeds bytes into a running FNV-1a digest
+pub(crate) fn update_digest(hash: &mut u64, bytes: &[u8]) {
+    for byte in bytes {
+        *hash ^= *byte as u64;
+        *hash = hash.wrapping_mul(FNV_PRIME);
+    }
+}
+
+/// Returns the initial value of a running digest
+pub(crate) fn new_digest() -> u64 {
+    FNV_OFFSET_BASIS
+}
+
+/// Normalizes a JSON number
+fn normalize_number(n: Number) -> Number {
+    if n.is_i64() || n.is_u64() {
+        return n;
+    }
+    match n.as_f64() {
+        Some(0.0) => Number::from(0),
+        Some(f) if f.fract() == 0.0 && f >= i64::MIN as f64 && f < i64::MAX as f64 => {
+            Number::from(f as i64)
+        }
+        Some(f) if f.fract() == 0.0 && f >= 0.0 && f < u64::MAX as f64 => Number::from(f as u64),
+        _ => n,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_canonicalize_numbers() {
+        let value = json!({"b": 1.0, "a": [-0.0, 2.5, 3]});
+        assert_eq!(to_canonical_string(&value), r#"{"a":[0,2.5,3],"b":1}"#);
+    }
+
+    #[test]
+    fn test_digest_is_stable() {
+        assert_eq!(digest(&json!({"x": 1.0})), digest(&json!({"x": 1})));
+        assert_ne!(digest(&json!({"x": 1})), digest(&json!({"x": 2})));
+    }
+}
@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{MIN_COMPATIBLE_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use crate::storage::layout::FORMAT_VERSION;
+
+/// Describes how a node is built and configured, so that fleet tooling can
+/// check that every node runs the intended configuration
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Versions of the binary and of the formats it speaks
+    pub build: BuildInfo,
+    /// Enabled features
+    pub features: Features,
+    /// Configured limits
+    pub limits: Limits,
+    /// Identity and cluster membership
+    pub node: NodeInfo,
+}
+
+/// Version information of the running binary
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    pub version: String,
+    pub protocol_version: u32,
+    pub min_compatible_protocol_version: u32,
+    pub storage_format_version: u32,
+    /// Optional Cargo features compiled in
+    pub cargo_features: Vec<String>,
+}
+
+impl Default for BuildInfo {
+    fn default() -> Self {
+        let mut cargo_features = Vec::new();
+        if cfg!(feature = "profiling") {
+            cargo_features.push("profiling".to_string());
+        }
+        if cfg!(feature = "scripting") {
+            cargo_features.push("scripting".to_string());
+        }
+        if cfg!(feature = "simd-json") {
+            cargo_features.push("simd-json".to_string());
+        }
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            min_compatible_protocol_version: MIN_COMPATIBLE_PROTOCOL_VERSION,
+            storage_format_version: FORMAT_VERSION,
+            cargo_features,
+        }
+    }
+}
+
+/// Features enabled on the node
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Features {
+    /// Raft replication with more than one member
+    pub raft: bool,
+    /// Data survives restarts
+    pub persistence: bool,
+    pub tls: bool,
+    pub http: bool,
+    pub canonical_json: bool,
+    pub write_transforms: bool,
+    pub write_triggers: bool,
+    pub write_fencing: bool,
+    /// Key events are posted to HTTP endpoints
+    pub webhooks: bool,
+    /// The change log is exported to a sink
+    pub cdc: bool,
+    /// Clients may run admin commands
+    pub admin_commands: bool,
+    /// Metadata fields are maintained inside object documents
+    pub inline_meta: bool,
+    /// Cold documents are spilled to disk beyond the memory limit
+    pub disk_spill: bool,
+    /// Clients may connect through WebSockets
+    pub websocket: bool,
+    /// Clients may connect through the gRPC service
+    pub grpc: bool,
+    /// Clients must authenticate with AUTH
+    pub auth: bool,
+    /// Mutating commands are recorded in an audit log
+    pub audit: bool,
+}
+
+/// Configured limits (None when unlimited)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Limits {
+    pub shards: usize,
+    pub initial_capacity: Option<usize>,
+    pub write_stall_timeout_ms: Option<u64>,
+    /// Number of namespaces with a quota
+    pub quotas: usize,
+    /// Revisions kept per key (0 when history is disabled)
+    pub history_depth: usize,
+    /// How long deleted keys leave a tombstone
+    pub tombstone_retention_secs: Option<u64>,
+    /// Changes kept in the change log of each namespace (0 when disabled)
+    pub change_log_capacity: usize,
+    /// Limit of the total size of every namespace
+    pub max_memory_bytes: Option<u64>,
+    /// Serialized size from which documents are stored compressed
+    pub compression_threshold: Option<usize>,
+    /// Namespaces whose keys may be evicted under memory pressure
+    pub cache_namespaces: Vec<String>,
+    /// When AOF writes are flushed to disk (None without an AOF)
+    pub aof_fsync: Option<String>,
+    /// How long connections may stay without sending a request
+    pub idle_timeout_secs: Option<u64>,
+}
+
+/// Identity and placement of the node
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeInfo {
+    pub node_id: String,
+    pub address: String,
+    pub cluster_members: Vec<u64>,
+    pub data_dir: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities_document() {
+        let capabilities = Capabilities::default();
+        let document = serde_json::to_value(&capabilities).unwrap();
+        assert_eq!(document["build"]["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(document["build"]["protocol_version"], PROTOCOL_VERSION);
+        assert_eq!(document["features"]["persistence"], false);
+
+        let parsed: Capabilities = serde_json::from_value(document).unwrap();
+        assert_eq!(parsed, capabilities);
+    }
+}
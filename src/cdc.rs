@@ -0,0 +1,255 @@
+use async_trait::async_trait;
+use log::{debug, error};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::changes::Change;
+use crate::database::Database;
+
+/// Consumer group used by the exporter in the change log of every namespace
+pub const CDC_GROUP: &str = "cdc";
+
+/// Destination of exported changes, such as a Kafka topic.
+///
+/// A batch is acknowledged in the change log only once `send` returns Ok, so
+/// a failed or interrupted batch is sent again: sinks must tolerate duplicates
+/// (every change carries its offset).
+#[async_trait]
+pub trait ChangeSink: Send + Sync {
+    /// Deliver changes of a namespace, in offset order
+    async fn send(&self, namespace: &str, changes: &[Change]) -> Result<(), String>;
+}
+
+/// A change as written by `JsonLinesSink`
+#[derive(Serialize)]
+struct ExportedChange<'a> {
+    namespace: &'a str,
+    #[serde(flatten)]
+    change: &'a Change,
+}
+
+/// Appends changes to a file, one JSON object per line
+#[derive(Debug)]
+pub struct JsonLinesSink {
+    file: Mutex<File>,
+}
+
+impl JsonLinesSink {
+    /// Open a file for appending, creating it if needed
+    pub fn open(path: &str) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open CDC file '{}': {}", path, e))?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+#[async_trait]
+impl ChangeSink for JsonLinesSink {
+    async fn send(&self, namespace: &str, changes: &[Change]) -> Result<(), String> {
+        let mut lines = Vec::new();
+        for change in changes {
+            serde_json::to_writer(&mut lines, &ExportedChange { namespace, change })
+                .map_err(|e| e.to_string())?;
+            lines.push(b'\n');
+        }
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&lines)
+            .and_then(|_| file.sync_data())
+            .map_err(|e| format!("Failed to write changes: {}", e))
+    }
+}
+
+/// Exports the change log of every namespace to a sink, with at-least-once
+/// delivery.
+///
+/// Progress is kept as the offset of the `cdc` consumer group, acknowledged
+/// through the AOF, so the export resumes after the last acknowledged change
+/// when the sink recovers or the server restarts (`GROUPACK` on the `cdc`
+/// group rewinds or skips it).
+pub struct CdcExporter {
+    sink: Arc<dyn ChangeSink>,
+    interval: Duration,
+    batch: usize,
+}
+
+impl CdcExporter {
+    /// Create an exporter polling every 100ms in batches of 100 changes
+    pub fn new(sink: Arc<dyn ChangeSink>) -> Self {
+        Self {
+            sink,
+            interval: Duration::from_millis(100),
+            batch: 100,
+        }
+    }
+
+    /// Poll the change logs every `interval`, reading up to `batch` changes at once
+    pub fn with_polling(mut self, interval: Duration, batch: usize) -> Self {
+        self.interval = interval;
+        self.batch = batch;
+        self
+    }
+
+    /// Export the pending changes of every namespace, returning how many were
+    /// sent.
+    ///
+    /// A namespace whose unexported changes were dropped from a full change
+    /// log is no longer exported, and the export fails, until the `cdc` group
+    /// is acknowledged past the gap: changes are never skipped silently.
+    pub async fn export(&self, database: &Database) -> Result<usize, String> {
+        let mut exported = 0;
+        let mut gaps = Vec::new();
+        for name in database.namespaces() {
+            let namespace = database.namespace(&name)?;
+            loop {
+                let changes = match namespace.change_log().read_group(CDC_GROUP, self.batch) {
+                    Ok(changes) => changes,
+                    Err(e) => {
+                        // The exporter fell behind the capacity of the change log
+                        gaps.push(format!("{}: {}", name, e));
+                        break;
+                    }
+                };
+                let Some(last) = changes.last() else {
+                    break;
+                };
+                self.sink.send(&name, &changes).await?;
+                namespace
+                    .commit_group(CDC_GROUP, last.offset)
+                    .await
+                    .map_err(|e| e.message)?;
+                debug!("Exported {} changes of {}", changes.len(), name);
+                exported += changes.len();
+                if changes.len() < self.batch {
+                    break;
+                }
+            }
+        }
+        if !gaps.is_empty() {
+            return Err(format!(
+                "CDC export stopped at dropped changes (GROUPACK the '{}' group past them to skip them): {}",
+                CDC_GROUP,
+                gaps.join("; ")
+            ));
+        }
+        Ok(exported)
+    }
+
+    /// Export changes in the background until the process exits
+    pub fn spawn(self, database: Arc<Database>) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(self.interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.export(&database).await {
+                    error!("CDC export failed, retrying: {}", e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changes::ChangeKind;
+    use crate::protocol::Command;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Collects changes, failing while `down` is set
+    #[derive(Default)]
+    struct TestSink {
+        down: AtomicBool,
+        received: Mutex<Vec<(String, u64)>>,
+    }
+
+    #[async_trait]
+    impl ChangeSink for TestSink {
+        async fn send(&self, namespace: &str, changes: &[Change]) -> Result<(), String> {
+            if self.down.load(Ordering::Relaxed) {
+                return Err("sink unavailable".to_string());
+            }
+            let mut received = self.received.lock().unwrap();
+            received.extend(changes.iter().map(|c| (namespace.to_string(), c.offset)));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_resumes_after_failure() {
+        let database = Database::new();
+        database.set_change_log_capacity(100);
+        let sink = Arc::new(TestSink::default());
+        let exporter = CdcExporter::new(sink.clone()).with_polling(Duration::from_millis(10), 2);
+
+        for key in ["a", "b", "c"] {
+            database
+                .execute_command(Command::Set {
+                    key: key.to_string(),
+                    value: json!(1),
+                })
+                .await;
+        }
+        assert_eq!(exporter.export(&database).await.unwrap(), 3);
+
+        // Changes made while the sink is down are sent once it recovers
+        database
+            .execute_command(Command::Delete {
+                key: "a".to_string(),
+            })
+            .await;
+        sink.down.store(true, Ordering::Relaxed);
+        assert!(exporter.export(&database).await.is_err());
+        sink.down.store(false, Ordering::Relaxed);
+        assert_eq!(exporter.export(&database).await.unwrap(), 1);
+        assert_eq!(exporter.export(&database).await.unwrap(), 0);
+
+        let received = sink.received.lock().unwrap();
+        let offsets: Vec<u64> = received.iter().map(|(_, offset)| *offset).collect();
+        assert_eq!(offsets, vec![1, 2, 3, 4]);
+        let log = database.change_log().read(3, 1).unwrap();
+        assert_eq!(log[0].kind, ChangeKind::Delete);
+    }
+
+    #[tokio::test]
+    async fn test_export_stops_at_dropped_changes() {
+        let database = Database::new();
+        database.set_change_log_capacity(2);
+        let sink = Arc::new(TestSink::default());
+        let exporter = CdcExporter::new(sink.clone());
+        let set = |key: &str| Command::Set {
+            key: key.to_string(),
+            value: json!(1),
+        };
+
+        database.execute_command(set("a")).await;
+        assert_eq!(exporter.export(&database).await.unwrap(), 1);
+        for key in ["b", "c", "d"] {
+            database.execute_command(set(key)).await;
+        }
+        // Change 2 was dropped before being exported
+        assert!(exporter.export(&database).await.is_err());
+        assert!(exporter.export(&database).await.is_err());
+        assert_eq!(sink.received.lock().unwrap().len(), 1);
+
+        // Skipping the gap resumes the export
+        database
+            .execute_command(Command::GroupAck {
+                group: CDC_GROUP.to_string(),
+                offset: 2,
+            })
+            .await;
+        assert_eq!(exporter.export(&database).await.unwrap(), 2);
+        let received = sink.received.lock().unwrap();
+        let offsets: Vec<u64> = received.iter().map(|(_, offset)| *offset).collect();
+        assert_eq!(offsets, vec![1, 3, 4]);
+    }
+}
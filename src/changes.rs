@@ -0,0 +1,255 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use tokio::sync::futures::Notified;
+use tokio::sync::Notify;
+
+/// Kind of change recorded in the change log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    /// A value was written
+    Set,
+    /// A key was deleted
+    Delete,
+    /// A key was deleted because its expiry passed
+    Expire,
+    /// A key of a cache-tier namespace was evicted under memory pressure
+    Evict,
+}
+
+impl ChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeKind::Set => "set",
+            ChangeKind::Delete => "delete",
+            ChangeKind::Expire => "expire",
+            ChangeKind::Evict => "evict",
+        }
+    }
+}
+
+/// A write to a namespace, as seen by change log consumers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    /// Position in the change log, starting at 1
+    pub offset: u64,
+    pub kind: ChangeKind,
+    pub key: String,
+    /// Value after the write (None for deletions)
+    pub value: Option<Value>,
+    /// Time of the change in milliseconds since the UNIX epoch
+    pub at: u64,
+}
+
+/// Retained changes and offsets of a change log, saved in snapshots so that
+/// offsets keep growing across restarts
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChangeLogState {
+    /// Offset of the last appended change
+    pub last_offset: u64,
+    /// Last offset acknowledged by each group
+    pub groups: BTreeMap<String, u64>,
+    /// Retained changes, oldest first
+    pub changes: Vec<Change>,
+}
+
+/// Bounded log of the changes to a namespace, with the offsets committed
+/// by each consumer group
+#[derive(Debug, Default)]
+pub(crate) struct ChangeLog {
+    inner: Mutex<Inner>,
+    /// Wakes the changefeeds waiting for new changes
+    appended: Notify,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Retained changes, oldest first
+    changes: VecDeque<Change>,
+    /// Offset of the last appended change (0 before the first one)
+    last_offset: u64,
+    /// Last offset acknowledged by each group
+    groups: BTreeMap<String, u64>,
+}
+
+impl Inner {
+    /// Offset of the oldest change that can still be read
+    fn first_offset(&self) -> u64 {
+        self.last_offset + 1 - self.changes.len() as u64
+    }
+
+    /// Returns up to `limit` changes after `after`, failing if some of them
+    /// were already dropped from the log
+    fn read(&self, after: u64, limit: usize) -> Result<Vec<Change>, String> {
+        if after > self.last_offset {
+            return Err(format!(
+                "Offset {} is ahead of the change log (last offset is {})",
+                after, self.last_offset
+            ));
+        }
+        let first = self.first_offset();
+        if after + 1 < first {
+            return Err(format!(
+                "Changes after offset {} were dropped from the change log (oldest offset is {})",
+                after, first
+            ));
+        }
+        let skip = (after + 1 - first) as usize;
+        Ok(self
+            .changes
+            .iter()
+            .skip(skip)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+}
+
+impl ChangeLog {
+    /// Appends a change, keeping at most `capacity` changes
+    pub fn append(
+        &self,
+        capacity: usize,
+        kind: ChangeKind,
+        key: &str,
+        value: Option<&Value>,
+        at: u64,
+    ) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.last_offset += 1;
+        let offset = inner.last_offset;
+        inner.changes.push_back(Change {
+            offset,
+            kind,
+            key: key.to_string(),
+            value: value.cloned(),
+            at,
+        });
+        while inner.changes.len() > capacity {
+            inner.changes.pop_front();
+        }
+        drop(inner);
+        self.appended.notify_waiters();
+    }
+
+    /// Completes on the next append; enable it before reading so that no
+    /// change is missed
+    pub fn appended(&self) -> Notified<'_> {
+        self.appended.notified()
+    }
+
+    /// Offset of the last appended change (0 before the first one)
+    pub fn last_offset(&self) -> u64 {
+        self.inner.lock().unwrap().last_offset
+    }
+
+    /// Returns up to `limit` changes with an offset greater than `after`
+    pub fn read(&self, after: u64, limit: usize) -> Result<Vec<Change>, String> {
+        self.inner.lock().unwrap().read(after, limit)
+    }
+
+    /// Returns up to `limit` changes not yet acknowledged by a group.
+    ///
+    /// Changes are delivered again until acknowledged; a new group starts
+    /// from the oldest retained change.
+    pub fn read_group(&self, group: &str, limit: usize) -> Result<Vec<Change>, String> {
+        let mut inner = self.inner.lock().unwrap();
+        let start = inner.first_offset() - 1;
+        let committed = *inner.groups.entry(group.to_string()).or_insert(start);
+        inner.read(committed, limit)
+    }
+
+    /// Acknowledges the changes of a group up to `offset` included
+    pub fn commit(&self, group: &str, offset: u64) -> Result<(), String> {
+        let mut inner = self.inner.lock().unwrap();
+        if offset > inner.last_offset {
+            return Err(format!(
+                "Offset {} is ahead of the change log (last offset is {})",
+                offset, inner.last_offset
+            ));
+        }
+        inner.groups.insert(group.to_string(), offset);
+        Ok(())
+    }
+
+    /// Acknowledges the changes of a group up to `offset`, as read back from
+    /// the AOF
+    pub fn restore_commit(&self, group: &str, offset: u64) {
+        self.inner
+            .lock()
+            .unwrap()
+            .groups
+            .insert(group.to_string(), offset);
+    }
+
+    /// Drops the retained changes; offsets keep growing
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().changes.clear();
+    }
+
+    /// Returns the retained changes and offsets, or None if nothing was ever
+    /// logged or acknowledged
+    pub fn state(&self) -> Option<ChangeLogState> {
+        let inner = self.inner.lock().unwrap();
+        if inner.last_offset == 0 && inner.groups.is_empty() {
+            return None;
+        }
+        Some(ChangeLogState {
+            last_offset: inner.last_offset,
+            groups: inner.groups.clone(),
+            changes: inner.changes.iter().cloned().collect(),
+        })
+    }
+
+    /// Replaces the changes and offsets with a saved state, keeping at most
+    /// `capacity` changes
+    pub fn restore(&self, state: ChangeLogState, capacity: usize) {
+        let mut changes = VecDeque::from(state.changes);
+        // Saved changes end at the saved last offset
+        changes.retain(|change| change.offset <= state.last_offset);
+        while changes.len() > capacity {
+            changes.pop_front();
+        }
+        *self.inner.lock().unwrap() = Inner {
+            changes,
+            last_offset: state.last_offset,
+            groups: state.groups,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consumer_groups() {
+        let log = ChangeLog::default();
+        for key in ["a", "b", "c"] {
+            log.append(2, ChangeKind::Set, key, Some(&Value::Null), 0);
+        }
+        // The first change was dropped to respect the capacity
+        assert!(log.read(0, 10).is_err());
+        assert_eq!(log.read(1, 10).unwrap().len(), 2);
+        assert!(log.read(4, 10).is_err());
+
+        // Unacknowledged changes are delivered again
+        let batch = log.read_group("indexer", 1).unwrap();
+        assert_eq!(batch[0].offset, 2);
+        assert_eq!(log.read_group("indexer", 1).unwrap(), batch);
+        log.commit("indexer", 2).unwrap();
+        assert_eq!(log.read_group("indexer", 10).unwrap()[0].key, "c");
+        assert!(log.commit("indexer", 4).is_err());
+
+        // A restored log resumes at the saved offsets
+        let restored = ChangeLog::default();
+        restored.restore(log.state().unwrap(), 1);
+        assert!(restored.read(1, 10).is_err());
+        assert_eq!(restored.read_group("indexer", 10).unwrap()[0].offset, 3);
+        restored.append(1, ChangeKind::Delete, "c", None, 0);
+        assert_eq!(restored.read(3, 10).unwrap()[0].offset, 4);
+        assert!(ChangeLog::default().state().is_none());
+    }
+}
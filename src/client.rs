@@ -1,8 +1,11 @@
 use clap::{Arg, Command as ClapCommand};
-use jsonvault::{Command, Response, TcpClient};
+use jsonvault::{AggregateOp, Command, LatencyTarget, ProfileKind, Response, TcpClient};
 use serde_json::Value;
 use std::io::{self, Write};
 
+/// Records per frame of a streamed export
+const EXPORT_CHUNK_SIZE: usize = 1000;
+
 #[tokio::main]
 async fn main() -> Result<(), String> {
     let matches = ClapCommand::new("jsonvault-client")
@@ -16,6 +19,25 @@ async fn main() -> Result<(), String> {
                 .help("Server address")
                 .default_value("127.0.0.1:8080"),
         )
+        .arg(
+            Arg::new("namespace")
+                .long("namespace")
+                .value_name("NAMESPACE")
+                .help("Namespace to operate on (the server default when omitted)"),
+        )
+        .arg(
+            Arg::new("user")
+                .long("user")
+                .value_name("USERNAME")
+                .help("Identity to authenticate as (any identity with the password when omitted)")
+                .requires("password"),
+        )
+        .arg(
+            Arg::new("password")
+                .long("password")
+                .value_name("PASSWORD")
+                .help("Password or API key to authenticate with"),
+        )
         .subcommand(ClapCommand::new("interactive").about("Interactive mode"))
         .subcommand(
             ClapCommand::new("set")
@@ -33,12 +55,59 @@ async fn main() -> Result<(), String> {
                 .about("Delete a value")
                 .arg(Arg::new("key").required(true)),
         )
+        .subcommand(
+            ClapCommand::new("setbytes")
+                .about("Set a binary value from the content of a file")
+                .arg(Arg::new("key").required(true))
+                .arg(Arg::new("file").required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("getbytes")
+                .about("Get a binary value")
+                .arg(Arg::new("key").required(true))
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Write the value to a file"),
+                ),
+        )
         .subcommand(
             ClapCommand::new("qget")
                 .about("Execute a JSONPath query")
                 .arg(Arg::new("key").required(true))
                 .arg(Arg::new("query").required(true)),
         )
+        .subcommand(
+            ClapCommand::new("qscan")
+                .about("Execute a JSONPath query on every key matching a glob pattern")
+                .arg(Arg::new("pattern").required(true))
+                .arg(Arg::new("query").required(true))
+                .arg(
+                    Arg::new("limit")
+                        .short('l')
+                        .long("limit")
+                        .value_parser(clap::value_parser!(usize)),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("aggregate")
+                .about("Aggregate a numeric JSONPath across keys matching a glob pattern")
+                .arg(Arg::new("pattern").required(true))
+                .arg(Arg::new("path").required(true))
+                .arg(
+                    Arg::new("op")
+                        .required(true)
+                        .value_parser(["count", "sum", "avg", "min", "max"]),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("jqget")
+                .about("Execute a jq program")
+                .arg(Arg::new("key").required(true))
+                .arg(Arg::new("program").required(true)),
+        )
         .subcommand(
             ClapCommand::new("qset")
                 .about("Set a sub-property using JSONPath")
@@ -52,15 +121,390 @@ async fn main() -> Result<(), String> {
                 .arg(Arg::new("key").required(true))
                 .arg(Arg::new("value").required(true)),
         )
+        .subcommand(
+            ClapCommand::new("qtype")
+                .about("Get the JSON type of the value at a JSONPath")
+                .arg(Arg::new("key").required(true))
+                .arg(Arg::new("path").required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("qappend")
+                .about("Append a value to the array at a JSONPath")
+                .arg(Arg::new("key").required(true))
+                .arg(Arg::new("path").required(true))
+                .arg(Arg::new("value").required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("qinsert")
+                .about("Insert a value into the array at a JSONPath")
+                .arg(Arg::new("key").required(true))
+                .arg(Arg::new("path").required(true))
+                .arg(
+                    Arg::new("index")
+                        .required(true)
+                        .allow_negative_numbers(true)
+                        .value_parser(clap::value_parser!(i64)),
+                )
+                .arg(Arg::new("value").required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("qpop")
+                .about("Remove and return an element of the array at a JSONPath")
+                .arg(Arg::new("key").required(true))
+                .arg(Arg::new("path").required(true))
+                .arg(
+                    Arg::new("index")
+                        .allow_negative_numbers(true)
+                        .value_parser(clap::value_parser!(i64)),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("objkeys")
+                .about("List the member names of the object at a JSONPath")
+                .arg(Arg::new("key").required(true))
+                .arg(Arg::new("path").required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("arrlen")
+                .about("Get the length of the array at a JSONPath")
+                .arg(Arg::new("key").required(true))
+                .arg(Arg::new("path").required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("digest")
+                .about("Compute a stable digest of a key or of the whole keyspace")
+                .arg(Arg::new("key")),
+        )
+        .subcommand(
+            ClapCommand::new("explain")
+                .about("Explain how a command would be executed (command as JSON)")
+                .arg(Arg::new("command").required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("eval")
+                .about("Run a server-side script atomically on keys")
+                .arg(Arg::new("script").required(true))
+                .arg(
+                    Arg::new("keys")
+                        .help("Keys the script reads and writes (comma-separated)")
+                        .required(true)
+                        .value_delimiter(','),
+                )
+                .arg(
+                    Arg::new("args")
+                        .help("Script arguments as a JSON array")
+                        .default_value("[]"),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("profile")
+                .about("Capture a pprof profile on the server")
+                .arg(
+                    Arg::new("kind")
+                        .required(true)
+                        .value_parser(["cpu", "heap"]),
+                )
+                .arg(
+                    Arg::new("seconds")
+                        .default_value("30")
+                        .value_parser(clap::value_parser!(u64)),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("inject-latency")
+                .about("Delay client responses or replication on the server (0 ms clears)")
+                .arg(
+                    Arg::new("target")
+                        .required(true)
+                        .value_parser(["responses", "replication"]),
+                )
+                .arg(
+                    Arg::new("millis")
+                        .required(true)
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    Arg::new("seconds")
+                        .default_value("60")
+                        .value_parser(clap::value_parser!(u64)),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("memory-usage")
+                .about("Estimate the serialized and in-memory size of a document")
+                .arg(Arg::new("key").required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("meta")
+                .about("Show creation time, last update time and version of a key")
+                .arg(Arg::new("key").required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("readonly")
+                .about("Reject every write on the server, or accept writes again")
+                .arg(Arg::new("mode").required(true).value_parser(["on", "off"])),
+        )
+        .subcommand(
+            ClapCommand::new("flush")
+                .about("Delete every key of the namespace, or those starting with a prefix")
+                .arg(Arg::new("prefix")),
+        )
+        .subcommand(
+            ClapCommand::new("expire")
+                .about("Delete a key once a number of seconds has passed")
+                .arg(Arg::new("key").required(true))
+                .arg(
+                    Arg::new("seconds")
+                        .required(true)
+                        .value_parser(clap::value_parser!(u64)),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("ttl")
+                .about("Show the remaining time to live of a key in milliseconds")
+                .arg(Arg::new("key").required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("persist")
+                .about("Remove the expiry of a key")
+                .arg(Arg::new("key").required(true)),
+        )
+        .subcommand(ClapCommand::new("randomkey").about("Show a random key"))
+        .subcommand(
+            ClapCommand::new("sample")
+                .about("Show random keys with their values")
+                .arg(
+                    Arg::new("n")
+                        .default_value("10")
+                        .value_parser(clap::value_parser!(usize)),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("tombstones")
+                .about("List keys deleted since a time (milliseconds since the UNIX epoch)")
+                .arg(
+                    Arg::new("since")
+                        .default_value("0")
+                        .value_parser(clap::value_parser!(u64)),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("changes")
+                .about("List changes with an offset greater than SINCE")
+                .arg(
+                    Arg::new("since")
+                        .default_value("0")
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(Arg::new("limit").value_parser(clap::value_parser!(usize))),
+        )
+        .subcommand(
+            ClapCommand::new("groupread")
+                .about("Read changes not yet acknowledged by a consumer group")
+                .arg(Arg::new("group").required(true))
+                .arg(
+                    Arg::new("count")
+                        .default_value("10")
+                        .value_parser(clap::value_parser!(usize)),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("groupack")
+                .about("Acknowledge the changes of a consumer group up to an offset")
+                .arg(Arg::new("group").required(true))
+                .arg(
+                    Arg::new("offset")
+                        .required(true)
+                        .value_parser(clap::value_parser!(u64)),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("watch")
+                .about("Print the value of a key every time it changes")
+                .arg(Arg::new("key").required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("subscribe")
+                .about("Print an event for every change to a key matching a glob")
+                .arg(Arg::new("pattern").default_value("*")),
+        )
+        .subcommand(
+            ClapCommand::new("changefeed")
+                .about("Print the changes after an offset, then every new change")
+                .arg(
+                    Arg::new("from")
+                        .default_value("0")
+                        .value_parser(clap::value_parser!(u64)),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("publish")
+                .about("Publish a message on a channel")
+                .arg(Arg::new("channel").required(true))
+                .arg(Arg::new("message").required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("listen")
+                .about("Print the messages published on channels")
+                .arg(Arg::new("channels").required(true).num_args(1..)),
+        )
+        .subcommand(
+            ClapCommand::new("history")
+                .about("Show the last revisions of a key")
+                .arg(Arg::new("key").required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("restore")
+                .about("Write back the value a key had at a past version")
+                .arg(Arg::new("key").required(true))
+                .arg(
+                    Arg::new("version")
+                        .required(true)
+                        .value_parser(clap::value_parser!(u64)),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("usage").about("Show resource usage and quota of the namespace"),
+        )
+        .subcommand(
+            ClapCommand::new("info")
+                .about("Show build information, features and limits of the node"),
+        )
+        .subcommand(
+            ClapCommand::new("routing-table").about("Show which nodes serve each key prefix"),
+        )
+        .subcommand(
+            ClapCommand::new("stats").about("Show operation counters and server statistics"),
+        )
+        .subcommand(
+            ClapCommand::new("shardstats")
+                .about("Show keys and lock waits of every shard of the namespace"),
+        )
+        .subcommand(
+            ClapCommand::new("command")
+                .about("List the supported commands with their arity and flags"),
+        )
+        .subcommand(
+            ClapCommand::new("save")
+                .about("Save a snapshot of every namespace to the data directory")
+                .arg(
+                    Arg::new("background")
+                        .long("background")
+                        .help("Answer once the keyspace is copied (BGSAVE)")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("verifybackup")
+                .about("Check that a backup restores into a temporary in-memory instance")
+                .arg(Arg::new("source").help(
+                    "Data directory on the server, or s3 for the configured bucket (the node's own data directory by default)",
+                )),
+        )
+        .subcommand(
+            ClapCommand::new("promote")
+                .about("Stop following the primary and accept writes (standby nodes)"),
+        )
+        .subcommand(
+            ClapCommand::new("aclset")
+                .about("Replace the grants of an identity (e.g. read:cache:* write:session:*)")
+                .arg(Arg::new("identity").required(true))
+                .arg(Arg::new("grants").num_args(0..)),
+        )
+        .subcommand(
+            ClapCommand::new("acldel")
+                .about("Remove the grants of an identity, leaving it without permissions")
+                .arg(Arg::new("identity").required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("acllist").about("Show the grants of every restricted identity"),
+        )
+        .subcommand(
+            ClapCommand::new("audit")
+                .about("Show the records of the audit log since a time")
+                .arg(
+                    Arg::new("since")
+                        .help("Milliseconds since the UNIX epoch")
+                        .default_value("0")
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(Arg::new("identity").long("identity").value_name("NAME"))
+                .arg(Arg::new("keys").long("keys").value_name("PATTERN"))
+                .arg(
+                    Arg::new("limit")
+                        .long("limit")
+                        .value_parser(clap::value_parser!(usize)),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("client-list").about("Show the open connections of the server"),
+        )
+        .subcommand(
+            ClapCommand::new("client-kill")
+                .about("Close a connection of the server")
+                .arg(
+                    Arg::new("id")
+                        .required(true)
+                        .value_parser(clap::value_parser!(u64)),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("config-get")
+                .about("Show the runtime configuration parameters matching a glob")
+                .arg(Arg::new("parameter").default_value("*")),
+        )
+        .subcommand(
+            ClapCommand::new("config-set")
+                .about("Change a runtime configuration parameter without a restart")
+                .arg(Arg::new("parameter").required(true))
+                .arg(Arg::new("value").required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("export")
+                .about("Export the keys matching a glob as NDJSON (key, value, meta)")
+                .arg(Arg::new("pattern").default_value("*"))
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Write the export to a file"),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("import")
+                .about("Import the keys of an NDJSON export file")
+                .arg(Arg::new("file").required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("bulkload")
+                .about("Stream the documents of an NDJSON file (- for stdin) in chunked frames")
+                .arg(Arg::new("file").required(true))
+                .arg(
+                    Arg::new("chunk-size")
+                        .long("chunk-size")
+                        .value_name("DOCUMENTS")
+                        .help("Documents per frame")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("1000"),
+                ),
+        )
         .subcommand(ClapCommand::new("ping").about("Ping the server"))
         .get_matches();
 
     let server_address = matches.get_one::<String>("server").unwrap();
+    let namespace = matches.get_one::<String>("namespace").map(String::as_str);
+    let auth = matches.get_one::<String>("password").map(|password| {
+        (
+            matches.get_one::<String>("user").map(String::as_str),
+            password.as_str(),
+        )
+    });
 
     if matches.subcommand_matches("interactive").is_some() {
-        run_interactive_mode(server_address).await?;
+        run_interactive_mode(server_address, auth, namespace).await?;
     } else {
-        run_single_command(&matches, server_address).await?;
+        run_single_command(&matches, server_address, auth, namespace).await?;
     }
 
     Ok(())
@@ -69,8 +513,60 @@ async fn main() -> Result<(), String> {
 async fn run_single_command(
     matches: &clap::ArgMatches,
     server_address: &str,
+    auth: Option<(Option<&str>, &str)>,
+    namespace: Option<&str>,
 ) -> Result<(), String> {
-    let mut client = TcpClient::connect(server_address).await?;
+    let mut client = connect(server_address, auth, namespace).await?;
+
+    // Streaming commands print pushed frames until interrupted
+    let streaming = match matches.subcommand() {
+        Some(("subscribe", sub_matches)) => {
+            let pattern = sub_matches.get_one::<String>("pattern").unwrap();
+            client.subscribe_events(pattern).await?;
+            true
+        }
+        Some(("watch", sub_matches)) => {
+            let key = sub_matches.get_one::<String>("key").unwrap();
+            print_response(&Response::Ok(client.watch(key).await?));
+            true
+        }
+        Some(("changefeed", sub_matches)) => {
+            let from = *sub_matches.get_one::<u64>("from").unwrap();
+            client.changefeed(from).await?;
+            true
+        }
+        Some(("listen", sub_matches)) => {
+            let channels: Vec<&str> = sub_matches
+                .get_many::<String>("channels")
+                .unwrap()
+                .map(String::as_str)
+                .collect();
+            client.subscribe(&channels).await?;
+            true
+        }
+        _ => false,
+    };
+    if streaming {
+        loop {
+            print_response(&client.next_event().await?);
+        }
+    }
+
+    if let Some(("bulkload", sub_matches)) = matches.subcommand() {
+        let file = sub_matches.get_one::<String>("file").unwrap();
+        let chunk_size = *sub_matches.get_one::<usize>("chunk-size").unwrap();
+        let reader: Box<dyn std::io::BufRead + Send> = if file == "-" {
+            Box::new(std::io::BufReader::new(std::io::stdin()))
+        } else {
+            let file = std::fs::File::open(file)
+                .map_err(|e| format!("Failed to read '{}': {}", file, e))?;
+            Box::new(std::io::BufReader::new(file))
+        };
+        let started = std::time::Instant::now();
+        let loaded = client.bulk_load(reader, chunk_size).await?;
+        println!("Loaded {} documents in {:.1?}", loaded, started.elapsed());
+        return client.close().await;
+    }
 
     let command = match matches.subcommand() {
         Some(("set", sub_matches)) => {
@@ -88,11 +584,50 @@ async fn run_single_command(
             let key = sub_matches.get_one::<String>("key").unwrap().clone();
             Command::Delete { key }
         }
+        Some(("setbytes", sub_matches)) => {
+            let key = sub_matches.get_one::<String>("key").unwrap().clone();
+            let file = sub_matches.get_one::<String>("file").unwrap();
+            let value =
+                std::fs::read(file).map_err(|e| format!("Failed to read '{}': {}", file, e))?;
+            Command::SetBytes { key, value }
+        }
+        Some(("getbytes", sub_matches)) => {
+            let key = sub_matches.get_one::<String>("key").unwrap().clone();
+            Command::GetBytes { key }
+        }
         Some(("qget", sub_matches)) => {
             let key = sub_matches.get_one::<String>("key").unwrap().clone();
             let query = sub_matches.get_one::<String>("query").unwrap().clone();
             Command::QGet { key, query }
         }
+        Some(("qscan", sub_matches)) => {
+            let key_pattern = sub_matches.get_one::<String>("pattern").unwrap().clone();
+            let query = sub_matches.get_one::<String>("query").unwrap().clone();
+            let limit = sub_matches.get_one::<usize>("limit").copied();
+            Command::QScan {
+                key_pattern,
+                query,
+                limit,
+            }
+        }
+        Some(("aggregate", sub_matches)) => {
+            let key_pattern = sub_matches.get_one::<String>("pattern").unwrap().clone();
+            let path = sub_matches.get_one::<String>("path").unwrap().clone();
+            let op = sub_matches
+                .get_one::<String>("op")
+                .unwrap()
+                .parse::<AggregateOp>()?;
+            Command::Aggregate {
+                key_pattern,
+                path,
+                op,
+            }
+        }
+        Some(("jqget", sub_matches)) => {
+            let key = sub_matches.get_one::<String>("key").unwrap().clone();
+            let program = sub_matches.get_one::<String>("program").unwrap().clone();
+            Command::JqGet { key, program }
+        }
         Some(("qset", sub_matches)) => {
             let key = sub_matches.get_one::<String>("key").unwrap().clone();
             let path = sub_matches.get_one::<String>("path").unwrap().clone();
@@ -108,6 +643,213 @@ async fn run_single_command(
                 .map_err(|e| format!("Invalid JSON value: {}", e))?;
             Command::Merge { key, value }
         }
+        Some(("qtype", sub_matches)) => {
+            let key = sub_matches.get_one::<String>("key").unwrap().clone();
+            let path = sub_matches.get_one::<String>("path").unwrap().clone();
+            Command::QType { key, path }
+        }
+        Some(("qappend", sub_matches)) => {
+            let key = sub_matches.get_one::<String>("key").unwrap().clone();
+            let path = sub_matches.get_one::<String>("path").unwrap().clone();
+            let value_str = sub_matches.get_one::<String>("value").unwrap();
+            let value: Value = serde_json::from_str(value_str)
+                .map_err(|e| format!("Invalid JSON value: {}", e))?;
+            Command::QAppend { key, path, value }
+        }
+        Some(("qinsert", sub_matches)) => {
+            let key = sub_matches.get_one::<String>("key").unwrap().clone();
+            let path = sub_matches.get_one::<String>("path").unwrap().clone();
+            let index = *sub_matches.get_one::<i64>("index").unwrap();
+            let value_str = sub_matches.get_one::<String>("value").unwrap();
+            let value: Value = serde_json::from_str(value_str)
+                .map_err(|e| format!("Invalid JSON value: {}", e))?;
+            Command::QInsert {
+                key,
+                path,
+                index,
+                value,
+            }
+        }
+        Some(("qpop", sub_matches)) => {
+            let key = sub_matches.get_one::<String>("key").unwrap().clone();
+            let path = sub_matches.get_one::<String>("path").unwrap().clone();
+            let index = sub_matches.get_one::<i64>("index").copied();
+            Command::QPop { key, path, index }
+        }
+        Some(("objkeys", sub_matches)) => {
+            let key = sub_matches.get_one::<String>("key").unwrap().clone();
+            let path = sub_matches.get_one::<String>("path").unwrap().clone();
+            Command::ObjKeys { key, path }
+        }
+        Some(("arrlen", sub_matches)) => {
+            let key = sub_matches.get_one::<String>("key").unwrap().clone();
+            let path = sub_matches.get_one::<String>("path").unwrap().clone();
+            Command::ArrLen { key, path }
+        }
+        Some(("digest", sub_matches)) => {
+            let key = sub_matches.get_one::<String>("key").cloned();
+            Command::Digest { key }
+        }
+        Some(("eval", sub_matches)) => {
+            let script = sub_matches.get_one::<String>("script").unwrap().clone();
+            let keys = sub_matches
+                .get_many::<String>("keys")
+                .unwrap()
+                .cloned()
+                .collect();
+            let args_str = sub_matches.get_one::<String>("args").unwrap();
+            let args: Vec<Value> = serde_json::from_str(args_str)
+                .map_err(|e| format!("Invalid JSON arguments: {}", e))?;
+            Command::Eval { script, keys, args }
+        }
+        Some(("explain", sub_matches)) => {
+            let command_str = sub_matches.get_one::<String>("command").unwrap();
+            let command: Command = serde_json::from_str(command_str)
+                .map_err(|e| format!("Invalid command JSON: {}", e))?;
+            Command::Explain {
+                command: Box::new(command),
+            }
+        }
+        Some(("profile", sub_matches)) => {
+            let kind = match sub_matches.get_one::<String>("kind").unwrap().as_str() {
+                "heap" => ProfileKind::Heap,
+                _ => ProfileKind::Cpu,
+            };
+            let seconds = *sub_matches.get_one::<u64>("seconds").unwrap();
+            Command::Profile { kind, seconds }
+        }
+        Some(("inject-latency", sub_matches)) => {
+            let target = match sub_matches.get_one::<String>("target").unwrap().as_str() {
+                "replication" => LatencyTarget::Replication,
+                _ => LatencyTarget::Responses,
+            };
+            let millis = *sub_matches.get_one::<u64>("millis").unwrap();
+            let seconds = *sub_matches.get_one::<u64>("seconds").unwrap();
+            Command::InjectLatency {
+                target,
+                millis,
+                seconds,
+            }
+        }
+        Some(("memory-usage", sub_matches)) => {
+            let key = sub_matches.get_one::<String>("key").unwrap().clone();
+            Command::MemoryUsage { key }
+        }
+        Some(("meta", sub_matches)) => {
+            let key = sub_matches.get_one::<String>("key").unwrap().clone();
+            Command::Meta { key }
+        }
+        Some(("readonly", sub_matches)) => Command::ReadOnly {
+            enabled: sub_matches.get_one::<String>("mode").unwrap() == "on",
+        },
+        Some(("flush", sub_matches)) => Command::Flush {
+            prefix: sub_matches.get_one::<String>("prefix").cloned(),
+        },
+        Some(("expire", sub_matches)) => {
+            let key = sub_matches.get_one::<String>("key").unwrap().clone();
+            let seconds = *sub_matches.get_one::<u64>("seconds").unwrap();
+            Command::Expire { key, seconds }
+        }
+        Some(("ttl", sub_matches)) => {
+            let key = sub_matches.get_one::<String>("key").unwrap().clone();
+            Command::Ttl { key }
+        }
+        Some(("persist", sub_matches)) => {
+            let key = sub_matches.get_one::<String>("key").unwrap().clone();
+            Command::Persist { key }
+        }
+        Some(("randomkey", _)) => Command::RandomKey,
+        Some(("sample", sub_matches)) => {
+            let n = *sub_matches.get_one::<usize>("n").unwrap();
+            Command::Sample { n }
+        }
+        Some(("tombstones", sub_matches)) => {
+            let since = *sub_matches.get_one::<u64>("since").unwrap();
+            Command::Tombstones { since }
+        }
+        Some(("changes", sub_matches)) => {
+            let since = *sub_matches.get_one::<u64>("since").unwrap();
+            let limit = sub_matches.get_one::<usize>("limit").copied();
+            Command::Changes { since, limit }
+        }
+        Some(("groupread", sub_matches)) => {
+            let group = sub_matches.get_one::<String>("group").unwrap().clone();
+            let count = *sub_matches.get_one::<usize>("count").unwrap();
+            Command::GroupRead { group, count }
+        }
+        Some(("groupack", sub_matches)) => {
+            let group = sub_matches.get_one::<String>("group").unwrap().clone();
+            let offset = *sub_matches.get_one::<u64>("offset").unwrap();
+            Command::GroupAck { group, offset }
+        }
+        Some(("publish", sub_matches)) => {
+            let channel = sub_matches.get_one::<String>("channel").unwrap().clone();
+            let message_str = sub_matches.get_one::<String>("message").unwrap();
+            let message: Value = serde_json::from_str(message_str)
+                .map_err(|e| format!("Invalid JSON message: {}", e))?;
+            Command::Publish { channel, message }
+        }
+        Some(("history", sub_matches)) => {
+            let key = sub_matches.get_one::<String>("key").unwrap().clone();
+            Command::History { key }
+        }
+        Some(("restore", sub_matches)) => {
+            let key = sub_matches.get_one::<String>("key").unwrap().clone();
+            let version = *sub_matches.get_one::<u64>("version").unwrap();
+            Command::Restore { key, version }
+        }
+        Some(("usage", _)) => Command::Usage,
+        Some(("info", _)) => Command::Info,
+        Some(("routing-table", _)) => Command::RoutingTable,
+        Some(("stats", _)) => Command::Stats,
+        Some(("shardstats", _)) => Command::ShardStats,
+        Some(("command", _)) => Command::Commands,
+        Some(("save", sub_matches)) => Command::Save {
+            background: sub_matches.get_flag("background"),
+        },
+        Some(("verifybackup", sub_matches)) => Command::VerifyBackup {
+            source: sub_matches.get_one::<String>("source").cloned(),
+        },
+        Some(("promote", _)) => Command::Promote,
+        Some(("aclset", sub_matches)) => Command::AclSet {
+            identity: sub_matches.get_one::<String>("identity").unwrap().clone(),
+            grants: sub_matches
+                .get_many::<String>("grants")
+                .unwrap_or_default()
+                .map(|grant| grant.parse())
+                .collect::<Result<_, _>>()?,
+        },
+        Some(("acldel", sub_matches)) => Command::AclDel {
+            identity: sub_matches.get_one::<String>("identity").unwrap().clone(),
+        },
+        Some(("acllist", _)) => Command::AclList,
+        Some(("audit", sub_matches)) => Command::Audit {
+            since: *sub_matches.get_one::<u64>("since").unwrap(),
+            identity: sub_matches.get_one::<String>("identity").cloned(),
+            key_pattern: sub_matches.get_one::<String>("keys").cloned(),
+            limit: sub_matches.get_one::<usize>("limit").copied(),
+        },
+        Some(("client-list", _)) => Command::ClientList,
+        Some(("client-kill", sub_matches)) => Command::ClientKill {
+            id: *sub_matches.get_one::<u64>("id").unwrap(),
+        },
+        Some(("config-get", sub_matches)) => Command::ConfigGet {
+            parameter: sub_matches.get_one::<String>("parameter").unwrap().clone(),
+        },
+        Some(("config-set", sub_matches)) => Command::ConfigSet {
+            parameter: sub_matches.get_one::<String>("parameter").unwrap().clone(),
+            value: sub_matches.get_one::<String>("value").unwrap().clone(),
+        },
+        Some(("export", sub_matches)) => {
+            let pattern = sub_matches.get_one::<String>("pattern").unwrap().clone();
+            Command::Export { pattern }
+        }
+        Some(("import", sub_matches)) => {
+            let file = sub_matches.get_one::<String>("file").unwrap();
+            let data = std::fs::read_to_string(file)
+                .map_err(|e| format!("Failed to read '{}': {}", file, e))?;
+            Command::Import { data }
+        }
         Some(("ping", _)) => Command::Ping,
         _ => {
             eprintln!("No command specified. Use --help to see available commands.");
@@ -115,14 +857,73 @@ async fn run_single_command(
         }
     };
 
+    // Exports are streamed and written out as NDJSON
+    if let Some(("export", sub_matches)) = matches.subcommand() {
+        export(
+            &mut client,
+            command,
+            sub_matches.get_one::<String>("output"),
+        )
+        .await?;
+        return client.close().await;
+    }
+
     let response = client.send_command(command).await?;
     print_response(&response);
+    let output = matches
+        .subcommand_matches("getbytes")
+        .and_then(|m| m.get_one::<String>("output"));
+    if let (Response::Bytes(bytes), Some(path)) = (&response, output) {
+        std::fs::write(path, bytes).map_err(|e| format!("Failed to write '{}': {}", path, e))?;
+    }
 
     client.close().await?;
     Ok(())
 }
 
-async fn run_interactive_mode(server_address: &str) -> Result<(), String> {
+/// Stream an export, writing its records as NDJSON to a file or to stdout
+async fn export(
+    client: &mut TcpClient,
+    command: Command,
+    output: Option<&String>,
+) -> Result<(), String> {
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(io::BufWriter::new(
+            std::fs::File::create(path)
+                .map_err(|e| format!("Failed to create '{}': {}", path, e))?,
+        )),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut records = client.stream(command, EXPORT_CHUNK_SIZE).await?;
+    while let Some(chunk) = records.next_chunk().await? {
+        for record in chunk {
+            writeln!(out, "{}", record).map_err(|e| format!("Write error: {}", e))?;
+        }
+    }
+    out.flush().map_err(|e| format!("Write error: {}", e))
+}
+
+/// Connect to the server, authenticating and switching namespace if asked
+async fn connect(
+    server_address: &str,
+    auth: Option<(Option<&str>, &str)>,
+    namespace: Option<&str>,
+) -> Result<TcpClient, String> {
+    let mut client = TcpClient::connect(server_address).await?;
+    if let Some((username, password)) = auth {
+        client.auth(username, password).await?;
+    }
+    if let Some(namespace) = namespace {
+        client.select(namespace).await?;
+    }
+    Ok(client)
+}
+
+async fn run_interactive_mode(
+    server_address: &str,
+    auth: Option<(Option<&str>, &str)>,
+    namespace: Option<&str>,
+) -> Result<(), String> {
     println!("Interactive mode for JSON DB client");
     println!("Connected to: {}", server_address);
     println!("Available commands:");
@@ -130,13 +931,67 @@ async fn run_interactive_mode(server_address: &str) -> Result<(), String> {
     println!("  get <key>                 - Get a value");
     println!("  delete <key>              - Delete a value");
     println!("  qget <key> <query>        - Execute a JSONPath query");
+    println!("  qscan <pattern> <query> [limit] - JSONPath query over matching keys");
+    println!("  aggregate <pattern> <path> <count|sum|avg|min|max> - Aggregate over matching keys");
+    println!("  jqget <key> <program>     - Execute a jq program");
     println!("  qset <key> <path> <value> - Set a sub-property using JSONPath");
     println!("  merge <key> <json_value>  - Merge a value");
+    println!("  qtype <key> <path>        - Get the JSON type at a JSONPath");
+    println!("  qappend <key> <path> <value>       - Append to the array at a JSONPath");
+    println!("  qinsert <key> <path> <index> <value> - Insert into the array at a JSONPath");
+    println!("  qpop <key> <path> [index]          - Pop from the array at a JSONPath");
+    println!("  objkeys <key> <path>      - List object member names at a JSONPath");
+    println!("  arrlen <key> <path>       - Get the array length at a JSONPath");
+    println!("  digest [key]              - Stable digest of a key or the keyspace");
+    println!("  eval <script> <keys> [args] - Run a script atomically on comma-separated keys");
+    println!("  explain <command_json>    - Explain how a command would be executed");
+    println!("  auth [user] <password>    - Authenticate with a password or an API key");
+    println!("  select <namespace>        - Switch to a namespace");
+    println!("  memory usage <key>        - Estimate the size of a document");
+    println!("  meta <key>                - Creation time, update time and version of a key");
+    println!("  flush [prefix]            - Delete all keys, or those with a prefix (admin)");
+    println!("  readonly <on|off>         - Reject every write, or accept writes again (admin)");
+    println!("  expire <key> <seconds>    - Delete a key after a number of seconds");
+    println!("  ttl <key>                 - Remaining time to live of a key (ms)");
+    println!("  persist <key>             - Remove the expiry of a key");
+    println!("  randomkey                 - A random key");
+    println!("  sample [n]                - Up to n random keys with their values");
+    println!("  tombstones [since_ms]     - Keys deleted since a time");
+    println!("  changes [since] [limit]   - Changes after an offset");
+    println!("  groupread <group> [n]     - Changes not yet acknowledged by a consumer group");
+    println!("  groupack <group> <offset> - Acknowledge changes of a consumer group");
+    println!("  publish <channel> <json>  - Publish a message on a channel");
+    println!("  history <key>             - Last revisions of a key");
+    println!("  restore <key> <version>   - Write back a past revision of a key");
+    println!("  usage                     - Resource usage and quota of the namespace");
+    println!("  info                      - Build information, features and limits of the node");
+    println!("  routing                   - Nodes serving each key prefix");
+    println!("  stats                     - Operation counters and server statistics");
+    println!("  shardstats                - Keys and lock waits of every shard");
+    println!("  command                   - Supported commands with arity and flags");
+    println!("  save                      - Save a snapshot to the data directory");
+    println!("  bgsave                    - Save a snapshot, answering once the data is copied");
+    println!("  verifybackup [dir|s3]     - Check that a backup restores (the data directory by default)");
+    println!("  promote                   - Promote a standby: stop following the primary, accept writes");
+    println!(
+        "  aclset <identity> [permission:glob...] - Replace the grants of an identity (admin)"
+    );
+    println!("  acldel <identity>         - Remove the grants of an identity (admin)");
+    println!("  acllist                   - Grants of every restricted identity (admin)");
+    println!("  audit [since] [pattern]   - Audit log records since a time (admin)");
+    println!("  client setname <name>     - Name this connection");
+    println!("  client list               - Open connections of the server (admin)");
+    println!("  client kill <id>          - Close a connection (admin)");
+    println!("  config get [pattern]      - Runtime configuration parameters (admin)");
+    println!("  config set <param> <val>  - Change a runtime configuration parameter (admin)");
+    println!("  export [pattern]          - Keys matching a glob as NDJSON (key, value, meta)");
+    println!("  import <file>             - Import the keys of an NDJSON export file");
+    println!("  (bulkload <file> is available as a subcommand for large NDJSON loads)");
     println!("  ping                      - Ping the server");
     println!("  quit/exit                 - Exit");
     println!();
 
-    let mut client = TcpClient::connect(server_address).await?;
+    let mut client = connect(server_address, auth, namespace).await?;
 
     loop {
         print!("json-db> ");
@@ -203,6 +1058,54 @@ async fn run_interactive_mode(server_address: &str) -> Result<(), String> {
                     query: parts[2].to_string(),
                 }
             }
+            "qscan" => {
+                if parts.len() < 3 {
+                    eprintln!("Usage: qscan <pattern> <query> [limit]");
+                    continue;
+                }
+                let limit = match parts.get(3).map(|l| l.parse::<usize>()) {
+                    Some(Ok(limit)) => Some(limit),
+                    Some(Err(e)) => {
+                        eprintln!("Invalid limit: {}", e);
+                        continue;
+                    }
+                    None => None,
+                };
+                Command::QScan {
+                    key_pattern: parts[1].to_string(),
+                    query: parts[2].to_string(),
+                    limit,
+                }
+            }
+            "aggregate" => {
+                if parts.len() != 4 {
+                    eprintln!("Usage: aggregate <pattern> <path> <count|sum|avg|min|max>");
+                    continue;
+                }
+                let op = match parts[3].parse::<AggregateOp>() {
+                    Ok(op) => op,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        continue;
+                    }
+                };
+                Command::Aggregate {
+                    key_pattern: parts[1].to_string(),
+                    path: parts[2].to_string(),
+                    op,
+                }
+            }
+            "jqget" => {
+                let parts: Vec<&str> = input.splitn(3, ' ').collect();
+                if parts.len() != 3 {
+                    eprintln!("Usage: jqget <key> <program>");
+                    continue;
+                }
+                Command::JqGet {
+                    key: parts[1].to_string(),
+                    program: parts[2].to_string(),
+                }
+            }
             "qset" => {
                 if parts.len() != 4 {
                     eprintln!("Usage: qset <key> <path> <json_value>");
@@ -232,6 +1135,417 @@ async fn run_interactive_mode(server_address: &str) -> Result<(), String> {
                     }
                 }
             }
+            "qtype" => {
+                if parts.len() != 3 {
+                    eprintln!("Usage: qtype <key> <path>");
+                    continue;
+                }
+                Command::QType {
+                    key: parts[1].to_string(),
+                    path: parts[2].to_string(),
+                }
+            }
+            "qappend" => {
+                if parts.len() != 4 {
+                    eprintln!("Usage: qappend <key> <path> <json_value>");
+                    continue;
+                }
+                let key = parts[1].to_string();
+                let path = parts[2].to_string();
+                match serde_json::from_str::<Value>(parts[3]) {
+                    Ok(value) => Command::QAppend { key, path, value },
+                    Err(e) => {
+                        eprintln!("Invalid JSON value: {}", e);
+                        continue;
+                    }
+                }
+            }
+            "qinsert" => {
+                let parts: Vec<&str> = input.splitn(5, ' ').collect();
+                if parts.len() != 5 {
+                    eprintln!("Usage: qinsert <key> <path> <index> <json_value>");
+                    continue;
+                }
+                let index = match parts[3].parse::<i64>() {
+                    Ok(index) => index,
+                    Err(e) => {
+                        eprintln!("Invalid index: {}", e);
+                        continue;
+                    }
+                };
+                match serde_json::from_str::<Value>(parts[4]) {
+                    Ok(value) => Command::QInsert {
+                        key: parts[1].to_string(),
+                        path: parts[2].to_string(),
+                        index,
+                        value,
+                    },
+                    Err(e) => {
+                        eprintln!("Invalid JSON value: {}", e);
+                        continue;
+                    }
+                }
+            }
+            "qpop" => {
+                if parts.len() < 3 {
+                    eprintln!("Usage: qpop <key> <path> [index]");
+                    continue;
+                }
+                let index = match parts.get(3).map(|i| i.parse::<i64>()) {
+                    Some(Ok(index)) => Some(index),
+                    Some(Err(e)) => {
+                        eprintln!("Invalid index: {}", e);
+                        continue;
+                    }
+                    None => None,
+                };
+                Command::QPop {
+                    key: parts[1].to_string(),
+                    path: parts[2].to_string(),
+                    index,
+                }
+            }
+            "objkeys" | "arrlen" => {
+                if parts.len() != 3 {
+                    eprintln!("Usage: {} <key> <path>", parts[0]);
+                    continue;
+                }
+                let key = parts[1].to_string();
+                let path = parts[2].to_string();
+                if parts[0] == "objkeys" {
+                    Command::ObjKeys { key, path }
+                } else {
+                    Command::ArrLen { key, path }
+                }
+            }
+            "digest" => Command::Digest {
+                key: parts.get(1).map(|k| k.to_string()),
+            },
+            "eval" => {
+                if parts.len() < 3 {
+                    eprintln!("Usage: eval <script> <key,...> [json_args_array]");
+                    continue;
+                }
+                let args = match parts.get(3).map(|s| serde_json::from_str::<Vec<Value>>(s)) {
+                    None => Vec::new(),
+                    Some(Ok(args)) => args,
+                    Some(Err(e)) => {
+                        eprintln!("Invalid JSON arguments: {}", e);
+                        continue;
+                    }
+                };
+                Command::Eval {
+                    script: parts[1].to_string(),
+                    keys: parts[2].split(',').map(str::to_string).collect(),
+                    args,
+                }
+            }
+            "explain" => {
+                let command_str = input.trim_start_matches("explain").trim();
+                match serde_json::from_str::<Command>(command_str) {
+                    Ok(command) => Command::Explain {
+                        command: Box::new(command),
+                    },
+                    Err(e) => {
+                        eprintln!("Invalid command JSON: {}", e);
+                        continue;
+                    }
+                }
+            }
+            "auth" => match parts.len() {
+                2 => Command::Auth {
+                    username: None,
+                    password: parts[1].to_string(),
+                },
+                3 => Command::Auth {
+                    username: Some(parts[1].to_string()),
+                    password: parts[2].to_string(),
+                },
+                _ => {
+                    eprintln!("Usage: auth [user] <password>");
+                    continue;
+                }
+            },
+            "select" => {
+                if parts.len() != 2 {
+                    eprintln!("Usage: select <namespace>");
+                    continue;
+                }
+                Command::Select {
+                    namespace: parts[1].to_string(),
+                }
+            }
+            "memory" => {
+                if parts.len() != 3 || !parts[1].eq_ignore_ascii_case("usage") {
+                    eprintln!("Usage: memory usage <key>");
+                    continue;
+                }
+                Command::MemoryUsage {
+                    key: parts[2].to_string(),
+                }
+            }
+            "meta" => {
+                if parts.len() != 2 {
+                    eprintln!("Usage: meta <key>");
+                    continue;
+                }
+                Command::Meta {
+                    key: parts[1].to_string(),
+                }
+            }
+            "flush" => Command::Flush {
+                prefix: parts.get(1).map(|prefix| prefix.to_string()),
+            },
+            "readonly" => match parts.get(1).copied() {
+                Some("on") => Command::ReadOnly { enabled: true },
+                Some("off") => Command::ReadOnly { enabled: false },
+                _ => {
+                    eprintln!("Usage: readonly <on|off>");
+                    continue;
+                }
+            },
+            "expire" => {
+                if parts.len() != 3 {
+                    eprintln!("Usage: expire <key> <seconds>");
+                    continue;
+                }
+                let seconds = match parts[2].parse::<u64>() {
+                    Ok(seconds) => seconds,
+                    Err(e) => {
+                        eprintln!("Invalid seconds: {}", e);
+                        continue;
+                    }
+                };
+                Command::Expire {
+                    key: parts[1].to_string(),
+                    seconds,
+                }
+            }
+            "ttl" | "persist" => {
+                if parts.len() != 2 {
+                    eprintln!("Usage: {} <key>", parts[0]);
+                    continue;
+                }
+                let key = parts[1].to_string();
+                if parts[0] == "ttl" {
+                    Command::Ttl { key }
+                } else {
+                    Command::Persist { key }
+                }
+            }
+            "randomkey" => Command::RandomKey,
+            "sample" => {
+                let n = match parts.get(1).map(|n| n.parse::<usize>()) {
+                    None => 10,
+                    Some(Ok(n)) => n,
+                    Some(Err(e)) => {
+                        eprintln!("Invalid sample size: {}", e);
+                        continue;
+                    }
+                };
+                Command::Sample { n }
+            }
+            "tombstones" => {
+                let since = match parts.get(1).map(|s| s.parse::<u64>()) {
+                    None => 0,
+                    Some(Ok(since)) => since,
+                    Some(Err(e)) => {
+                        eprintln!("Invalid time: {}", e);
+                        continue;
+                    }
+                };
+                Command::Tombstones { since }
+            }
+            "changes" => {
+                let since = match parts.get(1).map(|s| s.parse::<u64>()) {
+                    None => 0,
+                    Some(Ok(since)) => since,
+                    Some(Err(e)) => {
+                        eprintln!("Invalid offset: {}", e);
+                        continue;
+                    }
+                };
+                let limit = match parts.get(2).map(|s| s.parse::<usize>()) {
+                    None => None,
+                    Some(Ok(limit)) => Some(limit),
+                    Some(Err(e)) => {
+                        eprintln!("Invalid limit: {}", e);
+                        continue;
+                    }
+                };
+                Command::Changes { since, limit }
+            }
+            "groupread" => {
+                if parts.len() < 2 || parts.len() > 3 {
+                    eprintln!("Usage: groupread <group> [count]");
+                    continue;
+                }
+                let count = match parts.get(2).map(|s| s.parse::<usize>()) {
+                    None => 10,
+                    Some(Ok(count)) => count,
+                    Some(Err(e)) => {
+                        eprintln!("Invalid count: {}", e);
+                        continue;
+                    }
+                };
+                Command::GroupRead {
+                    group: parts[1].to_string(),
+                    count,
+                }
+            }
+            "groupack" => {
+                if parts.len() != 3 {
+                    eprintln!("Usage: groupack <group> <offset>");
+                    continue;
+                }
+                let offset = match parts[2].parse::<u64>() {
+                    Ok(offset) => offset,
+                    Err(e) => {
+                        eprintln!("Invalid offset: {}", e);
+                        continue;
+                    }
+                };
+                Command::GroupAck {
+                    group: parts[1].to_string(),
+                    offset,
+                }
+            }
+            "publish" => {
+                if parts.len() != 3 {
+                    eprintln!("Usage: publish <channel> <json_message>");
+                    continue;
+                }
+                let channel = parts[1].to_string();
+                match serde_json::from_str::<Value>(parts[2]) {
+                    Ok(message) => Command::Publish { channel, message },
+                    Err(e) => {
+                        eprintln!("Invalid JSON message: {}", e);
+                        continue;
+                    }
+                }
+            }
+            "history" => {
+                if parts.len() != 2 {
+                    eprintln!("Usage: history <key>");
+                    continue;
+                }
+                Command::History {
+                    key: parts[1].to_string(),
+                }
+            }
+            "restore" => {
+                if parts.len() != 3 {
+                    eprintln!("Usage: restore <key> <version>");
+                    continue;
+                }
+                let version = match parts[2].parse::<u64>() {
+                    Ok(version) => version,
+                    Err(e) => {
+                        eprintln!("Invalid version: {}", e);
+                        continue;
+                    }
+                };
+                Command::Restore {
+                    key: parts[1].to_string(),
+                    version,
+                }
+            }
+            "usage" => Command::Usage,
+            "info" => Command::Info,
+            "routing" => Command::RoutingTable,
+            "stats" => Command::Stats,
+            "shardstats" => Command::ShardStats,
+            "command" => Command::Commands,
+            "save" => Command::Save { background: false },
+            "bgsave" => Command::Save { background: true },
+            "verifybackup" => Command::VerifyBackup {
+                source: parts.get(1).map(|source| source.to_string()),
+            },
+            "promote" => Command::Promote,
+            "aclset" => {
+                if parts.len() < 2 {
+                    eprintln!("Usage: aclset <identity> [permission:glob...]");
+                    continue;
+                }
+                match parts[2..].iter().map(|grant| grant.parse()).collect() {
+                    Ok(grants) => Command::AclSet {
+                        identity: parts[1].to_string(),
+                        grants,
+                    },
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        continue;
+                    }
+                }
+            }
+            "acldel" => {
+                if parts.len() != 2 {
+                    eprintln!("Usage: acldel <identity>");
+                    continue;
+                }
+                Command::AclDel {
+                    identity: parts[1].to_string(),
+                }
+            }
+            "acllist" => Command::AclList,
+            "audit" => match parts.get(1).map_or(Ok(0), |since| since.parse()) {
+                Ok(since) => Command::Audit {
+                    since,
+                    identity: None,
+                    key_pattern: parts.get(2).map(|pattern| pattern.to_string()),
+                    limit: None,
+                },
+                Err(_) => {
+                    eprintln!("Usage: audit [since] [pattern]");
+                    continue;
+                }
+            },
+            "client" => match (parts.get(1).map(|s| s.to_ascii_lowercase()), parts.len()) {
+                (Some(sub), 3) if sub == "setname" => Command::ClientSetName {
+                    name: parts[2].to_string(),
+                },
+                (Some(sub), 2) if sub == "list" => Command::ClientList,
+                (Some(sub), 3) if sub == "kill" => match parts[2].parse() {
+                    Ok(id) => Command::ClientKill { id },
+                    Err(_) => {
+                        eprintln!("Invalid client id: {}", parts[2]);
+                        continue;
+                    }
+                },
+                _ => {
+                    eprintln!("Usage: client setname <name> | client list | client kill <id>");
+                    continue;
+                }
+            },
+            "config" => match (parts.get(1).map(|s| s.to_ascii_lowercase()), parts.len()) {
+                (Some(sub), 2 | 3) if sub == "get" => Command::ConfigGet {
+                    parameter: parts.get(2).unwrap_or(&"*").to_string(),
+                },
+                (Some(sub), 4) if sub == "set" => Command::ConfigSet {
+                    parameter: parts[2].to_string(),
+                    value: parts[3].to_string(),
+                },
+                _ => {
+                    eprintln!("Usage: config get [pattern] | config set <parameter> <value>");
+                    continue;
+                }
+            },
+            "export" => Command::Export {
+                pattern: parts.get(1).unwrap_or(&"*").to_string(),
+            },
+            "import" => {
+                if parts.len() != 2 {
+                    eprintln!("Usage: import <file>");
+                    continue;
+                }
+                match std::fs::read_to_string(parts[1]) {
+                    Ok(data) => Command::Import { data },
+                    Err(e) => {
+                        eprintln!("Failed to read '{}': {}", parts[1], e);
+                        continue;
+                    }
+                }
+            }
             "ping" => Command::Ping,
             _ => {
                 eprintln!("Unknown command: {}", parts[0]);
@@ -239,7 +1553,9 @@ async fn run_interactive_mode(server_address: &str) -> Result<(), String> {
             }
         };
 
+        let export = matches!(command, Command::Export { .. });
         match client.send_command(command).await {
+            Ok(Response::Ok(Some(Value::String(ndjson)))) if export => print!("{}", ndjson),
             Ok(response) => print_response(&response),
             Err(e) => eprintln!("Error: {}", e),
         }
@@ -261,11 +1577,23 @@ fn print_response(response: &Response) {
         Response::Ok(None) => {
             println!("OK");
         }
-        Response::Error(msg) => {
-            eprintln!("Error: {}", msg);
+        Response::Error(err) => {
+            eprintln!("Error: {}", err);
         }
         Response::Pong => {
             println!("PONG");
         }
+        Response::Bytes(bytes) => {
+            println!("({} bytes)", bytes.len());
+        }
+        Response::ClusterTopologyChanged(_)
+        | Response::KeyEvent(_)
+        | Response::KeyEventsDropped(_)
+        | Response::KeyUpdated(_)
+        | Response::ChannelMessage(_)
+        | Response::Change(_)
+        | Response::Chunk(_) => {
+            println!("{}", response);
+        }
     }
 }
@@ -0,0 +1,212 @@
+use dashmap::DashMap;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use tokio::sync::Notify;
+
+/// Connections open on a server, listed by CLIENT LIST and closed by
+/// CLIENT KILL
+#[derive(Debug, Default)]
+pub(crate) struct ClientRegistry {
+    next_id: AtomicU64,
+    clients: DashMap<u64, Arc<Client>>,
+}
+
+/// State of an open connection, updated by its handler
+#[derive(Debug)]
+pub(crate) struct Client {
+    pub id: u64,
+    /// Peer address of the connection
+    address: String,
+    connected_at: Instant,
+    /// Milliseconds from the connection to its last request
+    last_activity_ms: AtomicU64,
+    commands: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    /// Name set with CLIENT SETNAME, identity and namespace of the connection
+    session: RwLock<Session>,
+    killed: Notify,
+}
+
+#[derive(Debug, Default)]
+struct Session {
+    name: Option<String>,
+    identity: Option<String>,
+    namespace: String,
+}
+
+/// Entry of CLIENT LIST
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ClientReport {
+    pub id: u64,
+    pub address: String,
+    pub name: Option<String>,
+    pub identity: Option<String>,
+    pub namespace: String,
+    pub age_seconds: u64,
+    pub idle_seconds: u64,
+    pub commands: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+impl ClientRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a connection from `address`, until the returned guard is
+    /// dropped
+    pub fn register(self: &Arc<Self>, address: &str, namespace: &str) -> RegisteredClient {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let client = Arc::new(Client {
+            id,
+            address: address.to_string(),
+            connected_at: Instant::now(),
+            last_activity_ms: AtomicU64::new(0),
+            commands: AtomicU64::new(0),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            session: RwLock::new(Session {
+                namespace: namespace.to_string(),
+                ..Session::default()
+            }),
+            killed: Notify::new(),
+        });
+        self.clients.insert(id, Arc::clone(&client));
+        RegisteredClient {
+            registry: Arc::clone(self),
+            client,
+        }
+    }
+
+    /// Number of open connections
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Returns every open connection, oldest first
+    pub fn list(&self) -> Vec<ClientReport> {
+        let mut clients: Vec<ClientReport> =
+            self.clients.iter().map(|client| client.report()).collect();
+        clients.sort_unstable_by_key(|client| client.id);
+        clients
+    }
+
+    /// Closes a connection once its current command completes; returns
+    /// false if no connection has this id
+    pub fn kill(&self, id: u64) -> bool {
+        match self.clients.get(&id) {
+            Some(client) => {
+                client.killed.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Client {
+    /// Counts a request of `bytes` bytes
+    pub fn record_request(&self, bytes: usize) {
+        self.commands.fetch_add(1, Ordering::Relaxed);
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.last_activity_ms.store(
+            self.connected_at.elapsed().as_millis() as u64,
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Sets the number of bytes written to the connection so far
+    pub fn record_output(&self, bytes: u64) {
+        self.bytes_out.store(bytes, Ordering::Relaxed);
+    }
+
+    pub fn set_name(&self, name: String) {
+        self.session.write().unwrap().name = Some(name);
+    }
+
+    pub fn set_identity(&self, identity: &str) {
+        self.session.write().unwrap().identity = Some(identity.to_string());
+    }
+
+    pub fn set_namespace(&self, namespace: &str) {
+        self.session.write().unwrap().namespace = namespace.to_string();
+    }
+
+    /// Resolves once CLIENT KILL targets the connection
+    pub async fn killed(&self) {
+        self.killed.notified().await
+    }
+
+    fn report(&self) -> ClientReport {
+        let session = self.session.read().unwrap();
+        let age = self.connected_at.elapsed();
+        let last_activity = self.last_activity_ms.load(Ordering::Relaxed);
+        ClientReport {
+            id: self.id,
+            address: self.address.clone(),
+            name: session.name.clone(),
+            identity: session.identity.clone(),
+            namespace: session.namespace.clone(),
+            age_seconds: age.as_secs(),
+            idle_seconds: (age.as_millis() as u64).saturating_sub(last_activity) / 1000,
+            commands: self.commands.load(Ordering::Relaxed),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Lists a connection among the open ones while alive
+pub(crate) struct RegisteredClient {
+    registry: Arc<ClientRegistry>,
+    client: Arc<Client>,
+}
+
+impl std::ops::Deref for RegisteredClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        &self.client
+    }
+}
+
+impl Drop for RegisteredClient {
+    fn drop(&mut self) {
+        self.registry.clients.remove(&self.client.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_client_registry() {
+        let registry = Arc::new(ClientRegistry::new());
+        let first = registry.register("127.0.0.1:1000", "default");
+        let second = registry.register("127.0.0.1:1001", "default");
+        first.record_request(42);
+        first.record_output(10);
+        first.set_name("worker".to_string());
+        second.set_namespace("cache");
+
+        let clients = registry.list();
+        assert_eq!(clients.len(), 2);
+        assert_eq!(clients[0].name.as_deref(), Some("worker"));
+        assert_eq!((clients[0].commands, clients[0].bytes_in), (1, 42));
+        assert_eq!(clients[0].bytes_out, 10);
+        assert_eq!(clients[1].namespace, "cache");
+
+        // A kill wakes the connection even if it is not waiting yet
+        assert!(registry.kill(second.id));
+        assert!(!registry.kill(99));
+        second.killed().await;
+
+        drop(first);
+        assert_eq!(registry.len(), 1);
+    }
+}
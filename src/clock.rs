@@ -0,0 +1,93 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+/// Source of time for timers, heartbeats, leases and expirations.
+///
+/// Production code uses `SystemClock`; tests inject a `ManualClock` to drive
+/// time-dependent logic deterministically instead of sleeping.
+pub trait Clock: Send + Sync + fmt::Debug {
+    /// Current monotonic instant
+    fn now(&self) -> Instant;
+
+    /// Current wall-clock time in milliseconds since the UNIX epoch
+    fn unix_millis(&self) -> u64;
+}
+
+/// Real time (follows Tokio's paused time in tests, as it uses `tokio::time::Instant`)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn unix_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64)
+    }
+}
+
+/// Clock that only moves when advanced explicitly
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    start: Instant,
+    start_unix_millis: u64,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl ManualClock {
+    /// Create a clock frozen at the current time
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            start_unix_millis: SystemClock.unix_millis(),
+            elapsed: Arc::new(Mutex::new(Duration::ZERO)),
+        }
+    }
+
+    /// Move the clock forward
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + *self.elapsed.lock().unwrap()
+    }
+
+    fn unix_millis(&self) -> u64 {
+        self.start_unix_millis + self.elapsed.lock().unwrap().as_millis() as u64
+    }
+}
+
+/// Returns the default clock
+pub fn system_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new();
+        let (start, start_unix) = (clock.now(), clock.unix_millis());
+        assert_eq!(clock.now(), start);
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(clock.now() - start, Duration::from_secs(5));
+        assert_eq!(clock.unix_millis() - start_unix, 5000);
+    }
+}
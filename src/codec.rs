@@ -0,0 +1,488 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::protocol;
+
+/// Frame options negotiated at handshake
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Framing {
+    /// Compress large payloads
+    pub compress: bool,
+    /// Split messages larger than `MAX_FRAME_PIECE` across frames
+    pub chunked: bool,
+}
+
+/// Codec of the TCP protocol, shared by servers, clients and the proxy.
+///
+/// Format: [length:4 bytes][request id:8 bytes, if tagged][JSON payload]
+///
+/// The highest bit of the length flags a zstd-compressed payload, sent only
+/// on connections that negotiated compression; the next one flags a request
+/// id, and the third one a message continued in the next frame. Decoding
+/// yields whole messages, however their frames were split across reads.
+#[derive(Debug, Clone)]
+pub(crate) struct FrameCodec {
+    /// Options of the frames written
+    pub framing: Framing,
+    /// Options of the frames accepted from the peer: compressed and continued
+    /// frames are refused until enabled, which servers do once the client
+    /// negotiated them (and, for compression, authenticated)
+    pub accepted: Framing,
+    /// Largest message accepted from the peer, across its frames and once
+    /// decompressed
+    pub max_message: usize,
+    /// Payloads of the frames of a message split across frames, until its
+    /// last frame
+    pieces: Vec<Bytes>,
+    /// Size of those frames
+    size: usize,
+    /// Bytes of the frames encoded so far
+    pub written: u64,
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        Self {
+            framing: Framing::default(),
+            accepted: Framing::default(),
+            max_message: protocol::MAX_MESSAGE_SIZE,
+            pieces: Vec::new(),
+            size: 0,
+            written: 0,
+        }
+    }
+}
+
+/// A message read from a connection
+#[derive(Debug)]
+pub(crate) struct Message {
+    /// Request id of a tagged message
+    pub id: Option<u64>,
+    /// Payload, decompressed, in the pieces it was split in
+    pieces: Vec<Bytes>,
+    /// Size of the frames of the message
+    pub size: usize,
+}
+
+impl Message {
+    /// Decodes the JSON payload. The whole message is held in memory by then,
+    /// up to the `max_message` of the codec: its pieces are parsed one after
+    /// the other without being joined, except with SIMD parsing
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, String> {
+        match self.pieces.as_slice() {
+            [payload] => protocol::decode_frame(payload),
+            pieces => protocol::decode_pieces(pieces),
+        }
+    }
+
+    /// Returns the payload, joined
+    pub fn into_payload(mut self) -> Bytes {
+        match self.pieces.len() {
+            1 => self.pieces.pop().unwrap(),
+            _ => self.pieces.concat().into(),
+        }
+    }
+}
+
+/// A message to write, with its payload made of consecutive parts
+pub(crate) struct Payload<'a> {
+    pub id: Option<u64>,
+    pub parts: Vec<&'a [u8]>,
+}
+
+/// A message serialized as JSON, in the pieces it is framed in
+pub(crate) struct Serialized(Vec<Vec<u8>>);
+
+impl Serialized {
+    pub fn new(message: &impl Serialize) -> Result<Self, String> {
+        protocol::serialize_pieces(message).map(Self)
+    }
+
+    /// Returns the size of the message
+    pub fn len(&self) -> usize {
+        self.0.iter().map(Vec::len).sum()
+    }
+
+    /// Returns the message to write, tagged with `id`
+    pub fn payload(&self, id: Option<u64>) -> Payload<'_> {
+        Payload {
+            id,
+            parts: self.0.iter().map(Vec::as_slice).collect(),
+        }
+    }
+}
+
+/// Returns true if a buffer holds a whole message, so that decoding it
+/// needs no further read
+pub(crate) fn message_ready(mut buffer: &[u8]) -> bool {
+    while buffer.len() >= 4 {
+        let header = FrameHeader::parse(buffer[..4].try_into().unwrap());
+        let frame_length = 4 + header.body_length();
+        if buffer.len() < frame_length {
+            return false;
+        }
+        if !header.continued {
+            return true;
+        }
+        buffer = &buffer[frame_length..];
+    }
+    false
+}
+
+/// Length prefix of a frame
+struct FrameHeader {
+    /// Length of the payload
+    length: usize,
+    compressed: bool,
+    tagged: bool,
+    continued: bool,
+}
+
+impl FrameHeader {
+    fn parse(length_bytes: [u8; 4]) -> Self {
+        let length = u32::from_be_bytes(length_bytes);
+        let flags = protocol::COMPRESSED_FRAME | protocol::TAGGED_FRAME | protocol::CONTINUED_FRAME;
+        Self {
+            length: (length & !flags) as usize,
+            compressed: length & protocol::COMPRESSED_FRAME != 0,
+            tagged: length & protocol::TAGGED_FRAME != 0,
+            continued: length & protocol::CONTINUED_FRAME != 0,
+        }
+    }
+
+    /// Returns the length of the frame after the length prefix
+    fn body_length(&self) -> usize {
+        if self.tagged {
+            8 + self.length
+        } else {
+            self.length
+        }
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Message;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, io::Error> {
+        loop {
+            if src.len() < 4 {
+                return Ok(None); // Not enough data for length
+            }
+            let header = FrameHeader::parse(src[..4].try_into().unwrap());
+            if header.compressed && !self.accepted.compress {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Compressed frame on a connection without compression",
+                ));
+            }
+            if header.continued && !self.accepted.chunked {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Continued frame on a connection without chunked frames",
+                ));
+            }
+            let frame_length = 4 + header.body_length();
+            if self.size + frame_length > self.max_message {
+                return Err(message_too_large(self.max_message));
+            }
+            if src.len() < frame_length {
+                // Not enough data for the complete frame
+                src.reserve(frame_length - src.len());
+                return Ok(None);
+            }
+
+            // Extract the request id and the payload
+            let mut frame = src.split_to(frame_length).freeze();
+            frame.advance(4);
+            let id = header.tagged.then(|| frame.get_u64());
+            let held: usize = self.pieces.iter().map(Bytes::len).sum();
+            let payload = if header.compressed {
+                let limit = protocol::MAX_FRAME_LENGTH.min(self.max_message - held);
+                protocol::decompress_frame(&frame, limit)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+                    .into()
+            } else {
+                frame
+            };
+            self.pieces.push(payload);
+            self.size += frame_length;
+            if !header.continued {
+                return Ok(Some(Message {
+                    id,
+                    pieces: std::mem::take(&mut self.pieces),
+                    size: std::mem::take(&mut self.size),
+                }));
+            }
+        }
+    }
+}
+
+impl Encoder<Payload<'_>> for FrameCodec {
+    type Error = io::Error;
+
+    /// Encodes a message as a single frame, or with chunked frames as one
+    /// frame per `MAX_FRAME_PIECE` bytes of payload
+    fn encode(&mut self, payload: Payload<'_>, dst: &mut BytesMut) -> Result<(), io::Error> {
+        let Payload { id, parts } = payload;
+        let compress = self.framing.compress;
+        let start = dst.len();
+        let payload_length: usize = parts.iter().map(|part| part.len()).sum();
+        if !self.framing.chunked || payload_length <= protocol::MAX_FRAME_PIECE {
+            encode_frame(dst, id, &parts, compress, false)?;
+            self.written += (dst.len() - start) as u64;
+            return Ok(());
+        }
+        let mut piece: Vec<&[u8]> = Vec::new();
+        let mut piece_length = 0;
+        let mut remaining = payload_length;
+        for mut part in parts {
+            while !part.is_empty() {
+                let n = part.len().min(protocol::MAX_FRAME_PIECE - piece_length);
+                piece.push(&part[..n]);
+                piece_length += n;
+                remaining -= n;
+                part = &part[n..];
+                if piece_length == protocol::MAX_FRAME_PIECE || remaining == 0 {
+                    encode_frame(dst, id, &piece, compress, remaining > 0)?;
+                    piece.clear();
+                    piece_length = 0;
+                }
+            }
+        }
+        self.written += (dst.len() - start) as u64;
+        Ok(())
+    }
+}
+
+/// Encode a frame: length + request id + payload, compressing large payloads
+/// when `compress` is set, and flagged as continued in the next frame if
+/// `continued`. Payloads beyond `MAX_FRAME_LENGTH` are refused, leaving
+/// `dst` unchanged: their length would overflow into the flags
+fn encode_frame(
+    dst: &mut BytesMut,
+    id: Option<u64>,
+    parts: &[&[u8]],
+    compress: bool,
+    continued: bool,
+) -> Result<(), io::Error> {
+    let payload_length: usize = parts.iter().map(|part| part.len()).sum();
+    let compressed = if compress && payload_length >= protocol::COMPRESSION_THRESHOLD {
+        protocol::compress_frame(&parts.concat())
+    } else {
+        None
+    };
+    let frame_length = compressed.as_ref().map_or(payload_length, Vec::len);
+    if frame_length > protocol::MAX_FRAME_LENGTH {
+        return Err(frame_too_large(payload_length));
+    }
+
+    dst.reserve(12 + payload_length);
+    let mut length = match &compressed {
+        Some(compressed) => compressed.len() as u32 | protocol::COMPRESSED_FRAME,
+        None => payload_length as u32,
+    };
+    if id.is_some() {
+        length |= protocol::TAGGED_FRAME;
+    }
+    if continued {
+        length |= protocol::CONTINUED_FRAME;
+    }
+    dst.put_u32(length);
+    if let Some(id) = id {
+        dst.put_u64(id);
+    }
+    match &compressed {
+        Some(compressed) => dst.extend_from_slice(compressed),
+        None => {
+            for part in parts {
+                dst.extend_from_slice(part);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn message_too_large(limit: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Message exceeds the {} bytes accepted", limit),
+    )
+}
+
+fn frame_too_large(length: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "Message of {} bytes exceeds the {} bytes of a frame (negotiate chunked frames)",
+            length,
+            protocol::MAX_FRAME_LENGTH
+        ),
+    )
+}
+
+/// Returns true if a message of `length` bytes can be written with
+/// `framing`
+pub(crate) fn fits(framing: Framing, length: usize) -> bool {
+    framing.chunked || length <= protocol::MAX_FRAME_LENGTH
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Command;
+
+    #[test]
+    fn test_partial_and_chunked_frames() {
+        let command = Command::Set {
+            key: "huge".to_string(),
+            value: serde_json::json!("x".repeat(10 << 20)),
+        };
+        let message = Serialized::new(&command).unwrap();
+        let mut chunked = FrameCodec {
+            framing: Framing {
+                compress: true,
+                chunked: true,
+            },
+            ..FrameCodec::default()
+        };
+        let mut encoded = BytesMut::new();
+        chunked
+            .encode(message.payload(Some(7)), &mut encoded)
+            .unwrap();
+        let huge_length = encoded.len();
+        let ping = Serialized::new(&Command::Ping).unwrap();
+        chunked.encode(ping.payload(None), &mut encoded).unwrap();
+
+        // Messages come out whole, however the bytes are split across reads
+        let mut decoder = FrameCodec {
+            accepted: chunked.framing,
+            ..FrameCodec::default()
+        };
+        let mut buffer = BytesMut::new();
+        let mut messages = Vec::new();
+        for read in encoded.chunks(1 << 20) {
+            buffer.extend_from_slice(read);
+            while let Some(message) = decoder.decode(&mut buffer).unwrap() {
+                messages.push(message);
+            }
+        }
+        assert!(buffer.is_empty());
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].id, Some(7));
+        assert_eq!(messages[0].pieces.len(), 3);
+        assert!(
+            matches!(messages[0].decode(), Ok(Command::Set { value, .. }) if value.as_str().unwrap().len() == 10 << 20)
+        );
+        assert_eq!(messages[1].id, None);
+        assert!(matches!(messages[1].decode(), Ok(Command::Ping)));
+        assert_eq!(messages[0].size + messages[1].size, encoded.len());
+        assert_eq!(chunked.written, encoded.len() as u64);
+
+        assert!(message_ready(&encoded[..huge_length]));
+        assert!(!message_ready(&encoded[..huge_length - 1]));
+
+        // Without chunked frames the same message is a single frame
+        let mut single = BytesMut::new();
+        FrameCodec::default()
+            .encode(message.payload(None), &mut single)
+            .unwrap();
+        let decoded = FrameCodec::default().decode(&mut single).unwrap().unwrap();
+        assert_eq!(decoded.pieces.len(), 1);
+    }
+
+    #[test]
+    fn test_compressed_frames_need_negotiation() {
+        let message = Serialized::new(&"x".repeat(64 << 10)).unwrap();
+        let mut compressed = BytesMut::new();
+        FrameCodec {
+            framing: Framing {
+                compress: true,
+                chunked: false,
+            },
+            ..FrameCodec::default()
+        }
+        .encode(message.payload(None), &mut compressed)
+        .unwrap();
+        assert!(compressed.len() < 64 << 10);
+
+        let error = FrameCodec::default()
+            .decode(&mut compressed.clone())
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let mut accepting = FrameCodec::default();
+        accepting.accepted.compress = true;
+        assert!(accepting.decode(&mut compressed.clone()).unwrap().is_some());
+
+        // The limit on messages applies to their decompressed size
+        accepting.max_message = 32 << 10;
+        assert!(accepting.decode(&mut compressed).is_err());
+
+        // Decompression stops at the limit instead of trusting the sender
+        let bomb = zstd::stream::encode_all(&vec![0u8; 1 << 20][..], 19).unwrap();
+        assert!(protocol::decompress_frame(&bomb, 1 << 20).is_ok());
+        assert!(protocol::decompress_frame(&bomb, (1 << 20) - 1).is_err());
+    }
+
+    #[test]
+    fn test_continued_frames_are_bounded() {
+        let message = Serialized::new(&"x".repeat(10 << 20)).unwrap();
+        let mut encoded = BytesMut::new();
+        let chunked = Framing {
+            compress: false,
+            chunked: true,
+        };
+        FrameCodec {
+            framing: chunked,
+            ..FrameCodec::default()
+        }
+        .encode(message.payload(None), &mut encoded)
+        .unwrap();
+
+        // Continued frames need chunked frames to be negotiated
+        let error = FrameCodec::default()
+            .decode(&mut encoded.clone())
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // Pieces are refused once they add up past the limit, before the
+        // rest of the message is buffered
+        let bounded = |max_message| FrameCodec {
+            accepted: chunked,
+            max_message,
+            ..FrameCodec::default()
+        };
+        assert!(bounded(6 << 20).decode(&mut encoded.clone()).is_err());
+        let mut partial = BytesMut::from(&encoded[..1 << 20]);
+        assert!(bounded(1 << 10).decode(&mut partial).is_err());
+        assert!(bounded(11 << 20).decode(&mut encoded).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_oversized_frames_are_refused() {
+        let huge = vec![b' '; protocol::MAX_FRAME_LENGTH + 1];
+        let mut encoded = BytesMut::new();
+        let error = FrameCodec::default()
+            .encode(
+                Payload {
+                    id: None,
+                    parts: vec![&huge],
+                },
+                &mut encoded,
+            )
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(encoded.is_empty());
+        assert!(!fits(Framing::default(), huge.len()));
+        assert!(fits(
+            Framing {
+                compress: false,
+                chunked: true
+            },
+            huge.len()
+        ));
+    }
+}
@@ -0,0 +1,260 @@
+use serde::Serialize;
+
+use crate::protocol::Command;
+
+/// Description of a command, as listed by COMMAND
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CommandSpec {
+    /// Protocol name, as returned by `Command::name`
+    pub name: &'static str,
+    /// Name of the variant in JSON requests
+    pub variant: &'static str,
+    /// Arguments in order; optional ones are in brackets, repeated ones end
+    /// with an ellipsis
+    pub arguments: &'static [&'static str],
+    /// `write`, `readonly`, or `connection` for commands that only change the
+    /// state of the connection, then `admin` for admin commands
+    pub flags: &'static [&'static str],
+    /// Protocol version that introduced the command
+    pub since: u32,
+}
+
+impl CommandSpec {
+    /// Number of arguments, negated when some are optional or repeated, in
+    /// which case it is the minimum number of arguments
+    pub fn arity(&self) -> i64 {
+        let required = self
+            .arguments
+            .iter()
+            .filter(|argument| !argument.starts_with('['))
+            .count() as i64;
+        if self
+            .arguments
+            .iter()
+            .any(|argument| argument.starts_with('[') || argument.ends_with("..."))
+        {
+            -required
+        } else {
+            required
+        }
+    }
+
+    pub fn is_write(&self) -> bool {
+        self.flags.contains(&"write")
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.flags.contains(&"admin")
+    }
+}
+
+const READ: &[&str] = &["readonly"];
+const WRITE: &[&str] = &["write"];
+const CONNECTION: &[&str] = &["connection"];
+const READ_ADMIN: &[&str] = &["readonly", "admin"];
+const WRITE_ADMIN: &[&str] = &["write", "admin"];
+
+const fn spec(
+    name: &'static str,
+    variant: &'static str,
+    arguments: &'static [&'static str],
+    flags: &'static [&'static str],
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        variant,
+        arguments,
+        flags,
+        since: 1,
+    }
+}
+
+/// Every command of this build, in the order of the `Command` enum
+pub const COMMAND_TABLE: &[CommandSpec] = &[
+    spec("SET", "Set", &["key", "value"], WRITE),
+    spec("GET", "Get", &["key"], READ),
+    spec("DELETE", "Delete", &["key"], WRITE),
+    spec("SETBYTES", "SetBytes", &["key", "value"], WRITE),
+    spec("GETBYTES", "GetBytes", &["key"], READ),
+    spec("QGET", "QGet", &["key", "query"], READ),
+    spec("QSCAN", "QScan", &["key_pattern", "query", "[limit]"], READ),
+    spec(
+        "AGGREGATE",
+        "Aggregate",
+        &["key_pattern", "path", "op"],
+        READ,
+    ),
+    spec("JQGET", "JqGet", &["key", "program"], READ),
+    spec("QSET", "QSet", &["key", "path", "value"], WRITE),
+    spec("MERGE", "Merge", &["key", "value"], WRITE),
+    spec("QTYPE", "QType", &["key", "path"], READ),
+    spec("QAPPEND", "QAppend", &["key", "path", "value"], WRITE),
+    spec(
+        "QINSERT",
+        "QInsert",
+        &["key", "path", "index", "value"],
+        WRITE,
+    ),
+    spec("QPOP", "QPop", &["key", "path", "[index]"], WRITE),
+    spec("OBJKEYS", "ObjKeys", &["key", "path"], READ),
+    spec("ARRLEN", "ArrLen", &["key", "path"], READ),
+    spec("DIGEST", "Digest", &["[key]"], READ),
+    spec("EXPLAIN", "Explain", &["command"], READ),
+    spec("STREAM", "Stream", &["command", "[chunk_size]"], READ),
+    spec("EVAL", "Eval", &["script", "keys", "[args]"], WRITE),
+    spec("PROFILE", "Profile", &["kind", "seconds"], READ_ADMIN),
+    spec(
+        "INJECTLATENCY",
+        "InjectLatency",
+        &["target", "millis", "seconds"],
+        READ_ADMIN,
+    ),
+    spec("READONLY", "ReadOnly", &["enabled"], READ_ADMIN),
+    spec("SAVE", "Save", &["[background]"], READ_ADMIN),
+    spec("BGSAVE", "Save", &["[background]"], READ_ADMIN),
+    spec("VERIFYBACKUP", "VerifyBackup", &["[source]"], READ_ADMIN),
+    spec("AOFFETCH", "AofFetch", &["sequence", "offset"], READ_ADMIN),
+    spec(
+        "SNAPSHOTFETCH",
+        "SnapshotFetch",
+        &["index", "offset"],
+        READ_ADMIN,
+    ),
+    spec("PROMOTE", "Promote", &[], READ_ADMIN),
+    spec("ACLSET", "AclSet", &["identity", "grants"], READ_ADMIN),
+    spec("ACLDEL", "AclDel", &["identity"], READ_ADMIN),
+    spec("ACLLIST", "AclList", &[], READ_ADMIN),
+    spec("CONFIG GET", "ConfigGet", &["parameter"], READ_ADMIN),
+    spec(
+        "CONFIG SET",
+        "ConfigSet",
+        &["parameter", "value"],
+        READ_ADMIN,
+    ),
+    spec(
+        "AUDIT",
+        "Audit",
+        &["since", "[identity]", "[key_pattern]", "[limit]"],
+        READ_ADMIN,
+    ),
+    spec("MEMORY USAGE", "MemoryUsage", &["key"], READ),
+    spec("META", "Meta", &["key"], READ),
+    spec("FLUSH", "Flush", &["[prefix]"], WRITE_ADMIN),
+    spec("EXPIRE", "Expire", &["key", "seconds"], WRITE),
+    spec("TTL", "Ttl", &["key"], READ),
+    spec("PERSIST", "Persist", &["key"], WRITE),
+    spec("RANDOMKEY", "RandomKey", &[], READ),
+    spec("SAMPLE", "Sample", &["n"], READ),
+    spec("TOMBSTONES", "Tombstones", &["since"], READ),
+    spec("CHANGES", "Changes", &["since", "[limit]"], READ),
+    spec("GROUPREAD", "GroupRead", &["group", "count"], READ),
+    spec("GROUPACK", "GroupAck", &["group", "offset"], WRITE),
+    spec("HISTORY", "History", &["key"], READ),
+    spec("RESTORE", "Restore", &["key", "version"], WRITE),
+    spec("ROUTINGTABLE", "RoutingTable", &[], READ),
+    spec("INFO", "Info", &[], READ),
+    spec("USAGE", "Usage", &[], READ),
+    spec("STATS", "Stats", &[], READ),
+    spec("SHARDSTATS", "ShardStats", &[], READ),
+    spec("COMMAND", "Commands", &[], READ),
+    spec("EXPORT", "Export", &["pattern"], READ),
+    spec("IMPORT", "Import", &["data"], WRITE),
+    spec("BULKLOAD", "BulkLoad", &["data"], WRITE),
+    spec("WATCH", "Watch", &["key"], READ),
+    spec("CHANGEFEED", "Changefeed", &["from_offset"], READ),
+    spec("SUBSCRIBEEVENTS", "SubscribeEvents", &["pattern"], READ),
+    spec("PUBLISH", "Publish", &["channel", "message"], READ),
+    spec("SUBSCRIBE", "Subscribe", &["channels..."], READ),
+    spec("CLIENT SETNAME", "ClientSetName", &["name"], CONNECTION),
+    spec("CLIENT LIST", "ClientList", &[], READ_ADMIN),
+    spec("CLIENT KILL", "ClientKill", &["id"], READ_ADMIN),
+    spec("SELECT", "Select", &["namespace"], CONNECTION),
+    spec("AUTH", "Auth", &["[username]", "password"], CONNECTION),
+    spec(
+        "HELLO",
+        "Hello",
+        &[
+            "[topology_updates]",
+            "[protocol]",
+            "[encodings]",
+            "[compression]",
+            "[chunked_frames]",
+        ],
+        CONNECTION,
+    ),
+    spec("PING", "Ping", &[], READ),
+];
+
+/// Returns the description of a command by protocol name, case insensitive
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    COMMAND_TABLE
+        .iter()
+        .find(|spec| spec.name.eq_ignore_ascii_case(name))
+}
+
+impl Command {
+    /// Returns the description of the command
+    pub fn spec(&self) -> &'static CommandSpec {
+        lookup(self.name()).expect("every command is described in COMMAND_TABLE")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_command_table() {
+        let commands = [
+            Command::Set {
+                key: "k".to_string(),
+                value: json!(1),
+            },
+            Command::QScan {
+                key_pattern: "*".to_string(),
+                query: "$".to_string(),
+                limit: None,
+            },
+            Command::Flush { prefix: None },
+            Command::Save { background: true },
+            Command::VerifyBackup { source: None },
+            Command::Profile {
+                kind: crate::protocol::ProfileKind::Cpu,
+                seconds: 1,
+            },
+            Command::ConfigGet {
+                parameter: "*".to_string(),
+            },
+            Command::Subscribe { channels: vec![] },
+            Command::GroupAck {
+                group: "cdc".to_string(),
+                offset: 1,
+            },
+            Command::ClientSetName {
+                name: "worker".to_string(),
+            },
+            Command::Commands,
+            Command::Ping,
+        ];
+        for command in &commands {
+            let spec = command.spec();
+            assert_eq!(spec.is_write(), command.is_write(), "{}", spec.name);
+            assert_eq!(spec.is_admin(), command.is_admin(), "{}", spec.name);
+            let request = serde_json::to_value(command).unwrap();
+            let variant = match &request {
+                serde_json::Value::Object(map) => map.keys().next().unwrap().as_str(),
+                serde_json::Value::String(variant) => variant.as_str(),
+                other => panic!("unexpected request {}", other),
+            };
+            assert_eq!(spec.variant, variant);
+        }
+
+        assert_eq!(lookup("CLIENT SETNAME").unwrap().flags, CONNECTION);
+        assert_eq!(lookup("set").unwrap().arity(), 2);
+        assert_eq!(lookup("QSCAN").unwrap().arity(), -2);
+        assert_eq!(lookup("SUBSCRIBE").unwrap().arity(), -1);
+        assert_eq!(lookup("PING").unwrap().arity(), 0);
+        assert!(lookup("FLUSHALL").is_none());
+    }
+}
@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::time::Duration;
+
+/// Parameters of CONFIG GET and CONFIG SET, in the order they are listed
+pub const CONFIG_PARAMETERS: &[&str] = &[
+    "idle-timeout",
+    "max-value-size",
+    "max-memory",
+    "eviction-policy",
+    "log-level",
+];
+
+/// Which keys of cache-tier namespaces are evicted under memory pressure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EvictionPolicy {
+    /// The least recently written keys of a random sample
+    #[default]
+    Oldest,
+    /// Keys of a random sample
+    Random,
+    /// No key: writes beyond the memory limit fail, or spill when enabled
+    None,
+}
+
+impl EvictionPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EvictionPolicy::Oldest => "oldest",
+            EvictionPolicy::Random => "random",
+            EvictionPolicy::None => "none",
+        }
+    }
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "oldest" => Ok(EvictionPolicy::Oldest),
+            "random" => Ok(EvictionPolicy::Random),
+            "none" => Ok(EvictionPolicy::None),
+            other => Err(format!(
+                "Invalid eviction policy '{}': expected oldest, random or none",
+                other
+            )),
+        }
+    }
+}
+
+/// Settings tunable at runtime with CONFIG SET, shared by the namespaces of
+/// a database and the connections of its server
+#[derive(Debug, Default)]
+pub struct RuntimeConfig {
+    /// Milliseconds a connection may send no request (0 when unlimited)
+    idle_timeout_ms: AtomicU64,
+    /// Largest serialized value accepted by writes (0 when unlimited)
+    max_value_bytes: AtomicU64,
+    eviction_policy: AtomicU8,
+}
+
+impl RuntimeConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How long connections may send no request before being closed
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        match self.idle_timeout_ms.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    pub fn set_idle_timeout(&self, timeout: Option<Duration>) {
+        self.idle_timeout_ms.store(
+            timeout.map_or(0, |timeout| (timeout.as_millis() as u64).max(1)),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Largest serialized value accepted by writes
+    pub fn max_value_size(&self) -> Option<u64> {
+        match self.max_value_bytes.load(Ordering::Relaxed) {
+            0 => None,
+            max => Some(max),
+        }
+    }
+
+    pub fn set_max_value_size(&self, max_bytes: Option<u64>) {
+        self.max_value_bytes
+            .store(max_bytes.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub fn eviction_policy(&self) -> EvictionPolicy {
+        match self.eviction_policy.load(Ordering::Relaxed) {
+            1 => EvictionPolicy::Random,
+            2 => EvictionPolicy::None,
+            _ => EvictionPolicy::Oldest,
+        }
+    }
+
+    pub fn set_eviction_policy(&self, policy: EvictionPolicy) {
+        let value = match policy {
+            EvictionPolicy::Oldest => 0,
+            EvictionPolicy::Random => 1,
+            EvictionPolicy::None => 2,
+        };
+        self.eviction_policy.store(value, Ordering::Relaxed);
+    }
+}
+
+/// Parses a limit of CONFIG SET, where 0 and `none` mean no limit
+pub(crate) fn parse_limit(parameter: &str, value: &str) -> Result<Option<u64>, String> {
+    match value {
+        "none" => Ok(None),
+        value => value
+            .parse::<u64>()
+            .map(|limit| (limit > 0).then_some(limit))
+            .map_err(|_| {
+                format!(
+                    "Invalid value '{}' for {}: expected a number or none",
+                    value, parameter
+                )
+            }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runtime_config() {
+        let config = RuntimeConfig::new();
+        assert_eq!(config.idle_timeout(), None);
+        assert_eq!(config.eviction_policy(), EvictionPolicy::Oldest);
+
+        config.set_idle_timeout(Some(Duration::from_secs(30)));
+        config.set_max_value_size(Some(1024));
+        config.set_eviction_policy("none".parse().unwrap());
+        assert_eq!(config.idle_timeout(), Some(Duration::from_secs(30)));
+        assert_eq!(config.max_value_size(), Some(1024));
+        assert_eq!(config.eviction_policy(), EvictionPolicy::None);
+
+        assert!("lru".parse::<EvictionPolicy>().is_err());
+        assert_eq!(parse_limit("max-value-size", "0"), Ok(None));
+        assert_eq!(parse_limit("max-value-size", "none"), Ok(None));
+        assert!(parse_limit("max-value-size", "1k").is_err());
+    }
+}
@@ -1,392 +1,5880 @@
-use crate::protocol::{Command, Response};
+use crate::acl::AccessControl;
+use crate::audit::{self, AuditLog, AuditRecord, DEFAULT_AUDIT_LIMIT};
+use crate::canonical;
+use crate::capabilities::Capabilities;
+use crate::changes::{ChangeKind, ChangeLog};
+use crate::clock::{self, Clock};
+use crate::commands::COMMAND_TABLE;
+use crate::config::{self, EvictionPolicy, RuntimeConfig, CONFIG_PARAMETERS};
+use crate::document::{self, Document};
+use crate::export::ExportRecord;
+use crate::glob;
+use crate::jq;
+use crate::profiling;
+use crate::protocol::{
+    AggregateOp, Command, ErrorCode, ErrorInfo, KeyEvent, LatencyTarget, Response,
+};
+use crate::scripting::{ScriptInput, ScriptOutput, Scripts};
+use crate::stall::WriteStallMonitor;
+use crate::storage::aof::{AofRecord, AppendOnlyFile, BulkDocument};
+use crate::storage::backup::{self, S3Backup};
+use crate::storage::layout::DataDir;
+use crate::storage::snapshot::{Dump, DumpEntry, DumpValue, SnapshotStore};
+use crate::storage::spill::SpillStore;
+use crate::storage::standby::Standby;
+use crate::transform::WritePipeline;
+use crate::triggers::TriggerSet;
+use bytes::Bytes;
+use dashmap::mapref::entry::{Entry, OccupiedEntry};
 use dashmap::DashMap;
-use log::{debug, error};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::sync::Arc;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::time::Duration;
+use tokio::sync::{broadcast, oneshot};
+use tokio::time::Instant;
 
-/// In-memory thread-safe JSON key-value database optimized for Raft consensus
+/// Target number of keys per shard when sizing the map adaptively
+const KEYS_PER_SHARD: usize = 65_536;
+
+/// Upper bound for the adaptive shard count
+const MAX_SHARDS: usize = 4096;
+
+/// Field holding the metadata of object documents when inline metadata is enabled
+pub const INLINE_META_FIELD: &str = "_meta";
+
+/// Maximum number of entries returned by SAMPLE
+const MAX_SAMPLE_SIZE: usize = 1000;
+
+/// Longest delay INJECTLATENCY can add
+const MAX_INJECTED_LATENCY: Duration = Duration::from_secs(60);
+
+/// Longest time a latency injection can last before expiring
+const MAX_INJECTION_DURATION: Duration = Duration::from_secs(3600);
+
+/// Number of keys sampled per eviction round, evicted least recently written first
+const EVICTION_POOL_SIZE: usize = 64;
+
+/// Number of key events buffered for each subscriber
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Maximum number of changes returned by CHANGES and GROUPREAD
+const MAX_CHANGES_BATCH: usize = 1000;
+
+/// Number of changes returned by CHANGES when no limit is given
+const DEFAULT_CHANGES_BATCH: usize = 100;
+
+/// Attempts at counting the keys between writes before waiting for them
+const COUNT_ATTEMPTS: usize = 16;
+
+/// Runs of an EVAL script before giving up on keys that keep changing
+const MAX_EVAL_ATTEMPTS: usize = 8;
+
+/// Namespace used by connections that never select one
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// Bytes of AOF records or snapshot shipped to a standby per request
+const SHIPPING_CHUNK_BYTES: u64 = 1 << 20;
+
+/// Maximum length of a namespace name
+const MAX_NAMESPACE_LEN: usize = 64;
+
+/// Resource limits of a namespace (unset limits are not enforced)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quota {
+    /// Maximum number of keys
+    #[serde(default)]
+    pub max_keys: Option<usize>,
+    /// Maximum total size in bytes of keys and serialized values
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+}
+
+/// Metadata kept alongside each stored value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyMeta {
+    /// Creation time in milliseconds since the UNIX epoch
+    pub created_at: u64,
+    /// Time of the last write in milliseconds since the UNIX epoch
+    pub updated_at: u64,
+    /// Number of writes to the key since it was created
+    pub version: u64,
+}
+
+/// A past value of a key
+#[derive(Debug, Clone, Serialize)]
+struct Revision {
+    version: u64,
+    updated_at: u64,
+    value: Arc<Value>,
+}
+
+/// A write section entered with `Database::begin_write`, counted as in
+/// progress until it is dropped
+struct WriteSection<'a> {
+    _gate: RwLockReadGuard<'a, ()>,
+    writes_in_progress: &'a AtomicUsize,
+}
+
+impl Drop for WriteSection<'_> {
+    fn drop(&mut self) {
+        self.writes_in_progress.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Storage of a single namespace
+#[derive(Debug, Clone)]
+struct Keyspace {
+    data: Arc<DashMap<String, Document>>,
+    blobs: Arc<DashMap<String, Bytes>>,
+    meta: Arc<DashMap<String, KeyMeta>>,
+    history: Arc<DashMap<String, VecDeque<Revision>>>,
+    tombstones: Arc<DashMap<String, u64>>,
+    expires: Arc<DashMap<String, u64>>,
+    changes: Arc<ChangeLog>,
+    version: Arc<AtomicU64>,
+    write_gate: Arc<RwLock<()>>,
+    writes_in_progress: Arc<AtomicUsize>,
+    bytes: Arc<AtomicU64>,
+    shard_waits: Arc<[AtomicU64]>,
+    activity: Arc<Activity>,
+}
+
+impl Keyspace {
+    fn new(map: DashMap<String, Document>, shard_count: usize) -> Self {
+        Self {
+            blobs: Arc::new(DashMap::with_shard_amount(shard_count)),
+            meta: Arc::new(DashMap::with_shard_amount(shard_count)),
+            history: Arc::new(DashMap::with_shard_amount(shard_count)),
+            tombstones: Arc::new(DashMap::with_shard_amount(shard_count)),
+            expires: Arc::new(DashMap::with_shard_amount(shard_count)),
+            changes: Arc::new(ChangeLog::default()),
+            data: Arc::new(map),
+            version: Arc::new(AtomicU64::new(0)),
+            write_gate: Arc::new(RwLock::new(())),
+            writes_in_progress: Arc::new(AtomicUsize::new(0)),
+            bytes: Arc::new(AtomicU64::new(0)),
+            shard_waits: (0..shard_count).map(|_| AtomicU64::new(0)).collect(),
+            activity: Arc::default(),
+        }
+    }
+}
+
+/// Commands executed in a namespace, reported by USAGE
+#[derive(Debug, Default)]
+struct Activity {
+    reads: AtomicU64,
+    writes: AtomicU64,
+}
+
+/// Operation counters of a database, shared by all namespaces
+#[derive(Debug)]
+struct Stats {
+    started_at: std::time::Instant,
+    /// Key lookups that found the key
+    hits: AtomicU64,
+    /// Key lookups that did not find the key
+    misses: AtomicU64,
+    /// Keys deleted because their expiry passed
+    expired: AtomicU64,
+    /// Keys of cache-tier namespaces evicted under memory pressure
+    evicted: AtomicU64,
+    /// Executed commands by name
+    commands: DashMap<&'static str, AtomicU64>,
+}
+
+impl Stats {
+    fn new() -> Self {
+        Self {
+            started_at: std::time::Instant::now(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            expired: AtomicU64::new(0),
+            evicted: AtomicU64::new(0),
+            commands: DashMap::new(),
+        }
+    }
+
+    /// Records an executed command
+    fn record_command(&self, name: &'static str) {
+        // Fast path: the counter exists after the first execution of a command
+        if let Some(counter) = self.commands.get(name) {
+            counter.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.commands
+            .entry(name)
+            .or_insert_with(|| AtomicU64::new(0))
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records whether a read of a key found it
+    fn record_lookup(&self, found: bool) {
+        let counter = if found { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// In-memory thread-safe JSON key-value database optimized for Raft consensus.
+///
+/// A `Database` value is a handle on one namespace; `namespace()` returns a
+/// handle on another isolated keyspace sharing the same configuration.
 #[derive(Debug, Clone)]
 pub struct Database {
+    /// Name of the namespace this handle operates on
+    namespace: Arc<str>,
+    /// Every namespace of the database, by name
+    namespaces: Arc<DashMap<String, Keyspace>>,
     /// Main storage using DashMap for optimal concurrency
-    data: Arc<DashMap<String, Value>>,
+    data: Arc<DashMap<String, Document>>,
+    /// Binary values, stored as raw bytes next to the JSON documents
+    blobs: Arc<DashMap<String, Bytes>>,
+    /// Metadata of every key, updated while the key's entry is locked
+    meta: Arc<DashMap<String, KeyMeta>>,
+    /// Last revisions of every key, newest last
+    history: Arc<DashMap<String, VecDeque<Revision>>>,
+    /// Number of revisions kept per key (0 disables history)
+    history_depth: Arc<AtomicUsize>,
+    /// Deletion time of recently deleted keys, in milliseconds since the UNIX epoch
+    tombstones: Arc<DashMap<String, u64>>,
+    /// Expiration time of keys with a TTL, in milliseconds since the UNIX epoch
+    expires: Arc<DashMap<String, u64>>,
+    /// How long deletions leave a tombstone (None disables tombstones)
+    tombstone_retention: Arc<RwLock<Option<Duration>>>,
+    /// Recent changes to the keyspace and consumer group offsets
+    changes: Arc<ChangeLog>,
+    /// Number of changes kept in the change log (0 disables it)
+    change_log_capacity: Arc<AtomicUsize>,
+    /// Maintain metadata fields inside object documents
+    inline_meta: Arc<AtomicBool>,
+    /// Number of shards of the underlying map
+    shard_count: usize,
+    /// Transformations applied to values before they are stored
+    write_pipeline: Arc<RwLock<WritePipeline>>,
+    /// Derived-field rules fired on writes, after the transformations
+    triggers: Arc<RwLock<TriggerSet>>,
+    /// WASM modules run by EVAL
+    scripts: Arc<Scripts>,
+    /// Store values in canonical JSON form
+    canonical_json: Arc<AtomicBool>,
+    /// Serialized size from which documents are stored compressed (0 disables compression)
+    compression_threshold: Arc<AtomicUsize>,
+    /// Incremented on every write to the keyspace
+    version: Arc<AtomicU64>,
+    /// Shared by writers, held exclusively while reading a consistent view of the keyspace
+    write_gate: Arc<RwLock<()>>,
+    /// Writers in a section entered with `begin_write`
+    writes_in_progress: Arc<AtomicUsize>,
+    /// Total size of the keyspace, as accounted for quotas
+    bytes: Arc<AtomicU64>,
+    /// Accesses that found their shard locked, by shard
+    shard_waits: Arc<[AtomicU64]>,
+    /// Commands executed in the namespace
+    activity: Arc<Activity>,
+    /// Quotas by namespace name
+    quotas: Arc<RwLock<HashMap<String, Quota>>>,
+    /// Total size of every namespace, as accounted for quotas
+    used_memory: Arc<AtomicU64>,
+    /// Limit of `used_memory` (0 when unlimited)
+    max_memory: Arc<AtomicU64>,
+    /// Settings tunable with CONFIG SET
+    config: Arc<RuntimeConfig>,
+    /// Namespaces whose keys may be evicted to honor the memory limit
+    cache_tier: Arc<RwLock<HashSet<String>>>,
+    /// Tracks writes in progress to detect a stalled write pipeline
+    write_monitor: WriteStallMonitor,
+    /// Reject writes while the write pipeline is stalled
+    write_fenced: Arc<AtomicBool>,
+    /// Reject every write, for maintenance windows and replicas
+    read_only: Arc<AtomicBool>,
+    /// Log of the writes to every namespace, replayed on restart
+    aof: Arc<RwLock<Option<Arc<AppendOnlyFile>>>>,
+    /// Where mutating commands are recorded, for compliance
+    audit: Arc<RwLock<Option<Arc<AuditLog>>>>,
+    /// Where SAVE writes snapshots
+    snapshot_store: Arc<RwLock<Option<Arc<SnapshotStore>>>>,
+    /// Where the data directory is backed up, for VERIFYBACKUP s3
+    backup: Arc<RwLock<Option<Arc<S3Backup>>>>,
+    /// The primary this node follows until PROMOTE, as a standby
+    standby: Arc<RwLock<Option<Arc<Standby>>>>,
+    /// Where cold documents go once the memory limit is reached
+    spill: Arc<RwLock<Option<Arc<SpillStore>>>>,
+    /// Operation counters
+    stats: Arc<Stats>,
+    /// Changes to keys of every namespace, published to event subscribers
+    events: broadcast::Sender<KeyEvent>,
+    /// Source of key metadata timestamps
+    clock: Arc<dyn Clock>,
+    /// Node configuration reported by INFO, set at startup
+    capabilities: Arc<RwLock<Option<Capabilities>>>,
+    /// Grants of the authenticated identities
+    acl: Arc<AccessControl>,
+    /// Identity commands run on behalf of, subject to its grants (None for
+    /// the embedding application and unauthenticated servers)
+    identity: Option<Arc<str>>,
+    /// Artificial delays with their expiry, for game-day exercises
+    injected_latency: Arc<RwLock<HashMap<LatencyTarget, (Duration, Instant)>>>,
 }
 
 impl Database {
     /// Creates a new database instance
     pub fn new() -> Self {
+        let shards = Self::default_shard_count();
+        Self::from_map(DashMap::with_shard_amount(shards), shards)
+    }
+
+    /// Creates a database pre-sized for `capacity` keys with an explicit shard count
+    /// (must be a power of two greater than one)
+    pub fn with_capacity_and_shards(capacity: usize, shards: usize) -> Result<Self, String> {
+        if shards < 2 || !shards.is_power_of_two() {
+            return Err(format!(
+                "Shard count must be a power of two greater than 1, got {}",
+                shards
+            ));
+        }
+        Ok(Self::from_map(
+            DashMap::with_capacity_and_shard_amount(capacity, shards),
+            shards,
+        ))
+    }
+
+    /// Creates a database pre-sized for an expected number of keys, choosing
+    /// the shard count so that large keyspaces don't stall on rehashing
+    pub fn with_expected_keys(expected_keys: usize) -> Self {
+        let shards = Self::adaptive_shard_count(expected_keys);
+        Self::from_map(
+            DashMap::with_capacity_and_shard_amount(expected_keys, shards),
+            shards,
+        )
+    }
+
+    /// Creates a database with an explicit shard count (must be a power of
+    /// two greater than one)
+    pub fn with_shards(shards: usize) -> Result<Self, String> {
+        Self::with_capacity_and_shards(0, shards)
+    }
+
+    /// Returns the number of shards of the underlying map
+    pub fn shard_count(&self) -> usize {
+        self.shard_count
+    }
+
+    /// Default shard count, matching DashMap's own heuristic
+    fn default_shard_count() -> usize {
+        let parallelism = std::thread::available_parallelism().map_or(1, usize::from);
+        (parallelism * 4).next_power_of_two().max(2)
+    }
+
+    /// Shard count for an expected number of keys
+    fn adaptive_shard_count(expected_keys: usize) -> usize {
+        let by_size = (expected_keys / KEYS_PER_SHARD).next_power_of_two();
+        by_size.max(Self::default_shard_count()).min(MAX_SHARDS)
+    }
+
+    /// Builds a database around an existing map
+    fn from_map(map: DashMap<String, Document>, shard_count: usize) -> Self {
+        let keyspace = Keyspace::new(map, shard_count);
+        let namespaces = DashMap::new();
+        namespaces.insert(DEFAULT_NAMESPACE.to_string(), keyspace.clone());
         Self {
-            data: Arc::new(DashMap::new()),
+            namespace: Arc::from(DEFAULT_NAMESPACE),
+            namespaces: Arc::new(namespaces),
+            data: keyspace.data,
+            blobs: keyspace.blobs,
+            meta: keyspace.meta,
+            history: keyspace.history,
+            history_depth: Arc::new(AtomicUsize::new(0)),
+            tombstones: keyspace.tombstones,
+            expires: keyspace.expires,
+            tombstone_retention: Arc::new(RwLock::new(None)),
+            changes: keyspace.changes,
+            change_log_capacity: Arc::new(AtomicUsize::new(0)),
+            inline_meta: Arc::new(AtomicBool::new(false)),
+            shard_count,
+            write_pipeline: Arc::new(RwLock::new(WritePipeline::default())),
+            triggers: Arc::new(RwLock::new(TriggerSet::default())),
+            scripts: Arc::new(Scripts::new()),
+            canonical_json: Arc::new(AtomicBool::new(false)),
+            compression_threshold: Arc::new(AtomicUsize::new(0)),
+            version: keyspace.version,
+            write_gate: keyspace.write_gate,
+            writes_in_progress: keyspace.writes_in_progress,
+            bytes: keyspace.bytes,
+            shard_waits: keyspace.shard_waits,
+            activity: keyspace.activity,
+            quotas: Arc::new(RwLock::new(HashMap::new())),
+            used_memory: Arc::new(AtomicU64::new(0)),
+            max_memory: Arc::new(AtomicU64::new(0)),
+            config: Arc::new(RuntimeConfig::new()),
+            cache_tier: Arc::new(RwLock::new(HashSet::new())),
+            write_monitor: WriteStallMonitor::new(),
+            write_fenced: Arc::new(AtomicBool::new(false)),
+            read_only: Arc::new(AtomicBool::new(false)),
+            aof: Arc::new(RwLock::new(None)),
+            audit: Arc::new(RwLock::new(None)),
+            snapshot_store: Arc::new(RwLock::new(None)),
+            backup: Arc::new(RwLock::new(None)),
+            standby: Arc::new(RwLock::new(None)),
+            spill: Arc::new(RwLock::new(None)),
+            stats: Arc::new(Stats::new()),
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            clock: clock::system_clock(),
+            capabilities: Arc::new(RwLock::new(None)),
+            acl: Arc::new(AccessControl::new()),
+            identity: None,
+            injected_latency: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
-    /// Execute a command and return the response
-    pub async fn execute_command(&self, command: Command) -> Response {
-        match command {
-            Command::Set { key, value } => self.set(key, value).await,
-            Command::Get { key } => self.get(&key).await,
-            Command::Delete { key } => self.delete(key).await,
-            Command::QGet { key, query } => self.qget(&key, &query).await,
-            Command::QSet { key, path, value } => self.qset(key, path, value).await,
-            Command::Merge { key, value } => self.merge(key, value).await,
-            Command::Ping => Response::Pong,
+    /// Use a custom clock for key metadata and write stall detection
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.write_monitor = WriteStallMonitor::with_clock(clock.clone());
+        self.clock = clock;
+        self
+    }
+
+    /// Returns a handle on a namespace, creating it on first use.
+    ///
+    /// Namespaces have isolated keyspaces and share every other setting.
+    pub fn namespace(&self, name: &str) -> Result<Database, String> {
+        Self::validate_namespace(name)?;
+        let keyspace = self
+            .namespaces
+            .entry(name.to_string())
+            .or_insert_with(|| {
+                Keyspace::new(
+                    DashMap::with_shard_amount(self.shard_count),
+                    self.shard_count,
+                )
+            })
+            .clone();
+        Ok(Self {
+            namespace: Arc::from(name),
+            data: keyspace.data,
+            blobs: keyspace.blobs,
+            meta: keyspace.meta,
+            history: keyspace.history,
+            tombstones: keyspace.tombstones,
+            expires: keyspace.expires,
+            changes: keyspace.changes,
+            version: keyspace.version,
+            write_gate: keyspace.write_gate,
+            writes_in_progress: keyspace.writes_in_progress,
+            bytes: keyspace.bytes,
+            shard_waits: keyspace.shard_waits,
+            activity: keyspace.activity,
+            ..self.clone()
+        })
+    }
+
+    /// Returns a handle running commands on behalf of an authenticated
+    /// identity, refusing those its grants don't allow
+    pub fn with_identity(&self, identity: &str) -> Database {
+        Self {
+            identity: Some(Arc::from(identity)),
+            ..self.clone()
         }
     }
 
-    /// Sets a value for a key
-    async fn set(&self, key: String, value: Value) -> Response {
-        // JSON validation
-        if !self.is_valid_json(&value) {
-            return Response::Error("Invalid JSON value".to_string());
+    /// Grants of the authenticated identities, shared by every handle
+    pub fn access_control(&self) -> &AccessControl {
+        &self.acl
+    }
+
+    /// Returns an error unless the identity of this handle may run `command`
+    pub fn authorize(&self, command: &Command) -> Result<(), String> {
+        match &self.identity {
+            Some(identity) => self.acl.check(identity, command),
+            None => Ok(()),
         }
+    }
 
-        self.data.insert(key.clone(), value.clone());
-        debug!("SET: {} = {}", key, value);
+    /// Returns the name of the namespace this handle operates on
+    pub fn namespace_name(&self) -> &str {
+        &self.namespace
+    }
 
-        Response::Ok(None)
+    /// Lists the existing namespaces, sorted
+    pub fn namespaces(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.namespaces.iter().map(|e| e.key().clone()).collect();
+        names.sort();
+        names
     }
 
-    /// Reads a value for a key
-    async fn get(&self, key: &str) -> Response {
-        match self.data.get(key) {
-            Some(value) => {
-                debug!("GET: {} = {}", key, value.clone());
-                Response::Ok(Some(value.clone()))
+    /// Set the quota of a namespace
+    pub fn set_quota(&self, namespace: &str, quota: Quota) {
+        self.quotas
+            .write()
+            .unwrap()
+            .insert(namespace.to_string(), quota);
+    }
+
+    /// Replace the quotas of every namespace
+    pub fn set_quotas(&self, quotas: HashMap<String, Quota>) {
+        *self.quotas.write().unwrap() = quotas;
+    }
+
+    /// Load per-namespace quotas from a JSON file mapping namespace names to limits
+    pub fn load_quotas(&self, path: &str) -> Result<(), String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read quotas '{}': {}", path, e))?;
+        let quotas: HashMap<String, Quota> = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid quotas '{}': {}", path, e))?;
+        self.set_quotas(quotas);
+        Ok(())
+    }
+
+    /// Returns the quota of this namespace
+    fn quota(&self) -> Quota {
+        self.quotas
+            .read()
+            .unwrap()
+            .get(&*self.namespace)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Size accounted to a key-value pair for quotas
+    fn entry_size(key: &str, value: &Value) -> u64 {
+        key.len() as u64 + document::serialized_size(value)
+    }
+
+    /// Size of a stored entry, as accounted for quotas (compression does not
+    /// change it)
+    fn document_size(key: &str, document: &Document) -> u64 {
+        key.len() as u64 + document.size()
+    }
+
+    /// Checks that replacing an entry of `old_size` bytes (None for a new key)
+    /// with one of `new_size` bytes stays within the namespace quota
+    fn check_quota(
+        &self,
+        old_size: Option<u64>,
+        new_size: u64,
+        key_count: usize,
+    ) -> Result<(), ErrorInfo> {
+        self.check_batch_quota(
+            old_size.unwrap_or(0),
+            new_size,
+            usize::from(old_size.is_none()),
+            key_count,
+        )
+    }
+
+    /// Checks that writes replacing entries of `old_size` bytes in total with
+    /// entries of `new_size` bytes, `added_keys` of them new, stay within the
+    /// namespace quota
+    fn check_batch_quota(
+        &self,
+        old_size: u64,
+        new_size: u64,
+        added_keys: usize,
+        key_count: usize,
+    ) -> Result<(), ErrorInfo> {
+        let quota = self.quota();
+        if let Some(max_keys) = quota.max_keys {
+            if added_keys > 0 && key_count + added_keys > max_keys {
+                return Err(self.quota_exceeded("max_keys", max_keys as u64, key_count as u64));
             }
-            None => {
-                debug!("GET: {} not found", key);
-                Response::Ok(None)
+        }
+        if let Some(max_bytes) = quota.max_bytes {
+            let bytes = self.bytes.load(Ordering::Acquire);
+            if new_size > old_size && bytes - old_size.min(bytes) + new_size > max_bytes {
+                return Err(self.quota_exceeded("max_bytes", max_bytes, bytes));
             }
         }
+        Ok(())
     }
 
-    /// Deletes a value for a key
-    async fn delete(&self, key: String) -> Response {
-        match self.data.remove(&key) {
-            Some(_) => {
-                debug!("DELETE: {} removed", key);
-                Response::Ok(None)
-            }
-            None => {
-                debug!("DELETE: {} not found", key);
-                Response::Error("Key not found".to_string())
-            }
+    /// Checks that the value of an entry of `entry_size` bytes stays within
+    /// the `max-value-size` setting
+    fn check_value_size(&self, key: &str, entry_size: u64) -> Result<(), ErrorInfo> {
+        let Some(max) = self.config.max_value_size() else {
+            return Ok(());
+        };
+        let size = entry_size - key.len() as u64;
+        if size <= max {
+            return Ok(());
         }
+        Err(ErrorInfo::new(
+            ErrorCode::InvalidArgument,
+            format!(
+                "Value of '{}' is {} bytes, more than max-value-size",
+                key, size
+            ),
+        )
+        .with_details(serde_json::json!({
+            "limit": "max_value_size",
+            "max": max,
+            "current": size,
+        })))
     }
 
-    /// Execute a JSONPath query on a value
-    async fn qget(&self, key: &str, query: &str) -> Response {
-        match self.data.get(key) {
-            Some(value) => match jsonpath_lib::select(&value.clone(), query) {
-                Ok(result) => {
-                    debug!(
-                        "JSONPath query: {} with query '{}' = {:?}",
-                        key, query, result
-                    );
-                    if result.is_empty() {
-                        Response::Ok(Some(Value::Null))
-                    } else if result.len() == 1 {
-                        Response::Ok(Some(result[0].clone()))
-                    } else {
-                        Response::Ok(Some(Value::Array(result.into_iter().cloned().collect())))
-                    }
-                }
-                Err(e) => {
-                    error!("JSONPath error for {}: {}", key, e);
-                    Response::Error(format!("JSONPath query error: {}", e))
-                }
+    /// Builds a quota-exceeded error
+    fn quota_exceeded(&self, limit: &str, max: u64, current: u64) -> ErrorInfo {
+        ErrorInfo::new(
+            ErrorCode::QuotaExceeded,
+            format!("Namespace '{}' quota exceeded: {}", self.namespace, limit),
+        )
+        .with_details(serde_json::json!({
+            "namespace": &*self.namespace,
+            "limit": limit,
+            "max": max,
+            "current": current,
+        }))
+    }
+
+    /// Updates the accounted size after an entry of `old_size` bytes was
+    /// replaced with one of `new_size` bytes (0 when removed)
+    fn account(&self, old_size: Option<u64>, new_size: u64) {
+        let old_size = old_size.unwrap_or(0);
+        if new_size >= old_size {
+            self.bytes.fetch_add(new_size - old_size, Ordering::AcqRel);
+            self.used_memory
+                .fetch_add(new_size - old_size, Ordering::AcqRel);
+        } else {
+            self.bytes.fetch_sub(old_size - new_size, Ordering::AcqRel);
+            self.used_memory
+                .fetch_sub(old_size - new_size, Ordering::AcqRel);
+        }
+    }
+
+    /// Limit the total size of every namespace (None removes the limit).
+    ///
+    /// Writes beyond the limit evict keys of cache-tier namespaces, and fail
+    /// when only durable data is left.
+    pub fn set_max_memory(&self, max_bytes: Option<u64>) {
+        self.max_memory
+            .store(max_bytes.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Settings tunable at runtime with CONFIG SET, shared by every namespace
+    pub fn config(&self) -> &Arc<RuntimeConfig> {
+        &self.config
+    }
+
+    /// Returns the runtime configuration parameters matching a glob, by name
+    fn config_get(&self, pattern: &str) -> Value {
+        let parameters: serde_json::Map<String, Value> = CONFIG_PARAMETERS
+            .iter()
+            .filter(|name| glob::glob_match(pattern, name))
+            .map(|name| (name.to_string(), self.config_value(name)))
+            .collect();
+        Value::Object(parameters)
+    }
+
+    fn config_value(&self, parameter: &str) -> Value {
+        match parameter {
+            "idle-timeout" => self.config.idle_timeout().map(|t| t.as_secs()).into(),
+            "max-value-size" => self.config.max_value_size().into(),
+            "max-memory" => match self.max_memory.load(Ordering::Relaxed) {
+                0 => Value::Null,
+                max => max.into(),
             },
-            None => {
-                debug!("JSONPath query: {} not found", key);
-                Response::Error("Key not found".to_string())
+            "eviction-policy" => self.config.eviction_policy().as_str().into(),
+            "log-level" => log::max_level().as_str().to_lowercase().into(),
+            _ => Value::Null,
+        }
+    }
+
+    /// Changes a runtime configuration parameter
+    pub fn config_set(&self, parameter: &str, value: &str) -> Result<(), String> {
+        match parameter {
+            "idle-timeout" => {
+                let seconds = config::parse_limit(parameter, value)?;
+                self.config
+                    .set_idle_timeout(seconds.map(Duration::from_secs));
+            }
+            "max-value-size" => {
+                self.config
+                    .set_max_value_size(config::parse_limit(parameter, value)?);
+            }
+            "max-memory" => self.set_max_memory(config::parse_limit(parameter, value)?),
+            "eviction-policy" => self.config.set_eviction_policy(value.parse()?),
+            "log-level" => {
+                let level = value.parse::<log::LevelFilter>().map_err(|_| {
+                    format!(
+                        "Invalid log level '{}': expected off, error, warn, info, debug or trace",
+                        value
+                    )
+                })?;
+                log::set_max_level(level);
+            }
+            other => {
+                return Err(format!(
+                    "Unknown configuration parameter '{}': expected one of {}",
+                    other,
+                    CONFIG_PARAMETERS.join(", ")
+                ))
             }
         }
+        info!("CONFIG SET {} {}", parameter, value);
+        Ok(())
     }
 
-    /// Set a sub-property using JSONPath
-    async fn qset(&self, key: String, path: String, value: Value) -> Response {
-        // Validate JSON
-        if !self.is_valid_json(&value) {
-            return Response::Error("Invalid JSON value".to_string());
+    /// Mark a namespace as cache tier, allowing its keys to be evicted under
+    /// memory pressure (namespaces are durable by default)
+    pub fn set_cache_tier(&self, namespace: &str, cache: bool) {
+        let mut cache_tier = self.cache_tier.write().unwrap();
+        if cache {
+            cache_tier.insert(namespace.to_string());
+        } else {
+            cache_tier.remove(namespace);
         }
+    }
 
-        // Get existing value or create new empty object
-        let existing_value = self
-            .data
-            .get(&key)
-            .map(|v| v.clone())
-            .unwrap_or(Value::Object(serde_json::Map::new()));
+    /// Move the least recently written documents to disk instead of failing
+    /// writes once the memory limit is reached and no cache-tier data is
+    /// left; spilled documents are reloaded when their key is accessed
+    pub fn set_spill(&self, spill: Option<Arc<SpillStore>>) {
+        *self.spill.write().unwrap() = spill;
+    }
 
-        // Clone for modification
-        let mut modified_value = existing_value.clone();
+    /// Size of the documents kept in memory, as accounted for the memory limit
+    fn resident_memory(&self) -> u64 {
+        let used = self.used_memory.load(Ordering::Acquire);
+        match self.spill.read().unwrap().as_ref() {
+            Some(spill) => used.saturating_sub(spill.spilled_bytes()),
+            None => used,
+        }
+    }
 
-        // Use JSONPath to set the value
-        match self.set_json_path(&mut modified_value, &path, value.clone()) {
-            Ok(()) => {
-                self.data.insert(key.clone(), modified_value.clone());
-                debug!("QSET: {} at path '{}' = {}", key, path, value);
-                Response::Ok(None)
+    /// Evicts keys of cache-tier namespaces, then spills documents to disk
+    /// when enabled, until a write of `needed` bytes fits in the memory
+    /// limit; called before locking anything
+    fn make_room(&self, needed: u64) -> Result<(), ErrorInfo> {
+        let max = self.max_memory.load(Ordering::Relaxed);
+        if max == 0 {
+            return Ok(());
+        }
+        loop {
+            let used = self.resident_memory();
+            if used + needed <= max {
+                return Ok(());
             }
-            Err(e) => {
-                error!("QSET error for {} at path '{}': {}", key, path, e);
-                Response::Error(format!("JSONPath set error: {}", e))
+            if self.evict(used + needed - max) == 0 && self.spill(used + needed - max) == 0 {
+                return Err(ErrorInfo::new(
+                    ErrorCode::QuotaExceeded,
+                    "Memory limit reached and no cache-tier data is left to evict",
+                )
+                .with_details(serde_json::json!({
+                    "limit": "max_memory",
+                    "max": max,
+                    "current": used,
+                })));
             }
         }
     }
 
-    /// Merges a JSON value with an existing one
-    async fn merge(&self, key: String, new_value: Value) -> Response {
-        // JSON validation
-        if !self.is_valid_json(&new_value) {
-            return Response::Error("Invalid JSON value".to_string());
+    /// Evicts keys of the largest cache-tier namespace to free about
+    /// `to_free` bytes, returning the number of evicted keys
+    fn evict(&self, to_free: u64) -> usize {
+        if self.config.eviction_policy() == EvictionPolicy::None {
+            return 0;
+        }
+        let largest = self
+            .cache_tier
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|name| {
+                let bytes = self.namespaces.get(name)?.bytes.load(Ordering::Acquire);
+                (bytes > 0).then(|| (bytes, name.clone()))
+            })
+            .max();
+        match largest.map(|(_, name)| self.namespace(&name)) {
+            Some(Ok(namespace)) => namespace.evict_oldest(to_free),
+            _ => 0,
         }
+    }
 
-        let merged_value = match self.data.get(&key) {
-            Some(existing_value) => {
-                match Self::merge_json_values(&existing_value.clone(), &new_value) {
-                    Ok(merged) => merged,
-                    Err(e) => return Response::Error(e),
+    /// Spills documents of the largest namespace with documents in memory to
+    /// free about `to_free` bytes, returning the number of spilled documents
+    fn spill(&self, to_free: u64) -> usize {
+        let Some(spill) = self.spill.read().unwrap().clone() else {
+            return 0;
+        };
+        let mut namespaces: Vec<(u64, String)> = self
+            .namespaces
+            .iter()
+            .map(|entry| (entry.bytes.load(Ordering::Acquire), entry.key().clone()))
+            .filter(|(bytes, _)| *bytes > 0)
+            .collect();
+        namespaces.sort_unstable_by(|a, b| b.cmp(a));
+        for (_, name) in namespaces {
+            if let Ok(namespace) = self.namespace(&name) {
+                let spilled = namespace.spill_oldest(&spill, to_free);
+                if spilled > 0 {
+                    return spilled;
                 }
             }
-            None => new_value.clone(),
-        };
-
-        self.data.insert(key.clone(), merged_value.clone());
-        debug!("MERGE: {} = {}", key, merged_value);
-        Response::Ok(None)
+        }
+        0
     }
 
-    /// Sets a value at a JSONPath location
-    fn set_json_path(&self, value: &mut Value, path: &str, new_value: Value) -> Result<(), String> {
-        // Parse the JSONPath - simplified implementation for basic paths
-        let path = path.trim_start_matches('$').trim_start_matches('.');
+    /// Spills the least recently written documents among a random sample of
+    /// this namespace until `to_free` bytes are freed; values, metadata and
+    /// versions are unchanged
+    fn spill_oldest(&self, spill: &SpillStore, to_free: u64) -> usize {
+        let positions = Self::random_positions(self.data.len(), EVICTION_POOL_SIZE);
+        let mut pool: Vec<(u64, String)> = self
+            .data
+            .iter()
+            .enumerate()
+            .filter(|(position, entry)| positions.contains(position) && !entry.is_spilled())
+            .map(|(_, entry)| {
+                let updated_at = self.meta.get(entry.key()).map_or(0, |m| m.updated_at);
+                (updated_at, entry.key().clone())
+            })
+            .collect();
+        pool.sort_unstable();
 
-        if path.is_empty() {
-            // Root path, replace entire value
-            *value = new_value;
-            return Ok(());
+        let (mut spilled, mut freed) = (0, 0);
+        for (_, key) in pool {
+            if freed >= to_free {
+                break;
+            }
+            let Some(mut document) = self.data.get_mut(&key) else {
+                continue;
+            };
+            if document.is_spilled() {
+                continue;
+            }
+            let Ok(serialized) = document.serialized() else {
+                continue;
+            };
+            match spill.spill(&serialized) {
+                Ok(slot) => {
+                    freed += slot.size();
+                    *document = Document::Spilled(Arc::new(slot));
+                    spilled += 1;
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    break;
+                }
+            }
         }
-
-        let parts: Vec<&str> = path.split('.').collect();
-        Self::set_nested_value(value, &parts, new_value)
+        debug!(
+            "Spilled {} documents of namespace {} to disk",
+            spilled, self.namespace
+        );
+        spilled
     }
 
-    /// Recursively sets a nested value
-    fn set_nested_value(
-        value: &mut Value,
-        path_parts: &[&str],
-        new_value: Value,
-    ) -> Result<(), String> {
-        if path_parts.is_empty() {
-            *value = new_value;
+    /// Moves a spilled document back to memory before its key is accessed.
+    ///
+    /// The spill file is read on a blocking thread without holding the shard
+    /// lock, and the document is swapped in only if it was not written
+    /// meanwhile. It is reloaded even without room for it: the next write
+    /// needing room spills documents again.
+    async fn reload_spilled(&self, key: &str) -> Result<(), ErrorInfo> {
+        let Some(slot) = self.data.get(key).and_then(|document| match &*document {
+            Document::Spilled(slot) => Some(Arc::clone(slot)),
+            _ => None,
+        }) else {
             return Ok(());
+        };
+        if let Err(e) = self.make_room(key.len() as u64 + slot.size()) {
+            debug!("Reloading {} beyond the memory limit: {}", key, e.message);
+        }
+        let reading = Arc::clone(&slot);
+        let serialized =
+            match tokio::task::spawn_blocking(move || Document::read_spilled(&reading)).await {
+                Ok(result) => result?,
+                Err(e) => {
+                    return Err(ErrorInfo::new(
+                        ErrorCode::Internal,
+                        format!("Failed to read a spilled document: {}", e),
+                    ))
+                }
+            };
+        let value: Value = serde_json::from_slice(&serialized).map_err(|e| {
+            ErrorInfo::new(
+                ErrorCode::Internal,
+                format!("Spilled document of {} is not valid JSON: {}", key, e),
+            )
+        })?;
+        if let Some(mut document) = self.data.get_mut(key) {
+            if matches!(&*document, Document::Spilled(current) if Arc::ptr_eq(current, &slot)) {
+                *document = self.document(Arc::new(value));
+            }
         }
+        Ok(())
+    }
 
-        let current_key = path_parts[0];
-        let remaining_parts = &path_parts[1..];
+    /// Evicts the least recently written keys among a random sample of this
+    /// namespace, or keys of the sample in any order with the `random`
+    /// policy, until `to_free` bytes are freed
+    fn evict_oldest(&self, to_free: u64) -> usize {
+        let positions = Self::random_positions(self.data.len(), EVICTION_POOL_SIZE);
+        let mut pool: Vec<(u64, String)> = self
+            .data
+            .iter()
+            .enumerate()
+            .filter(|(position, _)| positions.contains(position))
+            .map(|(_, entry)| {
+                let updated_at = self.meta.get(entry.key()).map_or(0, |m| m.updated_at);
+                (updated_at, entry.key().clone())
+            })
+            .collect();
+        if self.config.eviction_policy() == EvictionPolicy::Oldest {
+            pool.sort_unstable();
+        }
 
-        // Handle array index access
-        if let Ok(index) = current_key.parse::<usize>() {
-            match value {
-                Value::Array(arr) => {
-                    // Extend array if necessary
-                    while arr.len() <= index {
-                        arr.push(Value::Null);
+        let _write = self.begin_write();
+        let (mut evicted, mut freed) = (0, 0);
+        for (_, key) in pool {
+            if freed >= to_free {
+                break;
+            }
+            if let Entry::Occupied(entry) = self.data.entry(key) {
+                let size = Self::document_size(entry.key(), entry.get());
+                match self.remove(entry, ChangeKind::Evict) {
+                    Ok(key) => {
+                        freed += size;
+                        self.notify("evicted", &key);
+                        evicted += 1;
                     }
-
-                    if remaining_parts.is_empty() {
-                        arr[index] = new_value;
-                    } else {
-                        Self::set_nested_value(&mut arr[index], remaining_parts, new_value)?;
+                    Err(e) => {
+                        error!("{}", e.message);
+                        break;
                     }
-                    return Ok(());
-                }
-                _ => {
-                    return Err(format!(
-                        "Cannot index non-array value with '{}'",
-                        current_key
-                    ));
                 }
             }
         }
+        self.stats
+            .evicted
+            .fetch_add(evicted as u64, Ordering::Relaxed);
+        debug!("Evicted {} keys from namespace {}", evicted, self.namespace);
+        evicted
+    }
 
-        // Handle object property access
-        match value {
-            Value::Object(map) => {
-                if remaining_parts.is_empty() {
-                    map.insert(current_key.to_string(), new_value);
-                } else {
-                    // Create nested object if it doesn't exist
-                    if !map.contains_key(current_key) {
-                        map.insert(
-                            current_key.to_string(),
-                            Value::Object(serde_json::Map::new()),
-                        );
-                    }
+    /// Stores a value under a key, enforcing the namespace quota
+    fn store(&self, key: &str, value: Value, clear_expiry: bool) -> Result<(), ErrorInfo> {
+        self.make_room(Self::entry_size(key, &value))?;
+        let _write = self.begin_write();
+        self.store_entry(key, value, clear_expiry)
+    }
 
-                    let nested_value = map.get_mut(current_key).unwrap();
-                    Self::set_nested_value(nested_value, remaining_parts, new_value)?;
+    /// Stores a value under a key once the caller holds the write gate
+    fn store_entry(
+        &self,
+        key: &str,
+        mut value: Value,
+        clear_expiry: bool,
+    ) -> Result<(), ErrorInfo> {
+        // Counted before locking the entry: len() locks every shard
+        let key_count = self.data.len();
+        self.probe_shard(key, true);
+        let entry = self.data.entry(key.to_string());
+        self.check_not_binary(key)?;
+        let old = match &entry {
+            Entry::Occupied(e) => Some(e.get()),
+            Entry::Vacant(_) => None,
+        };
+        let old_size = old.map(|old| Self::document_size(key, old));
+        if self.has_triggers() {
+            let old = old.map(Document::json).transpose()?;
+            self.fire_triggers(key, old.as_deref(), &mut value)?;
+        }
+        let meta = self.next_meta(key);
+        self.stamp(&meta, &mut value);
+        let new_size = Self::entry_size(key, &value);
+        self.check_value_size(key, new_size)?;
+        self.check_quota(old_size, new_size, key_count)?;
+        self.insert_entry(
+            entry,
+            key,
+            meta,
+            Arc::new(value),
+            old_size,
+            new_size,
+            clear_expiry,
+        )
+    }
+
+    /// Stores a checked value of `new_size` bytes in the locked entry of a
+    /// key, replacing one of `old_size` bytes
+    #[allow(clippy::too_many_arguments)]
+    fn insert_entry(
+        &self,
+        entry: Entry<'_, String, Document>,
+        key: &str,
+        meta: KeyMeta,
+        value: Arc<Value>,
+        old_size: Option<u64>,
+        new_size: u64,
+        clear_expiry: bool,
+    ) -> Result<(), ErrorInfo> {
+        let persist = clear_expiry && self.expires.contains_key(key);
+        if persist {
+            self.append_to_aof(|ns| AofRecord::Persist {
+                ns,
+                key: key.into(),
+            })?;
+        }
+        self.touch(key, meta, &value)?;
+        entry.insert(self.document(value));
+        if persist {
+            self.expires.remove(key);
+        }
+        self.account(old_size, new_size);
+        self.bump_version();
+        Ok(())
+    }
+
+    /// Counts a wait if the shard of a key is locked, before the caller locks it
+    fn probe_shard(&self, key: &str, write: bool) {
+        let shard = self.data.determine_map(key);
+        let lock = &self.data.shards()[shard];
+        let available = if write {
+            lock.try_write().is_some()
+        } else {
+            lock.try_read().is_some()
+        };
+        if !available {
+            self.shard_waits[shard].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Fails if a key holds a binary value, which JSON writes cannot replace;
+    /// called with the key's document entry locked, which SETBYTES holds
+    /// while storing, so that only one of them wins the key
+    fn check_not_binary(&self, key: &str) -> Result<(), ErrorInfo> {
+        if self.blobs.contains_key(key) {
+            return Err(ErrorInfo::new(
+                ErrorCode::WrongType,
+                "Key holds a binary value (delete it first)",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the metadata of a key after one more write; called while the
+    /// key's entry is locked, so that writes to a key are numbered in order
+    fn next_meta(&self, key: &str) -> KeyMeta {
+        let now = self.clock.unix_millis();
+        match self.meta.get(key) {
+            Some(meta) => KeyMeta {
+                created_at: meta.created_at,
+                updated_at: now,
+                version: meta.version + 1,
+            },
+            None => KeyMeta {
+                created_at: now,
+                updated_at: now,
+                version: 1,
+            },
+        }
+    }
+
+    /// Writes the metadata into an object document when inline metadata is enabled
+    fn stamp(&self, meta: &KeyMeta, value: &mut Value) {
+        if !self.inline_meta.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Value::Object(map) = value {
+            map.insert(
+                INLINE_META_FIELD.to_string(),
+                serde_json::json!({
+                    "created_at": meta.created_at,
+                    "updated_at": meta.updated_at,
+                    "revision": meta.version,
+                }),
+            );
+        }
+    }
+
+    /// Maintain metadata fields inside object documents on every write
+    pub fn set_inline_meta(&self, enabled: bool) {
+        self.inline_meta.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Records a write to a key; called while the key's entry is locked,
+    /// before the value is stored, which it must not be if this fails
+    fn touch(&self, key: &str, meta: KeyMeta, value: &Arc<Value>) -> Result<(), ErrorInfo> {
+        self.append_to_aof(|ns| AofRecord::Set {
+            ns,
+            key: key.into(),
+            value: Cow::Borrowed(value.as_ref()),
+            meta: Some(meta),
+        })?;
+        self.record_write(key, meta, value);
+        Ok(())
+    }
+
+    /// Records a write to a key already logged to the AOF
+    fn record_write(&self, key: &str, meta: KeyMeta, value: &Arc<Value>) {
+        self.tombstones.remove(key);
+        self.meta.insert(key.to_string(), meta);
+        self.record_change(ChangeKind::Set, key, Some(value.as_ref()), meta.updated_at);
+
+        let depth = self.history_depth.load(Ordering::Relaxed);
+        if depth == 0 {
+            return;
+        }
+        let mut history = self.history.entry(key.to_string()).or_default();
+        history.push_back(Revision {
+            version: meta.version,
+            updated_at: meta.updated_at,
+            value: Arc::clone(value),
+        });
+        while history.len() > depth {
+            history.pop_front();
+        }
+    }
+
+    /// Leave a tombstone for every deleted key, kept for `retention`
+    /// (None disables tombstones and drops the existing ones)
+    pub fn set_tombstone_retention(&self, retention: Option<Duration>) {
+        *self.tombstone_retention.write().unwrap() = retention;
+        if retention.is_none() {
+            for keyspace in self.namespaces.iter() {
+                keyspace.tombstones.clear();
+            }
+        }
+    }
+
+    /// Drop the tombstones older than the retention, returning how many were dropped
+    pub fn purge_tombstones(&self) -> usize {
+        let Some(retention) = *self.tombstone_retention.read().unwrap() else {
+            return 0;
+        };
+        let cutoff = self
+            .clock
+            .unix_millis()
+            .saturating_sub(retention.as_millis() as u64);
+        let mut purged = 0;
+        for keyspace in self.namespaces.iter() {
+            keyspace.tombstones.retain(|_, deleted_at| {
+                let keep = *deleted_at >= cutoff;
+                purged += usize::from(!keep);
+                keep
+            });
+        }
+        purged
+    }
+
+    /// Returns the keys deleted at or after `since` (milliseconds since the
+    /// UNIX epoch) that still have a tombstone, oldest first
+    async fn tombstones(&self, since: u64) -> Response {
+        if self.tombstone_retention.read().unwrap().is_none() {
+            return Response::error(
+                ErrorCode::Unsupported,
+                "Tombstones are disabled (see --tombstone-retention)",
+            );
+        }
+        let mut deleted: Vec<(u64, String)> = self
+            .tombstones
+            .iter()
+            .filter(|t| *t.value() >= since)
+            .map(|t| (*t.value(), t.key().clone()))
+            .collect();
+        deleted.sort_unstable();
+        let deleted: Vec<Value> = deleted
+            .into_iter()
+            .map(|(deleted_at, key)| serde_json::json!({"key": key, "deleted_at": deleted_at}))
+            .collect();
+        Response::Ok(Some(Value::Array(deleted)))
+    }
+
+    /// Set the number of changes kept in the change log of every namespace
+    /// (0 disables the change log)
+    pub fn set_change_log_capacity(&self, capacity: usize) {
+        self.change_log_capacity.store(capacity, Ordering::Relaxed);
+        if capacity == 0 {
+            for keyspace in self.namespaces.iter() {
+                keyspace.changes.clear();
+            }
+        }
+    }
+
+    /// Change log of the namespace
+    pub(crate) fn change_log(&self) -> &ChangeLog {
+        &self.changes
+    }
+
+    /// Appends a change to the change log when enabled; called while the
+    /// key's entry is locked, so that changes to a key are logged in order
+    fn record_change(&self, kind: ChangeKind, key: &str, value: Option<&Value>, at: u64) {
+        let capacity = self.change_log_capacity.load(Ordering::Relaxed);
+        if capacity > 0 {
+            self.changes.append(capacity, kind, key, value, at);
+        }
+    }
+
+    /// Returns the error of change log commands when the change log is disabled
+    fn check_change_log_enabled(&self) -> Result<(), Response> {
+        if self.change_log_capacity.load(Ordering::Relaxed) == 0 {
+            return Err(Response::error(
+                ErrorCode::Unsupported,
+                "The change log is disabled (see --change-log-capacity)",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that a changefeed can start after an offset
+    pub(crate) fn check_changefeed(&self, from_offset: u64) -> Result<(), Response> {
+        self.check_change_log_enabled()?;
+        self.changes
+            .read(from_offset, 0)
+            .map(|_| ())
+            .map_err(|e| Response::error(ErrorCode::InvalidArgument, e))
+    }
+
+    /// Returns the changes after an offset, oldest first
+    async fn changes(&self, since: u64, limit: Option<usize>) -> Response {
+        if let Err(response) = self.check_change_log_enabled() {
+            return response;
+        }
+        let limit = limit.unwrap_or(DEFAULT_CHANGES_BATCH);
+        if limit > MAX_CHANGES_BATCH {
+            return Response::error(
+                ErrorCode::InvalidArgument,
+                format!("At most {} changes can be read at once", MAX_CHANGES_BATCH),
+            );
+        }
+        match self.changes.read(since, limit) {
+            Ok(changes) => Response::Ok(serde_json::to_value(changes).ok()),
+            Err(e) => Response::error(ErrorCode::InvalidArgument, e),
+        }
+    }
+
+    /// Returns the changes a consumer group has not acknowledged yet
+    async fn group_read(&self, group: &str, count: usize) -> Response {
+        if let Err(response) = self.check_change_log_enabled() {
+            return response;
+        }
+        if count > MAX_CHANGES_BATCH {
+            return Response::error(
+                ErrorCode::InvalidArgument,
+                format!("At most {} changes can be read at once", MAX_CHANGES_BATCH),
+            );
+        }
+        match self.changes.read_group(group, count) {
+            Ok(changes) => Response::Ok(serde_json::to_value(changes).ok()),
+            Err(e) => Response::error(ErrorCode::InvalidArgument, e),
+        }
+    }
+
+    /// Acknowledges the changes of a consumer group up to an offset
+    async fn group_ack(&self, group: &str, offset: u64) -> Response {
+        if let Err(response) = self.check_change_log_enabled() {
+            return response;
+        }
+        match self.commit_group(group, offset).await {
+            Ok(()) => Response::Ok(None),
+            Err(e) => Response::Error(e),
+        }
+    }
+
+    /// Acknowledges the changes of a consumer group up to an offset, once the
+    /// acknowledgement is in the AOF
+    pub(crate) async fn commit_group(&self, group: &str, offset: u64) -> Result<(), ErrorInfo> {
+        let last_offset = self.changes.last_offset();
+        if offset > last_offset {
+            return Err(ErrorInfo::new(
+                ErrorCode::InvalidArgument,
+                format!(
+                    "Offset {} is ahead of the change log (last offset is {})",
+                    offset, last_offset
+                ),
+            ));
+        }
+        // Logged first, so that the group resumes past `offset` after a restart
+        self.append_to_aof(|ns| AofRecord::GroupAck {
+            ns,
+            group: group.into(),
+            offset,
+        })?;
+        self.wait_durable().await?;
+        self.changes
+            .commit(group, offset)
+            .map_err(|e| ErrorInfo::new(ErrorCode::InvalidArgument, e))
+    }
+
+    /// Set the number of revisions kept per key (0 disables history)
+    pub fn set_history_depth(&self, depth: usize) {
+        self.history_depth.store(depth, Ordering::Relaxed);
+        if depth == 0 {
+            for keyspace in self.namespaces.iter() {
+                keyspace.history.clear();
+            }
+        }
+    }
+
+    /// Returns the error of history commands when history is disabled
+    fn check_history_enabled(&self) -> Result<(), Response> {
+        if self.history_depth.load(Ordering::Relaxed) == 0 {
+            return Err(Response::error(
+                ErrorCode::Unsupported,
+                "History is disabled (see --history-depth)",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the last revisions of a key, oldest first
+    async fn history(&self, key: &str) -> Response {
+        if let Err(response) = self.check_history_enabled() {
+            return response;
+        }
+        let Some(_entry) = self.data.get(key) else {
+            return Response::error(ErrorCode::KeyNotFound, "Key not found");
+        };
+        let revisions: Vec<Revision> = self
+            .history
+            .get(key)
+            .map(|history| history.iter().cloned().collect())
+            .unwrap_or_default();
+        Response::Ok(serde_json::to_value(revisions).ok())
+    }
+
+    /// Writes back the value a key had at a past version
+    async fn restore(&self, key: &str, version: u64) -> Response {
+        if let Err(response) = self.check_history_enabled() {
+            return response;
+        }
+        if !self.data.contains_key(key) {
+            return Response::error(ErrorCode::KeyNotFound, "Key not found");
+        }
+        let revision = self.history.get(key).and_then(|history| {
+            history
+                .iter()
+                .find(|revision| revision.version == version)
+                .map(|revision| Value::clone(&revision.value))
+        });
+        let Some(value) = revision else {
+            return Response::error(
+                ErrorCode::InvalidArgument,
+                format!("Version {} of key '{}' is not in the history", version, key),
+            );
+        };
+        match self.store(key, value, false) {
+            Ok(()) => Response::Ok(None),
+            Err(e) => Response::Error(e),
+        }
+    }
+
+    /// Returns the metadata of a key
+    async fn meta(&self, key: &str) -> Response {
+        // Read under the entry lock so that the metadata matches the stored value
+        let Some(_entry) = self.data.get(key) else {
+            return Response::error(ErrorCode::KeyNotFound, "Key not found");
+        };
+        match self.meta.get(key) {
+            Some(meta) => Response::Ok(serde_json::to_value(*meta).ok()),
+            None => Response::error(ErrorCode::Internal, "Missing key metadata"),
+        }
+    }
+
+    /// Returns the resource usage and limits of this namespace
+    async fn usage(&self) -> Response {
+        let quota = self.quota();
+        Response::Ok(Some(serde_json::json!({
+            "namespace": &*self.namespace,
+            "keys": self.data.len(),
+            "bytes": self.bytes.load(Ordering::Acquire),
+            "max_keys": quota.max_keys,
+            "max_bytes": quota.max_bytes,
+            "reads": self.activity.reads.load(Ordering::Relaxed),
+            "writes": self.activity.writes.load(Ordering::Relaxed),
+            "tier": if self.cache_tier.read().unwrap().contains(&*self.namespace) {
+                "cache"
+            } else {
+                "durable"
+            },
+        })))
+    }
+
+    /// Returns the number of keys and of lock waits of every shard of this namespace
+    async fn shard_stats(&self) -> Response {
+        let shards: Vec<Value> = self
+            .data
+            .shards()
+            .iter()
+            .zip(self.shard_waits.iter())
+            .map(|(shard, waits)| {
+                serde_json::json!({
+                    "keys": shard.read().len(),
+                    "waits": waits.load(Ordering::Relaxed),
+                })
+            })
+            .collect();
+        Response::Ok(Some(serde_json::json!({
+            "namespace": &*self.namespace,
+            "shards": shards,
+        })))
+    }
+
+    /// Returns the number of keys and their estimated size across namespaces
+    fn totals(&self) -> (usize, u64) {
+        let (mut keys, mut bytes) = (0, 0);
+        for keyspace in self.namespaces.iter() {
+            keys += keyspace.data.len();
+            bytes += keyspace.bytes.load(Ordering::Acquire);
+        }
+        (keys, bytes)
+    }
+
+    /// Returns the primary followed as a standby
+    fn standby_of(&self) -> Option<String> {
+        self.standby
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|standby| standby.primary().to_string())
+    }
+
+    /// Returns the replication lag of a standby
+    fn standby_lag(&self) -> Option<Duration> {
+        self.standby
+            .read()
+            .unwrap()
+            .as_ref()
+            .and_then(|standby| standby.lag())
+    }
+
+    /// Returns the memory section of STATS and INFO
+    fn memory_report(&self, data_bytes: u64) -> Value {
+        let spill = self.spill.read().unwrap().clone();
+        serde_json::json!({
+            "data_bytes": data_bytes,
+            "max_bytes": match self.max_memory.load(Ordering::Relaxed) {
+                0 => None,
+                max => Some(max),
+            },
+            "shards": self.shard_count,
+            "spilled_bytes": spill.as_ref().map(|spill| spill.spilled_bytes()),
+            "spilled_keys": spill.as_ref().map(|spill| spill.spilled_documents()),
+        })
+    }
+
+    /// Returns the capabilities document with uptime, key count, memory and
+    /// replication role. The connection adds its server's connected clients
+    /// and Raft metrics.
+    pub(crate) fn info(&self) -> Value {
+        let (keys, bytes) = self.totals();
+        let standby_of = self.standby_of();
+        let mut info = serde_json::to_value(self.capabilities()).unwrap_or_default();
+        info["uptime_seconds"] = self.stats.started_at.elapsed().as_secs().into();
+        info["keyspace"] = serde_json::json!({
+            "keys": keys,
+            "namespaces": self.namespaces.len(),
+        });
+        info["memory"] = self.memory_report(bytes);
+        info["replication"] = serde_json::json!({
+            "role": if standby_of.is_some() { "standby" } else { "primary" },
+            "standby_of": standby_of,
+            "lag_ms": self.standby_lag().map(|lag| lag.as_millis() as u64),
+            "read_only": self.is_read_only(),
+        });
+        info
+    }
+
+    /// Returns the description of every command, for COMMAND
+    fn command_table() -> Value {
+        COMMAND_TABLE
+            .iter()
+            .map(|spec| {
+                serde_json::json!({
+                    "name": spec.name,
+                    "variant": spec.variant,
+                    "arguments": spec.arguments,
+                    "arity": spec.arity(),
+                    "flags": spec.flags,
+                    "since": spec.since,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns operation counters and size estimates of the whole database
+    async fn stats(&self) -> Response {
+        let (keys, bytes) = self.totals();
+        let standby_of = self.standby_of();
+        let mut commands: Vec<(&str, u64)> = self
+            .stats
+            .commands
+            .iter()
+            .map(|c| (*c.key(), c.value().load(Ordering::Relaxed)))
+            .collect();
+        commands.sort_unstable();
+        let commands: serde_json::Map<String, Value> = commands
+            .into_iter()
+            .map(|(name, count)| (name.to_string(), count.into()))
+            .collect();
+
+        Response::Ok(Some(serde_json::json!({
+            "uptime_seconds": self.stats.started_at.elapsed().as_secs(),
+            "keys": keys,
+            "namespaces": self.namespaces.len(),
+            "hits": self.stats.hits.load(Ordering::Relaxed),
+            "misses": self.stats.misses.load(Ordering::Relaxed),
+            "expired_keys": self.stats.expired.load(Ordering::Relaxed),
+            "evicted_keys": self.stats.evicted.load(Ordering::Relaxed),
+            "read_only": self.is_read_only(),
+            "standby_of": standby_of,
+            "commands": commands,
+            "memory": self.memory_report(bytes),
+        })))
+    }
+
+    /// Estimates the serialized and in-memory size of a document
+    async fn memory_usage(&self, key: &str) -> Response {
+        let Some(document) = self.data.get(key) else {
+            return Response::error(ErrorCode::KeyNotFound, "Key not found");
+        };
+        let value = match document.json() {
+            Ok(value) => value,
+            Err(e) => return Response::Error(e),
+        };
+        let stored = match document.compressed_size() {
+            Some(compressed) => compressed,
+            None => Self::estimate_memory(&value) + document.cached_size(),
+        };
+        Response::Ok(Some(serde_json::json!({
+            "serialized_bytes": document.size(),
+            "memory_bytes": std::mem::size_of::<String>() + key.len() + stored,
+            "compressed": document.compressed_size().is_some(),
+            "spilled": document.is_spilled(),
+            "nodes": Self::count_nodes(&value),
+        })))
+    }
+
+    /// Rough heap and inline footprint of a value
+    fn estimate_memory(value: &Value) -> usize {
+        // Per-entry bookkeeping of the map backing objects (hash slot and indices)
+        const MAP_ENTRY_OVERHEAD: usize = 2 * std::mem::size_of::<usize>();
+
+        std::mem::size_of::<Value>()
+            + match value {
+                Value::String(s) => s.capacity(),
+                Value::Array(arr) => {
+                    (arr.capacity() - arr.len()) * std::mem::size_of::<Value>()
+                        + arr.iter().map(Self::estimate_memory).sum::<usize>()
                 }
-                Ok(())
+                Value::Object(map) => map
+                    .iter()
+                    .map(|(k, v)| {
+                        std::mem::size_of::<String>()
+                            + k.capacity()
+                            + MAP_ENTRY_OVERHEAD
+                            + Self::estimate_memory(v)
+                    })
+                    .sum(),
+                _ => 0,
             }
-            Value::Null => {
-                // Create new object if current value is null
-                let mut new_obj = serde_json::Map::new();
-                if remaining_parts.is_empty() {
-                    new_obj.insert(current_key.to_string(), new_value);
-                } else {
-                    let mut nested = Value::Object(serde_json::Map::new());
-                    Self::set_nested_value(&mut nested, remaining_parts, new_value)?;
-                    new_obj.insert(current_key.to_string(), nested);
+    }
+
+    /// Checks that a namespace name is non-empty, short and made of `[A-Za-z0-9_.-]`
+    fn validate_namespace(name: &str) -> Result<(), String> {
+        let valid_chars = name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+        if name.is_empty() || name.len() > MAX_NAMESPACE_LEN || !valid_chars {
+            return Err(format!("Invalid namespace name '{}'", name));
+        }
+        Ok(())
+    }
+
+    /// Returns the current keyspace version.
+    ///
+    /// The version grows by one on every successful write, so comparing two
+    /// readings tells whether the keyspace changed in between.
+    pub fn keyspace_version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+
+    /// Enters a write section; writers run concurrently with each other but
+    /// never overlap a consistent read
+    fn begin_write(&self) -> WriteSection<'_> {
+        let gate = self.write_gate.read().unwrap();
+        self.writes_in_progress.fetch_add(1, Ordering::SeqCst);
+        WriteSection {
+            _gate: gate,
+            writes_in_progress: &self.writes_in_progress,
+        }
+    }
+
+    /// Advances the keyspace version after a write
+    fn bump_version(&self) {
+        self.version.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Returns the monitor tracking writes in progress
+    pub fn write_monitor(&self) -> &WriteStallMonitor {
+        &self.write_monitor
+    }
+
+    /// Fence or unfence writes; fenced writes fail with a retriable `UNAVAILABLE` error
+    pub fn set_write_fenced(&self, fenced: bool) {
+        self.write_fenced.store(fenced, Ordering::Release);
+    }
+
+    /// Returns true if writes are currently fenced
+    pub fn is_write_fenced(&self) -> bool {
+        self.write_fenced.load(Ordering::Acquire)
+    }
+
+    /// Reject every write with `READ_ONLY`, or accept writes again
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Release);
+    }
+
+    /// Returns true if writes are rejected because the server is read-only
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Acquire)
+    }
+
+    /// Append every write to an append-only file (None stops appending);
+    /// set after the file has been replayed
+    pub fn set_aof(&self, aof: Option<Arc<AppendOnlyFile>>) {
+        *self.aof.write().unwrap() = aof;
+    }
+
+    /// Record every mutating command of every namespace, with the identity
+    /// running it and hashes of the value of its key before and after
+    pub fn set_audit_log(&self, audit: Option<Arc<AuditLog>>) {
+        *self.audit.write().unwrap() = audit;
+    }
+
+    /// Returns the audit hash of the value of a key, JSON or binary
+    fn audit_hash(&self, key: &str) -> Option<String> {
+        if let Some(document) = self.data.get(key) {
+            return document.json().ok().map(|value| audit::value_hash(&value));
+        }
+        self.blobs.get(key).map(|bytes| audit::bytes_hash(&bytes))
+    }
+
+    /// Appends the record of a mutating command to the audit log; the
+    /// command already ran, so failures are logged rather than returned
+    fn record_audit(
+        &self,
+        audit: &AuditLog,
+        command: &str,
+        key: Option<String>,
+        before: Option<String>,
+        response: &Response,
+    ) {
+        let record = AuditRecord {
+            at: self.clock.unix_millis(),
+            identity: self.identity.as_deref().map(str::to_string),
+            namespace: self.namespace.to_string(),
+            command: command.to_string(),
+            after: key.as_deref().and_then(|key| self.audit_hash(key)),
+            key,
+            before,
+            outcome: match response {
+                Response::Error(e) => e.code.to_string(),
+                _ => "ok".to_string(),
+            },
+        };
+        if let Err(e) = audit.append(&record) {
+            error!("{}", e);
+        }
+    }
+
+    /// Returns records of the audit log, for AUDIT, of every namespace or
+    /// only of `namespace`
+    pub(crate) fn audit_records(
+        &self,
+        since: u64,
+        identity: Option<&str>,
+        namespace: Option<&str>,
+        key_pattern: Option<&str>,
+        limit: Option<usize>,
+    ) -> Response {
+        let Some(audit) = self.audit.read().unwrap().clone() else {
+            return Response::error(
+                ErrorCode::Unsupported,
+                "The audit log is disabled (see --audit-log)",
+            );
+        };
+        let limit = limit.unwrap_or(DEFAULT_AUDIT_LIMIT);
+        match audit.query(since, identity, namespace, key_pattern, limit) {
+            Ok(records) => Response::Ok(serde_json::to_value(records).ok()),
+            Err(e) => Response::error(ErrorCode::Internal, e),
+        }
+    }
+
+    /// Let SAVE write snapshots to a store
+    pub fn set_snapshot_store(&self, store: Option<Arc<SnapshotStore>>) {
+        *self.snapshot_store.write().unwrap() = store;
+    }
+
+    /// Let VERIFYBACKUP s3 check the backups of a bucket
+    pub fn set_backup(&self, backup: Option<Arc<S3Backup>>) {
+        *self.backup.write().unwrap() = backup;
+    }
+
+    /// Follow a primary as a standby: the database is read-only and applies
+    /// the shipped AOF until PROMOTE
+    pub fn set_standby(&self, standby: Arc<Standby>) {
+        self.set_read_only(true);
+        standby.spawn(self.clone());
+        *self.standby.write().unwrap() = Some(standby);
+    }
+
+    /// Copies every key of every namespace as of a single point in time,
+    /// returning the copy with its snapshot index: the sequence of a new AOF
+    /// segment started at that point, or `next_index` without an AOF
+    pub(crate) fn dump(&self, next_index: u64) -> Result<Dump, String> {
+        loop {
+            let namespaces: Vec<Database> = self
+                .namespaces()
+                .iter()
+                .filter_map(|name| self.namespace(name).ok())
+                .collect();
+            let gates: Vec<_> = namespaces
+                .iter()
+                .map(|namespace| namespace.write_gate.write().unwrap())
+                .collect();
+            // A namespace created meanwhile would be missed: start over
+            if self.namespaces.len() != namespaces.len() {
+                continue;
+            }
+            let index = match self.aof.read().unwrap().as_ref() {
+                Some(aof) => aof.rotate()?,
+                None => next_index,
+            };
+            let mut entries = Vec::new();
+            let mut change_logs = Vec::new();
+            for namespace in &namespaces {
+                if let Some(state) = namespace.changes.state() {
+                    change_logs.push((Arc::clone(&namespace.namespace), state));
                 }
-                *value = Value::Object(new_obj);
-                Ok(())
+                let expires_at = |key: &str| namespace.expires.get(key).map(|at| *at);
+                entries.extend(namespace.data.iter().map(|entry| DumpEntry {
+                    namespace: Arc::clone(&namespace.namespace),
+                    key: entry.key().clone(),
+                    value: DumpValue::Json(entry.value().clone()),
+                    expires_at: expires_at(entry.key()),
+                    meta: namespace.meta.get(entry.key()).map(|meta| *meta),
+                }));
+                entries.extend(namespace.blobs.iter().map(|entry| DumpEntry {
+                    namespace: Arc::clone(&namespace.namespace),
+                    key: entry.key().clone(),
+                    value: DumpValue::Binary(entry.value().clone()),
+                    expires_at: expires_at(entry.key()),
+                    meta: None,
+                }));
+            }
+            drop(gates);
+            return Ok(Dump {
+                index,
+                entries,
+                change_logs,
+            });
+        }
+    }
+
+    /// Saves a snapshot of every namespace; in the background, answers with
+    /// the snapshot index once the keyspace is copied
+    async fn save(&self, background: bool) -> Response {
+        if self.standby.read().unwrap().is_some() {
+            return Response::error(
+                ErrorCode::Unsupported,
+                "Standby nodes keep the primary's snapshots until promoted",
+            );
+        }
+        let Some(store) = self.snapshot_store.read().unwrap().clone() else {
+            return Response::error(
+                ErrorCode::Unsupported,
+                "Snapshots are disabled (see --data-dir)",
+            );
+        };
+        let started = Instant::now();
+        let (copied_tx, copied_rx) = oneshot::channel();
+        let database = self.clone();
+        let saving = tokio::task::spawn_blocking(move || {
+            store.save_with(&database, |index, keys| {
+                let _ = copied_tx.send((index, keys));
+            })
+        });
+        if background {
+            if let Ok((index, keys)) = copied_rx.await {
+                tokio::spawn(async move {
+                    if let Ok(Err(e)) = saving.await {
+                        error!("Background save of snapshot {} failed: {}", index, e);
+                    }
+                });
+                return Response::Ok(Some(serde_json::json!({
+                    "index": index,
+                    "keys": keys,
+                    "background": true,
+                    "duration_ms": started.elapsed().as_millis() as u64,
+                })));
+            }
+        }
+        match saving.await {
+            Ok(Ok(snapshot)) => Response::Ok(serde_json::to_value(snapshot).ok()),
+            Ok(Err(e)) => Response::error(ErrorCode::Internal, e),
+            Err(e) => Response::error(ErrorCode::Internal, e.to_string()),
+        }
+    }
+
+    /// Loads a backup into a temporary in-memory instance and reports whether
+    /// it restores, with the key count and digest of each namespace to
+    /// compare with DIGEST on a live node
+    async fn verify_backup(&self, source: Option<&str>) -> Response {
+        let checked = match source {
+            Some("s3") => {
+                let Some(backup) = self.backup.read().unwrap().clone() else {
+                    return Response::error(
+                        ErrorCode::Unsupported,
+                        "S3 backups are disabled (see --backup-s3-endpoint)",
+                    );
+                };
+                backup.verify().await
+            }
+            Some(path) => {
+                let path = path.to_string();
+                tokio::task::spawn_blocking(move || backup::verify(&DataDir::open_existing(&path)?))
+                    .await
+                    .unwrap_or_else(|e| Err(e.to_string()))
+            }
+            None => {
+                let Some(store) = self.snapshot_store.read().unwrap().clone() else {
+                    return Response::error(
+                        ErrorCode::Unsupported,
+                        "Snapshots are disabled (see --data-dir)",
+                    );
+                };
+                tokio::task::spawn_blocking(move || store.verify())
+                    .await
+                    .unwrap_or_else(|e| Err(e.to_string()))
+            }
+        };
+        let check = match checked {
+            Ok(check) => check,
+            Err(e) => return Response::error(ErrorCode::Internal, e),
+        };
+        let snapshots: Vec<Value> = check
+            .snapshots
+            .iter()
+            .map(|(index, keys)| match keys {
+                Ok(keys) => serde_json::json!({"index": index, "keys": keys}),
+                Err(e) => serde_json::json!({"index": index, "error": e}),
+            })
+            .collect();
+        let mut namespaces = serde_json::Map::new();
+        for name in check.database.namespaces() {
+            if let Ok(namespace) = check.database.namespace(&name) {
+                if let Response::Ok(Some(digest)) = namespace.digest(None).await {
+                    namespaces.insert(name, digest);
+                }
+            }
+        }
+        info!(
+            "Verified backup {}: {}",
+            source.unwrap_or("(data directory)"),
+            if check.is_ok() { "ok" } else { "failed" }
+        );
+        Response::Ok(Some(serde_json::json!({
+            "ok": check.is_ok(),
+            "error": check.error,
+            "snapshot": check.restored,
+            "snapshots": snapshots,
+            "segments": check.segments,
+            "records": check.records,
+            "namespaces": namespaces,
+        })))
+    }
+
+    /// Ships AOF records to a standby; once the requested segment has been
+    /// removed, points it at the newest snapshot instead
+    async fn aof_fetch(&self, sequence: u64, offset: u64) -> Response {
+        let Some(aof) = self.aof.read().unwrap().clone() else {
+            return Response::error(ErrorCode::Unsupported, "The AOF is disabled (see --aof)");
+        };
+        let dir = aof.dir();
+        let first = match dir.list_aof_segments() {
+            Ok(segments) => segments.first().map_or(sequence, |(first, _)| *first),
+            Err(e) => return Response::error(ErrorCode::Internal, e),
+        };
+        let (sequence, offset) = if sequence < first {
+            match dir.list_snapshots() {
+                Ok(mut snapshots) => match snapshots.pop() {
+                    Some((index, _)) => {
+                        return Response::Ok(Some(serde_json::json!({ "snapshot": index })))
+                    }
+                    // The whole log is still there
+                    None => (first, 0),
+                },
+                Err(e) => return Response::error(ErrorCode::Internal, e),
+            }
+        } else {
+            (sequence, offset)
+        };
+        let read = tokio::task::spawn_blocking(move || {
+            aof.read_chunk(sequence, offset, SHIPPING_CHUNK_BYTES)
+        });
+        match read.await {
+            Ok(Ok(chunk)) => Response::Ok(serde_json::to_value(chunk).ok()),
+            Ok(Err(e)) => Response::error(ErrorCode::Unavailable, e),
+            Err(e) => Response::error(ErrorCode::Internal, e.to_string()),
+        }
+    }
+
+    /// Ships part of a snapshot file to a standby; an empty reply marks its end
+    async fn snapshot_fetch(&self, index: u64, offset: u64) -> Response {
+        let Some(aof) = self.aof.read().unwrap().clone() else {
+            return Response::error(ErrorCode::Unsupported, "The AOF is disabled (see --aof)");
+        };
+        let path = aof.dir().snapshot_path(index);
+        let read = tokio::task::spawn_blocking(move || {
+            use std::io::{Read, Seek, SeekFrom};
+            let mut file = std::fs::File::open(&path)?;
+            file.seek(SeekFrom::Start(offset))?;
+            let mut bytes = Vec::new();
+            file.take(SHIPPING_CHUNK_BYTES).read_to_end(&mut bytes)?;
+            Ok::<_, std::io::Error>(bytes)
+        });
+        match read.await {
+            Ok(Ok(bytes)) => Response::Bytes(bytes),
+            Ok(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => Response::error(
+                ErrorCode::Unavailable,
+                format!("Snapshot {} was removed", index),
+            ),
+            Ok(Err(e)) => Response::error(ErrorCode::Internal, e.to_string()),
+            Err(e) => Response::error(ErrorCode::Internal, e.to_string()),
+        }
+    }
+
+    /// Stops following the primary and accepts writes, appending them to the
+    /// AOF from the end of the shipped log
+    async fn promote(&self) -> Response {
+        let Some(standby) = self.standby.write().unwrap().take() else {
+            return Response::error(ErrorCode::Unsupported, "Not a standby (see --standby-of)");
+        };
+        match standby.promote().await {
+            Ok(aof) => {
+                self.set_aof(Some(aof));
+                self.set_read_only(false);
+                Response::Ok(Some(serde_json::json!({ "primary": standby.primary() })))
+            }
+            Err(e) => {
+                *self.standby.write().unwrap() = Some(standby);
+                Response::error(ErrorCode::Internal, e)
+            }
+        }
+    }
+
+    /// Appends a write to the AOF when enabled; called while the key's entry
+    /// is locked, so that writes to a key are appended in order, and before
+    /// the write is applied: a write that could not be logged is not applied
+    fn append_to_aof<'a>(
+        &'a self,
+        record: impl FnOnce(Cow<'a, str>) -> AofRecord<'a>,
+    ) -> Result<(), ErrorInfo> {
+        let aof = self.aof.read().unwrap();
+        let Some(aof) = aof.as_ref() else {
+            return Ok(());
+        };
+        aof.append(&record(Cow::Borrowed(&self.namespace)))
+            .map(|_| ())
+            .map_err(|e| {
+                error!("{}", e);
+                ErrorInfo::new(ErrorCode::Internal, e)
+            })
+    }
+
+    /// With `--aof-fsync always`, waits until the writes appended so far are
+    /// on disk; called once the command released its locks, so that
+    /// concurrent writers share fsyncs
+    async fn wait_durable(&self) -> Result<(), ErrorInfo> {
+        let aof = self.aof.read().unwrap().clone();
+        let Some(aof) = aof else {
+            return Ok(());
+        };
+        aof.wait_durable(aof.appended()).await.map_err(|e| {
+            error!("{}", e);
+            ErrorInfo::new(ErrorCode::Internal, e)
+        })
+    }
+
+    /// Applies a write read back from the AOF, without running write
+    /// transformations or triggers again
+    pub(crate) fn apply_aof_record(&self, record: AofRecord<'_>) -> Result<(), String> {
+        match record {
+            AofRecord::Set {
+                ns,
+                key,
+                value,
+                meta,
+            } => {
+                self.namespace(&ns)?
+                    .restore_document(&key, value.into_owned(), meta)?;
+            }
+            AofRecord::SetMany { ns, documents } => {
+                let namespace = self.namespace(&ns)?;
+                let _write = namespace.write_gate.write().unwrap();
+                for BulkDocument { key, value, meta } in documents {
+                    namespace.restore_document(&key, value.into_owned(), Some(meta))?;
+                    namespace.expires.remove(key.as_ref());
+                }
+            }
+            AofRecord::SetBytes { ns, key, value } => {
+                let namespace = self.namespace(&ns)?;
+                let new_size = (key.len() + value.len()) as u64;
+                let old = namespace
+                    .blobs
+                    .insert(key.to_string(), Bytes::from(value.into_owned()));
+                namespace.account(old.map(|old| (key.len() + old.len()) as u64), new_size);
+                namespace.bump_version();
+            }
+            AofRecord::Delete { ns, key } => {
+                let namespace = self.namespace(&ns)?;
+                match namespace.data.entry(key.into_owned()) {
+                    Entry::Occupied(entry) => {
+                        namespace
+                            .remove(entry, ChangeKind::Delete)
+                            .map_err(|e| e.message)?;
+                    }
+                    Entry::Vacant(entry) => {
+                        if let Some((key, value)) = namespace.blobs.remove(entry.key()) {
+                            namespace.account(Some((key.len() + value.len()) as u64), 0);
+                            namespace.bump_version();
+                        }
+                    }
+                };
+            }
+            AofRecord::Expire { ns, key, at } => {
+                self.namespace(&ns)?.expires.insert(key.into_owned(), at);
+            }
+            AofRecord::Persist { ns, key } => {
+                self.namespace(&ns)?.expires.remove(key.as_ref());
+            }
+            AofRecord::Flush { ns, prefix } => {
+                let namespace = self.namespace(&ns)?;
+                let _write = namespace.write_gate.write().unwrap();
+                namespace.remove_prefixed(prefix.as_deref(), |_| {});
+            }
+            AofRecord::GroupAck { ns, group, offset } => {
+                self.namespace(&ns)?.changes.restore_commit(&group, offset);
+            }
+            AofRecord::ChangeLog { ns, state } => {
+                let namespace = self.namespace(&ns)?;
+                let capacity = namespace.change_log_capacity.load(Ordering::Relaxed);
+                namespace.changes.restore(state.into_owned(), capacity);
             }
-            _ => Err(format!(
-                "Cannot access property '{}' on non-object value",
-                current_key
-            )),
         }
+        Ok(())
+    }
+
+    /// Stores a document read back from the AOF
+    fn restore_document(
+        &self,
+        key: &str,
+        value: Value,
+        meta: Option<KeyMeta>,
+    ) -> Result<(), String> {
+        let value = Arc::new(value);
+        let new_size = Self::entry_size(key, &value);
+        let entry = self.data.entry(key.to_string());
+        let old_size = match &entry {
+            Entry::Occupied(e) => Some(Self::document_size(key, e.get())),
+            Entry::Vacant(_) => None,
+        };
+        let meta = meta.unwrap_or_else(|| self.next_meta(key));
+        self.touch(key, meta, &value).map_err(|e| e.message)?;
+        entry.insert(self.document(value));
+        self.account(old_size, new_size);
+        self.bump_version();
+        Ok(())
+    }
+
+    /// Enable or disable canonical JSON storage (normalized numbers, sorted keys)
+    pub fn set_canonical_json(&self, enabled: bool) {
+        self.canonical_json.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Store documents whose serialization has at least `threshold` bytes
+    /// zstd-compressed (0 disables compression); existing documents are
+    /// compressed or decompressed on their next write
+    pub fn set_compression_threshold(&self, threshold: usize) {
+        self.compression_threshold
+            .store(threshold, Ordering::Relaxed);
+    }
+
+    /// Wraps a value for storage, compressing it when large enough
+    fn document(&self, value: Arc<Value>) -> Document {
+        Document::new(value, self.compression_threshold.load(Ordering::Relaxed))
+    }
+
+    /// Replace the write transformation pipeline
+    pub fn set_write_pipeline(&self, pipeline: WritePipeline) {
+        *self.write_pipeline.write().unwrap() = pipeline;
+    }
+
+    /// Register a WASM module (binary or text format) run by EVAL
+    pub fn load_script(&self, name: &str, module: &[u8]) -> Result<(), String> {
+        self.scripts.load(name, module)
+    }
+
+    /// Register every `.wasm` and `.wat` module of a directory, named after
+    /// its file; returns the number of loaded scripts
+    pub fn load_scripts(&self, dir: &str) -> Result<usize, String> {
+        let entries = std::fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read scripts directory '{}': {}", dir, e))?;
+        let mut loaded = 0;
+        for entry in entries {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            let is_module = path
+                .extension()
+                .is_some_and(|extension| extension == "wasm" || extension == "wat");
+            let Some(name) = path
+                .file_stem()
+                .and_then(|n| n.to_str())
+                .filter(|_| is_module)
+            else {
+                continue;
+            };
+            let module = std::fs::read(&path)
+                .map_err(|e| format!("Failed to read script '{}': {}", path.display(), e))?;
+            self.scripts.load(name, &module)?;
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+
+    /// Replace the write triggers
+    pub fn set_triggers(&self, triggers: TriggerSet) {
+        *self.triggers.write().unwrap() = triggers;
+    }
+
+    /// Set the node configuration reported by INFO
+    pub fn set_capabilities(&self, capabilities: Capabilities) {
+        *self.capabilities.write().unwrap() = Some(capabilities);
+    }
+
+    /// Delay `target` by `delay` on this node until `duration` has passed
+    /// (a zero delay clears the injection)
+    pub fn inject_latency(&self, target: LatencyTarget, delay: Duration, duration: Duration) {
+        let mut injected = self.injected_latency.write().unwrap();
+        if delay.is_zero() {
+            injected.remove(&target);
+        } else {
+            injected.insert(target, (delay, self.clock.now() + duration));
+        }
+    }
+
+    /// Returns the delay currently injected into `target`, if any
+    pub fn injected_latency(&self, target: LatencyTarget) -> Option<Duration> {
+        let injected = self.injected_latency.read().unwrap();
+        let (delay, until) = injected.get(&target)?;
+        (self.clock.now() < *until).then_some(*delay)
+    }
+
+    /// Validates and applies an INJECTLATENCY command
+    async fn inject_latency_command(
+        &self,
+        target: LatencyTarget,
+        millis: u64,
+        seconds: u64,
+    ) -> Response {
+        let delay = Duration::from_millis(millis);
+        let duration = Duration::from_secs(seconds);
+        if delay > MAX_INJECTED_LATENCY {
+            return Response::error(
+                ErrorCode::InvalidArgument,
+                format!(
+                    "Injected latency must be at most {:?}",
+                    MAX_INJECTED_LATENCY
+                ),
+            );
+        }
+        if !delay.is_zero() && (duration.is_zero() || duration > MAX_INJECTION_DURATION) {
+            return Response::error(
+                ErrorCode::InvalidArgument,
+                format!(
+                    "Latency injections must last between 1 second and {:?}",
+                    MAX_INJECTION_DURATION
+                ),
+            );
+        }
+        self.inject_latency(target, delay, duration);
+        if delay.is_zero() {
+            info!("Latency injection into {} cleared", target.as_str());
+        } else {
+            warn!(
+                "Injecting {:?} of latency into {} for {:?}",
+                delay,
+                target.as_str(),
+                duration
+            );
+        }
+        Response::Ok(None)
+    }
+
+    /// Returns the node configuration, derived from the database settings
+    /// when none was set
+    pub fn capabilities(&self) -> Capabilities {
+        if let Some(capabilities) = self.capabilities.read().unwrap().as_ref() {
+            return capabilities.clone();
+        }
+        let mut capabilities = Capabilities::default();
+        capabilities.features.canonical_json = self.canonical_json.load(Ordering::Relaxed);
+        capabilities.features.inline_meta = self.inline_meta.load(Ordering::Relaxed);
+        capabilities.features.disk_spill = self.spill.read().unwrap().is_some();
+        capabilities.features.audit = self.audit.read().unwrap().is_some();
+        capabilities.features.write_transforms = !self.write_pipeline.read().unwrap().is_empty();
+        capabilities.features.write_triggers = !self.triggers.read().unwrap().is_empty();
+        capabilities.limits.shards = self.shard_count;
+        capabilities.limits.quotas = self.quotas.read().unwrap().len();
+        capabilities.limits.history_depth = self.history_depth.load(Ordering::Relaxed);
+        capabilities.limits.max_memory_bytes = match self.max_memory.load(Ordering::Relaxed) {
+            0 => None,
+            max => Some(max),
+        };
+        capabilities.limits.compression_threshold =
+            match self.compression_threshold.load(Ordering::Relaxed) {
+                0 => None,
+                threshold => Some(threshold),
+            };
+        let mut cache_namespaces: Vec<String> =
+            self.cache_tier.read().unwrap().iter().cloned().collect();
+        cache_namespaces.sort();
+        capabilities.limits.cache_namespaces = cache_namespaces;
+        capabilities.limits.aof_fsync = self
+            .aof
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|aof| aof.fsync_policy().to_string());
+        capabilities.limits.change_log_capacity = self.change_log_capacity.load(Ordering::Relaxed);
+        capabilities.limits.tombstone_retention_secs = self
+            .tombstone_retention
+            .read()
+            .unwrap()
+            .map(|retention| retention.as_secs());
+        capabilities
+    }
+
+    /// Apply the configured write transformations to a value about to be stored
+    fn transform_for_write(&self, key: &str, value: Value) -> Result<Value, ErrorInfo> {
+        let pipeline = self.write_pipeline.read().unwrap();
+        let value = if pipeline.is_empty() {
+            value
+        } else {
+            pipeline
+                .apply(key, value)
+                .map_err(|e| ErrorInfo::new(ErrorCode::Internal, e))?
+        };
+        if self.canonical_json.load(Ordering::Relaxed) {
+            Ok(canonical::canonicalize(value))
+        } else {
+            Ok(value)
+        }
+    }
+
+    /// Fire the triggers of a key on the value about to replace `old`; called
+    /// while the key's entry is locked, so that conditions see the stored value
+    fn fire_triggers(
+        &self,
+        key: &str,
+        old: Option<&Value>,
+        value: &mut Value,
+    ) -> Result<(), ErrorInfo> {
+        let triggers = self.triggers.read().unwrap();
+        if triggers.is_empty() {
+            return Ok(());
+        }
+        triggers
+            .apply(key, old, value, self.clock.unix_millis())
+            .map_err(|e| ErrorInfo::new(ErrorCode::InvalidArgument, e))
+    }
+
+    /// Returns true if write triggers are configured
+    fn has_triggers(&self) -> bool {
+        !self.triggers.read().unwrap().is_empty()
+    }
+
+    /// Returns true if writes need to go through the transformation step
+    fn has_write_transforms(&self) -> bool {
+        self.canonical_json.load(Ordering::Relaxed)
+            || !self.write_pipeline.read().unwrap().is_empty()
+    }
+
+    /// Execute a command and return the response
+    pub async fn execute_command(&self, command: Command) -> Response {
+        if let Err(e) = self.authorize(&command) {
+            return Response::error(ErrorCode::Forbidden, e);
+        }
+        let _write = if command.is_write() {
+            if self.is_read_only() {
+                return Response::error(ErrorCode::ReadOnly, "The server is in read-only mode");
+            }
+            if self.is_write_fenced() {
+                return Response::Error(
+                    ErrorInfo::new(
+                        ErrorCode::Unavailable,
+                        "Writes are fenced: the write pipeline is stalled",
+                    )
+                    .retriable(),
+                );
+            }
+            Some(self.write_monitor.begin())
+        } else {
+            None
+        };
+
+        self.stats.record_command(command.name());
+        let activity = if command.is_write() {
+            &self.activity.writes
+        } else {
+            &self.activity.reads
+        };
+        activity.fetch_add(1, Ordering::Relaxed);
+        // Expired keys are deleted when accessed, spilled ones reloaded
+        if let Some(key) = command.key() {
+            self.expire_if_due(key);
+            let spilling = self.spill.read().unwrap().is_some();
+            if spilling {
+                if let Err(e) = self.reload_spilled(key).await {
+                    return Response::Error(e);
+                }
+            }
+        }
+        // Successful writes to a key are published to event subscribers
+        let event_key = command
+            .key()
+            .filter(|_| command.is_write() && self.events.receiver_count() > 0)
+            .map(str::to_string);
+        let name = command.name();
+        // Mutating commands are audited with the value of their key before
+        let audited = match self.audit.read().unwrap().as_ref() {
+            Some(audit) if command.is_write() => {
+                let key = command.key().map(str::to_string);
+                let before = key.as_deref().and_then(|key| self.audit_hash(key));
+                Some((Arc::clone(audit), key, before))
+            }
+            _ => None,
+        };
+        // Reads of a single key count as a hit or a miss
+        let lookup = (!command.is_write() && command.key().is_some())
+            .then_some(matches!(command, Command::Get { .. }));
+        let is_write = command.is_write();
+        let mut response = self.dispatch(command).await;
+        if is_write && !matches!(response, Response::Error(_)) {
+            if let Err(e) = self.wait_durable().await {
+                response = Response::Error(e);
+            }
+        }
+        if let Some(is_get) = lookup {
+            let found = match &response {
+                Response::Ok(None) => !is_get,
+                Response::Error(e) => e.code != ErrorCode::KeyNotFound,
+                _ => true,
+            };
+            self.stats.record_lookup(found);
+        }
+        if let (Some(key), Response::Ok(_)) = (event_key, &response) {
+            self.notify(&name.to_lowercase(), &key);
+        }
+        if let Some((audit, key, before)) = audited {
+            self.record_audit(&audit, name, key, before, &response);
+        }
+        response
+    }
+
+    /// Subscribe to the changes to keys of every namespace
+    pub fn subscribe_events(&self) -> broadcast::Receiver<KeyEvent> {
+        self.events.subscribe()
+    }
+
+    /// Publishes a change to a key of this namespace
+    fn notify(&self, event: &str, key: &str) {
+        if self.events.receiver_count() == 0 {
+            return;
+        }
+        // Fails only when the last subscriber just went away
+        let _ = self.events.send(KeyEvent {
+            namespace: self.namespace.to_string(),
+            key: key.to_string(),
+            event: event.to_string(),
+        });
+    }
+
+    /// Execute a command without bookkeeping
+    async fn dispatch(&self, command: Command) -> Response {
+        match command {
+            Command::Set { key, value } => self.set(key, value).await,
+            Command::Get { key } => self.get(&key).await,
+            Command::SetBytes { key, value } => self.set_bytes(&key, Bytes::from(value)).await,
+            Command::GetBytes { key } => self.get_bytes(&key).await,
+            Command::Delete { key } => self.delete(key).await,
+            Command::QGet { key, query } => self.qget(&key, &query).await,
+            Command::QScan {
+                key_pattern,
+                query,
+                limit,
+            } => self.qscan(&key_pattern, &query, limit).await,
+            Command::Aggregate {
+                key_pattern,
+                path,
+                op,
+            } => self.aggregate(&key_pattern, &path, op).await,
+            Command::JqGet { key, program } => self.jqget(&key, &program).await,
+            Command::QSet { key, path, value } => self.qset(key, path, value).await,
+            Command::Merge { key, value } => self.merge(key, value).await,
+            Command::QType { key, path } => self.qtype(&key, &path).await,
+            Command::QAppend { key, path, value } => self.qappend(key, path, value).await,
+            Command::QInsert {
+                key,
+                path,
+                index,
+                value,
+            } => self.qinsert(key, path, index, value).await,
+            Command::QPop { key, path, index } => self.qpop(key, path, index).await,
+            Command::ObjKeys { key, path } => self.objkeys(&key, &path).await,
+            Command::ArrLen { key, path } => self.arrlen(&key, &path).await,
+            Command::Digest { key } => self.digest(key.as_deref()).await,
+            Command::Explain { command } => self.explain(*command).await,
+            Command::Eval { script, keys, args } => self.eval(&script, keys, args).await,
+            Command::Profile { kind, seconds } => match profiling::capture(kind, seconds).await {
+                Ok(profile) => Response::Ok(Some(profile)),
+                Err(e) => Response::Error(e),
+            },
+            Command::InjectLatency {
+                target,
+                millis,
+                seconds,
+            } => self.inject_latency_command(target, millis, seconds).await,
+            Command::ReadOnly { enabled } => {
+                self.set_read_only(enabled);
+                info!(
+                    "Read-only mode {}",
+                    if enabled { "enabled" } else { "disabled" }
+                );
+                Response::Ok(None)
+            }
+            Command::MemoryUsage { key } => self.memory_usage(&key).await,
+            Command::Usage => self.usage().await,
+            Command::Info => Response::Ok(Some(self.info())),
+            Command::Meta { key } => self.meta(&key).await,
+            Command::Flush { prefix } => self.flush(prefix.as_deref()).await,
+            Command::Expire { key, seconds } => self.expire(&key, seconds).await,
+            Command::Ttl { key } => self.ttl(&key).await,
+            Command::Persist { key } => self.persist(&key).await,
+            Command::RandomKey => self.random_key().await,
+            Command::Sample { n } => self.sample(n).await,
+            Command::Tombstones { since } => self.tombstones(since).await,
+            Command::History { key } => self.history(&key).await,
+            Command::Restore { key, version } => self.restore(&key, version).await,
+            Command::Stats => self.stats().await,
+            Command::ShardStats => self.shard_stats().await,
+            Command::Commands => Response::Ok(Some(Self::command_table())),
+            Command::Save { background } => self.save(background).await,
+            Command::VerifyBackup { source } => self.verify_backup(source.as_deref()).await,
+            Command::AofFetch { sequence, offset } => self.aof_fetch(sequence, offset).await,
+            Command::SnapshotFetch { index, offset } => self.snapshot_fetch(index, offset).await,
+            Command::Promote => self.promote().await,
+            Command::AclSet { identity, grants } => {
+                self.acl.set(&identity, grants);
+                Response::Ok(None)
+            }
+            Command::AclDel { identity } => {
+                Response::Ok(Some(serde_json::json!(self.acl.remove(&identity))))
+            }
+            Command::AclList => Response::Ok(serde_json::to_value(self.acl.list()).ok()),
+            Command::Audit {
+                since,
+                identity,
+                key_pattern,
+                limit,
+            } => self.audit_records(
+                since,
+                identity.as_deref(),
+                None,
+                key_pattern.as_deref(),
+                limit,
+            ),
+            Command::ConfigGet { parameter } => Response::Ok(Some(self.config_get(&parameter))),
+            Command::ConfigSet { parameter, value } => match self.config_set(&parameter, &value) {
+                Ok(()) => Response::Ok(None),
+                Err(e) => Response::error(ErrorCode::InvalidArgument, e),
+            },
+            Command::Export { pattern } => self.export(&pattern).await,
+            Command::Import { data } => self.import(&data).await,
+            Command::BulkLoad { data } => self.bulk_load(&data).await,
+            Command::Changes { since, limit } => self.changes(since, limit).await,
+            Command::GroupRead { group, count } => self.group_read(&group, count).await,
+            Command::GroupAck { group, offset } => self.group_ack(&group, offset).await,
+            // Handshakes, authentication, namespace selection, routing,
+            // streamed results, subscriptions and client management are
+            // answered by the connection handler
+            Command::Hello { .. }
+            | Command::ClientSetName { .. }
+            | Command::ClientList
+            | Command::ClientKill { .. }
+            | Command::Stream { .. }
+            | Command::Auth { .. }
+            | Command::Select { .. }
+            | Command::RoutingTable
+            | Command::Watch { .. }
+            | Command::SubscribeEvents { .. }
+            | Command::Changefeed { .. }
+            | Command::Publish { .. }
+            | Command::Subscribe { .. } => Response::Ok(None),
+            Command::Ping => Response::Pong,
+        }
+    }
+
+    /// Sets a value for a key
+    async fn set(&self, key: String, value: Value) -> Response {
+        // JSON validation
+        if !self.is_valid_json(&value) {
+            return Response::error(ErrorCode::InvalidArgument, "Invalid JSON value");
+        }
+
+        let value = match self.transform_for_write(&key, value) {
+            Ok(value) => value,
+            Err(e) => return Response::Error(e),
+        };
+
+        // Like a new document, a SET value has no expiry
+        if let Err(e) = self.store(&key, value.clone(), true) {
+            return Response::Error(e);
+        }
+        debug!("SET: {} = {}", key, value);
+
+        Response::Ok(None)
+    }
+
+    /// Reads a value for a key
+    async fn get(&self, key: &str) -> Response {
+        // Only the reference is taken under the shard lock
+        self.probe_shard(key, false);
+        let shared = self.data.get(key).map(|document| document.shared());
+        match shared {
+            Some(Err(e)) => Response::Error(e),
+            Some(Ok(value)) => {
+                debug!("GET: {} = {}", key, value);
+                Response::Ok(Some(Value::clone(&value)))
+            }
+            None if self.blobs.contains_key(key) => Response::error(
+                ErrorCode::WrongType,
+                "Key holds a binary value (use GETBYTES)",
+            ),
+            None => {
+                debug!("GET: {} not found", key);
+                Response::Ok(None)
+            }
+        }
+    }
+
+    /// Returns the serialized value of a key for the GET fast path, with the
+    /// bookkeeping of `execute_command`; None when the GET must go through
+    /// `execute_command` (missing or binary key)
+    pub(crate) fn get_serialized(&self, key: &str) -> Option<Bytes> {
+        self.expire_if_due(key);
+        self.probe_shard(key, false);
+        // Spilled documents take the command path, which reloads them
+        let serialized = self
+            .data
+            .get(key)
+            .filter(|document| !document.is_spilled())
+            .and_then(|document| document.serialized().ok())?;
+        self.stats.record_command("GET");
+        self.stats.record_lookup(true);
+        self.activity.reads.fetch_add(1, Ordering::Relaxed);
+        Some(serialized)
+    }
+
+    /// Stores a binary value, which is kept as raw bytes and never parsed
+    async fn set_bytes(&self, key: &str, value: Bytes) -> Response {
+        let new_size = (key.len() + value.len()) as u64;
+        if let Err(e) = self.check_value_size(key, new_size) {
+            return Response::Error(e);
+        }
+        if let Err(e) = self.make_room(new_size) {
+            return Response::Error(e);
+        }
+        let _write = self.begin_write();
+        // Counted before locking the entries: len() locks every shard
+        let key_count = self.data.len() + self.blobs.len();
+        // The document entry stays locked until the value is stored, so that
+        // a concurrent JSON write of the key finds it binary once it gets it
+        let document = self.data.entry(key.to_string());
+        if matches!(document, Entry::Occupied(_)) {
+            return Response::error(ErrorCode::WrongType, "Key holds a JSON document (use SET)");
+        }
+        let entry = self.blobs.entry(key.to_string());
+        let old_size = match &entry {
+            Entry::Occupied(e) => Some((key.len() + e.get().len()) as u64),
+            Entry::Vacant(_) => None,
+        };
+        if let Err(e) = self.check_quota(old_size, new_size, key_count) {
+            return Response::Error(e);
+        }
+        if let Err(e) = self.append_to_aof(|ns| AofRecord::SetBytes {
+            ns,
+            key: key.into(),
+            value: Cow::Borrowed(&value),
+        }) {
+            return Response::Error(e);
+        }
+        entry.insert(value);
+        self.account(old_size, new_size);
+        self.bump_version();
+        debug!("SETBYTES: {} ({} bytes)", key, new_size - key.len() as u64);
+        Response::Ok(None)
+    }
+
+    /// Returns the binary value of a key
+    async fn get_bytes(&self, key: &str) -> Response {
+        match self.blobs.get(key) {
+            Some(value) => Response::Bytes(value.to_vec()),
+            None if self.data.contains_key(key) => {
+                Response::error(ErrorCode::WrongType, "Key holds a JSON document (use GET)")
+            }
+            None => Response::Ok(None),
+        }
+    }
+
+    /// Deletes a value for a key
+    async fn delete(&self, key: String) -> Response {
+        let _write = self.begin_write();
+        self.probe_shard(&key, true);
+        match self.data.entry(key) {
+            Entry::Occupied(entry) => match self.remove(entry, ChangeKind::Delete) {
+                Ok(key) => {
+                    debug!("DELETE: {} removed", key);
+                    Response::Ok(None)
+                }
+                Err(e) => Response::Error(e),
+            },
+            // Appended to the AOF before the blob's entry is unlocked
+            Entry::Vacant(entry) => {
+                let mut logged = Ok(());
+                let removed = self.blobs.remove_if(entry.key(), |key, _| {
+                    logged = self.append_to_aof(|ns| AofRecord::Delete {
+                        ns,
+                        key: key.into(),
+                    });
+                    logged.is_ok()
+                });
+                match (removed, logged) {
+                    (_, Err(e)) => Response::Error(e),
+                    (Some((key, value)), Ok(())) => {
+                        self.account(Some((key.len() + value.len()) as u64), 0);
+                        self.bump_version();
+                        debug!("DELETE: binary value of {} removed", key);
+                        Response::Ok(None)
+                    }
+                    (None, Ok(())) => {
+                        debug!("DELETE: {} not found", entry.key());
+                        Response::error(ErrorCode::KeyNotFound, "Key not found")
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs a script on declared keys as a single write: no other write to
+    /// the namespace interleaves with its writes.
+    ///
+    /// The script runs on a blocking thread, within its fuel, before the
+    /// namespace is locked. Its writes are then applied under the exclusive
+    /// write gate, unless one of the keys changed meanwhile and the script
+    /// runs again: all of them are validated, transformed and checked against
+    /// the quota before the first one is applied.
+    async fn eval(&self, script: &str, keys: Vec<String>, args: Vec<Value>) -> Response {
+        for key in &keys {
+            self.expire_if_due(key);
+        }
+        if let Err(e) = self.make_room(0) {
+            return Response::Error(e);
+        }
+        for _ in 0..MAX_EVAL_ATTEMPTS {
+            // Read before the values: a write landing in between is detected
+            let seen = self.key_versions(&keys);
+            let values = keys
+                .iter()
+                .map(|key| {
+                    self.data
+                        .get(key)
+                        .map(|v| v.json().map(Cow::into_owned))
+                        .transpose()
+                })
+                .collect::<Result<_, _>>();
+            let input = ScriptInput {
+                keys: &keys,
+                values: match values {
+                    Ok(values) => values,
+                    Err(e) => return Response::Error(e),
+                },
+                args: &args,
+            };
+            let input = serde_json::to_vec(&input).expect("script input serializes");
+            let scripts = Arc::clone(&self.scripts);
+            let name = script.to_string();
+            let output = match tokio::task::spawn_blocking(move || scripts.run(&name, &input)).await
+            {
+                Ok(Ok(output)) => output,
+                Ok(Err(e)) => return Response::Error(e),
+                Err(e) => {
+                    return Response::error(
+                        ErrorCode::Internal,
+                        format!("Script '{}' did not complete: {}", script, e),
+                    )
+                }
+            };
+            let output: ScriptOutput = match serde_json::from_slice(&output) {
+                Ok(output) => output,
+                Err(e) => {
+                    return Response::error(
+                        ErrorCode::InvalidArgument,
+                        format!("Script '{}' returned an invalid document: {}", script, e),
+                    )
+                }
+            };
+            // Scripts may only write the keys they declare
+            if let Some(key) = output.writes.keys().find(|key| !keys.contains(key)) {
+                return Response::error(
+                    ErrorCode::InvalidArgument,
+                    format!("Script '{}' wrote the undeclared key '{}'", script, key),
+                );
+            }
+            match self.apply_script_writes(&keys, &seen, output.writes) {
+                Ok(true) => {
+                    debug!("EVAL {} on {:?}", script, keys);
+                    return Response::Ok(output.result);
+                }
+                // A key changed while the script ran
+                Ok(false) => continue,
+                Err(e) => return Response::Error(e),
+            }
+        }
+        Response::Error(
+            ErrorInfo::new(
+                ErrorCode::Unavailable,
+                format!("The keys of script '{}' kept changing while it ran", script),
+            )
+            .retriable(),
+        )
+    }
+
+    /// Returns the creation time and version of keys, to tell whether they
+    /// were written since
+    fn key_versions(&self, keys: &[String]) -> Vec<Option<(u64, u64)>> {
+        keys.iter()
+            .map(|key| {
+                self.meta
+                    .get(key)
+                    .map(|meta| (meta.created_at, meta.version))
+            })
+            .collect()
+    }
+
+    /// Applies the writes of a script under the exclusive write gate, unless
+    /// its keys changed since `seen` (returning false). Nothing is written if
+    /// one of the writes is invalid or the writes exceed the quota.
+    fn apply_script_writes(
+        &self,
+        keys: &[String],
+        seen: &[Option<(u64, u64)>],
+        writes: BTreeMap<String, Option<Value>>,
+    ) -> Result<bool, ErrorInfo> {
+        // Exclusive, like FLUSH: the writes apply to the keyspace the script saw
+        let _write = self.write_gate.write().unwrap();
+        if self.key_versions(keys) != seen {
+            return Ok(false);
+        }
+        let key_count = self.data.len();
+        let mut prepared = Vec::with_capacity(writes.len());
+        let (mut old_total, mut new_total, mut added_keys) = (0, 0, 0);
+        for (key, value) in writes {
+            let (old_size, value) = match value {
+                Some(value) => {
+                    let (old_size, meta, value, new_size) = self.prepare_write(&key, value)?;
+                    new_total += new_size;
+                    added_keys += usize::from(old_size.is_none());
+                    (old_size, Some((meta, value, new_size)))
+                }
+                None => {
+                    let old = self.data.get(&key);
+                    let old_size = old.map(|document| Self::document_size(&key, &document));
+                    (old_size, None)
+                }
+            };
+            old_total += old_size.unwrap_or(0);
+            prepared.push((key, old_size, value));
+        }
+        self.check_batch_quota(old_total, new_total, added_keys, key_count)?;
+
+        for (key, old_size, value) in prepared {
+            let entry = self.data.entry(key.clone());
+            match (value, entry) {
+                (Some((meta, value, new_size)), entry) => {
+                    self.insert_entry(entry, &key, meta, value, old_size, new_size, true)?;
+                    self.notify("set", &key);
+                }
+                (None, Entry::Occupied(entry)) => {
+                    self.remove(entry, ChangeKind::Delete)?;
+                    self.notify("delete", &key);
+                }
+                (None, Entry::Vacant(_)) => {}
+            }
+        }
+        Ok(true)
+    }
+
+    /// Prepares a document written by a batch under the exclusive write gate,
+    /// returning the size of the document it replaces, its metadata, the
+    /// value to store and its size
+    fn prepare_write(
+        &self,
+        key: &str,
+        value: Value,
+    ) -> Result<(Option<u64>, KeyMeta, Arc<Value>, u64), ErrorInfo> {
+        if !self.is_valid_json(&value) {
+            return Err(ErrorInfo::new(
+                ErrorCode::InvalidArgument,
+                "Invalid JSON value",
+            ));
+        }
+        self.check_not_binary(key)?;
+        let old = self.data.get(key);
+        let old_size = old
+            .as_ref()
+            .map(|document| Self::document_size(key, document));
+        let old_value = old
+            .filter(|_| self.has_triggers())
+            .map(|document| document.json().map(Cow::into_owned))
+            .transpose()?;
+        let mut value = self.transform_for_write(key, value)?;
+        self.fire_triggers(key, old_value.as_ref(), &mut value)?;
+        let meta = self.next_meta(key);
+        self.stamp(&meta, &mut value);
+        let new_size = Self::entry_size(key, &value);
+        self.check_value_size(key, new_size)?;
+        Ok((old_size, meta, Arc::new(value), new_size))
+    }
+
+    /// Removes a key with its metadata, returning the key; the key is kept
+    /// if the removal could not be logged
+    fn remove(
+        &self,
+        entry: OccupiedEntry<'_, String, Document>,
+        kind: ChangeKind,
+    ) -> Result<String, ErrorInfo> {
+        self.append_to_aof(|ns| AofRecord::Delete {
+            ns,
+            key: entry.key().into(),
+        })?;
+        let now = self.clock.unix_millis();
+        self.meta.remove(entry.key());
+        self.history.remove(entry.key());
+        self.expires.remove(entry.key());
+        if self.tombstone_retention.read().unwrap().is_some() {
+            self.tombstones.insert(entry.key().clone(), now);
+        }
+        self.record_change(kind, entry.key(), None, now);
+        let (key, document) = entry.remove_entry();
+        self.account(Some(Self::document_size(&key, &document)), 0);
+        self.bump_version();
+        Ok(key)
+    }
+
+    /// Returns true if the expiry of a key has passed
+    fn is_expired(&self, key: &str, now: u64) -> bool {
+        self.expires.get(key).is_some_and(|at| *at <= now)
+    }
+
+    /// Deletes a key whose expiry has passed; returns true if it was deleted
+    fn expire_if_due(&self, key: &str) -> bool {
+        let now = self.clock.unix_millis();
+        if !self.is_expired(key, now) {
+            return false;
+        }
+        let _write = self.begin_write();
+        match self.data.entry(key.to_string()) {
+            // Checked again under the entry lock: the key may have been rewritten
+            Entry::Occupied(entry) if self.is_expired(key, now) => {
+                if let Err(e) = self.remove(entry, ChangeKind::Expire) {
+                    error!("Failed to expire {}: {}", key, e.message);
+                    return false;
+                }
+                self.stats.expired.fetch_add(1, Ordering::Relaxed);
+                self.notify("expired", key);
+                debug!("Expired key {}", key);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Deletes up to `batch` expired keys of every namespace, returning the
+    /// number of deleted keys
+    pub fn expire_keys(&self, batch: usize) -> usize {
+        let mut expired = 0;
+        for name in self.namespaces() {
+            if let Ok(namespace) = self.namespace(&name) {
+                expired += namespace.expire_batch(batch);
+            }
+        }
+        expired
+    }
+
+    /// Deletes up to `batch` expired keys of this namespace
+    fn expire_batch(&self, batch: usize) -> usize {
+        let now = self.clock.unix_millis();
+        // Collected first: deleting a key updates the map being scanned
+        let due: Vec<String> = self
+            .expires
+            .iter()
+            .filter(|entry| *entry.value() <= now)
+            .take(batch)
+            .map(|entry| entry.key().clone())
+            .collect();
+        due.iter().filter(|key| self.expire_if_due(key)).count()
+    }
+
+    /// Sets the time to live of a key
+    async fn expire(&self, key: &str, seconds: u64) -> Response {
+        let _write = self.begin_write();
+        let Some(_entry) = self.data.get(key) else {
+            return Response::error(ErrorCode::KeyNotFound, "Key not found");
+        };
+        let at = self
+            .clock
+            .unix_millis()
+            .saturating_add(seconds.saturating_mul(1000));
+        if let Err(e) = self.append_to_aof(|ns| AofRecord::Expire {
+            ns,
+            key: key.into(),
+            at,
+        }) {
+            return Response::Error(e);
+        }
+        self.expires.insert(key.to_string(), at);
+        self.bump_version();
+        Response::Ok(None)
+    }
+
+    /// Returns the remaining time to live of a key in milliseconds (null without expiry)
+    async fn ttl(&self, key: &str) -> Response {
+        let Some(_entry) = self.data.get(key) else {
+            return Response::error(ErrorCode::KeyNotFound, "Key not found");
+        };
+        let now = self.clock.unix_millis();
+        let ttl = self.expires.get(key).map(|at| at.saturating_sub(now));
+        Response::Ok(ttl.map(Value::from))
+    }
+
+    /// Removes the expiry of a key, returning whether it had one
+    async fn persist(&self, key: &str) -> Response {
+        let _write = self.begin_write();
+        let Some(_entry) = self.data.get(key) else {
+            return Response::error(ErrorCode::KeyNotFound, "Key not found");
+        };
+        let removed = self.expires.contains_key(key);
+        if removed {
+            if let Err(e) = self.append_to_aof(|ns| AofRecord::Persist {
+                ns,
+                key: key.into(),
+            }) {
+                return Response::Error(e);
+            }
+            self.expires.remove(key);
+            self.bump_version();
+        }
+        Response::Ok(Some(Value::Bool(removed)))
+    }
+
+    /// Execute a JSONPath query on a value
+    async fn qget(&self, key: &str, query: &str) -> Response {
+        let document = match self
+            .data
+            .get(key)
+            .map(|document| document.json().map(Cow::into_owned))
+        {
+            Some(Ok(document)) => document,
+            Some(Err(e)) => return Response::Error(e),
+            None => {
+                debug!("JSONPath query: {} not found", key);
+                return Response::error(ErrorCode::KeyNotFound, "Key not found");
+            }
+        };
+        match jsonpath_lib::select(&document, query) {
+            Ok(result) => {
+                debug!(
+                    "JSONPath query: {} with query '{}' = {:?}",
+                    key, query, result
+                );
+                if result.is_empty() {
+                    Response::Ok(Some(Value::Null))
+                } else if result.len() == 1 {
+                    Response::Ok(Some(result[0].clone()))
+                } else {
+                    Response::Ok(Some(Value::Array(result.into_iter().cloned().collect())))
+                }
+            }
+            Err(e) => {
+                error!("JSONPath error for {}: {}", key, e);
+                Response::error(
+                    ErrorCode::InvalidQuery,
+                    format!("JSONPath query error: {}", e),
+                )
+            }
+        }
+    }
+
+    /// Execute a JSONPath query on every key matching a glob pattern
+    async fn qscan(&self, key_pattern: &str, query: &str, limit: Option<usize>) -> Response {
+        let compiled = match jsonpath_lib::Compiled::compile(query) {
+            Ok(compiled) => compiled,
+            Err(e) => {
+                return Response::error(
+                    ErrorCode::InvalidQuery,
+                    format!("JSONPath query error: {}", e),
+                )
+            }
+        };
+
+        let mut results = Vec::new();
+        for key in self.matching_keys(key_pattern) {
+            if limit.is_some_and(|limit| results.len() >= limit) {
+                break;
+            }
+            match self.qscan_match(&key, &compiled) {
+                Ok(Some(result)) => results.push(result),
+                Ok(None) => {}
+                Err(e) => return Response::error(ErrorCode::InvalidQuery, e),
+            }
+        }
+
+        debug!(
+            "QSCAN: pattern '{}' with query '{}' = {} results",
+            key_pattern,
+            query,
+            results.len()
+        );
+        Response::Ok(Some(Value::Array(results)))
+    }
+
+    /// Returns the QSCAN result of a key (`{"key": ..., "result": ...}`), or
+    /// None if the key is gone or the query matches nothing
+    pub(crate) fn qscan_match(
+        &self,
+        key: &str,
+        compiled: &jsonpath_lib::Compiled,
+    ) -> Result<Option<Value>, String> {
+        let Some(document) = self.data.get(key) else {
+            return Ok(None);
+        };
+        let value = document.json().map_err(|e| e.message)?;
+        let matches = compiled
+            .select(&value)
+            .map_err(|e| format!("JSONPath query error: {}", e))?;
+        let result = match matches.len() {
+            0 => return Ok(None),
+            1 => matches[0].clone(),
+            _ => Value::Array(matches.into_iter().cloned().collect()),
+        };
+        Ok(Some(serde_json::json!({ "key": key, "result": result })))
+    }
+
+    /// Aggregate the numeric values at a JSONPath across every key matching a glob pattern.
+    ///
+    /// Non-numeric matches are ignored; avg/min/max over no values yield null.
+    async fn aggregate(&self, key_pattern: &str, path: &str, op: AggregateOp) -> Response {
+        let compiled = match jsonpath_lib::Compiled::compile(path) {
+            Ok(compiled) => compiled,
+            Err(e) => {
+                return Response::error(
+                    ErrorCode::InvalidQuery,
+                    format!("JSONPath query error: {}", e),
+                )
+            }
+        };
+
+        let mut count = 0u64;
+        let mut sum = 0.0;
+        let mut min: Option<Value> = None;
+        let mut max: Option<Value> = None;
+        for key in self.matching_keys(key_pattern) {
+            let Some(document) = self.data.get(&key) else {
+                continue;
+            };
+            let value = match document.json() {
+                Ok(value) => value,
+                Err(e) => return Response::Error(e),
+            };
+            let matches = match compiled.select(&value) {
+                Ok(matches) => matches,
+                Err(e) => {
+                    return Response::error(
+                        ErrorCode::InvalidQuery,
+                        format!("JSONPath query error: {}", e),
+                    )
+                }
+            };
+            for number in matches.into_iter().filter(|v| v.is_number()) {
+                let n = number.as_f64().unwrap_or(0.0);
+                count += 1;
+                sum += n;
+                if min.as_ref().is_none_or(|m| n < m.as_f64().unwrap_or(0.0)) {
+                    min = Some(number.clone());
+                }
+                if max.as_ref().is_none_or(|m| n > m.as_f64().unwrap_or(0.0)) {
+                    max = Some(number.clone());
+                }
+            }
+        }
+
+        let result = match op {
+            AggregateOp::Count => Value::from(count),
+            AggregateOp::Sum => Self::number_value(sum),
+            AggregateOp::Avg if count > 0 => Value::from(sum / count as f64),
+            AggregateOp::Avg => Value::Null,
+            AggregateOp::Min => min.unwrap_or(Value::Null),
+            AggregateOp::Max => max.unwrap_or(Value::Null),
+        };
+        debug!(
+            "AGGREGATE: {} of '{}' over pattern '{}' = {}",
+            op.as_str(),
+            path,
+            key_pattern,
+            result
+        );
+        Response::Ok(Some(result))
+    }
+
+    /// Converts a float to a JSON number, keeping integral values as integers
+    fn number_value(n: f64) -> Value {
+        canonical::canonicalize(Value::from(n))
+    }
+
+    /// Draws `n` distinct positions among `len` (all of them if `n >= len`)
+    fn random_positions(len: usize, n: usize) -> BTreeSet<usize> {
+        let mut positions = BTreeSet::new();
+        if n >= len {
+            positions.extend(0..len);
+        } else {
+            while positions.len() < n {
+                positions.insert(fastrand::usize(..len));
+            }
+        }
+        positions
+    }
+
+    /// Returns up to `n` distinct random entries of the keyspace.
+    ///
+    /// The map has no random access, so positions are drawn up front and
+    /// collected in a single pass without copying the keyspace.
+    fn random_entries(&self, n: usize) -> Vec<(String, Value)> {
+        let positions = Self::random_positions(self.data.len(), n);
+        let mut entries: Vec<(String, Value)> = self
+            .data
+            .iter()
+            .enumerate()
+            .filter(|(position, _)| positions.contains(position))
+            .take(n)
+            .filter_map(|(_, entry)| match entry.json() {
+                Ok(value) => Some((entry.key().clone(), value.into_owned())),
+                Err(e) => {
+                    error!("{}", e.message);
+                    None
+                }
+            })
+            .collect();
+        fastrand::shuffle(&mut entries);
+        entries
+    }
+
+    /// Deletes every key, or the keys starting with `prefix`, as a single
+    /// write; returns the number of deleted keys
+    async fn flush(&self, prefix: Option<&str>) -> Response {
+        // Exclusive: concurrent writes land either before or after the flush
+        let _write = self.write_gate.write().unwrap();
+        // Logged as a single record, so that replicas apply it as one write
+        if let Err(e) = self.append_to_aof(|ns| AofRecord::Flush {
+            ns,
+            prefix: prefix.map(Cow::Borrowed),
+        }) {
+            return Response::Error(e);
+        }
+        let deleted = self.remove_prefixed(prefix, |key| self.notify("flush", key));
+        debug!("FLUSH {:?}: {} keys removed", prefix, deleted);
+        Response::Ok(Some(deleted.into()))
+    }
+
+    /// Removes every key starting with `prefix` (every key when None),
+    /// calling `on_removed` with each; called under the exclusive write gate
+    fn remove_prefixed(&self, prefix: Option<&str>, mut on_removed: impl FnMut(&str)) -> u64 {
+        let tombstones = self.tombstone_retention.read().unwrap().is_some();
+        let now = self.clock.unix_millis();
+        let (mut deleted, mut bytes) = (0u64, 0u64);
+        self.data.retain(|key, document| {
+            if prefix.is_some_and(|prefix| !key.starts_with(prefix)) {
+                return true;
+            }
+            deleted += 1;
+            bytes += Self::document_size(key, document);
+            self.meta.remove(key);
+            self.history.remove(key);
+            self.expires.remove(key);
+            if tombstones {
+                self.tombstones.insert(key.clone(), now);
+            }
+            self.record_change(ChangeKind::Delete, key, None, now);
+            on_removed(key);
+            false
+        });
+        self.blobs.retain(|key, value| {
+            if prefix.is_some_and(|prefix| !key.starts_with(prefix)) {
+                return true;
+            }
+            deleted += 1;
+            bytes += (key.len() + value.len()) as u64;
+            on_removed(key);
+            false
+        });
+        self.account(Some(bytes), 0);
+        self.bump_version();
+        deleted
+    }
+
+    /// Returns a random key, or None if the keyspace is empty
+    async fn random_key(&self) -> Response {
+        let key = self
+            .random_entries(1)
+            .pop()
+            .map(|(key, _)| Value::String(key));
+        Response::Ok(key)
+    }
+
+    /// Returns up to `n` random keys with their values
+    async fn sample(&self, n: usize) -> Response {
+        if n > MAX_SAMPLE_SIZE {
+            return Response::error(
+                ErrorCode::InvalidArgument,
+                format!("Sample size must be at most {}", MAX_SAMPLE_SIZE),
+            );
+        }
+        let sample: serde_json::Map<String, Value> = self.random_entries(n).into_iter().collect();
+        Response::Ok(Some(Value::Object(sample)))
+    }
+
+    /// Returns the keys matching a glob pattern with their values and
+    /// metadata as NDJSON, consistent with a single keyspace version
+    async fn export(&self, pattern: &str) -> Response {
+        let mut entries: Vec<(String, Arc<Value>, Option<KeyMeta>)> = Vec::new();
+        {
+            let _read = self.write_gate.write().unwrap();
+            for key in self.matching_keys(pattern) {
+                let value = match self.try_shared_value(&key) {
+                    Ok(Some(value)) => value,
+                    Ok(None) => continue,
+                    Err(e) => return Response::Error(e),
+                };
+                let meta = self.meta.get(&key).map(|meta| *meta);
+                entries.push((key, value, meta));
+            }
+        }
+        let records: Vec<ExportRecord> = entries
+            .into_iter()
+            .map(|(key, value, meta)| ExportRecord {
+                key,
+                value: Arc::unwrap_or_clone(value),
+                meta,
+            })
+            .collect();
+        debug!("EXPORT {}: {} keys", pattern, records.len());
+        Response::Ok(Some(Value::String(ExportRecord::to_ndjson(&records))))
+    }
+
+    /// Returns the EXPORT record of a key, or None if the key is gone
+    pub(crate) fn export_record(&self, key: &str) -> Result<Option<ExportRecord>, ErrorInfo> {
+        let Some(value) = self.try_shared_value(key)? else {
+            return Ok(None);
+        };
+        Ok(Some(ExportRecord {
+            key: key.to_string(),
+            value: Arc::unwrap_or_clone(value),
+            meta: self.meta.get(key).map(|meta| *meta),
+        }))
+    }
+
+    /// Writes the keys of an NDJSON export like SETs, once every record has
+    /// been parsed; stops at the first failed write
+    async fn import(&self, data: &str) -> Response {
+        let records = match ExportRecord::parse_ndjson(data) {
+            Ok(records) => records,
+            Err(e) => return Response::error(ErrorCode::InvalidArgument, e),
+        };
+        let mut imported = 0;
+        for record in records {
+            if let Response::Error(e) = self.set(record.key, record.value).await {
+                return Response::Error(
+                    e.with_details(serde_json::json!({ "imported": imported })),
+                );
+            }
+            imported += 1;
+        }
+        debug!("IMPORT: {} keys", imported);
+        Response::Ok(Some(serde_json::json!({ "imported": imported })))
+    }
+
+    /// Writes a chunk of documents streamed by a bulk load like SETs, as a
+    /// single write logged once rather than once per document. Nothing is
+    /// written if one of the documents is invalid or the chunk exceeds the
+    /// quota.
+    async fn bulk_load(&self, data: &str) -> Response {
+        let records = match ExportRecord::parse_ndjson(data) {
+            Ok(records) => records,
+            Err(e) => return Response::error(ErrorCode::InvalidArgument, e),
+        };
+        let loaded = records.len();
+        // The last document of a key loaded twice replaces the first
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        let mut documents: Vec<(String, Value)> = Vec::with_capacity(records.len());
+        for record in records {
+            match positions.get(&record.key) {
+                Some(&position) => documents[position].1 = record.value,
+                None => {
+                    positions.insert(record.key.clone(), documents.len());
+                    documents.push((record.key, record.value));
+                }
+            }
+        }
+        let needed = documents
+            .iter()
+            .map(|(key, value)| Self::entry_size(key, value))
+            .sum();
+        if let Err(e) = self.make_room(needed) {
+            return Response::Error(e);
+        }
+        if let Err(e) = self.store_documents(documents) {
+            return Response::Error(e);
+        }
+        debug!("BULKLOAD: {} documents", loaded);
+        Response::Ok(Some(serde_json::json!({ "loaded": loaded })))
+    }
+
+    /// Stores the documents of a bulk load under the exclusive write gate, so
+    /// they are prepared against the keyspace they replace, logging them as
+    /// one AOF record. Nothing is loaded on failure, which names the key at
+    /// fault, if any.
+    fn store_documents(&self, documents: Vec<(String, Value)>) -> Result<(), ErrorInfo> {
+        let _write = self.write_gate.write().unwrap();
+        let key_count = self.data.len();
+        let mut prepared = Vec::with_capacity(documents.len());
+        let (mut old_total, mut new_total, mut added_keys) = (0, 0, 0);
+        for (key, value) in documents {
+            let (old_size, meta, value, new_size) = match self.prepare_write(&key, value) {
+                Ok(document) => document,
+                Err(e) => {
+                    let details = serde_json::json!({ "loaded": 0, "key": key });
+                    return Err(e.with_details(details));
+                }
+            };
+            old_total += old_size.unwrap_or(0);
+            new_total += new_size;
+            added_keys += usize::from(old_size.is_none());
+            prepared.push((key, old_size, meta, value, new_size));
+        }
+        self.check_batch_quota(old_total, new_total, added_keys, key_count)
+            .map_err(|e| e.with_details(serde_json::json!({ "loaded": 0 })))?;
+        self.append_to_aof(|ns| AofRecord::SetMany {
+            ns,
+            documents: prepared
+                .iter()
+                .map(|(key, _, meta, value, _)| BulkDocument {
+                    key: key.into(),
+                    value: Cow::Borrowed(value.as_ref()),
+                    meta: *meta,
+                })
+                .collect(),
+        })?;
+
+        for (key, old_size, meta, value, new_size) in prepared {
+            self.record_write(&key, meta, &value);
+            self.expires.remove(&key);
+            self.data.insert(key, self.document(value));
+            self.account(old_size, new_size);
+            self.bump_version();
+        }
+        Ok(())
+    }
+
+    /// Returns the keys matching a glob pattern, sorted
+    pub(crate) fn matching_keys(&self, pattern: &str) -> Vec<String> {
+        let prefix = glob::literal_prefix(pattern);
+        let mut keys: Vec<String> = self
+            .data
+            .iter()
+            .filter(|entry| {
+                entry.key().starts_with(prefix) && glob::glob_match(pattern, entry.key())
+            })
+            .map(|entry| entry.key().clone())
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    /// Execute a jq program on a value
+    async fn jqget(&self, key: &str, program: &str) -> Response {
+        let value = match self.data.get(key) {
+            Some(value) => value,
+            None => {
+                debug!("JQ query: {} not found", key);
+                return Response::error(ErrorCode::KeyNotFound, "Key not found");
+            }
+        };
+
+        let value = match value.json() {
+            Ok(value) => value,
+            Err(e) => return Response::Error(e),
+        };
+        match jq::run(program, &value) {
+            Ok(mut results) => {
+                debug!(
+                    "JQ query: {} with program '{}' = {:?}",
+                    key, program, results
+                );
+                match results.len() {
+                    0 => Response::Ok(Some(Value::Null)),
+                    1 => Response::Ok(results.pop()),
+                    _ => Response::Ok(Some(Value::Array(results))),
+                }
+            }
+            Err(e) => {
+                error!("jq error for {}: {}", key, e);
+                Response::error(ErrorCode::InvalidQuery, e)
+            }
+        }
+    }
+
+    /// Set a sub-property using JSONPath
+    async fn qset(&self, key: String, path: String, value: Value) -> Response {
+        // Validate JSON
+        if !self.is_valid_json(&value) {
+            return Response::error(ErrorCode::InvalidArgument, "Invalid JSON value");
+        }
+
+        // Get existing value or create new empty object
+        let existing_value = match self.data.get(&key).map(|v| v.json().map(Cow::into_owned)) {
+            Some(Ok(value)) => value,
+            Some(Err(e)) => return Response::Error(e),
+            None => Value::Object(serde_json::Map::new()),
+        };
+
+        // Clone for modification
+        let mut modified_value = existing_value.clone();
+
+        // Use JSONPath to set the value
+        match self.set_json_path(&mut modified_value, &path, value.clone()) {
+            Ok(()) => {
+                let modified_value = match self.transform_for_write(&key, modified_value) {
+                    Ok(value) => value,
+                    Err(e) => return Response::Error(e),
+                };
+                if let Err(e) = self.store(&key, modified_value, false) {
+                    return Response::Error(e);
+                }
+                debug!("QSET: {} at path '{}' = {}", key, path, value);
+                Response::Ok(None)
+            }
+            Err(e) => {
+                error!("QSET error for {} at path '{}': {}", key, path, e);
+                Response::error(
+                    ErrorCode::InvalidArgument,
+                    format!("JSONPath set error: {}", e),
+                )
+            }
+        }
+    }
+
+    /// Merges a JSON value with an existing one
+    async fn merge(&self, key: String, new_value: Value) -> Response {
+        // JSON validation
+        if !self.is_valid_json(&new_value) {
+            return Response::error(ErrorCode::InvalidArgument, "Invalid JSON value");
+        }
+
+        let merged_value = match self.data.get(&key) {
+            Some(existing_value) => {
+                let existing_value = match existing_value.json() {
+                    Ok(value) => value,
+                    Err(e) => return Response::Error(e),
+                };
+                match Self::merge_json_values(&existing_value, &new_value) {
+                    Ok(merged) => merged,
+                    Err(e) => return Response::error(ErrorCode::InvalidArgument, e),
+                }
+            }
+            None => new_value.clone(),
+        };
+
+        let merged_value = match self.transform_for_write(&key, merged_value) {
+            Ok(value) => value,
+            Err(e) => return Response::Error(e),
+        };
+
+        if let Err(e) = self.store(&key, merged_value.clone(), false) {
+            return Response::Error(e);
+        }
+        debug!("MERGE: {} = {}", key, merged_value);
+        Response::Ok(None)
+    }
+
+    /// Returns the JSON type of the value at a JSONPath, plus its length for arrays and objects
+    async fn qtype(&self, key: &str, path: &str) -> Response {
+        let value = match self.data.get(key) {
+            Some(value) => value,
+            None => {
+                debug!("QTYPE: {} not found", key);
+                return Response::error(ErrorCode::KeyNotFound, "Key not found");
+            }
+        };
+
+        let value = match value.json() {
+            Ok(value) => value,
+            Err(e) => return Response::Error(e),
+        };
+        let target = match Self::select_single(&value, path) {
+            Ok(Some(target)) => target,
+            Ok(None) => {
+                return Response::error(
+                    ErrorCode::PathNotFound,
+                    format!("Path '{}' not found", path),
+                )
+            }
+            Err(e) => {
+                return Response::error(
+                    ErrorCode::InvalidQuery,
+                    format!("JSONPath query error: {}", e),
+                )
+            }
+        };
+
+        let mut result = serde_json::Map::new();
+        result.insert("type".to_string(), Value::from(Self::type_name(target)));
+        match target {
+            Value::Array(arr) => {
+                result.insert("length".to_string(), Value::from(arr.len()));
+            }
+            Value::Object(map) => {
+                result.insert("length".to_string(), Value::from(map.len()));
+            }
+            _ => {}
+        }
+
+        debug!("QTYPE: {} at path '{}' = {:?}", key, path, result);
+        Response::Ok(Some(Value::Object(result)))
+    }
+
+    /// Returns the member names of the object at a JSONPath
+    async fn objkeys(&self, key: &str, path: &str) -> Response {
+        let value = match self.data.get(key) {
+            Some(value) => value,
+            None => return Response::error(ErrorCode::KeyNotFound, "Key not found"),
+        };
+
+        let value = match value.json() {
+            Ok(value) => value,
+            Err(e) => return Response::Error(e),
+        };
+        match Self::select_single(&value, path) {
+            Ok(Some(Value::Object(map))) => {
+                let keys: Vec<Value> = map.keys().map(|k| Value::String(k.clone())).collect();
+                debug!("OBJKEYS: {} at path '{}' = {} keys", key, path, keys.len());
+                Response::Ok(Some(Value::Array(keys)))
+            }
+            Ok(Some(other)) => Response::error(
+                ErrorCode::WrongType,
+                format!(
+                    "Value at path '{}' is {}, not an object",
+                    path,
+                    Self::type_name(other)
+                ),
+            ),
+            Ok(None) => Response::error(
+                ErrorCode::PathNotFound,
+                format!("Path '{}' not found", path),
+            ),
+            Err(e) => Response::error(
+                ErrorCode::InvalidQuery,
+                format!("JSONPath query error: {}", e),
+            ),
+        }
+    }
+
+    /// Returns the length of the array at a JSONPath
+    async fn arrlen(&self, key: &str, path: &str) -> Response {
+        let value = match self.data.get(key) {
+            Some(value) => value,
+            None => return Response::error(ErrorCode::KeyNotFound, "Key not found"),
+        };
+
+        let value = match value.json() {
+            Ok(value) => value,
+            Err(e) => return Response::Error(e),
+        };
+        match Self::select_single(&value, path) {
+            Ok(Some(Value::Array(arr))) => {
+                debug!("ARRLEN: {} at path '{}' = {}", key, path, arr.len());
+                Response::Ok(Some(Value::from(arr.len())))
+            }
+            Ok(Some(other)) => Response::error(
+                ErrorCode::WrongType,
+                format!(
+                    "Value at path '{}' is {}, not an array",
+                    path,
+                    Self::type_name(other)
+                ),
+            ),
+            Ok(None) => Response::error(
+                ErrorCode::PathNotFound,
+                format!("Path '{}' not found", path),
+            ),
+            Err(e) => Response::error(
+                ErrorCode::InvalidQuery,
+                format!("JSONPath query error: {}", e),
+            ),
+        }
+    }
+
+    /// Describes how a command would be executed without running it
+    async fn explain(&self, command: Command) -> Response {
+        let name = command.name();
+        let (strategy, keys, query) = match &command {
+            Command::QGet { key, query } => ("point_lookup", vec![key.clone()], Some(query)),
+            Command::QScan {
+                key_pattern, query, ..
+            } => ("pattern_scan", self.matching_keys(key_pattern), Some(query)),
+            Command::Aggregate {
+                key_pattern, path, ..
+            } => ("pattern_scan", self.matching_keys(key_pattern), Some(path)),
+            Command::Digest { key: None } => ("full_scan", self.matching_keys("*"), None),
+            Command::Export { pattern } => ("pattern_scan", self.matching_keys(pattern), None),
+            Command::Eval { keys, .. } => ("script", keys.clone(), None),
+            Command::Ping
+            | Command::Usage
+            | Command::Info
+            | Command::Flush { .. }
+            | Command::RandomKey
+            | Command::Sample { .. }
+            | Command::Tombstones { .. }
+            | Command::Changes { .. }
+            | Command::GroupRead { .. }
+            | Command::GroupAck { .. }
+            | Command::RoutingTable
+            | Command::Stats
+            | Command::ShardStats
+            | Command::Commands
+            | Command::SubscribeEvents { .. }
+            | Command::Changefeed { .. }
+            | Command::Publish { .. }
+            | Command::Subscribe { .. }
+            | Command::Hello { .. }
+            | Command::Auth { .. }
+            | Command::Select { .. }
+            | Command::ClientSetName { .. }
+            | Command::ClientList
+            | Command::ClientKill { .. }
+            | Command::Explain { .. }
+            | Command::Stream { .. }
+            | Command::Profile { .. }
+            | Command::InjectLatency { .. }
+            | Command::ReadOnly { .. }
+            | Command::Save { .. }
+            | Command::VerifyBackup { .. }
+            | Command::AofFetch { .. }
+            | Command::SnapshotFetch { .. }
+            | Command::Promote
+            | Command::AclSet { .. }
+            | Command::AclDel { .. }
+            | Command::AclList
+            | Command::ConfigGet { .. }
+            | Command::ConfigSet { .. }
+            | Command::Audit { .. }
+            | Command::Import { .. }
+            | Command::BulkLoad { .. } => ("none", Vec::new(), None),
+            other => (
+                "point_lookup",
+                other.key().map(|k| vec![k.to_string()]).unwrap_or_default(),
+                None,
+            ),
+        };
+
+        let keys_examined = keys.len();
+        let document_nodes: usize = keys
+            .iter()
+            .filter_map(|k| Some(Self::count_nodes(self.data.get(k)?.json().ok()?.as_ref())))
+            .sum();
+
+        let mut plan = serde_json::json!({
+            "command": name,
+            "strategy": strategy,
+            "index": Value::Null,
+            "keys_examined": keys_examined,
+            "document_nodes": document_nodes,
+        });
+        if let Command::QScan { key_pattern, .. }
+        | Command::Aggregate { key_pattern, .. }
+        | Command::Export {
+            pattern: key_pattern,
+        } = &command
+        {
+            plan["key_prefix"] = Value::from(glob::literal_prefix(key_pattern));
+        }
+
+        let mut cost = keys_examined as u64;
+        if let Some(query) = query {
+            let analysis = Self::analyze_jsonpath(query);
+            let full_traversal = analysis["recursive_descent"].as_bool().unwrap_or(false)
+                || analysis["filters"].as_u64().unwrap_or(0) > 0
+                || analysis["wildcards"].as_u64().unwrap_or(0) > 0;
+            cost = if full_traversal {
+                document_nodes.max(keys_examined) as u64
+            } else {
+                keys_examined as u64 * analysis["depth"].as_u64().unwrap_or(1).max(1)
+            };
+            plan["query"] = analysis;
+        }
+        plan["estimated_cost"] = Value::from(cost);
+
+        debug!("EXPLAIN: {} = {}", name, plan);
+        Response::Ok(Some(plan))
+    }
+
+    /// Statically analyzes a JSONPath expression
+    fn analyze_jsonpath(query: &str) -> Value {
+        let error = jsonpath_lib::Compiled::compile(query).err();
+        serde_json::json!({
+            "valid": error.is_none(),
+            "error": error,
+            "recursive_descent": query.contains(".."),
+            "filters": query.matches("?(").count(),
+            "wildcards": query.matches('*').count(),
+            "depth": query.matches(['.', '[']).count(),
+        })
+    }
+
+    /// Counts the JSON nodes of a document
+    fn count_nodes(value: &Value) -> usize {
+        match value {
+            Value::Array(arr) => 1 + arr.iter().map(Self::count_nodes).sum::<usize>(),
+            Value::Object(map) => 1 + map.values().map(Self::count_nodes).sum::<usize>(),
+            _ => 1,
+        }
+    }
+
+    /// Computes a stable digest of a single key or of the whole keyspace
+    async fn digest(&self, key: Option<&str>) -> Response {
+        let (digest, keys) = match key {
+            Some(key) => match self
+                .data
+                .get(key)
+                .map(|value| value.json().map(Cow::into_owned))
+            {
+                Some(Ok(value)) => (canonical::digest(&value), 1),
+                Some(Err(e)) => return Response::Error(e),
+                None => return Response::error(ErrorCode::KeyNotFound, "Key not found"),
+            },
+            None => {
+                let mut keys: Vec<String> = self.data.iter().map(|e| e.key().clone()).collect();
+                keys.sort();
+                let mut digest = canonical::new_digest();
+                let mut count = 0usize;
+                for key in keys {
+                    if let Some(value) = self.data.get(&key) {
+                        let value = match value.json() {
+                            Ok(value) => value,
+                            Err(e) => return Response::Error(e),
+                        };
+                        canonical::update_digest(&mut digest, key.as_bytes());
+                        canonical::update_digest(&mut digest, &[0]);
+                        let text = canonical::to_canonical_string(&value);
+                        canonical::update_digest(&mut digest, text.as_bytes());
+                        canonical::update_digest(&mut digest, &[0]);
+                        count += 1;
+                    }
+                }
+                (digest, count)
+            }
+        };
+
+        debug!("DIGEST: {:?} = {:016x}", key, digest);
+        Response::Ok(Some(serde_json::json!({
+            "digest": format!("{:016x}", digest),
+            "keys": keys,
+        })))
+    }
+
+    /// Resolves a JSONPath to the first matching value without cloning the document
+    fn select_single<'a>(value: &'a Value, path: &str) -> Result<Option<&'a Value>, String> {
+        let trimmed = path.trim();
+        if trimmed.is_empty() || trimmed == "$" {
+            return Ok(Some(value));
+        }
+        let query = if trimmed.starts_with('$') {
+            trimmed.to_string()
+        } else {
+            format!("$.{}", trimmed)
+        };
+        jsonpath_lib::select(value, &query)
+            .map(|matches| matches.into_iter().next())
+            .map_err(|e| e.to_string())
+    }
+
+    /// Returns the JSON type name of a value
+    fn type_name(value: &Value) -> &'static str {
+        match value {
+            Value::Null => "null",
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Object(_) => "object",
+        }
+    }
+
+    /// Appends a value to the array at a JSONPath, creating the array if needed
+    async fn qappend(&self, key: String, path: String, value: Value) -> Response {
+        let response = self.mutate_array(&key, &path, true, |arr| {
+            arr.push(value);
+            Ok(Some(Value::from(arr.len())))
+        });
+        debug!("QAPPEND: {} at path '{}' -> {}", key, path, response);
+        response
+    }
+
+    /// Inserts a value at an index of the array at a JSONPath
+    async fn qinsert(&self, key: String, path: String, index: i64, value: Value) -> Response {
+        let response = self.mutate_array(&key, &path, false, |arr| {
+            let position = Self::array_position(arr.len() + 1, index).ok_or_else(|| {
+                ErrorInfo::new(
+                    ErrorCode::InvalidArgument,
+                    format!("Index {} out of range", index),
+                )
+            })?;
+            arr.insert(position, value);
+            Ok(Some(Value::from(arr.len())))
+        });
+        debug!(
+            "QINSERT: {} at path '{}' index {} -> {}",
+            key, path, index, response
+        );
+        response
+    }
+
+    /// Removes and returns an element (the last one by default) of the array at a JSONPath
+    async fn qpop(&self, key: String, path: String, index: Option<i64>) -> Response {
+        let response = self.mutate_array(&key, &path, false, |arr| {
+            if arr.is_empty() {
+                return Ok(None);
+            }
+            let position =
+                Self::array_position(arr.len(), index.unwrap_or(-1)).ok_or_else(|| {
+                    ErrorInfo::new(
+                        ErrorCode::InvalidArgument,
+                        format!("Index {} out of range", index.unwrap_or(-1)),
+                    )
+                })?;
+            Ok(Some(arr.remove(position)))
+        });
+        debug!("QPOP: {} at path '{}' -> {}", key, path, response);
+        response
+    }
+
+    /// Converts a possibly negative index into a position within `len` elements
+    fn array_position(len: usize, index: i64) -> Option<usize> {
+        let position = if index < 0 { len as i64 + index } else { index };
+        if position >= 0 && (position as usize) < len {
+            Some(position as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Atomically applies an operation to the array at a JSONPath of a
+    /// document; nothing is written if the array is left unchanged
+    fn mutate_array<F>(&self, key: &str, path: &str, create: bool, op: F) -> Response
+    where
+        F: FnOnce(&mut Vec<Value>) -> Result<Option<Value>, ErrorInfo>,
+    {
+        let parts = Self::path_parts(path);
+        if let Err(e) = self.make_room(0) {
+            return Response::Error(e);
+        }
+        let _write = self.begin_write();
+        // Counted before locking the entry: len() locks every shard
+        let key_count = self.data.len();
+        self.probe_shard(key, true);
+        let entry = self.data.entry(key.to_string());
+        if let Err(e) = self.check_not_binary(key) {
+            return Response::Error(e);
+        }
+        let result = match entry {
+            Entry::Occupied(mut entry) => {
+                let old_size = Self::document_size(key, entry.get());
+                // The operation runs on a copy, swapped in only once every
+                // check passed: a failure leaves the document unchanged
+                let old = match entry.get().shared() {
+                    Ok(old) => old,
+                    Err(e) => return Response::Error(e),
+                };
+                let mut document = Value::clone(&old);
+                let result = match Self::apply_array_op(&mut document, &parts, create, op) {
+                    // Such as a QPOP of an empty array: no write, no new version
+                    Ok((value, false)) => return Response::Ok(value),
+                    result => result.map(|(value, _)| value),
+                };
+                if result.is_ok() {
+                    if self.has_write_transforms() {
+                        document = match self.transform_for_write(key, document) {
+                            Ok(transformed) => transformed,
+                            Err(e) => return Response::Error(e),
+                        };
+                    }
+                    if let Err(e) = self.fire_triggers(key, Some(&old), &mut document) {
+                        return Response::Error(e);
+                    }
+                    let meta = self.next_meta(key);
+                    self.stamp(&meta, &mut document);
+                    let new_size = Self::entry_size(key, &document);
+                    if let Err(e) = self.check_value_size(key, new_size) {
+                        return Response::Error(e);
+                    }
+                    if let Err(e) = self.check_quota(Some(old_size), new_size, key_count) {
+                        return Response::Error(e);
+                    }
+                    let document = Arc::new(document);
+                    if let Err(e) = self.touch(key, meta, &document) {
+                        return Response::Error(e);
+                    }
+                    entry.insert(self.document(document));
+                    self.account(Some(old_size), new_size);
+                }
+                result
+            }
+            Entry::Vacant(entry) => {
+                if !create {
+                    return Response::error(ErrorCode::KeyNotFound, "Key not found");
+                }
+                let mut document = if parts.is_empty() {
+                    Value::Array(Vec::new())
+                } else {
+                    Value::Object(serde_json::Map::new())
+                };
+                let result =
+                    Self::apply_array_op(&mut document, &parts, create, op).map(|(value, _)| value);
+                if result.is_ok() {
+                    let mut transformed = match self.transform_for_write(key, document) {
+                        Ok(transformed) => transformed,
+                        Err(e) => return Response::Error(e),
+                    };
+                    if let Err(e) = self.fire_triggers(key, None, &mut transformed) {
+                        return Response::Error(e);
+                    }
+                    let meta = self.next_meta(key);
+                    self.stamp(&meta, &mut transformed);
+                    let new_size = Self::entry_size(key, &transformed);
+                    if let Err(e) = self.check_value_size(key, new_size) {
+                        return Response::Error(e);
+                    }
+                    if let Err(e) = self.check_quota(None, new_size, key_count) {
+                        return Response::Error(e);
+                    }
+                    let transformed = Arc::new(transformed);
+                    if let Err(e) = self.touch(key, meta, &transformed) {
+                        return Response::Error(e);
+                    }
+                    entry.insert(self.document(transformed));
+                    self.account(None, new_size);
+                }
+                result
+            }
+        };
+
+        match result {
+            Ok(value) => {
+                self.bump_version();
+                Response::Ok(value)
+            }
+            Err(e) => Response::Error(e),
+        }
+    }
+
+    /// Applies an operation to the array found at the given path parts,
+    /// returning its result and whether the array was created or resized
+    fn apply_array_op<F>(
+        document: &mut Value,
+        parts: &[&str],
+        create: bool,
+        op: F,
+    ) -> Result<(Option<Value>, bool), ErrorInfo>
+    where
+        F: FnOnce(&mut Vec<Value>) -> Result<Option<Value>, ErrorInfo>,
+    {
+        let created = create && Self::get_nested_mut(document, parts).is_none();
+        if created {
+            Self::set_nested_value(document, parts, Value::Array(Vec::new()))
+                .map_err(|e| ErrorInfo::new(ErrorCode::WrongType, e))?;
+        }
+        match Self::get_nested_mut(document, parts) {
+            Some(Value::Array(arr)) => {
+                let len = arr.len();
+                let value = op(arr)?;
+                Ok((value, created || arr.len() != len))
+            }
+            Some(other) => Err(ErrorInfo::new(
+                ErrorCode::WrongType,
+                format!(
+                    "Value at path '{}' is {}, not an array",
+                    parts.join("."),
+                    Self::type_name(other)
+                ),
+            )),
+            None => Err(ErrorInfo::new(
+                ErrorCode::PathNotFound,
+                format!("Path '{}' not found", parts.join(".")),
+            )),
+        }
+    }
+
+    /// Returns a mutable reference to the value at the given path parts
+    pub(crate) fn get_nested_mut<'a>(
+        value: &'a mut Value,
+        parts: &[&str],
+    ) -> Option<&'a mut Value> {
+        let mut current = value;
+        for part in parts {
+            current = match current {
+                Value::Array(arr) => arr.get_mut(part.parse::<usize>().ok()?)?,
+                Value::Object(map) => map.get_mut(*part)?,
+                _ => return None,
+            };
+        }
+        Some(current)
+    }
+
+    /// Splits a simplified JSONPath (`$.a.b.0`) into its parts
+    pub(crate) fn path_parts(path: &str) -> Vec<&str> {
+        let path = path.trim_start_matches('$').trim_start_matches('.');
+        if path.is_empty() {
+            Vec::new()
+        } else {
+            path.split('.').collect()
+        }
+    }
+
+    /// Sets a value at a JSONPath location
+    fn set_json_path(&self, value: &mut Value, path: &str, new_value: Value) -> Result<(), String> {
+        // Parse the JSONPath - simplified implementation for basic paths
+        let parts = Self::path_parts(path);
+
+        if parts.is_empty() {
+            // Root path, replace entire value
+            *value = new_value;
+            return Ok(());
+        }
+
+        Self::set_nested_value(value, &parts, new_value)
+    }
+
+    /// Recursively sets a nested value
+    pub(crate) fn set_nested_value(
+        value: &mut Value,
+        path_parts: &[&str],
+        new_value: Value,
+    ) -> Result<(), String> {
+        if path_parts.is_empty() {
+            *value = new_value;
+            return Ok(());
+        }
+
+        let current_key = path_parts[0];
+        let remaining_parts = &path_parts[1..];
+
+        // Handle array index access
+        if let Ok(index) = current_key.parse::<usize>() {
+            match value {
+                Value::Array(arr) => {
+                    // Extend array if necessary
+                    while arr.len() <= index {
+                        arr.push(Value::Null);
+                    }
+
+                    if remaining_parts.is_empty() {
+                        arr[index] = new_value;
+                    } else {
+                        Self::set_nested_value(&mut arr[index], remaining_parts, new_value)?;
+                    }
+                    return Ok(());
+                }
+                _ => {
+                    return Err(format!(
+                        "Cannot index non-array value with '{}'",
+                        current_key
+                    ));
+                }
+            }
+        }
+
+        // Handle object property access
+        match value {
+            Value::Object(map) => {
+                if remaining_parts.is_empty() {
+                    map.insert(current_key.to_string(), new_value);
+                } else {
+                    // Create nested object if it doesn't exist
+                    if !map.contains_key(current_key) {
+                        map.insert(
+                            current_key.to_string(),
+                            Value::Object(serde_json::Map::new()),
+                        );
+                    }
+
+                    let nested_value = map.get_mut(current_key).unwrap();
+                    Self::set_nested_value(nested_value, remaining_parts, new_value)?;
+                }
+                Ok(())
+            }
+            Value::Null => {
+                // Create new object if current value is null
+                let mut new_obj = serde_json::Map::new();
+                if remaining_parts.is_empty() {
+                    new_obj.insert(current_key.to_string(), new_value);
+                } else {
+                    let mut nested = Value::Object(serde_json::Map::new());
+                    Self::set_nested_value(&mut nested, remaining_parts, new_value)?;
+                    new_obj.insert(current_key.to_string(), nested);
+                }
+                *value = Value::Object(new_obj);
+                Ok(())
+            }
+            _ => Err(format!(
+                "Cannot access property '{}' on non-object value",
+                current_key
+            )),
+        }
+    }
+
+    /// Validates if a value is valid JSON
+    fn is_valid_json(&self, value: &Value) -> bool {
+        // serde_json::Value values are always valid JSON
+        // We only check that they are not unintentional nulls
+        match value {
+            Value::Null => true, // Explicit null is valid
+            _ => true,
+        }
+    }
+
+    /// Merges two JSON values
+    fn merge_json_values(existing: &Value, new: &Value) -> Result<Value, String> {
+        match (existing, new) {
+            (Value::Object(existing_obj), Value::Object(new_obj)) => {
+                let mut merged = existing_obj.clone();
+                for (key, value) in new_obj {
+                    if let Some(existing_value) = merged.get(key) {
+                        // Recursive merge for nested objects
+                        merged.insert(key.clone(), Self::merge_json_values(existing_value, value)?);
+                    } else {
+                        merged.insert(key.clone(), value.clone());
+                    }
+                }
+                Ok(Value::Object(merged))
+            }
+            (Value::Array(existing_arr), Value::Array(new_arr)) => {
+                let mut merged = existing_arr.clone();
+                merged.extend(new_arr.clone());
+                Ok(Value::Array(merged))
+            }
+            _ => {
+                // For incompatible types, the new value replaces the existing one
+                Ok(new.clone())
+            }
+        }
+    }
+
+    /// Returns the value of a key
+    pub fn value(&self, key: &str) -> Option<Value> {
+        self.shared_value(key).map(|value| Value::clone(&value))
+    }
+
+    /// Returns the value of a key without copying it: the value is shared with
+    /// the keyspace until the key is written again (compressed documents are
+    /// decompressed)
+    pub fn shared_value(&self, key: &str) -> Option<Arc<Value>> {
+        self.try_shared_value(key).unwrap_or_else(|e| {
+            error!("{}", e.message);
+            None
+        })
+    }
+
+    /// Returns the value of a key like [`Database::shared_value`], failing if
+    /// a spilled document cannot be read back
+    pub(crate) fn try_shared_value(&self, key: &str) -> Result<Option<Arc<Value>>, ErrorInfo> {
+        self.data
+            .get(key)
+            .map(|document| document.shared())
+            .transpose()
+    }
+
+    /// Gets the number of keys in the database
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Checks if the database is empty
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Gets the number of keys together with the keyspace version it was
+    /// observed at.
+    ///
+    /// The keys are counted between writes, without stopping them: the count
+    /// is retried until no write was in progress and the version stayed the
+    /// same. Under a steady stream of writes, it waits for a gap between them.
+    pub fn len_with_version(&self) -> (usize, u64) {
+        {
+            // Shared, so that only the writes holding the gate exclusively wait
+            let _read = self.write_gate.read().unwrap();
+            for _ in 0..COUNT_ATTEMPTS {
+                let version = self.keyspace_version();
+                if self.writes_in_progress.load(Ordering::SeqCst) == 0 {
+                    let len = self.data.len();
+                    if self.writes_in_progress.load(Ordering::SeqCst) == 0
+                        && self.keyspace_version() == version
+                    {
+                        return (len, version);
+                    }
+                }
+                std::thread::yield_now();
+            }
+        }
+        let _read = self.write_gate.write().unwrap();
+        (self.data.len(), self.keyspace_version())
+    }
+
+    /// Returns a copy of every key-value pair together with the keyspace
+    /// version the copy is consistent with
+    pub fn get_all_data(&self) -> (HashMap<String, Value>, u64) {
+        let _read = self.write_gate.write().unwrap();
+        let data = self
+            .data
+            .iter()
+            .filter_map(|entry| match entry.json() {
+                Ok(value) => Some((entry.key().clone(), value.into_owned())),
+                Err(e) => {
+                    error!("{}", e.message);
+                    None
+                }
+            })
+            .collect();
+        (data, self.keyspace_version())
+    }
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Maximum number of consecutive batches of a single active expiry cycle
+const MAX_EXPIRY_BATCHES_PER_CYCLE: usize = 16;
+
+/// Periodically delete expired keys that are never accessed again.
+///
+/// Every `interval` up to `batch` expired keys are deleted per namespace;
+/// while batches come back full, more follow right away (within a bound).
+pub fn spawn_active_expiry(database: Arc<Database>, interval: Duration, batch: usize) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            for _ in 0..MAX_EXPIRY_BATCHES_PER_CYCLE {
+                let expired = database.expire_keys(batch);
+                if expired > 0 {
+                    debug!("Active expiry deleted {} keys", expired);
+                }
+                if expired < batch {
+                    break;
+                }
+                tokio::task::yield_now().await;
+            }
+        }
+    });
+}
+
+/// Periodically drop the tombstones older than the retention of the database
+pub fn spawn_tombstone_purger(database: Arc<Database>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let purged = database.purge_tombstones();
+            if purged > 0 {
+                debug!("Purged {} expired tombstones", purged);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_set_and_get() {
+        let db = Database::new();
+        let value = json!({"name": "test", "value": 42});
+
+        let response = db.set("test_key".to_string(), value.clone()).await;
+        assert!(matches!(response, Response::Ok(None)));
+
+        let response = db.get("test_key").await;
+        assert!(matches!(response, Response::Ok(Some(v)) if v == value));
+    }
+
+    #[tokio::test]
+    async fn test_delete() {
+        let db = Database::new();
+        let value = json!({"test": true});
+
+        db.set("test_key".to_string(), value).await;
+        let response = db.delete("test_key".to_string()).await;
+        assert!(matches!(response, Response::Ok(None)));
+
+        let response = db.get("test_key").await;
+        assert!(matches!(response, Response::Ok(None)));
+    }
+
+    #[tokio::test]
+    async fn test_qget_jsonpath() {
+        let db = Database::new();
+        let value = json!({"user": {"name": "Alice", "age": 30}});
+
+        db.set("test_key".to_string(), value).await;
+
+        let response = db.qget("test_key", "$.user.name").await;
+        if let Response::Ok(Some(result)) = response {
+            assert_eq!(result, json!("Alice"));
+        } else {
+            panic!("Expected JSONPath query result");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_qset_nested_property() {
+        let db = Database::new();
+        let initial = json!({"user": {"name": "Alice"}});
+
+        db.set("test_key".to_string(), initial).await;
+
+        let response = db
+            .qset("test_key".to_string(), "user.age".to_string(), json!(25))
+            .await;
+        assert!(matches!(response, Response::Ok(None)));
+
+        let response = db.get("test_key").await;
+        if let Response::Ok(Some(result)) = response {
+            let expected = json!({"user": {"name": "Alice", "age": 25}});
+            assert_eq!(result, expected);
+        } else {
+            panic!("Expected result after QSET");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_qset_create_new_key() {
+        let db = Database::new();
+
+        let response = db
+            .qset(
+                "new_key".to_string(),
+                "config.timeout".to_string(),
+                json!(5000),
+            )
+            .await;
+        assert!(matches!(response, Response::Ok(None)));
+
+        let response = db.get("new_key").await;
+        if let Response::Ok(Some(result)) = response {
+            let expected = json!({"config": {"timeout": 5000}});
+            assert_eq!(result, expected);
+        } else {
+            panic!("Expected result after QSET on new key");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_qtype() {
+        let db = Database::new();
+        let value = json!({"user": {"name": "Alice", "tags": ["a", "b", "c"]}});
+        db.set("test_key".to_string(), value).await;
+
+        let response = db.qtype("test_key", "$.user").await;
+        assert!(
+            matches!(response, Response::Ok(Some(v)) if v == json!({"type": "object", "length": 2}))
+        );
+
+        let response = db.qtype("test_key", "user.tags").await;
+        assert!(
+            matches!(response, Response::Ok(Some(v)) if v == json!({"type": "array", "length": 3}))
+        );
+
+        let response = db.qtype("test_key", "$.user.name").await;
+        assert!(matches!(response, Response::Ok(Some(v)) if v == json!({"type": "string"})));
+
+        let response = db.qtype("test_key", "$.missing").await;
+        assert!(matches!(response, Response::Error(_)));
+    }
+
+    #[tokio::test]
+    async fn test_write_pipeline() {
+        use crate::transform::{Transform, TransformRule};
+
+        let db = Database::new();
+        db.set_write_pipeline(WritePipeline::new(vec![TransformRule {
+            prefix: "user:".to_string(),
+            transforms: vec![
+                Transform::StripNulls,
+                Transform::Lowercase {
+                    paths: vec!["$.email".to_string()],
+                },
+            ],
+        }]));
+
+        let value = json!({"email": "Alice@Example.COM", "phone": null});
+        db.set("user:1".to_string(), value.clone()).await;
+        db.set("other".to_string(), value.clone()).await;
+
+        let response = db.get("user:1").await;
+        assert!(
+            matches!(response, Response::Ok(Some(v)) if v == json!({"email": "alice@example.com"}))
+        );
+
+        let response = db.get("other").await;
+        assert!(matches!(response, Response::Ok(Some(v)) if v == value));
+    }
+
+    #[tokio::test]
+    async fn test_write_triggers() {
+        use crate::triggers::{Trigger, TriggerAction, TriggerCondition};
+
+        let clock = Arc::new(clock::ManualClock::new());
+        let db = Database::new().with_clock(clock.clone());
+        let closed_at = clock.unix_millis();
+        db.set_triggers(TriggerSet::new(vec![Trigger {
+            prefix: "ticket:".to_string(),
+            when: TriggerCondition::Becomes {
+                path: "$.status".to_string(),
+                value: json!("closed"),
+            },
+            then: vec![TriggerAction::SetNow {
+                path: "$.closed_at".to_string(),
+            }],
+        }]));
+
+        db.set("ticket:1".to_string(), json!({"status": "open"}))
+            .await;
+        assert!(
+            matches!(db.get("ticket:1").await, Response::Ok(Some(v)) if v == json!({"status": "open"}))
+        );
+
+        db.merge("ticket:1".to_string(), json!({"status": "closed"}))
+            .await;
+        let closed = json!({"status": "closed", "closed_at": closed_at});
+        assert!(matches!(db.get("ticket:1").await, Response::Ok(Some(v)) if v == closed));
+
+        // The trigger fires only when the status changes to closed
+        clock.advance(Duration::from_secs(5));
+        db.merge("ticket:1".to_string(), json!({"note": "done"}))
+            .await;
+        assert!(
+            matches!(db.get("ticket:1").await, Response::Ok(Some(v)) if v["closed_at"] == closed_at)
+        );
+    }
+
+    #[cfg(feature = "scripting")]
+    #[tokio::test]
+    async fn test_eval_script() {
+        // Deletes `a` and sets `b` to 2, whatever its input
+        const SCRIPT: &str = r#"
+            (module
+              (memory (export "memory") 1)
+              (data (i32.const 0) "{\"result\":\"done\",\"writes\":{\"a\":null,\"b\":2}}")
+              (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+              (func (export "eval") (param i32 i32) (result i64) (i64.const 43)))
+        "#;
+        let db = Database::new();
+        db.load_script("swap", SCRIPT.as_bytes()).unwrap();
+        db.set("a".to_string(), json!(1)).await;
+
+        let eval = |keys: &[&str]| Command::Eval {
+            script: "swap".to_string(),
+            keys: keys.iter().map(|k| k.to_string()).collect(),
+            args: vec![json!(1), json!(2)],
+        };
+        // Writes to undeclared keys are rejected before anything is written
+        let response = db.execute_command(eval(&["a"])).await;
+        assert!(matches!(response, Response::Error(e) if e.code == ErrorCode::InvalidArgument));
+        assert!(matches!(db.get("a").await, Response::Ok(Some(_))));
+
+        // So are all the writes when one of them exceeds the quota
+        db.set_quota(
+            DEFAULT_NAMESPACE,
+            Quota {
+                max_keys: Some(1),
+                max_bytes: None,
+            },
+        );
+        let response = db.execute_command(eval(&["a", "b"])).await;
+        assert!(matches!(response, Response::Error(e) if e.code == ErrorCode::QuotaExceeded));
+        assert!(matches!(db.get("a").await, Response::Ok(Some(_))));
+        db.set_quota(DEFAULT_NAMESPACE, Quota::default());
+
+        let response = db.execute_command(eval(&["a", "b"])).await;
+        assert!(matches!(response, Response::Ok(Some(v)) if v == json!("done")));
+        assert!(matches!(db.get("a").await, Response::Ok(None)));
+        assert!(matches!(db.get("b").await, Response::Ok(Some(v)) if v == json!(2)));
+    }
+
+    #[tokio::test]
+    async fn test_binary_values() {
+        let db = Database::new();
+        let png = vec![0x89, b'P', b'N', b'G', 0, 0xff];
+        let set_bytes = |key: &str, value: Vec<u8>| Command::SetBytes {
+            key: key.to_string(),
+            value,
+        };
+        let get_bytes = |key: &str| Command::GetBytes {
+            key: key.to_string(),
+        };
+
+        db.execute_command(set_bytes("thumb", png.clone())).await;
+        assert!(
+            matches!(db.execute_command(get_bytes("thumb")).await, Response::Bytes(b) if b == png)
+        );
+        assert_eq!(db.bytes.load(Ordering::Acquire), (5 + png.len()) as u64);
+
+        // JSON and binary values do not replace each other
+        db.set("doc".to_string(), json!({})).await;
+        let wrong_type =
+            |response| matches!(response, Response::Error(e) if e.code == ErrorCode::WrongType);
+        assert!(wrong_type(
+            db.execute_command(set_bytes("doc", vec![1])).await
+        ));
+        assert!(wrong_type(db.execute_command(get_bytes("doc")).await));
+        assert!(wrong_type(db.get("thumb").await));
+        assert!(wrong_type(db.set("thumb".to_string(), json!(1)).await));
+
+        db.delete("thumb".to_string()).await;
+        assert!(matches!(
+            db.execute_command(get_bytes("thumb")).await,
+            Response::Ok(None)
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_racing_json_and_binary_writes() {
+        let db = Database::new();
+        for i in 0..200 {
+            let key = format!("k{}", i);
+            let json = tokio::spawn({
+                let (db, key) = (db.clone(), key.clone());
+                async move { db.set(key, json!(1)).await }
+            });
+            let binary = tokio::spawn({
+                let (db, key) = (db.clone(), key.clone());
+                async move {
+                    db.execute_command(Command::SetBytes {
+                        key,
+                        value: vec![1],
+                    })
+                    .await
+                }
+            });
+            let stored = |response| matches!(response, Response::Ok(None));
+            // Exactly one of them gets the key
+            let json_won = stored(json.await.unwrap());
+            let binary_won = stored(binary.await.unwrap());
+            assert_ne!(json_won, binary_won);
+            assert_eq!(db.data.contains_key(&key), json_won);
+            assert_eq!(db.blobs.contains_key(&key), binary_won);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shared_values() {
+        let db = Database::new();
+        db.set_history_depth(2);
+        db.set("doc".to_string(), json!({"tags": ["a"]})).await;
+
+        // Reads share the stored document instead of copying it
+        let first = db.shared_value("doc").unwrap();
+        assert!(Arc::ptr_eq(&first, &db.shared_value("doc").unwrap()));
+
+        // Writes leave the values handed out to readers untouched
+        db.qappend("doc".to_string(), "$.tags".to_string(), json!("b"))
+            .await;
+        assert_eq!(*first, json!({"tags": ["a"]}));
+        assert_eq!(db.value("doc"), Some(json!({"tags": ["a", "b"]})));
+        let history = db.history.get("doc").unwrap();
+        assert!(Arc::ptr_eq(&history[0].value, &first));
+        assert!(Arc::ptr_eq(
+            &history[1].value,
+            &db.shared_value("doc").unwrap()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_serialized_get() {
+        let db = Database::new();
+        db.set("doc".to_string(), json!({"tags": ["a"]})).await;
+        let first = db.get_serialized("doc").unwrap();
+        assert_eq!(&first[..], br#"{"tags":["a"]}"#);
+        // The serialization is cached until the document is modified
+        assert_eq!(first.as_ptr(), db.get_serialized("doc").unwrap().as_ptr());
+
+        db.qappend("doc".to_string(), "$.tags".to_string(), json!("b"))
+            .await;
+        assert_eq!(
+            &db.get_serialized("doc").unwrap()[..],
+            br#"{"tags":["a","b"]}"#
+        );
+        assert!(db.get_serialized("missing").is_none());
+
+        // Compressed documents are served decompressed
+        db.set_compression_threshold(1);
+        let large = json!({ "text": "x".repeat(1000) });
+        db.set("large".to_string(), large.clone()).await;
+        let serialized = db.get_serialized("large").unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&serialized).unwrap(), large);
+    }
+
+    #[tokio::test]
+    async fn test_compressed_documents() {
+        let db = Database::new();
+        db.set_compression_threshold(256);
+        let items: Vec<Value> = (0..100).map(|i| json!({"id": i, "tag": "same"})).collect();
+        let large = json!({ "items": items });
+        db.set("large".to_string(), large.clone()).await;
+        db.set("small".to_string(), json!({"id": 1})).await;
+        assert!(db.data.get("large").unwrap().compressed_size().is_some());
+        assert!(db.data.get("small").unwrap().compressed_size().is_none());
+
+        // Reads, queries and accounting see the uncompressed document
+        assert!(matches!(db.get("large").await, Response::Ok(Some(v)) if v == large));
+        assert!(matches!(
+            db.qget("large", "$.items[99].id").await,
+            Response::Ok(Some(v)) if v == json!(99)
+        ));
+        let size = Database::entry_size("large", &large)
+            + Database::entry_size("small", &json!({"id": 1}));
+        assert_eq!(db.bytes.load(Ordering::Acquire), size);
+
+        // In-place array updates keep the document compressed
+        let response = db
+            .qappend(
+                "large".to_string(),
+                "$.items".to_string(),
+                json!({"id": 100, "tag": "same"}),
+            )
+            .await;
+        assert!(matches!(response, Response::Ok(Some(n)) if n == json!(101)));
+        assert!(db.data.get("large").unwrap().compressed_size().is_some());
+        assert!(matches!(
+            db.qget("large", "$.items[100].id").await,
+            Response::Ok(Some(v)) if v == json!(100)
+        ));
+
+        db.delete("large".to_string()).await;
+        assert_eq!(
+            db.bytes.load(Ordering::Acquire),
+            Database::entry_size("small", &json!({"id": 1}))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_canonical_json_and_digest() {
+        let db = Database::new();
+        db.set_canonical_json(true);
+        db.set("a".to_string(), json!({"n": 1.0})).await;
+
+        let response = db.get("a").await;
+        if let Response::Ok(Some(result)) = response {
+            assert_eq!(result.to_string(), r#"{"n":1}"#);
+        } else {
+            panic!("Expected canonical value");
+        }
+
+        let other = Database::new();
+        other.set("a".to_string(), json!({"n": 1})).await;
+
+        let left = db.digest(None).await;
+        let right = other.digest(None).await;
+        assert_eq!(left.to_string(), right.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_objkeys_and_arrlen() {
+        let db = Database::new();
+        let value = json!({"user": {"name": "Alice", "tags": ["a", "b"]}});
+        db.set("test_key".to_string(), value).await;
+
+        let response = db.objkeys("test_key", "$.user").await;
+        assert!(matches!(response, Response::Ok(Some(v)) if v == json!(["name", "tags"])));
+
+        let response = db.arrlen("test_key", "$.user.tags").await;
+        assert!(matches!(response, Response::Ok(Some(v)) if v == json!(2)));
+
+        let response = db.arrlen("test_key", "$.user").await;
+        assert!(matches!(response, Response::Error(_)));
+    }
+
+    #[tokio::test]
+    async fn test_array_manipulation() {
+        let db = Database::new();
+
+        let response = db
+            .qappend("log".to_string(), "events".to_string(), json!("a"))
+            .await;
+        assert!(matches!(response, Response::Ok(Some(v)) if v == json!(1)));
+        db.qappend("log".to_string(), "$.events".to_string(), json!("c"))
+            .await;
+
+        let response = db
+            .qinsert("log".to_string(), "events".to_string(), 1, json!("b"))
+            .await;
+        assert!(matches!(response, Response::Ok(Some(v)) if v == json!(3)));
+
+        let response = db
+            .qpop("log".to_string(), "events".to_string(), Some(0))
+            .await;
+        assert!(matches!(response, Response::Ok(Some(v)) if v == json!("a")));
+
+        let response = db.qpop("log".to_string(), "events".to_string(), None).await;
+        assert!(matches!(response, Response::Ok(Some(v)) if v == json!("c")));
+
+        let response = db.get("log").await;
+        assert!(matches!(response, Response::Ok(Some(v)) if v == json!({"events": ["b"]})));
+
+        let response = db
+            .qinsert("log".to_string(), "events".to_string(), 5, json!("x"))
+            .await;
+        assert!(matches!(response, Response::Error(_)));
+
+        // Popping an empty array writes nothing
+        db.set("empty".to_string(), json!([])).await;
+        let (version, meta) = (db.keyspace_version(), db.meta.get("empty").map(|m| *m));
+        let response = db.qpop("empty".to_string(), "$".to_string(), None).await;
+        assert!(matches!(response, Response::Ok(None)));
+        assert_eq!(db.keyspace_version(), version);
+        assert_eq!(db.meta.get("empty").map(|m| *m), meta);
+
+        // A failing transformation leaves the document unchanged
+        use crate::transform::{Transform, TransformRule};
+        db.set_write_pipeline(WritePipeline::new(vec![TransformRule {
+            prefix: "log".to_string(),
+            transforms: vec![Transform::RemoveFields {
+                paths: vec!["$[".to_string()],
+            }],
+        }]));
+        let response = db
+            .qappend("log".to_string(), "events".to_string(), json!("y"))
+            .await;
+        assert!(matches!(response, Response::Error(_)));
+        assert_eq!(db.value("log"), Some(json!({"events": ["b"]})));
+    }
+
+    #[tokio::test]
+    async fn test_explain_qget() {
+        let db = Database::new();
+        db.set("doc".to_string(), json!({"items": [{"a": 1}, {"a": 2}]}))
+            .await;
+
+        let response = db
+            .explain(Command::QGet {
+                key: "doc".to_string(),
+                query: "$..a".to_string(),
+            })
+            .await;
+        if let Response::Ok(Some(plan)) = response {
+            assert_eq!(plan["strategy"], json!("point_lookup"));
+            assert_eq!(plan["query"]["valid"], json!(true));
+            assert_eq!(plan["query"]["recursive_descent"], json!(true));
+            assert_eq!(plan["document_nodes"], json!(6));
+            assert_eq!(plan["estimated_cost"], json!(6));
+        } else {
+            panic!("Expected explain plan");
+        }
+
+        // Explaining must not execute the command
+        db.explain(Command::Delete {
+            key: "doc".to_string(),
+        })
+        .await;
+        assert_eq!(db.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_jqget() {
+        let db = Database::new();
+        db.set(
+            "orders".to_string(),
+            json!({"items": [{"price": 10}, {"price": 5}]}),
+        )
+        .await;
+
+        let response = db.jqget("orders", "[.items[].price] | add").await;
+        assert!(matches!(response, Response::Ok(Some(v)) if v == json!(15)));
+
+        let response = db.jqget("orders", ".items[].price").await;
+        assert!(matches!(response, Response::Ok(Some(v)) if v == json!([10, 5])));
+
+        let response = db.jqget("orders", ".items[").await;
+        assert!(matches!(response, Response::Error(e) if e.code == ErrorCode::InvalidQuery));
+    }
+
+    #[test]
+    fn test_shard_configuration() {
+        let db = Database::with_capacity_and_shards(1024, 64).unwrap();
+        assert_eq!(db.shard_count(), 64);
+        assert!(Database::with_capacity_and_shards(1024, 3).is_err());
+
+        let small = Database::with_expected_keys(10);
+        let large = Database::with_expected_keys(100_000_000);
+        assert!(large.shard_count() >= small.shard_count());
+        assert!(large.shard_count().is_power_of_two());
+        assert!(large.shard_count() <= MAX_SHARDS);
+    }
+
+    #[tokio::test]
+    async fn test_qscan() {
+        let db = Database::new();
+        db.set("user:2".to_string(), json!({"name": "Bob"})).await;
+        db.set("user:1".to_string(), json!({"name": "Alice"})).await;
+        db.set("user:3".to_string(), json!({"age": 40})).await;
+        db.set("order:1".to_string(), json!({"name": "Order"}))
+            .await;
+
+        let response = db.qscan("user:*", "$.name", None).await;
+        assert!(matches!(response, Response::Ok(Some(v)) if v == json!([
+            {"key": "user:1", "result": "Alice"},
+            {"key": "user:2", "result": "Bob"},
+        ])));
+
+        let response = db.qscan("user:*", "$.name", Some(1)).await;
+        assert!(matches!(response, Response::Ok(Some(Value::Array(v))) if v.len() == 1));
+    }
+
+    #[tokio::test]
+    async fn test_keyspace_version() {
+        let db = Database::new();
+        assert_eq!(db.keyspace_version(), 0);
+        assert!(db.is_empty());
+
+        db.set("a".to_string(), json!(1)).await;
+        db.qappend("b".to_string(), "$".to_string(), json!(1)).await;
+        assert_eq!(db.keyspace_version(), 2);
+
+        // Failed writes don't advance the version
+        db.delete("missing".to_string()).await;
+        db.qpop("missing".to_string(), "$".to_string(), None).await;
+        assert_eq!(db.keyspace_version(), 2);
+
+        let (data, version) = db.get_all_data();
+        assert_eq!(version, 2);
+        assert_eq!(data.get("b"), Some(&json!([1])));
+        assert_eq!(db.len_with_version(), (2, 2));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_get_all_data_is_consistent_under_writes() {
+        let db = Database::new();
+        let writers: Vec<_> = (0..4)
+            .map(|w| {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    for i in 0..250 {
+                        db.set(format!("w{}:{}", w, i), json!(i)).await;
+                    }
+                })
+            })
+            .collect();
+
+        for _ in 0..50 {
+            // Every write here inserts a new key, so the version equals the key count
+            let (data, version) = db.get_all_data();
+            assert_eq!(data.len() as u64, version);
+            let (len, version) = db.len_with_version();
+            assert_eq!(len as u64, version);
+            tokio::task::yield_now().await;
+        }
+
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        assert_eq!(db.len_with_version(), (1000, 1000));
+    }
+
+    #[tokio::test]
+    async fn test_aggregate() {
+        let db = Database::new();
+        db.set("order:1".to_string(), json!({"total": 10})).await;
+        db.set("order:2".to_string(), json!({"total": 25.5})).await;
+        db.set("order:3".to_string(), json!({"total": "n/a"})).await;
+        db.set("user:1".to_string(), json!({"total": 1000})).await;
+
+        let cases = [
+            (AggregateOp::Count, json!(2)),
+            (AggregateOp::Sum, json!(35.5)),
+            (AggregateOp::Avg, json!(17.75)),
+            (AggregateOp::Min, json!(10)),
+            (AggregateOp::Max, json!(25.5)),
+        ];
+        for (op, expected) in cases {
+            let response = db.aggregate("order:*", "$.total", op).await;
+            assert!(
+                matches!(&response, Response::Ok(Some(v)) if *v == expected),
+                "{:?} returned {}",
+                op,
+                response
+            );
+        }
+
+        let response = db.aggregate("none:*", "$.total", AggregateOp::Avg).await;
+        assert!(matches!(response, Response::Ok(Some(Value::Null))));
+    }
+
+    #[tokio::test]
+    async fn test_write_fencing() {
+        let db = Database::new();
+        db.set_write_fenced(true);
+
+        let response = db
+            .execute_command(Command::Set {
+                key: "a".to_string(),
+                value: json!(1),
+            })
+            .await;
+        assert!(
+            matches!(response, Response::Error(e) if e.code == ErrorCode::Unavailable && e.retriable)
+        );
+
+        // Reads are still served
+        let response = db
+            .execute_command(Command::Get {
+                key: "a".to_string(),
+            })
+            .await;
+        assert!(matches!(response, Response::Ok(None)));
     }
 
-    /// Validates if a value is valid JSON
-    fn is_valid_json(&self, value: &Value) -> bool {
-        // serde_json::Value values are always valid JSON
-        // We only check that they are not unintentional nulls
-        match value {
-            Value::Null => true, // Explicit null is valid
-            _ => true,
-        }
+    #[tokio::test]
+    async fn test_read_only_mode() {
+        let db = Database::new();
+        db.set("a".to_string(), json!(1)).await;
+        let read_only = |enabled| Command::ReadOnly { enabled };
+        assert!(read_only(true).is_admin() && !read_only(true).is_write());
+
+        db.execute_command(read_only(true)).await;
+        let response = db
+            .execute_command(Command::Delete {
+                key: "a".to_string(),
+            })
+            .await;
+        assert!(
+            matches!(response, Response::Error(e) if e.code == ErrorCode::ReadOnly && !e.retriable)
+        );
+        let response = db
+            .execute_command(Command::Get {
+                key: "a".to_string(),
+            })
+            .await;
+        assert!(matches!(response, Response::Ok(Some(v)) if v == json!(1)));
+
+        // Switching back at runtime accepts writes again
+        db.execute_command(read_only(false)).await;
+        let response = db
+            .execute_command(Command::Delete {
+                key: "a".to_string(),
+            })
+            .await;
+        assert!(matches!(response, Response::Ok(None)));
     }
 
-    /// Merges two JSON values
-    fn merge_json_values(existing: &Value, new: &Value) -> Result<Value, String> {
-        match (existing, new) {
-            (Value::Object(existing_obj), Value::Object(new_obj)) => {
-                let mut merged = existing_obj.clone();
-                for (key, value) in new_obj {
-                    if let Some(existing_value) = merged.get(key) {
-                        // Recursive merge for nested objects
-                        merged.insert(key.clone(), Self::merge_json_values(existing_value, value)?);
-                    } else {
-                        merged.insert(key.clone(), value.clone());
-                    }
-                }
-                Ok(Value::Object(merged))
-            }
-            (Value::Array(existing_arr), Value::Array(new_arr)) => {
-                let mut merged = existing_arr.clone();
-                merged.extend(new_arr.clone());
-                Ok(Value::Array(merged))
-            }
-            _ => {
-                // For incompatible types, the new value replaces the existing one
-                Ok(new.clone())
-            }
+    #[tokio::test]
+    async fn test_namespaces_are_isolated() {
+        let db = Database::new();
+        let app = db.namespace("app").unwrap();
+        db.set("k".to_string(), json!("default")).await;
+        app.set("k".to_string(), json!("app")).await;
+
+        assert!(matches!(db.get("k").await, Response::Ok(Some(v)) if v == json!("default")));
+        assert!(matches!(app.get("k").await, Response::Ok(Some(v)) if v == json!("app")));
+        assert_eq!(app.keyspace_version(), 1);
+
+        // Handles on the same namespace share the keyspace
+        let again = db.namespace("app").unwrap();
+        assert_eq!(again.len(), 1);
+        assert_eq!(db.namespaces(), vec!["app", "default"]);
+        assert!(db.namespace("bad name").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_namespace_quotas() {
+        let db = Database::new();
+        db.set_quota(
+            "small",
+            Quota {
+                max_keys: Some(2),
+                max_bytes: Some(64),
+            },
+        );
+        let small = db.namespace("small").unwrap();
+
+        assert!(matches!(
+            small.set("a".to_string(), json!(1)).await,
+            Response::Ok(None)
+        ));
+        assert!(matches!(
+            small.set("b".to_string(), json!(2)).await,
+            Response::Ok(None)
+        ));
+        let response = small.set("c".to_string(), json!(3)).await;
+        assert!(matches!(response, Response::Error(e) if e.code == ErrorCode::QuotaExceeded));
+
+        // Replacing an existing key doesn't count against max_keys, but against max_bytes
+        let response = small.set("a".to_string(), json!("x".repeat(100))).await;
+        assert!(matches!(response, Response::Error(e) if e.code == ErrorCode::QuotaExceeded));
+        small.set("b".to_string(), json!([])).await;
+        let response = small
+            .qappend("b".to_string(), "$".to_string(), json!("x".repeat(100)))
+            .await;
+        assert!(matches!(response, Response::Error(e) if e.code == ErrorCode::QuotaExceeded));
+        assert!(matches!(small.get("b").await, Response::Ok(Some(v)) if v == json!([])));
+
+        small.delete("b".to_string()).await;
+        if let Response::Ok(Some(usage)) = small.usage().await {
+            assert_eq!(usage["keys"], json!(1));
+            assert_eq!(usage["bytes"], json!(2));
+            assert_eq!(usage["max_keys"], json!(2));
+        } else {
+            panic!("USAGE failed");
         }
+
+        // Other namespaces are not limited
+        db.set("c".to_string(), json!("x".repeat(100))).await;
+        assert_eq!(db.len(), 1);
     }
 
-    /// Gets the number of keys in the database
-    pub fn len(&self) -> usize {
-        self.data.len()
+    #[tokio::test]
+    async fn test_memory_usage() {
+        let db = Database::new();
+        db.set("small".to_string(), json!({"a": 1})).await;
+        db.set("large".to_string(), json!({"a": "x".repeat(1000)}))
+            .await;
+
+        let usage = |response: Response| match response {
+            Response::Ok(Some(v)) => v,
+            other => panic!("unexpected response {}", other),
+        };
+        let small = usage(db.memory_usage("small").await);
+        let large = usage(db.memory_usage("large").await);
+        assert_eq!(small["serialized_bytes"], json!(7));
+        assert!(large["memory_bytes"].as_u64() > Some(1000));
+        assert!(small["memory_bytes"].as_u64() < large["memory_bytes"].as_u64());
+
+        let response = db.memory_usage("missing").await;
+        assert!(matches!(response, Response::Error(e) if e.code == ErrorCode::KeyNotFound));
     }
 
-    /// Checks if the database is empty
-    pub fn is_empty(&self) -> bool {
-        self.data.is_empty()
+    #[tokio::test]
+    async fn test_shard_stats() {
+        assert!(Database::with_shards(3).is_err());
+        let db = Database::with_shards(4).unwrap();
+        for i in 0..20 {
+            db.set(format!("k{}", i), json!(i)).await;
+        }
+
+        // A write waits while a reader holds the shard of its key
+        let reader = db.data.get("k0").unwrap();
+        let writer = {
+            let db = db.clone();
+            std::thread::spawn(move || db.probe_shard("k0", true))
+        };
+        writer.join().unwrap();
+        drop(reader);
+
+        let Response::Ok(Some(stats)) = db.execute_command(Command::ShardStats).await else {
+            panic!("SHARDSTATS failed");
+        };
+        let shards = stats["shards"].as_array().unwrap();
+        assert_eq!(shards.len(), 4);
+        let total = |field: &str| {
+            shards
+                .iter()
+                .map(|s| s[field].as_u64().unwrap())
+                .sum::<u64>()
+        };
+        assert_eq!(total("keys"), 20);
+        assert_eq!(total("waits"), 1);
     }
-}
 
-impl Default for Database {
-    fn default() -> Self {
-        Self::new()
+    #[tokio::test]
+    async fn test_stats() {
+        let db = Database::new();
+        let other = db.namespace("other").unwrap();
+        db.execute_command(Command::Set {
+            key: "a".to_string(),
+            value: json!(1),
+        })
+        .await;
+        other
+            .execute_command(Command::Set {
+                key: "b".to_string(),
+                value: json!(2),
+            })
+            .await;
+        db.execute_command(Command::Get {
+            key: "a".to_string(),
+        })
+        .await;
+        db.execute_command(Command::Get {
+            key: "missing".to_string(),
+        })
+        .await;
+
+        let Response::Ok(Some(stats)) = db.execute_command(Command::Stats).await else {
+            panic!("STATS failed");
+        };
+        assert_eq!(stats["keys"], json!(2));
+        assert_eq!(stats["hits"], json!(1));
+        assert_eq!(stats["misses"], json!(1));
+        assert_eq!(stats["commands"]["SET"], json!(2));
+        assert_eq!(stats["commands"]["GET"], json!(2));
+        assert_eq!(stats["commands"]["STATS"], json!(1));
+        assert!(stats["memory"]["data_bytes"].as_u64() > Some(0));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serde_json::json;
+    #[tokio::test]
+    async fn test_key_meta() {
+        let clock = Arc::new(clock::ManualClock::new());
+        let db = Database::new().with_clock(clock.clone());
+        let meta = |response: Response| match response {
+            Response::Ok(Some(v)) => serde_json::from_value::<KeyMeta>(v).unwrap(),
+            other => panic!("unexpected response {}", other),
+        };
+
+        db.set("doc".to_string(), json!({"items": []})).await;
+        let created = meta(db.meta("doc").await);
+        assert_eq!(created.version, 1);
+        assert_eq!(created.created_at, created.updated_at);
+
+        clock.advance(Duration::from_secs(2));
+        db.execute_command(Command::QAppend {
+            key: "doc".to_string(),
+            path: "$.items".to_string(),
+            value: json!(1),
+        })
+        .await;
+        let updated = meta(db.meta("doc").await);
+        assert_eq!(updated.version, 2);
+        assert_eq!(updated.created_at, created.created_at);
+        assert_eq!(updated.updated_at - created.updated_at, 2000);
+
+        // Metadata starts over once a key is deleted and recreated
+        db.delete("doc".to_string()).await;
+        assert!(
+            matches!(db.meta("doc").await, Response::Error(e) if e.code == ErrorCode::KeyNotFound)
+        );
+        db.set("doc".to_string(), json!(1)).await;
+        assert_eq!(meta(db.meta("doc").await).version, 1);
+    }
 
     #[tokio::test]
-    async fn test_set_and_get() {
+    async fn test_info_reports_capabilities() {
+        let db = Database::with_capacity_and_shards(0, 8).unwrap();
+        db.set_canonical_json(true);
+        let Response::Ok(Some(info)) = db.execute_command(Command::Info).await else {
+            panic!("INFO failed");
+        };
+        assert_eq!(info["limits"]["shards"], json!(8));
+        assert_eq!(info["features"]["canonical_json"], json!(true));
+        assert_eq!(info["keyspace"]["keys"], json!(0));
+        assert_eq!(info["replication"]["role"], json!("primary"));
+        assert!(info["uptime_seconds"].is_u64());
+
+        let mut capabilities = db.capabilities();
+        capabilities.node.node_id = "node-1".to_string();
+        db.set_capabilities(capabilities);
+        let Response::Ok(Some(info)) = db.execute_command(Command::Info).await else {
+            panic!("INFO failed");
+        };
+        assert_eq!(info["node"]["node_id"], json!("node-1"));
+    }
+
+    #[tokio::test]
+    async fn test_history_and_restore() {
         let db = Database::new();
-        let value = json!({"name": "test", "value": 42});
+        db.set("doc".to_string(), json!(1)).await;
+        let response = db.history("doc").await;
+        assert!(matches!(response, Response::Error(e) if e.code == ErrorCode::Unsupported));
 
-        let response = db.set("test_key".to_string(), value.clone()).await;
+        db.set_history_depth(2);
+        for value in [json!("a"), json!("b"), json!("c")] {
+            db.set("doc".to_string(), value).await;
+        }
+        let Response::Ok(Some(history)) = db.history("doc").await else {
+            panic!("HISTORY failed");
+        };
+        let versions: Vec<u64> = history
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["version"].as_u64().unwrap())
+            .collect();
+        assert_eq!(versions, vec![3, 4]);
+
+        // Restoring is a new write of the old value
+        let response = db
+            .execute_command(Command::Restore {
+                key: "doc".to_string(),
+                version: 3,
+            })
+            .await;
         assert!(matches!(response, Response::Ok(None)));
+        assert!(matches!(db.get("doc").await, Response::Ok(Some(v)) if v == json!("b")));
+        let response = db.restore("doc", 1).await;
+        assert!(matches!(response, Response::Error(e) if e.code == ErrorCode::InvalidArgument));
+    }
 
-        let response = db.get("test_key").await;
-        assert!(matches!(response, Response::Ok(Some(v)) if v == value));
+    #[tokio::test]
+    async fn test_tombstones() {
+        let clock = Arc::new(clock::ManualClock::new());
+        let db = Database::new().with_clock(clock.clone());
+        db.set_tombstone_retention(Some(Duration::from_secs(60)));
+        let since = clock.unix_millis();
+        for key in ["a", "b"] {
+            db.set(key.to_string(), json!(1)).await;
+            db.delete(key.to_string()).await;
+        }
+        // Recreating a key clears its tombstone
+        db.set("b".to_string(), json!(2)).await;
+
+        let Response::Ok(Some(deleted)) = db.tombstones(since).await else {
+            panic!("TOMBSTONES failed");
+        };
+        assert_eq!(deleted, json!([{"key": "a", "deleted_at": since}]));
+
+        clock.advance(Duration::from_secs(61));
+        assert_eq!(db.purge_tombstones(), 1);
+        assert!(matches!(db.tombstones(0).await, Response::Ok(Some(v)) if v == json!([])));
     }
 
     #[tokio::test]
-    async fn test_delete() {
+    async fn test_change_log() {
         let db = Database::new();
-        let value = json!({"test": true});
+        assert!(matches!(
+            db.execute_command(Command::Changes { since: 0, limit: None }).await,
+            Response::Error(e) if e.code == ErrorCode::Unsupported
+        ));
+        db.set_change_log_capacity(10);
+        db.set("a".to_string(), json!(1)).await;
+        db.merge("a".to_string(), json!(2)).await;
+        db.delete("a".to_string()).await;
 
-        db.set("test_key".to_string(), value).await;
-        let response = db.delete("test_key".to_string()).await;
-        assert!(matches!(response, Response::Ok(None)));
+        let Response::Ok(Some(changes)) = db.changes(0, None).await else {
+            panic!("CHANGES failed");
+        };
+        let kinds: Vec<&Value> = changes
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| &c["kind"])
+            .collect();
+        assert_eq!(kinds, [&json!("set"), &json!("set"), &json!("delete")]);
+        assert_eq!(changes[1]["value"], json!(2));
 
-        let response = db.get("test_key").await;
-        assert!(matches!(response, Response::Ok(None)));
+        // A consumer group resumes after its last acknowledged offset
+        let read = Command::GroupRead {
+            group: "indexer".to_string(),
+            count: 2,
+        };
+        let Response::Ok(Some(batch)) = db.execute_command(read.clone()).await else {
+            panic!("GROUPREAD failed");
+        };
+        assert_eq!(batch.as_array().unwrap().len(), 2);
+        db.execute_command(Command::GroupAck {
+            group: "indexer".to_string(),
+            offset: 2,
+        })
+        .await;
+        assert!(matches!(
+            db.execute_command(read).await,
+            Response::Ok(Some(v)) if v[0]["offset"] == json!(3) && v[0]["kind"] == json!("delete")
+        ));
     }
 
     #[tokio::test]
-    async fn test_qget_jsonpath() {
+    async fn test_latency_injection() {
+        let clock = Arc::new(clock::ManualClock::new());
+        let db = Database::new().with_clock(clock.clone());
+        let inject = |millis, seconds| Command::InjectLatency {
+            target: LatencyTarget::Responses,
+            millis,
+            seconds,
+        };
+        assert!(matches!(
+            db.execute_command(inject(100, 0)).await,
+            Response::Error(e) if e.code == ErrorCode::InvalidArgument
+        ));
+        db.execute_command(inject(100, 10)).await;
+        assert_eq!(
+            db.injected_latency(LatencyTarget::Responses),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(db.injected_latency(LatencyTarget::Replication), None);
+
+        // Injections expire on their own
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(db.injected_latency(LatencyTarget::Responses), None);
+    }
+
+    #[tokio::test]
+    async fn test_cache_tier_eviction() {
         let db = Database::new();
-        let value = json!({"user": {"name": "Alice", "age": 30}});
+        let cache = db.namespace("cache").unwrap();
+        db.set_cache_tier("cache", true);
+        let entry_size = Database::entry_size("k00", &json!(0));
+        db.set_max_memory(Some(entry_size * 10));
 
-        db.set("test_key".to_string(), value).await;
+        for i in 0..5 {
+            db.set(format!("k{:02}", i), json!(0)).await;
+            cache.set(format!("c{:02}", i), json!(0)).await;
+        }
+        // Durable writes reclaim memory from the cache tier
+        for i in 5..10 {
+            let response = db.set(format!("k{:02}", i), json!(0)).await;
+            assert!(matches!(response, Response::Ok(None)), "{}", response);
+        }
+        assert_eq!(db.len(), 10);
+        assert!(cache.is_empty());
 
-        let response = db.qget("test_key", "$.user.name").await;
-        if let Response::Ok(Some(result)) = response {
-            assert_eq!(result, json!("Alice"));
-        } else {
-            panic!("Expected JSONPath query result");
+        // Durable keys are never evicted
+        assert!(matches!(
+            db.set("k10".to_string(), json!(0)).await,
+            Response::Error(e) if e.code == ErrorCode::QuotaExceeded
+        ));
+        assert!(matches!(
+            db.execute_command(Command::Stats).await,
+            Response::Ok(Some(stats)) if stats["evicted_keys"] == json!(5)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let path =
+            std::env::temp_dir().join(format!("jsonvault-audit-{}.jsonl", uuid::Uuid::new_v4()));
+        let db = Database::new();
+        db.set_audit_log(Some(Arc::new(AuditLog::open(&path).unwrap())));
+        let ops = db.with_identity("ops");
+        ops.execute_command(Command::Set {
+            key: "k".to_string(),
+            value: json!({"v": 1}),
+        })
+        .await;
+        ops.execute_command(Command::Set {
+            key: "k".to_string(),
+            value: json!({"v": 2}),
+        })
+        .await;
+        db.execute_command(Command::Delete {
+            key: "missing".to_string(),
+        })
+        .await;
+        db.execute_command(Command::Get {
+            key: "k".to_string(),
+        })
+        .await;
+
+        let Response::Ok(Some(records)) = db
+            .execute_command(Command::Audit {
+                since: 0,
+                identity: None,
+                key_pattern: None,
+                limit: None,
+            })
+            .await
+        else {
+            panic!("AUDIT failed");
+        };
+        let records: Vec<AuditRecord> = serde_json::from_value(records).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].identity.as_deref(), Some("ops"));
+        assert_eq!(records[0].before, None);
+        assert_eq!(records[0].after, Some(audit::value_hash(&json!({"v": 1}))));
+        assert_eq!(records[1].before, records[0].after);
+        assert_eq!(records[1].after, Some(audit::value_hash(&json!({"v": 2}))));
+        assert_eq!(records[2].identity, None);
+        assert_eq!(records[2].command, "DELETE");
+        assert_eq!(records[2].outcome, "KEY_NOT_FOUND");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_config_get_set() {
+        let db = Database::new();
+        let cache = db.namespace("cache").unwrap();
+        db.set_cache_tier("cache", true);
+        let config_set = |parameter: &str, value: &str| Command::ConfigSet {
+            parameter: parameter.to_string(),
+            value: value.to_string(),
+        };
+
+        // Values beyond max-value-size are rejected
+        let response = db.execute_command(config_set("max-value-size", "16")).await;
+        assert!(matches!(response, Response::Ok(None)), "{}", response);
+        assert!(matches!(
+            db.set("big".to_string(), json!("x".repeat(32))).await,
+            Response::Error(e) if e.code == ErrorCode::InvalidArgument
+        ));
+        assert!(matches!(
+            db.set("small".to_string(), json!("x")).await,
+            Response::Ok(None)
+        ));
+        // Binary values and arrays growing past it too
+        let too_big = |response| matches!(response, Response::Error(e) if e.message.contains("max-value-size"));
+        assert!(too_big(
+            db.execute_command(Command::SetBytes {
+                key: "blob".to_string(),
+                value: vec![0; 32],
+            })
+            .await
+        ));
+        db.set("list".to_string(), json!([])).await;
+        assert!(too_big(
+            db.qappend("list".to_string(), "$".to_string(), json!("x".repeat(32)))
+                .await
+        ));
+        assert!(too_big(
+            db.execute_command(Command::QInsert {
+                key: "list".to_string(),
+                path: "$".to_string(),
+                index: 0,
+                value: json!("x".repeat(32)),
+            })
+            .await
+        ));
+        assert_eq!(db.value("list"), Some(json!([])));
+        assert!(db.blobs.is_empty());
+        // A bulk load chunk with one document past it writes none of them
+        let data = format!(
+            "{}\n{}\n",
+            json!({"key": "loaded", "value": 1}),
+            json!({"key": "big", "value": "x".repeat(32)})
+        );
+        assert!(matches!(
+            db.execute_command(Command::BulkLoad { data }).await,
+            Response::Error(e) if e.details == Some(json!({"loaded": 0, "key": "big"}))
+        ));
+        assert_eq!(db.value("loaded"), None);
+
+        // Without eviction, writes beyond the memory limit fail
+        cache.set("c".to_string(), json!(0)).await;
+        let limit = (db.used_memory.load(Ordering::Acquire) + 1).to_string();
+        db.execute_command(config_set("max-memory", &limit)).await;
+        db.execute_command(config_set("eviction-policy", "none"))
+            .await;
+        assert!(matches!(
+            db.set("other".to_string(), json!(0)).await,
+            Response::Error(e) if e.code == ErrorCode::QuotaExceeded
+        ));
+        assert_eq!(cache.len(), 1);
+
+        let Response::Ok(Some(config)) = db
+            .execute_command(Command::ConfigGet {
+                parameter: "*".to_string(),
+            })
+            .await
+        else {
+            panic!("CONFIG GET failed");
+        };
+        assert_eq!(config["max-value-size"], json!(16));
+        assert_eq!(config["max-memory"], json!(limit.parse::<u64>().unwrap()));
+        assert_eq!(config["eviction-policy"], json!("none"));
+        assert_eq!(config["idle-timeout"], Value::Null);
+        assert!(config["log-level"].is_string());
+
+        assert!(matches!(
+            db.execute_command(config_set("eviction-policy", "lru")).await,
+            Response::Error(e) if e.code == ErrorCode::InvalidArgument
+        ));
+        assert!(matches!(
+            db.execute_command(config_set("max-keys", "10")).await,
+            Response::Error(e) if e.code == ErrorCode::InvalidArgument
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_disk_spill() {
+        let dir = std::env::temp_dir().join(format!("jsonvault-spill-{}", uuid::Uuid::new_v4()));
+        let db = Database::new();
+        db.set_spill(Some(Arc::new(SpillStore::open(&dir).unwrap())));
+        let value = json!("x".repeat(100));
+        db.set_max_memory(Some(Database::entry_size("k00", &value) * 10));
+
+        // Durable writes beyond the limit spill cold documents instead of failing
+        for i in 0..30 {
+            let response = db.set(format!("k{:02}", i), value.clone()).await;
+            assert!(matches!(response, Response::Ok(None)), "{}", response);
+        }
+        let spilled = |db: &Database| match db.data.get("k00") {
+            Some(document) => document.is_spilled(),
+            None => false,
+        };
+        assert!(db.resident_memory() <= Database::entry_size("k00", &value) * 10);
+        assert!(spilled(&db));
+        assert_eq!(db.value("k05"), Some(value.clone()));
+
+        // Accessing a key reloads it
+        let response = db
+            .execute_command(Command::Get {
+                key: "k00".to_string(),
+            })
+            .await;
+        assert!(
+            matches!(&response, Response::Ok(Some(v)) if *v == value),
+            "{}",
+            response
+        );
+        assert!(!spilled(&db));
+        let Response::Ok(Some(stats)) = db.execute_command(Command::Stats).await else {
+            panic!("STATS failed");
+        };
+        assert!(stats["memory"]["spilled_keys"].as_u64().unwrap() >= 19);
+
+        // The GET fast path leaves spilled documents to the command path,
+        // where an unreadable spill file fails the command
+        let key = (0..30)
+            .map(|i| format!("k{:02}", i))
+            .find(|key| {
+                db.data
+                    .get(key)
+                    .is_some_and(|document| document.is_spilled())
+            })
+            .unwrap();
+        assert!(db.get_serialized(&key).is_none());
+        for segment in std::fs::read_dir(&dir).unwrap() {
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .open(segment.unwrap().path())
+                .unwrap();
+            file.set_len(0).unwrap();
         }
+        let response = db.execute_command(Command::Get { key }).await;
+        assert!(
+            matches!(&response, Response::Error(e) if e.code == ErrorCode::Internal),
+            "{}",
+            response
+        );
+        db.execute_command(Command::Flush { prefix: None }).await;
+        assert_eq!(
+            db.spill.read().unwrap().as_ref().unwrap().spilled_bytes(),
+            0
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[tokio::test]
-    async fn test_qset_nested_property() {
+    async fn test_random_key_and_sample() {
         let db = Database::new();
-        let initial = json!({"user": {"name": "Alice"}});
+        assert!(matches!(db.random_key().await, Response::Ok(None)));
+        for i in 0..20 {
+            db.set(format!("key:{}", i), json!(i)).await;
+        }
 
-        db.set("test_key".to_string(), initial).await;
+        let Response::Ok(Some(Value::String(key))) = db.random_key().await else {
+            panic!("RANDOMKEY failed");
+        };
+        assert!(key.starts_with("key:"));
+
+        let Response::Ok(Some(Value::Object(sample))) = db.sample(5).await else {
+            panic!("SAMPLE failed");
+        };
+        assert_eq!(sample.len(), 5);
+        for (key, value) in &sample {
+            assert_eq!(format!("key:{}", value), *key);
+        }
+        let Response::Ok(Some(Value::Object(all))) = db.sample(100).await else {
+            panic!("SAMPLE failed");
+        };
+        assert_eq!(all.len(), 20);
+        assert!(matches!(
+            db.sample(MAX_SAMPLE_SIZE + 1).await,
+            Response::Error(_)
+        ));
+    }
 
+    #[tokio::test]
+    async fn test_flush() {
+        let db = Database::new();
+        for key in ["user:1", "user:2", "order:1"] {
+            db.set(key.to_string(), json!({"id": key})).await;
+        }
         let response = db
-            .qset("test_key".to_string(), "user.age".to_string(), json!(25))
+            .execute_command(Command::Flush {
+                prefix: Some("user:".to_string()),
+            })
             .await;
-        assert!(matches!(response, Response::Ok(None)));
+        assert!(matches!(response, Response::Ok(Some(n)) if n == json!(2)));
+        assert_eq!(db.matching_keys("*"), vec!["order:1".to_string()]);
 
-        let response = db.get("test_key").await;
-        if let Response::Ok(Some(result)) = response {
-            let expected = json!({"user": {"name": "Alice", "age": 25}});
-            assert_eq!(result, expected);
-        } else {
-            panic!("Expected result after QSET");
+        db.flush(None).await;
+        assert!(db.is_empty());
+        assert_eq!(db.bytes.load(Ordering::Acquire), 0);
+    }
+
+    #[tokio::test]
+    async fn test_inline_meta() {
+        let clock = Arc::new(clock::ManualClock::new());
+        let db = Database::new().with_clock(clock.clone());
+        db.set_inline_meta(true);
+        let created_at = clock.unix_millis();
+
+        // Client-provided metadata is overwritten
+        db.set("doc".to_string(), json!({"items": [], "_meta": "forged"}))
+            .await;
+        clock.advance(Duration::from_secs(1));
+        db.execute_command(Command::QAppend {
+            key: "doc".to_string(),
+            path: "$.items".to_string(),
+            value: json!(1),
+        })
+        .await;
+
+        let Response::Ok(Some(doc)) = db.get("doc").await else {
+            panic!("GET failed");
+        };
+        assert_eq!(
+            doc[INLINE_META_FIELD],
+            json!({"created_at": created_at, "updated_at": created_at + 1000, "revision": 2})
+        );
+        assert_eq!(doc["items"], json!([1]));
+
+        // Only object documents carry inline metadata
+        db.set("n".to_string(), json!(1)).await;
+        assert!(matches!(db.get("n").await, Response::Ok(Some(v)) if v == json!(1)));
+    }
+
+    #[tokio::test]
+    async fn test_expire_ttl_and_persist() {
+        let clock = Arc::new(clock::ManualClock::new());
+        let db = Database::new().with_clock(clock.clone());
+        for key in ["a", "b"] {
+            db.set(key.to_string(), json!(1)).await;
+            db.execute_command(Command::Expire {
+                key: key.to_string(),
+                seconds: 10,
+            })
+            .await;
+        }
+        clock.advance(Duration::from_secs(4));
+        let ttl = db
+            .execute_command(Command::Ttl {
+                key: "a".to_string(),
+            })
+            .await;
+        assert!(matches!(ttl, Response::Ok(Some(ms)) if ms == json!(6000)));
+
+        let persisted = db.persist("b").await;
+        assert!(matches!(persisted, Response::Ok(Some(v)) if v == json!(true)));
+        assert!(matches!(db.ttl("b").await, Response::Ok(None)));
+
+        // Expired keys are deleted on access
+        clock.advance(Duration::from_secs(6));
+        let get = |key: &str| {
+            db.execute_command(Command::Get {
+                key: key.to_string(),
+            })
+        };
+        assert!(matches!(get("a").await, Response::Ok(None)));
+        assert!(matches!(get("b").await, Response::Ok(Some(_))));
+        assert_eq!(db.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_active_expiry() {
+        let clock = Arc::new(clock::ManualClock::new());
+        let db = Database::new().with_clock(clock.clone());
+        let other = db.namespace("other").unwrap();
+        for (handle, key) in [(&db, "a"), (&db, "b"), (&other, "c")] {
+            handle.set(key.to_string(), json!(1)).await;
+            handle.expire(key, 1).await;
         }
+        db.set("kept".to_string(), json!(1)).await;
+
+        assert_eq!(db.expire_keys(10), 0);
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(db.expire_keys(1), 2);
+        assert_eq!(db.expire_keys(10), 1);
+        assert_eq!(db.len(), 1);
+        assert!(other.is_empty());
     }
 
     #[tokio::test]
-    async fn test_qset_create_new_key() {
+    async fn test_save_commands() {
+        let db = Database::new();
+        let save = |background| Command::Save { background };
+        assert!(matches!(
+            db.execute_command(save(false)).await,
+            Response::Error(e) if e.code == ErrorCode::Unsupported
+        ));
+
+        let root = std::env::temp_dir().join(format!("jsonvault-save-{}", uuid::Uuid::new_v4()));
+        let dir = crate::storage::layout::DataDir::open(&root, Some("node-1")).unwrap();
+        db.set_snapshot_store(Some(Arc::new(SnapshotStore::new(dir.clone(), 2))));
+        db.set("a".to_string(), json!(1)).await;
+
+        let Response::Ok(Some(saved)) = db.execute_command(save(false)).await else {
+            panic!("SAVE failed");
+        };
+        assert_eq!(saved["index"], 1);
+        assert_eq!(saved["keys"], 1);
+        assert!(saved["duration_ms"].is_u64());
+        assert!(dir.snapshot_path(1).exists());
+
+        let Response::Ok(Some(saved)) = db.execute_command(save(true)).await else {
+            panic!("BGSAVE failed");
+        };
+        assert_eq!(saved["index"], 2);
+        assert_eq!(saved["background"], true);
+        for _ in 0..100 {
+            if dir.snapshot_path(2).exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(dir.snapshot_path(2).exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_export_import() {
         let db = Database::new();
+        db.set("user:2".to_string(), json!({"name": "b"})).await;
+        db.set("user:1".to_string(), json!({"name": "a"})).await;
+        db.set("user:1".to_string(), json!({"name": "a2"})).await;
+        db.set("order:1".to_string(), json!(10)).await;
+
+        let export = Command::Export {
+            pattern: "user:*".to_string(),
+        };
+        let Response::Ok(Some(Value::String(ndjson))) = db.execute_command(export).await else {
+            panic!("EXPORT failed");
+        };
+        let records = ExportRecord::parse_ndjson(&ndjson).unwrap();
+        let keys: Vec<&str> = records.iter().map(|r| r.key.as_str()).collect();
+        assert_eq!(keys, vec!["user:1", "user:2"]);
+        assert_eq!(records[0].value, json!({"name": "a2"}));
+        assert_eq!(records[0].meta.map(|meta| meta.version), Some(2));
 
+        let other = Database::new();
+        let import = |data: &str| Command::Import {
+            data: data.to_string(),
+        };
+        // An invalid line fails the import before anything is written
+        let invalid = format!("{}not json\n", ndjson);
+        assert!(matches!(
+            other.execute_command(import(&invalid)).await,
+            Response::Error(e) if e.code == ErrorCode::InvalidArgument && e.message.contains("line 3")
+        ));
+        assert!(other.is_empty());
+
+        let Response::Ok(Some(imported)) = other.execute_command(import(&ndjson)).await else {
+            panic!("IMPORT failed");
+        };
+        assert_eq!(imported["imported"], 2);
+        assert_eq!(other.value("user:1"), Some(json!({"name": "a2"})));
+        assert_eq!(other.value("order:1"), None);
+    }
+
+    #[tokio::test]
+    async fn test_acl_commands() {
+        use crate::acl::{Grant, Permission};
+
+        let db = Database::new();
         let response = db
-            .qset(
-                "new_key".to_string(),
-                "config.timeout".to_string(),
-                json!(5000),
-            )
+            .execute_command(Command::AclSet {
+                identity: "dashboard".to_string(),
+                grants: vec![Grant::new(Permission::Read, "cache:*")],
+            })
             .await;
         assert!(matches!(response, Response::Ok(None)));
+        db.execute_command(Command::Set {
+            key: "cache:a".to_string(),
+            value: json!(1),
+        })
+        .await;
 
-        let response = db.get("new_key").await;
-        if let Response::Ok(Some(result)) = response {
-            let expected = json!({"config": {"timeout": 5000}});
-            assert_eq!(result, expected);
-        } else {
-            panic!("Expected result after QSET on new key");
+        let dashboard = db.with_identity("dashboard").namespace("default").unwrap();
+        assert!(matches!(
+            dashboard
+                .execute_command(Command::Get {
+                    key: "cache:a".to_string()
+                })
+                .await,
+            Response::Ok(Some(_))
+        ));
+        for command in [
+            Command::Set {
+                key: "cache:a".to_string(),
+                value: json!(2),
+            },
+            Command::Get {
+                key: "user:1".to_string(),
+            },
+            Command::AclList,
+        ] {
+            assert!(matches!(
+                dashboard.execute_command(command).await,
+                Response::Error(e) if e.code == ErrorCode::Forbidden
+            ));
+        }
+
+        match db.execute_command(Command::AclList).await {
+            Response::Ok(Some(list)) => {
+                assert_eq!(
+                    list,
+                    json!({"dashboard": [{"permission": "read", "keys": "cache:*"}]})
+                )
+            }
+            other => panic!("unexpected response: {}", other),
         }
+        // Without grants, the identity is denied everything
+        db.execute_command(Command::AclDel {
+            identity: "dashboard".to_string(),
+        })
+        .await;
+        assert!(matches!(
+            dashboard
+                .execute_command(Command::Get {
+                    key: "cache:a".to_string()
+                })
+                .await,
+            Response::Error(e) if e.code == ErrorCode::Forbidden
+        ));
     }
 }
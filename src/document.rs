@@ -0,0 +1,167 @@
+use bytes::Bytes;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::sync::{Arc, OnceLock};
+
+use crate::protocol::{ErrorCode, ErrorInfo};
+use crate::storage::spill::SpillSlot;
+
+/// zstd level used for stored documents, favoring speed over ratio
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Counts the bytes written to it
+struct ByteCounter(u64);
+
+impl std::io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Size of the JSON serialization of a value
+pub(crate) fn serialized_size(value: &Value) -> u64 {
+    let mut counter = ByteCounter(0);
+    let _ = serde_json::to_writer(&mut counter, value);
+    counter.0
+}
+
+/// A JSON document as kept in the keyspace.
+///
+/// Documents whose serialization reaches the compression threshold are kept
+/// zstd-compressed and decompressed on every read; smaller ones, and those
+/// that don't compress, are kept as plain values shared with readers and the
+/// key history, and replaced rather than modified by writes. Cold documents may
+/// be spilled to disk under memory pressure and are then read from there.
+#[derive(Debug, Clone)]
+pub(crate) enum Document {
+    Plain {
+        value: Arc<Value>,
+        /// Serialization of `value`, cached by the first read that needs it
+        serialized: OnceLock<Bytes>,
+    },
+    Compressed {
+        data: Box<[u8]>,
+        /// Size of the uncompressed serialization
+        size: u64,
+    },
+    Spilled(Arc<SpillSlot>),
+}
+
+impl Document {
+    /// Wrap a value, compressing it if its serialization has at least
+    /// `threshold` bytes (0 disables compression)
+    pub fn new(value: Arc<Value>, threshold: usize) -> Self {
+        if threshold == 0 {
+            return Self::plain(value);
+        }
+        let serialized = serde_json::to_vec(&*value).expect("JSON values serialize");
+        if serialized.len() < threshold {
+            return Self::plain(value);
+        }
+        match zstd::bulk::compress(&serialized, COMPRESSION_LEVEL) {
+            Ok(data) if data.len() < serialized.len() => Document::Compressed {
+                data: data.into_boxed_slice(),
+                size: serialized.len() as u64,
+            },
+            _ => Self::plain(value),
+        }
+    }
+
+    fn plain(value: Arc<Value>) -> Self {
+        Document::Plain {
+            value,
+            serialized: OnceLock::new(),
+        }
+    }
+
+    /// Returns the value, decompressing it if needed; only reading a
+    /// spilled document back from disk can fail
+    pub fn json(&self) -> Result<Cow<'_, Value>, ErrorInfo> {
+        Ok(match self {
+            Document::Plain { value, .. } => Cow::Borrowed(value),
+            Document::Compressed { data, size } => Cow::Owned(Self::decompress(data, *size)),
+            Document::Spilled(slot) => Cow::Owned(Self::parse(&Self::read_spilled(slot)?)),
+        })
+    }
+
+    /// Returns the value without copying it, unless it is compressed or
+    /// spilled
+    pub fn shared(&self) -> Result<Arc<Value>, ErrorInfo> {
+        Ok(match self {
+            Document::Plain { value, .. } => Arc::clone(value),
+            Document::Compressed { data, size } => Arc::new(Self::decompress(data, *size)),
+            Document::Spilled(slot) => Arc::new(Self::parse(&Self::read_spilled(slot)?)),
+        })
+    }
+
+    /// Returns the JSON serialization of the value, serializing a plain value
+    /// only once until it is modified
+    pub fn serialized(&self) -> Result<Bytes, ErrorInfo> {
+        Ok(match self {
+            Document::Plain { value, serialized } => serialized
+                .get_or_init(|| {
+                    Bytes::from(serde_json::to_vec(&**value).expect("JSON values serialize"))
+                })
+                .clone(),
+            Document::Compressed { data, size } => Bytes::from(Self::decompress_bytes(data, *size)),
+            Document::Spilled(slot) => Bytes::from(Self::read_spilled(slot)?),
+        })
+    }
+
+    /// Size of the JSON serialization of the document
+    pub fn size(&self) -> u64 {
+        match self {
+            Document::Plain { value, serialized } => match serialized.get() {
+                Some(serialized) => serialized.len() as u64,
+                None => serialized_size(value),
+            },
+            Document::Compressed { size, .. } => *size,
+            Document::Spilled(slot) => slot.size(),
+        }
+    }
+
+    /// Returns the compressed size of a compressed document
+    pub fn compressed_size(&self) -> Option<usize> {
+        match self {
+            Document::Compressed { data, .. } => Some(data.len()),
+            Document::Plain { .. } | Document::Spilled(_) => None,
+        }
+    }
+
+    /// Size of the cached serialization of a plain document
+    pub fn cached_size(&self) -> usize {
+        match self {
+            Document::Plain { serialized, .. } => serialized.get().map_or(0, Bytes::len),
+            Document::Compressed { .. } | Document::Spilled(_) => 0,
+        }
+    }
+
+    /// Returns true if the document is kept on disk
+    pub fn is_spilled(&self) -> bool {
+        matches!(self, Document::Spilled(_))
+    }
+
+    /// Reads a spilled document, failing the command that needs it if the
+    /// spill file cannot be read
+    pub fn read_spilled(slot: &SpillSlot) -> Result<Vec<u8>, ErrorInfo> {
+        slot.read()
+            .map_err(|e| ErrorInfo::new(ErrorCode::Internal, e))
+    }
+
+    fn decompress_bytes(data: &[u8], size: u64) -> Vec<u8> {
+        zstd::bulk::decompress(data, size as usize).expect("stored documents decompress")
+    }
+
+    fn decompress(data: &[u8], size: u64) -> Value {
+        Self::parse(&Self::decompress_bytes(data, size))
+    }
+
+    fn parse(serialized: &[u8]) -> Value {
+        serde_json::from_slice(serialized).expect("stored documents are valid JSON")
+    }
+}
@@ -0,0 +1,43 @@
+use crate::database::KeyMeta;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A key of an export, as one line of NDJSON.
+///
+/// Exports list every matching key with its value and metadata, sorted by
+/// key, so that they can be imported into another cluster or checked into
+/// fixtures and diffed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportRecord {
+    pub key: String,
+    pub value: Value,
+    /// Metadata at export time; ignored on import
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<KeyMeta>,
+}
+
+impl ExportRecord {
+    /// Serializes records as NDJSON, one record per line
+    pub fn to_ndjson(records: &[ExportRecord]) -> String {
+        let mut ndjson = String::new();
+        for record in records {
+            ndjson.push_str(&serde_json::to_string(record).expect("records serialize"));
+            ndjson.push('\n');
+        }
+        ndjson
+    }
+
+    /// Parses NDJSON records, skipping blank lines; fails on the first
+    /// invalid line, naming it
+    pub fn parse_ndjson(ndjson: &str) -> Result<Vec<ExportRecord>, String> {
+        ndjson
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(number, line)| {
+                serde_json::from_str(line)
+                    .map_err(|e| format!("Invalid record on line {}: {}", number + 1, e))
+            })
+            .collect()
+    }
+}
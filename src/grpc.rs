@@ -0,0 +1,1098 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, error};
+use serde_json::Value;
+use tokio::io::DuplexStream;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tokio_util::codec::{Framed, FramedRead, FramedWrite};
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::{http, Body, BoxFuture, Service, StdError};
+use tonic::metadata::MetadataMap;
+use tonic::server::{Grpc, NamedService, ServerStreamingService, UnaryService};
+use tonic::{Code, Status};
+
+use crate::codec::{FrameCodec, Serialized};
+use crate::network::TcpServer;
+use crate::protocol::{Command, ErrorCode, ErrorInfo, Response, MAX_MESSAGE_SIZE};
+
+/// Messages of `proto/jsonvault.proto`
+pub mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CommandRequest {
+        #[prost(string, tag = "1")]
+        pub namespace: String,
+        #[prost(
+            oneof = "command_request::Command",
+            tags = "2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 15"
+        )]
+        pub command: Option<command_request::Command>,
+    }
+
+    pub mod command_request {
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum Command {
+            #[prost(message, tag = "2")]
+            Get(super::Key),
+            #[prost(message, tag = "3")]
+            Set(super::Document),
+            #[prost(message, tag = "4")]
+            Delete(super::Key),
+            #[prost(message, tag = "5")]
+            Merge(super::Document),
+            #[prost(message, tag = "6")]
+            GetBytes(super::Key),
+            #[prost(message, tag = "7")]
+            SetBytes(super::Binary),
+            #[prost(message, tag = "8")]
+            Qget(super::PathQuery),
+            #[prost(message, tag = "9")]
+            Qset(super::PathValue),
+            #[prost(message, tag = "10")]
+            Expire(super::Expiry),
+            #[prost(message, tag = "11")]
+            Ttl(super::Key),
+            #[prost(message, tag = "12")]
+            Persist(super::Key),
+            #[prost(string, tag = "15")]
+            Json(String),
+        }
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Key {
+        #[prost(string, tag = "1")]
+        pub key: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Document {
+        #[prost(string, tag = "1")]
+        pub key: String,
+        #[prost(string, tag = "2")]
+        pub value: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Binary {
+        #[prost(string, tag = "1")]
+        pub key: String,
+        #[prost(bytes = "vec", tag = "2")]
+        pub value: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PathQuery {
+        #[prost(string, tag = "1")]
+        pub key: String,
+        #[prost(string, tag = "2")]
+        pub query: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PathValue {
+        #[prost(string, tag = "1")]
+        pub key: String,
+        #[prost(string, tag = "2")]
+        pub path: String,
+        #[prost(string, tag = "3")]
+        pub value: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Expiry {
+        #[prost(string, tag = "1")]
+        pub key: String,
+        #[prost(uint64, tag = "2")]
+        pub seconds: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CommandReply {
+        #[prost(oneof = "command_reply::Result", tags = "1, 2, 3")]
+        pub result: Option<command_reply::Result>,
+    }
+
+    pub mod command_reply {
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum Result {
+            #[prost(string, tag = "1")]
+            Value(String),
+            #[prost(bytes = "vec", tag = "2")]
+            Bytes(Vec<u8>),
+            #[prost(bool, tag = "3")]
+            Pong(bool),
+        }
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ScanRequest {
+        #[prost(string, tag = "1")]
+        pub namespace: String,
+        #[prost(string, tag = "2")]
+        pub key_pattern: String,
+        #[prost(string, tag = "3")]
+        pub query: String,
+        #[prost(uint64, tag = "4")]
+        pub limit: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ScanMatch {
+        #[prost(string, tag = "1")]
+        pub key: String,
+        #[prost(bytes = "vec", tag = "2")]
+        pub value: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ChangefeedRequest {
+        #[prost(string, tag = "1")]
+        pub namespace: String,
+        #[prost(uint64, tag = "2")]
+        pub from_offset: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Change {
+        #[prost(uint64, tag = "1")]
+        pub offset: u64,
+        #[prost(string, tag = "2")]
+        pub kind: String,
+        #[prost(string, tag = "3")]
+        pub key: String,
+        #[prost(bytes = "vec", tag = "4")]
+        pub value: Vec<u8>,
+        #[prost(uint64, tag = "5")]
+        pub at: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SubscribeEventsRequest {
+        #[prost(string, tag = "1")]
+        pub namespace: String,
+        #[prost(string, tag = "2")]
+        pub pattern: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct KeyEvent {
+        #[prost(string, tag = "1")]
+        pub namespace: String,
+        #[prost(string, tag = "2")]
+        pub key: String,
+        #[prost(string, tag = "3")]
+        pub event: String,
+    }
+}
+
+/// Name of the service in `proto/jsonvault.proto`
+const SERVICE: &str = "jsonvault.v1.JsonVault";
+
+/// Capacity of the in-memory pipe between the gateway and a session
+const SESSION_BUFFER: usize = 64 << 10;
+
+/// Sessions kept for unary calls; beyond it, the least recently used one is
+/// closed
+const MAX_POOLED_SESSIONS: usize = 256;
+
+/// Time after which a session no unary call used is closed
+const POOLED_SESSION_IDLE: Duration = Duration::from_secs(300);
+
+/// Messages buffered for a streaming call before its session waits for the
+/// client to read them
+const STREAM_BUFFER: usize = 64;
+
+/// Serve the gRPC service on a listener. Calls run in sessions of the
+/// server, like TCP connections: they authenticate with the `authorization`
+/// metadata, select the namespace of the request, and are subject to the
+/// same grants and admin restrictions.
+pub(crate) async fn serve(server: TcpServer, listener: TcpListener) {
+    let gateway = Gateway {
+        server,
+        sessions: SessionPool::default(),
+    };
+    let result = tonic::transport::Server::builder()
+        .add_service(gateway)
+        .serve_with_incoming(TcpListenerStream::new(listener))
+        .await;
+    if let Err(e) = result {
+        error!("gRPC gateway stopped: {}", e);
+    }
+}
+
+/// The JsonVault service, answering calls through sessions of the server
+#[derive(Clone)]
+struct Gateway {
+    server: TcpServer,
+    /// Sessions of the unary calls
+    sessions: SessionPool,
+}
+
+impl NamedService for Gateway {
+    const NAME: &'static str = SERVICE;
+}
+
+impl<B> Service<http::Request<B>> for Gateway
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let gateway = self.clone();
+        let method = request
+            .uri()
+            .path()
+            .strip_prefix('/')
+            .and_then(|path| path.strip_prefix(SERVICE))
+            .and_then(|path| path.strip_prefix('/'))
+            .unwrap_or_default()
+            .to_string();
+        Box::pin(async move {
+            Ok(match method.as_str() {
+                "Execute" => {
+                    let mut grpc = codec::<proto::CommandReply, proto::CommandRequest>();
+                    grpc.unary(gateway, request).await
+                }
+                "Scan" => {
+                    let mut grpc = codec::<proto::ScanMatch, proto::ScanRequest>();
+                    grpc.server_streaming(gateway, request).await
+                }
+                "Changefeed" => {
+                    let mut grpc = codec::<proto::Change, proto::ChangefeedRequest>();
+                    grpc.server_streaming(gateway, request).await
+                }
+                "SubscribeEvents" => {
+                    let mut grpc = codec::<proto::KeyEvent, proto::SubscribeEventsRequest>();
+                    grpc.server_streaming(gateway, request).await
+                }
+                _ => Status::unimplemented(format!("Unknown method {}", method)).into_http(),
+            })
+        })
+    }
+}
+
+/// Returns the codec of a method, accepting requests as large as those of
+/// the TCP protocol
+fn codec<E, D>() -> Grpc<ProstCodec<E, D>>
+where
+    E: prost::Message + Send + 'static,
+    D: prost::Message + Default + Send + 'static,
+{
+    Grpc::new(ProstCodec::default())
+        .max_decoding_message_size(MAX_MESSAGE_SIZE)
+        .max_encoding_message_size(usize::MAX)
+}
+
+impl UnaryService<proto::CommandRequest> for Gateway {
+    type Response = proto::CommandReply;
+    type Future = BoxFuture<tonic::Response<Self::Response>, Status>;
+
+    fn call(&mut self, request: tonic::Request<proto::CommandRequest>) -> Self::Future {
+        let gateway = self.clone();
+        Box::pin(async move {
+            let (metadata, _, request) = request.into_parts();
+            let command = command(request.command)?;
+            // Commands pushing frames or changing the state of the session
+            // can't run in a shared session: they have RPCs of their own, or
+            // need the TCP protocol
+            if command.binds_connection() {
+                return Err(Status::invalid_argument(format!(
+                    "{} can't be executed alone: use the streaming RPCs or the TCP protocol",
+                    command.name()
+                )));
+            }
+            let session = gateway
+                .sessions
+                .get(&gateway.server, &metadata, &request.namespace)
+                .await?;
+            let response = session.request(command).await?;
+            Ok(tonic::Response::new(reply(response)?))
+        })
+    }
+}
+
+/// Returns the command of a request
+fn command(command: Option<proto::command_request::Command>) -> Result<Command, Status> {
+    use proto::command_request::Command as Typed;
+    let json = |value: &str| {
+        serde_json::from_str::<Value>(value)
+            .map_err(|e| Status::invalid_argument(format!("Invalid JSON value: {}", e)))
+    };
+    Ok(
+        match command.ok_or_else(|| Status::invalid_argument("Missing command"))? {
+            Typed::Get(proto::Key { key }) => Command::Get { key },
+            Typed::Set(proto::Document { key, value }) => Command::Set {
+                key,
+                value: json(&value)?,
+            },
+            Typed::Delete(proto::Key { key }) => Command::Delete { key },
+            Typed::Merge(proto::Document { key, value }) => Command::Merge {
+                key,
+                value: json(&value)?,
+            },
+            Typed::GetBytes(proto::Key { key }) => Command::GetBytes { key },
+            Typed::SetBytes(proto::Binary { key, value }) => Command::SetBytes { key, value },
+            Typed::Qget(proto::PathQuery { key, query }) => Command::QGet { key, query },
+            Typed::Qset(proto::PathValue { key, path, value }) => Command::QSet {
+                key,
+                path,
+                value: json(&value)?,
+            },
+            Typed::Expire(proto::Expiry { key, seconds }) => Command::Expire { key, seconds },
+            Typed::Ttl(proto::Key { key }) => Command::Ttl { key },
+            Typed::Persist(proto::Key { key }) => Command::Persist { key },
+            Typed::Json(command) => serde_json::from_str(&command)
+                .map_err(|e| Status::invalid_argument(format!("Invalid command: {}", e)))?,
+        },
+    )
+}
+
+/// Returns the reply of a response, or the status of an error response
+fn reply(response: Response) -> Result<proto::CommandReply, Status> {
+    use proto::command_reply::Result as Outcome;
+    let result = match response {
+        Response::Ok(value) => value.map(|value| Outcome::Value(value.to_string())),
+        Response::Bytes(bytes) => Some(Outcome::Bytes(bytes)),
+        Response::Pong => Some(Outcome::Pong(true)),
+        Response::Error(error) => return Err(status(error)),
+        response => return Err(unexpected(response)),
+    };
+    Ok(proto::CommandReply { result })
+}
+
+impl ServerStreamingService<proto::ScanRequest> for Gateway {
+    type Response = proto::ScanMatch;
+    type ResponseStream = ReceiverStream<Result<Self::Response, Status>>;
+    type Future = BoxFuture<tonic::Response<Self::ResponseStream>, Status>;
+
+    fn call(&mut self, request: tonic::Request<proto::ScanRequest>) -> Self::Future {
+        let server = self.server.clone();
+        Box::pin(async move {
+            let session = Session::open(
+                server,
+                request.metadata(),
+                peer(&request),
+                &request.get_ref().namespace,
+            )
+            .await?;
+            let scan = request.into_inner();
+            let command = Command::Stream {
+                command: Box::new(Command::QScan {
+                    key_pattern: scan.key_pattern,
+                    query: scan.query,
+                    limit: (scan.limit > 0).then_some(scan.limit as usize),
+                }),
+                chunk_size: None,
+            };
+            session
+                .stream(command, |response| match response {
+                    Response::Chunk(chunk) => Ok(Some(
+                        chunk
+                            .into_iter()
+                            .map(|mut result| proto::ScanMatch {
+                                key: result["key"].as_str().unwrap_or_default().to_string(),
+                                value: serde_json::to_vec(&result["result"].take())
+                                    .unwrap_or_default(),
+                            })
+                            .collect(),
+                    )),
+                    // The final response ends the stream
+                    Response::Ok(_) => Ok(None),
+                    response => Err(unexpected(response)),
+                })
+                .await
+        })
+    }
+}
+
+impl ServerStreamingService<proto::ChangefeedRequest> for Gateway {
+    type Response = proto::Change;
+    type ResponseStream = ReceiverStream<Result<Self::Response, Status>>;
+    type Future = BoxFuture<tonic::Response<Self::ResponseStream>, Status>;
+
+    fn call(&mut self, request: tonic::Request<proto::ChangefeedRequest>) -> Self::Future {
+        let server = self.server.clone();
+        Box::pin(async move {
+            let mut session = Session::open(
+                server,
+                request.metadata(),
+                peer(&request),
+                &request.get_ref().namespace,
+            )
+            .await?;
+            let from_offset = request.get_ref().from_offset;
+            expect_ok(session.request(Command::Changefeed { from_offset }).await?)?;
+            session
+                .stream_pushes(|response| match response {
+                    Response::Change(change) => Ok(Some(vec![proto::Change {
+                        offset: change.offset,
+                        kind: change.kind.as_str().to_string(),
+                        key: change.key,
+                        value: change
+                            .value
+                            .map(|value| serde_json::to_vec(&value).unwrap_or_default())
+                            .unwrap_or_default(),
+                        at: change.at,
+                    }])),
+                    response => Err(unexpected(response)),
+                })
+                .await
+        })
+    }
+}
+
+impl ServerStreamingService<proto::SubscribeEventsRequest> for Gateway {
+    type Response = proto::KeyEvent;
+    type ResponseStream = ReceiverStream<Result<Self::Response, Status>>;
+    type Future = BoxFuture<tonic::Response<Self::ResponseStream>, Status>;
+
+    fn call(&mut self, request: tonic::Request<proto::SubscribeEventsRequest>) -> Self::Future {
+        let server = self.server.clone();
+        Box::pin(async move {
+            let mut session = Session::open(
+                server,
+                request.metadata(),
+                peer(&request),
+                &request.get_ref().namespace,
+            )
+            .await?;
+            let pattern = request.into_inner().pattern;
+            expect_ok(
+                session
+                    .request(Command::SubscribeEvents { pattern })
+                    .await?,
+            )?;
+            session
+                .stream_pushes(|response| match response {
+                    Response::KeyEvent(event) => Ok(Some(vec![proto::KeyEvent {
+                        namespace: event.namespace,
+                        key: event.key,
+                        event: event.event,
+                    }])),
+                    // A gap in the events ends the stream, so that the client
+                    // resubscribes and reconciles
+                    Response::KeyEventsDropped(dropped) => Err(Status::data_loss(format!(
+                        "{} events dropped: the subscriber fell behind",
+                        dropped
+                    ))),
+                    response => Err(unexpected(response)),
+                })
+                .await
+        })
+    }
+}
+
+/// Sessions answering the unary calls, one per call context: the calls made
+/// with the same `authorization` metadata in the same namespace share a
+/// session, authenticated once. Closed and idle sessions are dropped on
+/// every call, and the pool keeps at most `capacity` sessions.
+#[derive(Clone)]
+struct SessionPool {
+    sessions: Arc<Mutex<HashMap<CallContext, PooledSession>>>,
+    capacity: usize,
+    idle: Duration,
+}
+
+/// The `authorization` metadata and namespace of a call
+type CallContext = (Option<String>, String);
+
+/// A session of the pool, with the time of its last call
+struct PooledSession {
+    session: SharedSession,
+    last_used: Instant,
+}
+
+impl Default for SessionPool {
+    fn default() -> Self {
+        Self::new(MAX_POOLED_SESSIONS, POOLED_SESSION_IDLE)
+    }
+}
+
+impl SessionPool {
+    fn new(capacity: usize, idle: Duration) -> Self {
+        Self {
+            sessions: Arc::default(),
+            capacity: capacity.max(1),
+            idle,
+        }
+    }
+
+    /// Returns the open session of a call context, dropping the closed and
+    /// idle ones. Calls still using a dropped session keep it until they end.
+    fn find(
+        &self,
+        sessions: &mut HashMap<CallContext, PooledSession>,
+        context: &CallContext,
+    ) -> Option<SharedSession> {
+        let now = Instant::now();
+        sessions.retain(|_, pooled| {
+            !pooled.session.is_closed() && now.duration_since(pooled.last_used) < self.idle
+        });
+        let pooled = sessions.get_mut(context)?;
+        pooled.last_used = now;
+        Some(pooled.session.clone())
+    }
+
+    /// Returns the session of a call context, opening it unless it is open
+    async fn get(
+        &self,
+        server: &TcpServer,
+        metadata: &MetadataMap,
+        namespace: &str,
+    ) -> Result<SharedSession, Status> {
+        let authorization = metadata
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let context = (authorization, namespace.to_string());
+        if let Some(session) = self.find(&mut self.sessions.lock().unwrap(), &context) {
+            return Ok(session);
+        }
+        // Sessions failing to authenticate are never kept
+        let session = Session::open(server.clone(), metadata, "grpc".to_string(), namespace)
+            .await?
+            .share();
+        let mut sessions = self.sessions.lock().unwrap();
+        // A concurrent call may have opened one meanwhile
+        if let Some(open) = self.find(&mut sessions, &context) {
+            return Ok(open);
+        }
+        if sessions.len() >= self.capacity {
+            let least_recent = sessions
+                .iter()
+                .min_by_key(|(_, pooled)| pooled.last_used)
+                .map(|(context, _)| context.clone());
+            if let Some(least_recent) = least_recent {
+                sessions.remove(&least_recent);
+            }
+        }
+        let pooled = PooledSession {
+            session: session.clone(),
+            last_used: Instant::now(),
+        };
+        sessions.insert(context, pooled);
+        Ok(session)
+    }
+}
+
+/// Reply to a request of a shared session
+type Reply = oneshot::Sender<Result<Response, Status>>;
+
+/// A session shared by concurrent calls, whose requests are tagged so that
+/// the session runs them concurrently and answers them as they complete
+#[derive(Clone)]
+struct SharedSession {
+    requests: mpsc::UnboundedSender<(Command, Reply)>,
+}
+
+impl SharedSession {
+    /// Returns true once the session is closed, by the server (idle timeout,
+    /// CLIENT KILL) or after a failed write
+    fn is_closed(&self) -> bool {
+        self.requests.is_closed()
+    }
+
+    /// Send a command and wait for its response
+    async fn request(&self, command: Command) -> Result<Response, Status> {
+        let (reply, response) = oneshot::channel();
+        self.requests
+            .send((command, reply))
+            .map_err(|_| Status::unavailable("Session closed"))?;
+        response
+            .await
+            .map_err(|_| Status::unavailable("Session closed"))?
+    }
+}
+
+/// A session of the server, reached through an in-memory pipe
+struct Session {
+    stream: Framed<DuplexStream, FrameCodec>,
+}
+
+impl Session {
+    /// Start a session for a client at `address`, authenticated with the
+    /// `authorization` metadata of a call and in `namespace`, unless empty
+    async fn open(
+        server: TcpServer,
+        metadata: &MetadataMap,
+        address: String,
+        namespace: &str,
+    ) -> Result<Self, Status> {
+        let (session, gateway) = tokio::io::duplex(SESSION_BUFFER);
+        tokio::spawn(async move {
+            if let Err(e) = server.serve(session, address).await {
+                debug!("gRPC session closed: {}", e);
+            }
+        });
+        let mut session = Self {
+            stream: Framed::new(gateway, FrameCodec::default()),
+        };
+        if let Some((username, password)) = credentials(metadata)? {
+            expect_ok(
+                session
+                    .request(Command::Auth { username, password })
+                    .await?,
+            )?;
+        }
+        if !namespace.is_empty() {
+            let namespace = namespace.to_string();
+            expect_ok(session.request(Command::Select { namespace }).await?)?;
+        }
+        Ok(session)
+    }
+
+    /// Share the session between concurrent calls. Its requests are written
+    /// and its responses read by tasks of their own, so that a slow reader
+    /// doesn't stop the session from taking requests and the other way round.
+    fn share(self) -> SharedSession {
+        let (requests, mut rx) = mpsc::unbounded_channel::<(Command, Reply)>();
+        let pending: Arc<Mutex<HashMap<u64, Reply>>> = Arc::default();
+        // Every request was answered so far: nothing is left in the buffers
+        let (reader, writer) = tokio::io::split(self.stream.into_inner());
+        let (closed_tx, mut closed_rx) = oneshot::channel::<()>();
+
+        let writer_pending = Arc::clone(&pending);
+        tokio::spawn(async move {
+            let mut sink = FramedWrite::new(writer, FrameCodec::default());
+            let mut id = 0;
+            loop {
+                let (command, reply) = tokio::select! {
+                    request = rx.recv() => match request {
+                        Some(request) => request,
+                        None => break,
+                    },
+                    _ = &mut closed_rx => break,
+                };
+                let message = match Serialized::new(&command) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        let _ = reply.send(Err(Status::internal(e)));
+                        continue;
+                    }
+                };
+                id += 1;
+                writer_pending.lock().unwrap().insert(id, reply);
+                if sink.send(message.payload(Some(id))).await.is_err() {
+                    break;
+                }
+            }
+            // Calls waiting for the session fail with it, and closing the
+            // pipe ends the session
+            drop(rx);
+            writer_pending.lock().unwrap().clear();
+            let _ = sink.close().await;
+        });
+
+        tokio::spawn(async move {
+            let mut frames = FramedRead::new(reader, FrameCodec::default());
+            while let Some(Ok(message)) = frames.next().await {
+                let reply = message
+                    .id
+                    .and_then(|id| pending.lock().unwrap().remove(&id));
+                if let Some(reply) = reply {
+                    let _ = reply.send(message.decode().map_err(Status::internal));
+                }
+            }
+            pending.lock().unwrap().clear();
+            drop(closed_tx);
+        });
+
+        SharedSession { requests }
+    }
+
+    /// Send a command and read its response
+    async fn request(&mut self, command: Command) -> Result<Response, Status> {
+        let message = Serialized::new(&command).map_err(Status::internal)?;
+        self.stream
+            .send(message.payload(None))
+            .await
+            .map_err(|e| Status::unavailable(format!("Session closed: {}", e)))?;
+        self.next()
+            .await?
+            .ok_or_else(|| Status::unavailable("Session closed"))
+    }
+
+    /// Read the next frame of the session
+    async fn next(&mut self) -> Result<Option<Response>, Status> {
+        match self.stream.next().await {
+            None => Ok(None),
+            Some(Ok(message)) => message.decode().map(Some).map_err(Status::internal),
+            Some(Err(e)) => Err(Status::unavailable(format!("Session closed: {}", e))),
+        }
+    }
+
+    /// Send a command and stream the messages its frames are converted to,
+    /// until `convert` returns None
+    async fn stream<T, F>(
+        mut self,
+        command: Command,
+        convert: F,
+    ) -> Result<tonic::Response<ReceiverStream<Result<T, Status>>>, Status>
+    where
+        T: Send + 'static,
+        F: FnMut(Response) -> Result<Option<Vec<T>>, Status> + Send + 'static,
+    {
+        let message = Serialized::new(&command).map_err(Status::internal)?;
+        self.stream
+            .send(message.payload(None))
+            .await
+            .map_err(|e| Status::unavailable(format!("Session closed: {}", e)))?;
+        self.stream_pushes(convert).await
+    }
+
+    /// Stream the messages the frames pushed by the session are converted
+    /// to, until `convert` returns None or the client cancels the call
+    async fn stream_pushes<T, F>(
+        mut self,
+        mut convert: F,
+    ) -> Result<tonic::Response<ReceiverStream<Result<T, Status>>>, Status>
+    where
+        T: Send + 'static,
+        F: FnMut(Response) -> Result<Option<Vec<T>>, Status> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let frame = tokio::select! {
+                    frame = self.next() => frame,
+                    _ = tx.closed() => return,
+                };
+                let messages = match frame {
+                    Ok(Some(Response::Error(error))) => Err(status(error)),
+                    Ok(Some(response)) => convert(response),
+                    Ok(None) => Err(Status::unavailable("Session closed")),
+                    Err(status) => Err(status),
+                };
+                match messages {
+                    Ok(Some(messages)) => {
+                        for message in messages {
+                            if tx.send(Ok(message)).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Ok(None) => return,
+                    Err(status) => {
+                        let _ = tx.send(Err(status)).await;
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(tonic::Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+/// Returns the address of the client of a call
+fn peer<T>(request: &tonic::Request<T>) -> String {
+    request
+        .remote_addr()
+        .map_or_else(|| "grpc".to_string(), |address| address.to_string())
+}
+
+/// Returns the username and password of a call: `Bearer <secret>`, as AUTH
+/// with a password only, or `Basic <base64 of username:password>`
+fn credentials(metadata: &MetadataMap) -> Result<Option<(Option<String>, String)>, Status> {
+    let Some(value) = metadata.get("authorization") else {
+        return Ok(None);
+    };
+    let value = value
+        .to_str()
+        .map_err(|_| Status::unauthenticated("Invalid authorization metadata"))?;
+    if let Some(password) = value.strip_prefix("Bearer ") {
+        return Ok(Some((None, password.to_string())));
+    }
+    let decoded = value
+        .strip_prefix("Basic ")
+        .and_then(|encoded| {
+            base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .ok()
+        })
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .ok_or_else(|| Status::unauthenticated("Authorization must be Bearer or Basic"))?;
+    let (username, password) = decoded
+        .split_once(':')
+        .ok_or_else(|| Status::unauthenticated("Basic authorization without a password"))?;
+    Ok(Some((Some(username.to_string()), password.to_string())))
+}
+
+/// Fails with the status of an error response
+fn expect_ok(response: Response) -> Result<(), Status> {
+    match response {
+        Response::Ok(_) => Ok(()),
+        Response::Error(error) => Err(status(error)),
+        response => Err(unexpected(response)),
+    }
+}
+
+fn unexpected(response: Response) -> Status {
+    Status::internal(format!("Unexpected response: {}", response))
+}
+
+/// Returns the status of a call failing with an error response
+fn status(error: ErrorInfo) -> Status {
+    let code = match error.code {
+        ErrorCode::KeyNotFound | ErrorCode::PathNotFound => Code::NotFound,
+        ErrorCode::WrongType | ErrorCode::ReadOnly | ErrorCode::NotLeader => {
+            Code::FailedPrecondition
+        }
+        ErrorCode::InvalidArgument | ErrorCode::InvalidQuery => Code::InvalidArgument,
+        ErrorCode::Unsupported => Code::Unimplemented,
+        ErrorCode::Unavailable => Code::Unavailable,
+        ErrorCode::QuotaExceeded => Code::ResourceExhausted,
+        ErrorCode::Forbidden => Code::PermissionDenied,
+        ErrorCode::Unauthenticated => Code::Unauthenticated,
+        ErrorCode::Internal => Code::Internal,
+        ErrorCode::Unknown => Code::Unknown,
+    };
+    Status::new(code, error.message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Credentials;
+    use crate::database::Database;
+    use serde_json::json;
+    use std::sync::Arc;
+    use tonic::codegen::http::uri::PathAndQuery;
+    use tonic::transport::Channel;
+
+    async fn execute(
+        client: &mut tonic::client::Grpc<Channel>,
+        authorization: Option<&str>,
+        namespace: &str,
+        command: proto::command_request::Command,
+    ) -> Result<Option<proto::command_reply::Result>, Status> {
+        client.ready().await.unwrap();
+        let mut request = tonic::Request::new(proto::CommandRequest {
+            namespace: namespace.to_string(),
+            command: Some(command),
+        });
+        if let Some(authorization) = authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", authorization.parse().unwrap());
+        }
+        let reply: tonic::Response<proto::CommandReply> = client
+            .unary(
+                request,
+                PathAndQuery::from_static("/jsonvault.v1.JsonVault/Execute"),
+                ProstCodec::default(),
+            )
+            .await?;
+        Ok(reply.into_inner().result)
+    }
+
+    #[tokio::test]
+    async fn test_grpc_gateway() {
+        use proto::command_reply::Result as Outcome;
+        use proto::command_request::Command as Typed;
+
+        let database = Arc::new(Database::new());
+        let server = TcpServer::new(Arc::clone(&database), "127.0.0.1:8113".to_string())
+            .with_grpc("127.0.0.1:8114".to_string())
+            .with_admin_commands(true)
+            .with_credentials(Credentials::new().with_identity("admin", "secret"));
+        let gateway = server.clone();
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let channel = tonic::transport::Endpoint::from_static("http://127.0.0.1:8114")
+            .connect()
+            .await
+            .unwrap();
+        let mut client = tonic::client::Grpc::new(channel);
+        let key = |key: &str| proto::Key {
+            key: key.to_string(),
+        };
+        let json = |command: serde_json::Value| Typed::Json(command.to_string());
+
+        // Calls authenticate like connections, and errors end them
+        let denied = execute(&mut client, None, "", Typed::Get(key("a"))).await;
+        assert_eq!(denied.unwrap_err().code(), Code::Unauthenticated);
+        let denied = execute(&mut client, Some("Bearer wrong"), "", json(json!("Ping"))).await;
+        assert_eq!(denied.unwrap_err().code(), Code::Unauthenticated);
+
+        let basic = format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode("admin:secret")
+        );
+        let set = Typed::Set(proto::Document {
+            key: "user:1".to_string(),
+            value: json!({"name": "Ada"}).to_string(),
+        });
+        let reply = execute(&mut client, Some(&basic), "", set).await.unwrap();
+        assert_eq!(reply, None);
+        let reply = execute(&mut client, Some(&basic), "", Typed::Get(key("user:1"))).await;
+        let Ok(Some(Outcome::Value(value))) = reply else {
+            panic!("unexpected reply {:?}", reply);
+        };
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&value).unwrap(),
+            json!({"name": "Ada"})
+        );
+        let set_bytes = Typed::SetBytes(proto::Binary {
+            key: "blob".to_string(),
+            value: vec![0, 0xff],
+        });
+        execute(&mut client, Some(&basic), "", set_bytes)
+            .await
+            .unwrap();
+        let reply = execute(&mut client, Some(&basic), "", Typed::GetBytes(key("blob"))).await;
+        assert_eq!(reply.unwrap(), Some(Outcome::Bytes(vec![0, 0xff])));
+        // The namespace of the request is selected
+        let reply = execute(
+            &mut client,
+            Some(&basic),
+            "other",
+            Typed::Get(key("user:1")),
+        )
+        .await;
+        assert_eq!(reply.unwrap(), None);
+        // Other commands are carried as JSON
+        let reply = execute(&mut client, Some(&basic), "", json(json!("Ping"))).await;
+        assert_eq!(reply.unwrap(), Some(Outcome::Pong(true)));
+        let invalid = Typed::Set(proto::Document {
+            key: "a".to_string(),
+            value: "{".to_string(),
+        });
+        let refused = execute(&mut client, Some(&basic), "", invalid).await;
+        assert_eq!(refused.unwrap_err().code(), Code::InvalidArgument);
+        // Commands pushing frames or changing the session are refused
+        for command in [
+            json!({"Watch": {"key": "user:1"}}),
+            json!({"Select": {"namespace": "other"}}),
+        ] {
+            let refused = execute(&mut client, Some(&basic), "", json(command)).await;
+            assert_eq!(refused.unwrap_err().code(), Code::InvalidArgument);
+        }
+
+        // Concurrent calls of a context share its session
+        let calls: Vec<_> = (0..20)
+            .map(|_| {
+                let (mut client, basic) = (client.clone(), basic.clone());
+                tokio::spawn(async move {
+                    execute(&mut client, Some(&basic), "", Typed::Get(key("user:1"))).await
+                })
+            })
+            .collect();
+        for call in calls {
+            assert!(matches!(call.await.unwrap(), Ok(Some(Outcome::Value(_)))));
+        }
+        let reply = execute(&mut client, Some(&basic), "", json(json!("ClientList"))).await;
+        let Ok(Some(Outcome::Value(clients))) = reply else {
+            panic!("unexpected reply {:?}", reply);
+        };
+        let clients: Vec<serde_json::Value> = serde_json::from_str(&clients).unwrap();
+        // Without credentials, and as admin in the default and other namespaces
+        let sessions: Vec<_> = clients
+            .iter()
+            .filter(|client| client["address"] == "grpc")
+            .collect();
+        assert_eq!(sessions.len(), 3);
+        let admin = sessions
+            .iter()
+            .find(|client| client["identity"] == "admin" && client["namespace"] == "default")
+            .unwrap();
+        assert!(admin["commands"].as_u64().unwrap() >= 25);
+
+        // Beyond its capacity, the pool closes its least recently used session
+        let pool = SessionPool::new(2, Duration::from_secs(60));
+        let mut metadata = MetadataMap::new();
+        metadata.insert("authorization", basic.parse().unwrap());
+        for namespace in ["a", "b", "a", "c"] {
+            pool.get(&gateway, &metadata, namespace).await.unwrap();
+        }
+        let pooled = |pool: &SessionPool| {
+            let mut namespaces: Vec<_> = pool
+                .sessions
+                .lock()
+                .unwrap()
+                .keys()
+                .map(|(_, namespace)| namespace.clone())
+                .collect();
+            namespaces.sort();
+            namespaces
+        };
+        assert_eq!(pooled(&pool), ["a", "c"]);
+        // and drops the idle ones
+        let pool = SessionPool::new(2, Duration::ZERO);
+        pool.get(&gateway, &metadata, "a").await.unwrap();
+        pool.get(&gateway, &metadata, "b").await.unwrap();
+        assert_eq!(pooled(&pool), ["b"]);
+
+        // Scans stream one match per key
+        client.ready().await.unwrap();
+        let mut request = tonic::Request::new(proto::ScanRequest {
+            namespace: String::new(),
+            key_pattern: "user:*".to_string(),
+            query: "$.name".to_string(),
+            limit: 0,
+        });
+        request
+            .metadata_mut()
+            .insert("authorization", basic.parse().unwrap());
+        let response: tonic::Response<tonic::codec::Streaming<proto::ScanMatch>> = client
+            .server_streaming(
+                request,
+                PathAndQuery::from_static("/jsonvault.v1.JsonVault/Scan"),
+                ProstCodec::default(),
+            )
+            .await
+            .unwrap();
+        let mut matches = response.into_inner();
+        let found = matches.message().await.unwrap().unwrap();
+        assert_eq!(found.key, "user:1");
+        assert_eq!(found.value, b"\"Ada\"");
+        assert!(matches.message().await.unwrap().is_none());
+
+        // Key events are streamed as they happen
+        client.ready().await.unwrap();
+        let mut request = tonic::Request::new(proto::SubscribeEventsRequest {
+            namespace: String::new(),
+            pattern: "user:*".to_string(),
+        });
+        request
+            .metadata_mut()
+            .insert("authorization", "Bearer secret".parse().unwrap());
+        let response: tonic::Response<tonic::codec::Streaming<proto::KeyEvent>> = client
+            .server_streaming(
+                request,
+                PathAndQuery::from_static("/jsonvault.v1.JsonVault/SubscribeEvents"),
+                ProstCodec::default(),
+            )
+            .await
+            .unwrap();
+        let mut events = response.into_inner();
+        database
+            .execute_command(Command::Delete {
+                key: "user:1".to_string(),
+            })
+            .await;
+        let event = events.message().await.unwrap().unwrap();
+        assert_eq!(
+            (event.key.as_str(), event.event.as_str()),
+            ("user:1", "delete")
+        );
+    }
+}
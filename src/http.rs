@@ -0,0 +1,47 @@
+use std::sync::OnceLock;
+
+/// Largest response body read into memory, such as a bucket listing or an
+/// error document
+pub const MAX_RESPONSE_BYTES: usize = 16 << 20;
+
+/// Returns the HTTP client shared by S3 backups and webhooks: connections
+/// are pooled, and `https://` URLs are verified against the native roots
+pub fn client() -> Result<&'static reqwest::Client, String> {
+    static CLIENT: OnceLock<Result<reqwest::Client, String>> = OnceLock::new();
+    CLIENT
+        .get_or_init(|| {
+            reqwest::Client::builder()
+                .build()
+                .map_err(|e| format!("Failed to create the HTTP client: {}", e))
+        })
+        .as_ref()
+        .map_err(Clone::clone)
+}
+
+/// Parses an `http://` or `https://` URL
+pub fn parse_url(url: &str) -> Result<reqwest::Url, String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid URL '{}': {}", url, e))?;
+    match parsed.scheme() {
+        "http" | "https" if parsed.host_str().is_some() => Ok(parsed),
+        _ => Err(format!(
+            "Unsupported URL '{}': only http:// and https:// are supported",
+            url
+        )),
+    }
+}
+
+/// Reads a response body, failing once it exceeds `limit` bytes
+pub async fn read_body(mut response: reqwest::Response, limit: usize) -> Result<Vec<u8>, String> {
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| format!("read error: {}", e))?
+    {
+        if body.len() + chunk.len() > limit {
+            return Err(format!("response body exceeds {} bytes", limit));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
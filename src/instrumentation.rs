@@ -0,0 +1,51 @@
+use crate::protocol::ClusterTopology;
+use std::time::Duration;
+
+/// Outcome of a client request as seen by instrumentation hooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestOutcome {
+    /// The server answered with a non-error response
+    Success,
+    /// The server answered with an error response
+    ServerError,
+    /// The request failed before a response was received
+    TransportError,
+}
+
+/// Information available when a client request starts
+#[derive(Debug, Clone)]
+pub struct RequestStart {
+    /// Command name (e.g. "SET", "QGET")
+    pub command: &'static str,
+    /// Size of the encoded request frame in bytes
+    pub bytes_sent: usize,
+}
+
+/// Information available when a client request completes
+#[derive(Debug, Clone)]
+pub struct RequestEnd {
+    /// Command name (e.g. "SET", "QGET")
+    pub command: &'static str,
+    /// Size of the encoded request frame in bytes
+    pub bytes_sent: usize,
+    /// Size of the received response frame in bytes (0 on transport errors)
+    pub bytes_received: usize,
+    /// Time elapsed between sending the request and receiving the response
+    pub latency: Duration,
+    /// Request outcome
+    pub outcome: RequestOutcome,
+}
+
+/// Hooks invoked by clients around every request, used to feed client-side
+/// metrics into application telemetry
+pub trait ClientHooks: Send + Sync {
+    /// Called right before a request is written to the connection
+    fn on_request_start(&self, _request: &RequestStart) {}
+
+    /// Called once the request has completed, successfully or not
+    fn on_request_end(&self, _request: &RequestEnd) {}
+
+    /// Called when the server pushes a cluster topology change
+    /// (only after subscribing with `TcpClient::subscribe_topology`)
+    fn on_topology_changed(&self, _topology: &ClusterTopology) {}
+}
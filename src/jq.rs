@@ -0,0 +1,52 @@
+use jaq_core::load::{Arena, File, Loader};
+use jaq_core::{Compiler, Ctx, RcIter};
+use jaq_json::Val;
+use serde_json::Value;
+
+/// Runs a jq program against a JSON document and collects every output value
+pub fn run(program: &str, input: &Value) -> Result<Vec<Value>, String> {
+    let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs()));
+    let arena = Arena::default();
+    let modules = loader
+        .load(
+            &arena,
+            File {
+                code: program,
+                path: (),
+            },
+        )
+        .map_err(|errors| format!("jq parse error: {:?}", errors))?;
+
+    let filter = Compiler::default()
+        .with_funs(jaq_std::funs().chain(jaq_json::funs()))
+        .compile(modules)
+        .map_err(|errors| format!("jq compile error: {:?}", errors))?;
+
+    let inputs = RcIter::new(core::iter::empty());
+    filter
+        .run((Ctx::new([], &inputs), Val::from(input.clone())))
+        .map(|result| {
+            result
+                .map(Value::from)
+                .map_err(|e| format!("jq runtime error: {}", e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_run_program() {
+        let input = json!({"users": [{"name": "Alice", "age": 30}, {"name": "Bob", "age": 20}]});
+        let output = run("[.users[] | select(.age > 25) | .name]", &input).unwrap();
+        assert_eq!(output, vec![json!(["Alice"])]);
+
+        let output = run(".users | map(.age) | add", &input).unwrap();
+        assert_eq!(output, vec![json!(50)]);
+
+        assert!(run(".users[", &input).is_err());
+    }
+}
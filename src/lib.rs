@@ -1,9 +1,66 @@
+mod acl;
+mod audit;
+mod auth;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod canonical;
+pub mod capabilities;
+mod cdc;
+mod changes;
+mod clients;
+mod clock;
+mod codec;
+mod commands;
+mod config;
 mod database;
+mod document;
+mod export;
+mod glob;
+// Calls fail with tonic::Status, which is large
+#[cfg(feature = "grpc")]
+#[allow(clippy::result_large_err)]
+mod grpc;
+mod http;
+mod ids;
+mod instrumentation;
+mod jq;
 mod network;
+mod profiling;
 mod protocol;
+mod pubsub;
 mod raft;
+mod scripting;
+pub mod soak;
+mod stall;
+pub mod storage;
+#[cfg(feature = "tls")]
+mod tls;
+mod transform;
+mod triggers;
+mod webhooks;
+mod websocket;
 
-pub use database::Database;
-pub use network::{TcpClient, TcpServer};
-pub use protocol::{Command, Response};
-pub use raft::{RaftManager, NodeId, ClusterMetrics};
+pub use acl::{AccessControl, Grant, Permission};
+pub use audit::{AuditLog, AuditRecord};
+pub use auth::{Credentials, DEFAULT_IDENTITY};
+pub use cdc::{CdcExporter, ChangeSink, JsonLinesSink, CDC_GROUP};
+pub use changes::{Change, ChangeKind, ChangeLogState};
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use commands::{CommandSpec, COMMAND_TABLE};
+pub use config::{EvictionPolicy, RuntimeConfig};
+pub use export::ExportRecord;
+pub use database::{
+    spawn_active_expiry, spawn_tombstone_purger, Database, KeyMeta, Quota, DEFAULT_NAMESPACE, INLINE_META_FIELD,
+};
+pub use ids::{IdGenerator, RandomIdGenerator, SequentialIdGenerator};
+pub use instrumentation::{ClientHooks, RequestEnd, RequestOutcome, RequestStart};
+pub use network::{MultiplexedClient, PooledClient, ResultStream, RoutingClient, SocketOptions, TcpClient, TcpClientPool, TcpProxy, TcpServer};
+pub use protocol::{AggregateOp, ClusterTopology, Command, Handshake, ProtocolVersion, ErrorCode, ChannelMessage, ErrorInfo, Event, KeyEvent, KeyUpdate, LatencyTarget, ProfileKind, Response, Route, RoutingTable};
+pub use pubsub::PubSub;
+pub use raft::{RaftManager, RaftMonitor, NodeId, ClusterMetrics, HardState};
+pub use stall::{spawn_fencing_watchdog, WriteStallMonitor, WriteTicket};
+#[cfg(feature = "tls")]
+pub use tls::TlsConfig;
+pub use transform::{Transform, TransformRule, WritePipeline};
+pub use triggers::{Trigger, TriggerAction, TriggerCondition, TriggerSet};
+pub use webhooks::{WebhookDispatcher, WebhookRule};
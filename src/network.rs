@@ -1,35 +1,172 @@
+use crate::auth::Credentials;
+use crate::clients::{ClientRegistry, ClientReport};
+use crate::codec::{self, FrameCodec, Framing, Payload, Serialized};
 use crate::database::Database;
-use crate::protocol::{Command, Response};
-use bytes::{BufMut, BytesMut};
-use log::{debug, error, info};
-use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use crate::glob;
+#[cfg(feature = "grpc")]
+use crate::grpc;
+use crate::instrumentation::{ClientHooks, RequestEnd, RequestOutcome, RequestStart};
+use crate::protocol::{
+    ChannelMessage, ClusterTopology, Command, ErrorCode, ErrorInfo, Event, Handshake, KeyEvent,
+    KeyUpdate, LatencyTarget, ProtocolVersion, Response, RoutingTable, COMPRESSIONS, ENCODINGS,
+    MAX_MESSAGE_SIZE, MAX_UNAUTHENTICATED_MESSAGE,
+};
+use crate::pubsub::{PubSub, Subscriptions};
+use crate::raft::RaftMonitor;
+use crate::websocket;
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, error, info, warn};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf, ReadHalf, WriteHalf};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc, oneshot, Semaphore};
+use tokio_util::codec::{Framed, FramedRead, FramedWrite};
 
 /// TCP server for JSON database
+#[derive(Clone)]
 pub struct TcpServer {
     database: Arc<Database>,
     address: String,
+    /// Address of the WebSocket gateway, if enabled
+    websocket_address: Option<String>,
+    /// Address of the gRPC gateway, if enabled
+    #[cfg(feature = "grpc")]
+    grpc_address: Option<String>,
+    /// Applied to every accepted connection
+    socket_options: SocketOptions,
+    /// Secrets accepted by AUTH; connections must authenticate when set
+    credentials: Option<Arc<Credentials>>,
+    topology: Option<broadcast::Sender<ClusterTopology>>,
+    /// Latest known topology, used to answer routing table requests
+    current_topology: Arc<RwLock<Option<ClusterTopology>>>,
+    /// Allow clients to run admin commands
+    admin_commands: bool,
+    /// Publish/subscribe channels shared by the connections
+    pubsub: Arc<PubSub>,
+    /// Open client connections, reported by INFO and CLIENT LIST
+    clients: Arc<ClientRegistry>,
+    /// Consensus state reported by INFO
+    raft: Option<RaftMonitor>,
 }
 
 impl TcpServer {
     /// Create a new TCP server
     pub fn new(database: Arc<Database>, address: String) -> Self {
-        Self { database, address }
+        Self {
+            database,
+            address,
+            websocket_address: None,
+            #[cfg(feature = "grpc")]
+            grpc_address: None,
+            socket_options: SocketOptions::default(),
+            credentials: None,
+            topology: None,
+            current_topology: Arc::new(RwLock::new(None)),
+            admin_commands: false,
+            pubsub: Arc::new(PubSub::new()),
+            clients: Arc::new(ClientRegistry::new()),
+            raft: None,
+        }
+    }
+
+    /// Channels of the server, to publish messages from the embedding process
+    pub fn pubsub(&self) -> Arc<PubSub> {
+        Arc::clone(&self.pubsub)
+    }
+
+    /// Allow clients to run admin commands such as FLUSH
+    pub fn with_admin_commands(mut self, enabled: bool) -> Self {
+        self.admin_commands = enabled;
+        self
+    }
+
+    /// Also accept WebSocket connections on this address, carrying one
+    /// command or response per message
+    pub fn with_websocket(mut self, address: String) -> Self {
+        self.websocket_address = Some(address);
+        self
+    }
+
+    /// Also serve the gRPC service of `proto/jsonvault.proto` on this
+    /// address, running calls in sessions like TCP connections
+    #[cfg(feature = "grpc")]
+    pub fn with_grpc(mut self, address: String) -> Self {
+        self.grpc_address = Some(address);
+        self
+    }
+
+    /// Close connections that send no request for `timeout`, including
+    /// connections only receiving pushes; CONFIG SET idle-timeout changes it
+    /// at runtime
+    pub fn with_idle_timeout(self, timeout: Duration) -> Self {
+        self.database.config().set_idle_timeout(Some(timeout));
+        self
+    }
+
+    /// Require connections to authenticate with one of these credentials
+    /// before running commands other than HELLO and PING
+    pub fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(Arc::new(credentials));
+        self
+    }
+
+    /// Tune the sockets of accepted connections
+    pub fn with_socket_options(mut self, options: SocketOptions) -> Self {
+        self.socket_options = options;
+        self
+    }
+
+    /// Push topology changes from this source to clients that subscribe at
+    /// handshake, starting from the `current` topology
+    pub fn with_topology(
+        mut self,
+        topology: broadcast::Sender<ClusterTopology>,
+        current: ClusterTopology,
+    ) -> Self {
+        self.topology = Some(topology);
+        *self.current_topology.write().unwrap() = Some(current);
+        self
+    }
+
+    /// Report the Raft metrics of this node in INFO
+    pub fn with_raft_monitor(mut self, monitor: RaftMonitor) -> Self {
+        self.raft = Some(monitor);
+        self
     }
 
     /// Start the server
     pub async fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
         let listener = TcpListener::bind(&self.address).await?;
         info!("Server started on {}", self.address);
+        if let Some(topology) = &self.topology {
+            track_topology(topology.subscribe(), self.current_topology.clone());
+        }
+        if let Some(address) = &self.websocket_address {
+            let listener = TcpListener::bind(address).await?;
+            info!("WebSocket gateway started on {}", address);
+            tokio::spawn(self.clone().accept_websockets(listener));
+        }
+        #[cfg(feature = "grpc")]
+        if let Some(address) = &self.grpc_address {
+            let listener = TcpListener::bind(address).await?;
+            info!("gRPC gateway started on {}", address);
+            tokio::spawn(grpc::serve(self.clone(), listener));
+        }
 
         loop {
             match listener.accept().await {
                 Ok((stream, addr)) => {
                     info!("New connection from {}", addr);
-                    let db = Arc::clone(&self.database);
+                    if let Err(e) = self.socket_options.apply(&stream) {
+                        warn!("Failed to tune connection from {}: {}", addr, e);
+                    }
+                    let server = self.clone();
                     tokio::spawn(async move {
-                        if let Err(e) = handle_connection(stream, db).await {
+                        if let Err(e) = server.serve(stream, addr.to_string()).await {
                             error!("Error handling connection from {}: {}", addr, e);
                         }
                     });
@@ -40,232 +177,3551 @@ impl TcpServer {
             }
         }
     }
+
+    /// Accept WebSocket connections, serving each one like a TCP connection
+    /// whose frames are carried by WebSocket messages
+    async fn accept_websockets(self, listener: TcpListener) {
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    error!("Error accepting WebSocket connection: {}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = self.socket_options.apply(&stream) {
+                warn!("Failed to tune WebSocket connection from {}: {}", addr, e);
+            }
+            let server = self.clone();
+            tokio::spawn(async move {
+                let socket = match websocket::handshake(stream).await {
+                    Ok(socket) => socket,
+                    Err(e) => {
+                        warn!("Rejected WebSocket connection from {}: {}", addr, e);
+                        return;
+                    }
+                };
+                info!("New WebSocket connection from {}", addr);
+                let (session, gateway) = tokio::io::duplex(WEBSOCKET_BUFFER);
+                let session = tokio::spawn(server.serve(session, addr.to_string()));
+                if let Err(e) = websocket::bridge(socket, gateway).await {
+                    debug!("WebSocket connection from {} closed: {}", addr, e);
+                }
+                if let Ok(Err(e)) = session.await {
+                    error!("Error handling WebSocket connection from {}: {}", addr, e);
+                }
+            });
+        }
+    }
+
+    /// Serve a connection until the client disconnects
+    pub(crate) async fn serve<S: AsyncRead + AsyncWrite + Unpin>(
+        self,
+        stream: S,
+        address: String,
+    ) -> Result<(), String> {
+        handle_connection(stream, &address, self).await
+    }
+}
+
+/// Socket-level settings of TCP connections; the OS defaults apply to
+/// anything left unset
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    nodelay: bool,
+    keepalive: Option<Duration>,
+    recv_buffer_size: Option<usize>,
+    send_buffer_size: Option<usize>,
+}
+
+impl SocketOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Disable Nagle's algorithm, so that small frames are sent right away
+    /// instead of waiting for the acknowledgement of the previous ones
+    pub fn with_nodelay(mut self, enabled: bool) -> Self {
+        self.nodelay = enabled;
+        self
+    }
+
+    /// Send keepalive probes once a connection has been silent for `idle`,
+    /// so that dead peers are detected
+    pub fn with_keepalive(mut self, idle: Duration) -> Self {
+        self.keepalive = Some(idle);
+        self
+    }
+
+    /// Size of the kernel receive buffer of a connection
+    pub fn with_recv_buffer_size(mut self, bytes: usize) -> Self {
+        self.recv_buffer_size = Some(bytes);
+        self
+    }
+
+    /// Size of the kernel send buffer of a connection
+    pub fn with_send_buffer_size(mut self, bytes: usize) -> Self {
+        self.send_buffer_size = Some(bytes);
+        self
+    }
+
+    /// Apply the options to a connected socket. Buffer sizes set after the
+    /// connection is established don't change the window scale negotiated
+    /// at connect time.
+    fn apply(&self, stream: &TcpStream) -> Result<(), String> {
+        let socket = socket2::SockRef::from(stream);
+        if self.nodelay {
+            socket
+                .set_nodelay(true)
+                .map_err(|e| format!("Failed to set TCP_NODELAY: {}", e))?;
+        }
+        if let Some(idle) = self.keepalive {
+            socket
+                .set_tcp_keepalive(&socket2::TcpKeepalive::new().with_time(idle))
+                .map_err(|e| format!("Failed to set SO_KEEPALIVE: {}", e))?;
+        }
+        if let Some(bytes) = self.recv_buffer_size {
+            socket
+                .set_recv_buffer_size(bytes)
+                .map_err(|e| format!("Failed to set SO_RCVBUF: {}", e))?;
+        }
+        if let Some(bytes) = self.send_buffer_size {
+            socket
+                .set_send_buffer_size(bytes)
+                .map_err(|e| format!("Failed to set SO_SNDBUF: {}", e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Keep the latest topology published by a source
+fn track_topology(
+    rx: broadcast::Receiver<ClusterTopology>,
+    current: Arc<RwLock<Option<ClusterTopology>>>,
+) {
+    tokio::spawn(async move {
+        let mut rx = Some(rx);
+        while let Some(change) = next_topology_change(&mut rx).await {
+            *current.write().unwrap() = Some(change);
+        }
+    });
 }
 
+/// Number of tagged requests of a connection executed at the same time;
+/// reading further requests waits for one of them to complete
+const MAX_TAGGED_IN_FLIGHT: usize = 1024;
+
+/// Size of the in-process pipe between a WebSocket and its session
+const WEBSOCKET_BUFFER: usize = 64 << 10;
+
+/// Size of the read and write buffers of an accepted connection
+const CONNECTION_BUFFER: usize = 64 << 10;
+
+/// Results per STREAM frame when the client doesn't choose
+const DEFAULT_CHUNK_SIZE: usize = 1000;
+
+/// Largest number of results per STREAM frame
+const MAX_CHUNK_SIZE: usize = 10_000;
+
 /// Handle a single TCP connection
-async fn handle_connection(mut stream: TcpStream, database: Arc<Database>) -> Result<(), String> {
-    let mut buffer = BytesMut::with_capacity(4096);
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    address: &str,
+    server: TcpServer,
+) -> Result<(), String> {
+    let TcpServer {
+        database,
+        topology,
+        current_topology,
+        admin_commands,
+        pubsub,
+        credentials,
+        clients,
+        raft,
+        ..
+    } = server;
+    let client = clients.register(address, database.namespace_name());
+    let mut stream = frame_connection(stream);
+    // Until authenticated, only small messages are read
+    if credentials.is_some() {
+        stream.codec_mut().max_message = MAX_UNAUTHENTICATED_MESSAGE;
+    }
+    // Identity authenticated with AUTH
+    let mut identity: Option<String> = None;
+    // Namespace the authenticated tenant is confined to
+    let mut tenant: Option<String> = None;
+    // Time of the last data received from the client
+    let mut last_read = Instant::now();
+    // Namespace selected by the client
+    let mut namespace = (*database).clone();
+    // Set once the client subscribes to topology changes at handshake
+    let mut topology_rx: Option<broadcast::Receiver<ClusterTopology>> = None;
+    // Set once the client subscribes to key events
+    let mut events: Option<EventSubscription> = None;
+    // Set once the client watches a key
+    let mut watch: Option<Watch> = None;
+    // Set once the client subscribes to a channel
+    let mut subscriptions: Option<Subscriptions> = None;
+    // Set once the client tails the change log
+    let mut changefeed: Option<Changefeed> = None;
+    // Responses of tagged requests, sent as they complete
+    let (responses_tx, mut responses_rx) = mpsc::unbounded_channel();
+    let tagged_in_flight = Arc::new(Semaphore::new(MAX_TAGGED_IN_FLIGHT));
 
     loop {
-        // Read data from socket
-        match stream.read_buf(&mut buffer).await {
-            Ok(0) => {
+        flush_responses(&mut stream).await?;
+        client.record_output(stream.codec().written);
+        // Read the next message, pushing topology changes and events while idle
+        let message = tokio::select! {
+            message = stream.next() => message,
+            Some((id, response)) = responses_rx.recv() => {
+                send_response(&mut stream, Some(id), response).await?;
+                continue;
+            }
+            Some(change) = next_topology_change(&mut topology_rx) => {
+                send_response(&mut stream, None, Response::ClusterTopologyChanged(change)).await?;
+                continue;
+            }
+            Some(event) = next_key_event(&mut events) => {
+                send_response(&mut stream, None, event).await?;
+                continue;
+            }
+            Some(update) = next_key_update(&mut watch, &database) => {
+                send_response(&mut stream, None, update).await?;
+                continue;
+            }
+            Some(mut message) = next_channel_message(&mut subscriptions) => {
+                if let Some(channel) = tenant
+                    .as_ref()
+                    .and_then(|tenant| message.channel.strip_prefix(&format!("{}/", tenant)))
+                {
+                    message.channel = channel.to_string();
+                }
+                send_response(&mut stream, None, Response::ChannelMessage(message)).await?;
+                continue;
+            }
+            Some(change) = next_change(&mut changefeed) => {
+                send_response(&mut stream, None, change).await?;
+                continue;
+            }
+            _ = idle(last_read, database.config().idle_timeout()) => {
+                info!("Closing connection idle for {:?}", last_read.elapsed());
+                break;
+            }
+            _ = client.killed() => {
+                info!("Closing connection {} from {} (CLIENT KILL)", client.id, address);
+                break;
+            }
+        };
+        let message = match message {
+            None => {
                 debug!("Connection closed by client");
                 break;
             }
-            Ok(n) => {
-                debug!("Received {} bytes", n);
+            Some(Ok(message)) => message,
+            Some(Err(e)) => return Err(format!("Read error: {}", e)),
+        };
+        debug!("Received {} bytes", message.size);
+        last_read = Instant::now();
+        client.record_request(message.size);
+
+        let id = message.id;
+        let command: Command = message.decode()?;
+        debug!("Received command: {}", command);
+
+        let authenticated = credentials.is_none() || identity.is_some();
+        // Grants are checked here for the commands answered by the connection
+        let denied = namespace.authorize(&command).err();
+
+        // Plain GETs are answered with the cached serialization of the document
+        if let (Command::Get { key }, true, None) = (&command, authenticated, &denied) {
+            if let Some(value) = namespace
+                .get_serialized(key)
+                .filter(|value| codec::fits(stream.codec().framing, value.len() + 7))
+            {
+                if let Some(delay) = database.injected_latency(LatencyTarget::Responses) {
+                    tokio::time::sleep(delay).await;
+                }
+                send_payload(&mut stream, id, vec![b"{\"Ok\":", &value, b"}"]).await?;
+                continue;
+            }
+        }
+
+        // Execute command
+        let response = match command {
+            Command::Auth { username, password } => match &credentials {
+                Some(credentials) => {
+                    match credentials.authenticate(username.as_deref(), &password) {
+                        Some(name) => {
+                            let bound = credentials.namespace_of(name);
+                            match bound
+                                .map_or(Ok(namespace.clone()), |bound| database.namespace(bound))
+                            {
+                                Ok(selected) => {
+                                    identity = Some(name.to_string());
+                                    tenant = bound.map(str::to_string);
+                                    namespace = selected.with_identity(name);
+                                    client.set_identity(name);
+                                    client.set_namespace(namespace.namespace_name());
+                                    let codec = stream.codec_mut();
+                                    codec.accepted.compress = codec.framing.compress;
+                                    codec.max_message = MAX_MESSAGE_SIZE;
+                                    Response::Ok(Some(serde_json::json!({
+                                        "identity": name,
+                                        "namespace": namespace.namespace_name(),
+                                    })))
+                                }
+                                Err(e) => Response::error(ErrorCode::Internal, e),
+                            }
+                        }
+                        None => Response::error(ErrorCode::Unauthenticated, "Invalid credentials"),
+                    }
+                }
+                None => Response::error(
+                    ErrorCode::Unsupported,
+                    "AUTH is not required: the server has no credentials",
+                ),
+            },
+            command
+                if !authenticated && !matches!(command, Command::Hello { .. } | Command::Ping) =>
+            {
+                Response::error(
+                    ErrorCode::Unauthenticated,
+                    format!("{} requires authentication (see AUTH)", command.name()),
+                )
+            }
+            _ if denied.is_some() => {
+                Response::error(ErrorCode::Forbidden, denied.unwrap_or_default())
+            }
+            command if command.is_admin() && !admin_commands => Response::error(
+                ErrorCode::Forbidden,
+                format!(
+                    "{} is an admin command (see --admin-commands)",
+                    command.name()
+                ),
+            ),
+            command if tenant.is_some() && !tenant_may_run(&command) => Response::error(
+                ErrorCode::Forbidden,
+                format!(
+                    "{} reaches beyond namespace '{}': tenants cannot run it",
+                    command.name(),
+                    tenant.as_deref().unwrap_or_default()
+                ),
+            ),
+            Command::Hello {
+                topology_updates,
+                protocol,
+                encodings,
+                compression,
+                chunked_frames,
+            } => match Handshake::negotiate(protocol, &encodings, &compression) {
+                Ok(mut handshake) => {
+                    topology_rx = match (&topology, topology_updates) {
+                        (Some(topology), true) => Some(topology.subscribe()),
+                        _ => None,
+                    };
+                    handshake.topology_updates = topology_rx.is_some();
+                    handshake.auth_required = !authenticated;
+                    handshake.chunked_frames = chunked_frames;
+                    let framing = Framing {
+                        compress: handshake.compression == "zstd",
+                        chunked: chunked_frames,
+                    };
+                    stream.codec_mut().framing = framing;
+                    // Compressed requests are decoded only once authenticated
+                    stream.codec_mut().accepted = Framing {
+                        compress: framing.compress && authenticated,
+                        ..framing
+                    };
+                    Response::Ok(serde_json::to_value(handshake).ok())
+                }
+                Err(e) => Response::error(ErrorCode::Unsupported, e),
+            },
+            Command::Select { namespace: name }
+                if tenant.as_ref().is_some_and(|tenant| *tenant != name) =>
+            {
+                Response::error(
+                    ErrorCode::Forbidden,
+                    format!(
+                        "'{}' is confined to namespace '{}'",
+                        identity.as_deref().unwrap_or_default(),
+                        tenant.as_deref().unwrap_or_default()
+                    ),
+                )
+            }
+            Command::Select { namespace: name } => match namespace.namespace(&name) {
+                Ok(selected) => {
+                    namespace = selected;
+                    client.set_namespace(&name);
+                    Response::Ok(None)
+                }
+                Err(e) => Response::error(ErrorCode::InvalidArgument, e),
+            },
+            Command::Info => {
+                let mut info = namespace.info();
+                info["clients"] = serde_json::json!({
+                    "connected": clients.len(),
+                });
+                if let Some(raft) = &raft {
+                    let metrics = raft.metrics().await;
+                    if info["replication"]["role"] == "primary" {
+                        info["replication"]["role"] = metrics.state.to_lowercase().into();
+                    }
+                    info["replication"]["raft"] = serde_json::to_value(metrics).unwrap_or_default();
+                }
+                Response::Ok(Some(info))
+            }
+            Command::ClientSetName { name } => {
+                client.set_name(name);
+                Response::Ok(None)
+            }
+            Command::ClientList => {
+                let visible: Vec<_> = clients
+                    .list()
+                    .into_iter()
+                    .filter(|report| owns_client(credentials.as_deref(), tenant.as_deref(), report))
+                    .collect();
+                Response::Ok(serde_json::to_value(visible).ok())
+            }
+            Command::ClientKill { id }
+                if clients.list().iter().any(|report| {
+                    report.id == id
+                        && owns_client(credentials.as_deref(), tenant.as_deref(), report)
+                }) && clients.kill(id) =>
+            {
+                Response::Ok(None)
+            }
+            Command::ClientKill { id } => Response::error(
+                ErrorCode::InvalidArgument,
+                format!("No client with id {}", id),
+            ),
+            // Tenants only read the records of their namespace
+            Command::Audit {
+                since,
+                identity,
+                key_pattern,
+                limit,
+            } if tenant.is_some() => namespace.audit_records(
+                since,
+                identity.as_deref(),
+                tenant.as_deref(),
+                key_pattern.as_deref(),
+                limit,
+            ),
+            Command::RoutingTable => {
+                let current = current_topology.read().unwrap();
+                let table = RoutingTable::from_topology(current.as_ref());
+                Response::Ok(serde_json::to_value(table).ok())
+            }
+            Command::Watch { key } => {
+                let watch = watch.get_or_insert_with(|| Watch {
+                    keys: HashMap::new(),
+                    rx: database.subscribe_events(),
+                    pending: VecDeque::new(),
+                });
+                watch
+                    .keys
+                    .entry(namespace.namespace_name().to_string())
+                    .or_default()
+                    .insert(key.clone());
+                // Read after subscribing, so that no change is missed
+                Response::Ok(namespace.value(&key))
+            }
+            Command::SubscribeEvents { pattern } => {
+                events = Some(EventSubscription {
+                    namespace: namespace.namespace_name().to_string(),
+                    pattern,
+                    rx: database.subscribe_events(),
+                });
+                Response::Ok(None)
             }
-            Err(e) => return Err(format!("Read error: {}", e)),
+            Command::Changefeed { from_offset } => match namespace.check_changefeed(from_offset) {
+                Ok(()) => {
+                    changefeed = Some(Changefeed {
+                        namespace: namespace.clone(),
+                        offset: from_offset,
+                    });
+                    Response::Ok(None)
+                }
+                Err(response) => response,
+            },
+            Command::Publish { channel, message } => {
+                let channel = tenant_channel(tenant.as_deref(), &channel);
+                let receivers = pubsub.publish(&channel, message);
+                Response::Ok(Some(receivers.into()))
+            }
+            Command::Stream {
+                command,
+                chunk_size,
+            } => match ResultCursor::open(&namespace, *command) {
+                Ok(mut cursor) => {
+                    let chunk_size = chunk_size
+                        .unwrap_or(DEFAULT_CHUNK_SIZE)
+                        .clamp(1, MAX_CHUNK_SIZE);
+                    loop {
+                        match cursor.next_chunk(chunk_size) {
+                            Ok(chunk) if chunk.is_empty() => {
+                                break Response::Ok(Some(
+                                    serde_json::json!({ "count": cursor.produced }),
+                                ))
+                            }
+                            Ok(chunk) => {
+                                send_response(&mut stream, id, Response::Chunk(chunk)).await?
+                            }
+                            Err(e) => break Response::error(ErrorCode::InvalidQuery, e),
+                        }
+                    }
+                }
+                Err(response) => response,
+            },
+            Command::Subscribe { channels } => {
+                let subscriptions = subscriptions.get_or_insert_with(Subscriptions::new);
+                for channel in &channels {
+                    let channel = tenant_channel(tenant.as_deref(), channel);
+                    subscriptions.subscribe(&pubsub, &channel);
+                }
+                Response::Ok(None)
+            }
+            command => match id {
+                // Tagged requests run concurrently, answered as they complete
+                Some(id) => {
+                    let permit = Arc::clone(&tagged_in_flight)
+                        .acquire_owned()
+                        .await
+                        .expect("the semaphore is never closed");
+                    let namespace = namespace.clone();
+                    let database = Arc::clone(&database);
+                    let responses = responses_tx.clone();
+                    tokio::spawn(async move {
+                        let response = namespace.execute_command(command).await;
+                        debug!("Response: {}", response);
+                        if let Some(delay) = database.injected_latency(LatencyTarget::Responses) {
+                            tokio::time::sleep(delay).await;
+                        }
+                        let _ = responses.send((id, response));
+                        drop(permit);
+                    });
+                    continue;
+                }
+                None => namespace.execute_command(command).await,
+            },
+        };
+        debug!("Response: {}", response);
+
+        // Simulates a slow node during game days
+        if let Some(delay) = database.injected_latency(LatencyTarget::Responses) {
+            tokio::time::sleep(delay).await;
         }
 
-        // Process messages in buffer
-        while let Some((command, remaining)) = parse_message(&buffer)? {
-            buffer = remaining;
+        // Send response
+        send_response(&mut stream, id, response).await?;
+    }
 
-            debug!("Received command: {}", command);
+    Ok(())
+}
 
-            // Execute command
-            let response = database.execute_command(command).await;
-            debug!("Response: {}", response);
+/// Wait until a connection has received nothing for `timeout` since
+/// `last_read`; never resolves without a timeout
+async fn idle(last_read: Instant, timeout: Option<Duration>) {
+    match timeout {
+        Some(timeout) => tokio::time::sleep_until((last_read + timeout).into()).await,
+        None => std::future::pending().await,
+    }
+}
 
-            // Send response
-            send_response(&mut stream, response).await?;
+/// Wait for the next topology change; never resolves without a subscription
+async fn next_topology_change(
+    topology_rx: &mut Option<broadcast::Receiver<ClusterTopology>>,
+) -> Option<ClusterTopology> {
+    let Some(rx) = topology_rx else {
+        return std::future::pending().await;
+    };
+    loop {
+        match rx.recv().await {
+            Ok(change) => return Some(change),
+            // Only the latest topology matters to the client
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => {
+                *topology_rx = None;
+                return None;
+            }
         }
     }
+}
 
-    Ok(())
+/// Key events a connection subscribed to
+struct EventSubscription {
+    namespace: String,
+    /// Glob the keys must match
+    pattern: String,
+    rx: broadcast::Receiver<KeyEvent>,
 }
 
-/// Simple communication protocol based on length + payload
-/// Format: [length:4 bytes][JSON payload]
-fn parse_message(buffer: &BytesMut) -> Result<Option<(Command, BytesMut)>, String> {
-    if buffer.len() < 4 {
-        return Ok(None); // Not enough data for length
+/// Wait for the next event frame of a subscription; never resolves without one
+async fn next_key_event(events: &mut Option<EventSubscription>) -> Option<Response> {
+    let Some(subscription) = events else {
+        return std::future::pending().await;
+    };
+    loop {
+        match subscription.rx.recv().await {
+            Ok(event)
+                if event.namespace == subscription.namespace
+                    && glob::glob_match(&subscription.pattern, &event.key) =>
+            {
+                return Some(Response::KeyEvent(event));
+            }
+            Ok(_) => continue,
+            // Unlike topology changes every event matters: tell the client
+            Err(broadcast::error::RecvError::Lagged(count)) => {
+                return Some(Response::KeyEventsDropped(count));
+            }
+            Err(broadcast::error::RecvError::Closed) => {
+                *events = None;
+                return None;
+            }
+        }
     }
+}
+
+/// Produces the results of a streamed QSCAN or EXPORT key by key, so that
+/// neither end of the connection holds more than a chunk of them.
+///
+/// Only the matching keys are collected upfront: keys written after the
+/// cursor is opened are not streamed, and streamed values are read as the
+/// cursor reaches them rather than at a single point in time.
+struct ResultCursor {
+    database: Database,
+    keys: std::vec::IntoIter<String>,
+    source: CursorSource,
+    /// Largest number of results to produce
+    limit: Option<usize>,
+    /// Number of results produced so far
+    produced: usize,
+}
+
+enum CursorSource {
+    QScan(jsonpath_lib::Compiled),
+    Export,
+}
 
-    let mut length_bytes = [0u8; 4];
-    length_bytes.copy_from_slice(&buffer[0..4]);
-    let message_length = u32::from_be_bytes(length_bytes) as usize;
+impl ResultCursor {
+    /// Open a cursor on the results of a command, or return the response
+    /// refusing it
+    fn open(database: &Database, command: Command) -> Result<Self, Response> {
+        let (pattern, source, limit) = match command {
+            Command::QScan {
+                key_pattern,
+                query,
+                limit,
+            } => match jsonpath_lib::Compiled::compile(&query) {
+                Ok(compiled) => (key_pattern, CursorSource::QScan(compiled), limit),
+                Err(e) => {
+                    return Err(Response::error(
+                        ErrorCode::InvalidQuery,
+                        format!("JSONPath query error: {}", e),
+                    ))
+                }
+            },
+            Command::Export { pattern } => (pattern, CursorSource::Export, None),
+            command => {
+                return Err(Response::error(
+                    ErrorCode::Unsupported,
+                    format!(
+                        "{} can't be streamed: only QSCAN and EXPORT",
+                        command.name()
+                    ),
+                ))
+            }
+        };
+        Ok(Self {
+            database: database.clone(),
+            keys: database.matching_keys(&pattern).into_iter(),
+            source,
+            limit,
+            produced: 0,
+        })
+    }
 
-    if buffer.len() < 4 + message_length {
-        return Ok(None); // Not enough data for complete message
+    /// Returns up to `size` results, none once the cursor is exhausted
+    fn next_chunk(&mut self, size: usize) -> Result<Vec<Value>, String> {
+        let mut chunk = Vec::new();
+        while chunk.len() < size && self.limit.is_none_or(|limit| self.produced < limit) {
+            let Some(key) = self.keys.next() else {
+                break;
+            };
+            let result = match &self.source {
+                CursorSource::QScan(compiled) => self.database.qscan_match(&key, compiled)?,
+                CursorSource::Export => self
+                    .database
+                    .export_record(&key)
+                    .map_err(|e| e.message)?
+                    .and_then(|record| serde_json::to_value(record).ok()),
+            };
+            if let Some(result) = result {
+                chunk.push(result);
+                self.produced += 1;
+            }
+        }
+        Ok(chunk)
     }
+}
 
-    // Extract the payload
-    let payload = &buffer[4..4 + message_length];
+/// Returns false for the commands tenants cannot run: the admin commands
+/// acting on the whole server and the server-wide reports. CLIENT LIST,
+/// CLIENT KILL and AUDIT are scoped to the tenant instead.
+fn tenant_may_run(command: &Command) -> bool {
+    match command {
+        Command::ClientList | Command::ClientKill { .. } | Command::Audit { .. } => true,
+        Command::Info | Command::Stats => false,
+        command => !command.is_admin(),
+    }
+}
 
-    // Deserialize the command using JSON
-    let payload_str =
-        std::str::from_utf8(payload).map_err(|e| format!("Non-UTF-8 payload: {}", e))?;
-    let command: Command = serde_json::from_str(payload_str)
-        .map_err(|e| format!("JSON deserialization error: {}", e))?;
+/// Returns true if a connection is visible to a tenant (every connection
+/// is, to identities that are not tenants): its identity is bound to the
+/// tenant's namespace
+fn owns_client(
+    credentials: Option<&Credentials>,
+    tenant: Option<&str>,
+    report: &ClientReport,
+) -> bool {
+    let Some(tenant) = tenant else {
+        return true;
+    };
+    let bound = report
+        .identity
+        .as_deref()
+        .and_then(|identity| credentials?.namespace_of(identity));
+    bound == Some(tenant)
+}
 
-    // Create the remaining buffer
-    let mut remaining = BytesMut::new();
-    if buffer.len() > 4 + message_length {
-        remaining.extend_from_slice(&buffer[4 + message_length..]);
+/// Channels of tenants are scoped to their namespace, so that tenants only
+/// exchange messages with their own connections
+fn tenant_channel(tenant: Option<&str>, channel: &str) -> String {
+    match tenant {
+        Some(tenant) => format!("{}/{}", tenant, channel),
+        None => channel.to_string(),
     }
+}
+
+/// Wait for the next message of a subscribed channel; never resolves
+/// without subscriptions
+async fn next_channel_message(subscriptions: &mut Option<Subscriptions>) -> Option<ChannelMessage> {
+    let Some(subscriptions) = subscriptions else {
+        return std::future::pending().await;
+    };
+    subscriptions.recv().await
+}
+
+/// Change log a connection tails
+struct Changefeed {
+    namespace: Database,
+    /// Offset of the last pushed change
+    offset: u64,
+}
+
+/// Wait for the next change of a changefeed; never resolves without one.
+///
+/// A feed that falls behind the capacity of the change log ends with an error.
+async fn next_change(changefeed: &mut Option<Changefeed>) -> Option<Response> {
+    let Some(feed) = changefeed else {
+        return std::future::pending().await;
+    };
+    let error = loop {
+        let log = feed.namespace.change_log();
+        let appended = log.appended();
+        tokio::pin!(appended);
+        appended.as_mut().enable();
+        match log.read(feed.offset, 1) {
+            Ok(changes) => match changes.into_iter().next() {
+                Some(change) => {
+                    feed.offset = change.offset;
+                    return Some(Response::Change(change));
+                }
+                None => appended.await,
+            },
+            Err(e) => break e,
+        }
+    };
+    *changefeed = None;
+    Some(Response::error(ErrorCode::InvalidArgument, error))
+}
 
-    Ok(Some((command, remaining)))
+/// Keys a connection watches
+struct Watch {
+    /// Watched keys by namespace
+    keys: HashMap<String, HashSet<String>>,
+    rx: broadcast::Receiver<KeyEvent>,
+    /// Keys whose value must be pushed again after the watcher fell behind
+    pending: VecDeque<(String, String)>,
 }
 
-/// Send a response to the client
-async fn send_response(stream: &mut TcpStream, response: Response) -> Result<(), String> {
-    // Serialize response using JSON
-    let payload_str = serde_json::to_string(&response)
-        .map_err(|e| format!("JSON serialization error: {}", e))?;
-    let payload = payload_str.as_bytes();
-    let payload_length = payload.len() as u32;
+/// Wait for the next change to a watched key and return its current value;
+/// never resolves without watched keys
+async fn next_key_update(watch: &mut Option<Watch>, database: &Database) -> Option<Response> {
+    let Some(state) = watch else {
+        return std::future::pending().await;
+    };
+    loop {
+        if let Some((namespace, key)) = state.pending.pop_front() {
+            return Some(key_update(database, namespace, key));
+        }
+        match state.rx.recv().await {
+            Ok(event)
+                if state
+                    .keys
+                    .get(&event.namespace)
+                    .is_some_and(|keys| keys.contains(&event.key)) =>
+            {
+                return Some(key_update(database, event.namespace, event.key));
+            }
+            Ok(_) => continue,
+            // Changes were missed: push the current value of every watched key
+            Err(broadcast::error::RecvError::Lagged(_)) => {
+                state.pending = state
+                    .keys
+                    .iter()
+                    .flat_map(|(namespace, keys)| {
+                        keys.iter().map(|key| (namespace.clone(), key.clone()))
+                    })
+                    .collect();
+            }
+            Err(broadcast::error::RecvError::Closed) => {
+                *watch = None;
+                return None;
+            }
+        }
+    }
+}
 
-    // Create message with length + payload
-    let mut message = BytesMut::with_capacity(4 + payload.len());
-    message.put_u32(payload_length);
-    message.extend_from_slice(payload);
+/// Build the frame pushing the current value of a watched key
+fn key_update(database: &Database, namespace: String, key: String) -> Response {
+    let value = database
+        .namespace(&namespace)
+        .ok()
+        .and_then(|database| database.value(&key));
+    Response::KeyUpdated(KeyUpdate {
+        namespace,
+        key,
+        value,
+    })
+}
 
-    // Send the message
+/// Frame a connection accepted by a server or a proxy.
+///
+/// Pipelined requests are read, and their responses written, a buffer at a
+/// time rather than with a syscall per frame: responses are buffered by
+/// `send_response` and written by `flush_responses`.
+fn frame_connection<S: AsyncRead + AsyncWrite>(stream: S) -> Framed<S, FrameCodec> {
+    let mut stream = Framed::with_capacity(stream, FrameCodec::default(), CONNECTION_BUFFER);
+    stream.set_backpressure_boundary(CONNECTION_BUFFER);
     stream
-        .write_all(&message)
-        .await
-        .map_err(|e| format!("Send error: {}", e))?;
+}
+
+/// Write the buffered responses, unless another request is already read:
+/// its response is then written with them
+async fn flush_responses<S: AsyncWrite + Unpin>(
+    stream: &mut Framed<S, FrameCodec>,
+) -> Result<(), String> {
+    if stream.write_buffer().is_empty() || codec::message_ready(stream.read_buffer()) {
+        return Ok(());
+    }
     stream
         .flush()
         .await
-        .map_err(|e| format!("Flush error: {}", e))?;
+        .map_err(|e| format!("Flush error: {}", e))
+}
 
-    Ok(())
+/// Buffer a response to the client, with the id of the request it answers
+async fn send_response<S: AsyncWrite + Unpin>(
+    stream: &mut Framed<S, FrameCodec>,
+    id: Option<u64>,
+    response: Response,
+) -> Result<(), String> {
+    // Serialize response using JSON, in pieces to split across frames
+    let mut message = Serialized::new(&response)?;
+    if !codec::fits(stream.codec().framing, message.len()) {
+        message = Serialized::new(&Response::error(
+            ErrorCode::InvalidArgument,
+            format!(
+                "Response of {} bytes exceeds a frame: negotiate chunked frames",
+                message.len()
+            ),
+        ))?;
+    }
+    stream
+        .feed(message.payload(id))
+        .await
+        .map_err(|e| format!("Send error: {}", e))
 }
 
-/// TCP client for JSON database
-pub struct TcpClient {
-    stream: TcpStream,
+/// Buffer a serialized response, made of consecutive parts, to the client
+async fn send_payload<S: AsyncWrite + Unpin>(
+    stream: &mut Framed<S, FrameCodec>,
+    id: Option<u64>,
+    parts: Vec<&[u8]>,
+) -> Result<(), String> {
+    stream
+        .feed(Payload { id, parts })
+        .await
+        .map_err(|e| format!("Send error: {}", e))
 }
 
-impl TcpClient {
-    /// Connect to server
-    pub async fn connect(address: &str) -> Result<Self, String> {
-        let stream = TcpStream::connect(address)
-            .await
-            .map_err(|e| format!("Connection failed: {}", e))?;
-        info!("Connected to server {}", address);
-        Ok(Self { stream })
-    }
+/// TCP proxy exposing a single endpoint in front of several servers.
+///
+/// Each client connection is forwarded to one backend at a time and fails
+/// over to the next one when it becomes unreachable. Reads are retried on
+/// failover; writes are only retried when the server reports a retriable
+/// error, since a write lost in transit may already have been applied.
+pub struct TcpProxy {
+    address: String,
+    backends: Arc<Vec<String>>,
+    max_retries: usize,
+    retry_backoff: Duration,
+}
 
-    /// Send a command and receive the response
-    pub async fn send_command(&mut self, command: Command) -> Result<Response, String> {
-        debug!("Sending command: {}", command);
+impl TcpProxy {
+    /// Create a proxy forwarding to `backends`, tried in order
+    pub fn new(address: String, backends: Vec<String>) -> Self {
+        Self {
+            address,
+            backends: Arc::new(backends),
+            max_retries: 3,
+            retry_backoff: Duration::from_millis(100),
+        }
+    }
 
-        // Serialize command using JSON
-        let payload_str = serde_json::to_string(&command)
-            .map_err(|e| format!("JSON serialization error: {}", e))?;
-        let payload = payload_str.as_bytes();
-        let payload_length = payload.len() as u32;
+    /// Set how many times a request is retried and the delay between attempts
+    pub fn with_retries(mut self, max_retries: usize, retry_backoff: Duration) -> Self {
+        self.max_retries = max_retries;
+        self.retry_backoff = retry_backoff;
+        self
+    }
 
-        // Create message with length + payload
-        let mut message = BytesMut::with_capacity(4 + payload.len());
-        message.put_u32(payload_length);
-        message.extend_from_slice(payload);
+    /// Start the proxy
+    pub async fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.backends.is_empty() {
+            return Err("The proxy needs at least one backend".into());
+        }
+        let listener = TcpListener::bind(&self.address).await?;
+        info!(
+            "Proxy started on {}, forwarding to {}",
+            self.address,
+            self.backends.join(", ")
+        );
 
-        // Send the message
-        self.stream
-            .write_all(&message)
-            .await
-            .map_err(|e| format!("Send error: {}", e))?;
-        self.stream
-            .flush()
-            .await
-            .map_err(|e| format!("Flush error: {}", e))?;
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) => {
+                    debug!("New proxied connection from {}", addr);
+                    let connection = ProxyConnection {
+                        backends: Arc::clone(&self.backends),
+                        max_retries: self.max_retries,
+                        retry_backoff: self.retry_backoff,
+                        backend: None,
+                        next_backend: 0,
+                        namespace: None,
+                        auth: None,
+                    };
+                    tokio::spawn(async move {
+                        if let Err(e) = connection.run(stream).await {
+                            error!("Error proxying connection from {}: {}", addr, e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    error!("Error accepting connection: {}", e);
+                }
+            }
+        }
+    }
+}
 
-        // Receive the response
-        let response = self.receive_response().await?;
-        debug!("Response received: {}", response);
+/// State of a single proxied client connection
+struct ProxyConnection {
+    backends: Arc<Vec<String>>,
+    max_retries: usize,
+    retry_backoff: Duration,
+    /// Connection to the current backend
+    backend: Option<TcpClient>,
+    /// Index of the backend to connect to next
+    next_backend: usize,
+    /// Namespace selected by the client, restored on every backend connection
+    namespace: Option<String>,
+    /// Credentials the client authenticated with, presented again on every
+    /// backend connection
+    auth: Option<(Option<String>, String)>,
+}
 
-        Ok(response)
+impl ProxyConnection {
+    /// Forward commands from the client until it disconnects
+    async fn run(mut self, stream: TcpStream) -> Result<(), String> {
+        let mut stream = frame_connection(stream);
+        loop {
+            flush_responses(&mut stream).await?;
+            let Some(message) = stream.next().await else {
+                break;
+            };
+            let message = message.map_err(|e| format!("Read error: {}", e))?;
+            let id = message.id;
+            let command: Command = message.decode()?;
+            let response = match command {
+                Command::SubscribeEvents { .. }
+                | Command::Watch { .. }
+                | Command::Changefeed { .. } => Response::error(
+                    ErrorCode::Unsupported,
+                    "The proxy does not relay key events: subscribe on a server",
+                ),
+                Command::Stream { .. } => Response::error(
+                    ErrorCode::Unsupported,
+                    "The proxy does not relay streamed results: stream from a server",
+                ),
+                Command::Subscribe { .. } => Response::error(
+                    ErrorCode::Unsupported,
+                    "The proxy does not relay channel messages: subscribe on a server",
+                ),
+                // The proxy does not relay topology changes
+                Command::Hello {
+                    protocol,
+                    encodings,
+                    compression,
+                    ..
+                } => match Handshake::negotiate(protocol, &encodings, &compression) {
+                    Ok(handshake) => {
+                        let codec = stream.codec_mut();
+                        codec.framing.compress = handshake.compression == "zstd";
+                        codec.accepted.compress = codec.framing.compress;
+                        Response::Ok(serde_json::to_value(handshake).ok())
+                    }
+                    Err(e) => Response::error(ErrorCode::Unsupported, e),
+                },
+                Command::Select { namespace } => {
+                    let response = self
+                        .forward(Command::Select {
+                            namespace: namespace.clone(),
+                        })
+                        .await;
+                    if matches!(response, Response::Ok(_)) {
+                        self.namespace = Some(namespace);
+                    }
+                    response
+                }
+                Command::Auth { username, password } => {
+                    let response = self
+                        .forward(Command::Auth {
+                            username: username.clone(),
+                            password: password.clone(),
+                        })
+                        .await;
+                    if matches!(response, Response::Ok(_)) {
+                        self.auth = Some((username, password));
+                    }
+                    response
+                }
+                command => self.forward(command).await,
+            };
+            send_response(&mut stream, id, response).await?;
+        }
+        if let Some(backend) = self.backend.take() {
+            let _ = backend.close().await;
+        }
+        Ok(())
     }
 
-    /// Receive a response from the server
-    async fn receive_response(&mut self) -> Result<Response, String> {
-        // Read the length
-        let mut length_bytes = [0u8; 4];
-        self.stream
-            .read_exact(&mut length_bytes)
-            .await
-            .map_err(|e| format!("Length read error: {}", e))?;
-        let message_length = u32::from_be_bytes(length_bytes) as usize;
-
-        // Read the payload
-        let mut payload = vec![0u8; message_length];
-        self.stream
-            .read_exact(&mut payload)
-            .await
-            .map_err(|e| format!("Payload read error: {}", e))?;
+    /// Send a command to the current backend, failing over and retrying as allowed
+    async fn forward(&mut self, command: Command) -> Response {
+        let mut last_error = String::new();
+        for attempt in 0..=self.max_retries {
+            if attempt > 0 {
+                tokio::time::sleep(self.retry_backoff).await;
+            }
+            let backend = match self.backend.as_mut() {
+                Some(backend) => backend,
+                None => match self.connect().await {
+                    Ok(backend) => self.backend.insert(backend),
+                    Err(e) => {
+                        last_error = e;
+                        continue;
+                    }
+                },
+            };
 
-        // Deserialize response using JSON
-        let payload_str =
-            std::str::from_utf8(&payload).map_err(|e| format!("Non-UTF-8 payload: {}", e))?;
-        let response: Response = serde_json::from_str(payload_str)
-            .map_err(|e| format!("JSON deserialization error: {}", e))?;
-        Ok(response)
+            match backend.send_command(command.clone()).await {
+                Ok(Response::Error(e)) if e.retriable && attempt < self.max_retries => {
+                    // Another backend may be able to serve the request
+                    last_error = e.message;
+                    self.backend = None;
+                }
+                Ok(response) => return response,
+                Err(e) => {
+                    warn!("Lost connection to backend: {}", e);
+                    self.backend = None;
+                    if command.is_write() {
+                        return Response::error(
+                            ErrorCode::Unavailable,
+                            format!(
+                                "Backend connection lost, the write may have been applied: {}",
+                                e
+                            ),
+                        );
+                    }
+                    last_error = e;
+                }
+            }
+        }
+        Response::Error(
+            ErrorInfo::new(
+                ErrorCode::Unavailable,
+                format!("No backend available: {}", last_error),
+            )
+            .retriable(),
+        )
     }
 
-    /// Close the connection
-    pub async fn close(mut self) -> Result<(), String> {
-        self.stream
-            .shutdown()
-            .await
-            .map_err(|e| format!("Close error: {}", e))?;
-        Ok(())
+    /// Connect to the next backend, restoring the selected namespace
+    async fn connect(&mut self) -> Result<TcpClient, String> {
+        let address = &self.backends[self.next_backend % self.backends.len()];
+        self.next_backend += 1;
+        let mut backend = TcpClient::connect(address).await?;
+        if let Some((username, password)) = &self.auth {
+            backend.auth(username.as_deref(), password).await?;
+        }
+        if let Some(namespace) = &self.namespace {
+            backend.select(namespace).await?;
+        }
+        Ok(backend)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::protocol::Command;
-    use serde_json::json;
-    use std::time::Duration;
-    use tokio::time::sleep;
+/// Results of a request sent with `TcpClient::stream`, read chunk by chunk
+pub struct ResultStream<'a> {
+    client: &'a mut TcpClient,
+}
 
-    #[tokio::test]
-    async fn test_tcp_communication() {
-        // Start the server in background
-        let database = Arc::new(Database::new());
-        let _server = TcpServer::new(database, "127.0.0.1:0".to_string());
+impl ResultStream<'_> {
+    /// Returns the next chunk of results, None once all of them are read
+    pub async fn next_chunk(&mut self) -> Result<Option<Vec<Value>>, String> {
+        if self.client.streaming.is_none() {
+            return Ok(None);
+        }
+        let response = self.client.read_stream_reply().await?;
+        if let Response::Chunk(results) = response {
+            return Ok(Some(results));
+        }
+        self.client.connection.in_flight = false;
+        match response {
+            Response::Ok(_) => Ok(None),
+            Response::Error(e) => Err(e.to_string()),
+            other => Err(format!("Unexpected response: {}", other)),
+        }
+    }
+}
+
+/// Socket of a client connection, in plain text or over TLS
+enum ClientStream {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<tokio_rustls::client::TlsStream<TcpStream>>),
+}
+
+impl ClientStream {
+    /// Returns the underlying TCP socket
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            ClientStream::Plain(stream) => stream,
+            #[cfg(feature = "tls")]
+            ClientStream::Tls(stream) => stream.get_ref().0,
+        }
+    }
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => std::pin::Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            ClientStream::Tls(stream) => std::pin::Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => std::pin::Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            ClientStream::Tls(stream) => std::pin::Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => std::pin::Pin::new(stream).poll_write_vectored(cx, bufs),
+            #[cfg(feature = "tls")]
+            ClientStream::Tls(stream) => std::pin::Pin::new(stream).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            ClientStream::Plain(stream) => stream.is_write_vectored(),
+            #[cfg(feature = "tls")]
+            ClientStream::Tls(stream) => stream.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => std::pin::Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "tls")]
+            ClientStream::Tls(stream) => std::pin::Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => std::pin::Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "tls")]
+            ClientStream::Tls(stream) => std::pin::Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Owns a client connection and shuts it down when dropped.
+///
+/// Dropping the guard inside a Tokio runtime flushes and shuts the socket
+/// down on a background task; outside a runtime the socket is simply closed.
+struct ConnectionGuard {
+    stream: Option<Framed<ClientStream, FrameCodec>>,
+    address: String,
+    /// Set while a request is written but its response not yet read
+    in_flight: bool,
+}
+
+impl ConnectionGuard {
+    fn new(stream: ClientStream, address: String) -> Self {
+        Self {
+            stream: Some(Framed::new(stream, FrameCodec::default())),
+            address,
+            in_flight: false,
+        }
+    }
+
+    /// Returns the framed stream
+    fn stream(&mut self) -> Result<&mut Framed<ClientStream, FrameCodec>, String> {
+        self.stream
+            .as_mut()
+            .ok_or_else(|| "Connection closed".to_string())
+    }
+
+    /// Flush and shut down the connection
+    async fn close(&mut self) -> Result<(), String> {
+        if let Some(mut stream) = self.stream.take() {
+            stream
+                .close()
+                .await
+                .map_err(|e| format!("Close error: {}", e))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let Some(mut stream) = self.stream.take() else {
+            return;
+        };
+        if self.in_flight {
+            warn!(
+                "Connection to {} dropped with a request in flight; the response is lost",
+                self.address
+            );
+        } else {
+            debug!("Connection to {} dropped without close()", self.address);
+        }
+
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                let address = std::mem::take(&mut self.address);
+                handle.spawn(async move {
+                    if let Err(e) = stream.close().await {
+                        debug!(
+                            "Shutdown of dropped connection to {} failed: {}",
+                            address, e
+                        );
+                    }
+                });
+            }
+            // No runtime to run the shutdown on: dropping the socket closes it
+            Err(_) => drop(stream),
+        }
+    }
+}
+
+/// Number of BULKLOAD frames a client sends ahead of their responses
+const BULK_LOAD_WINDOW: usize = 4;
+
+/// Number of batched requests a client sends ahead of their responses
+const BATCH_WINDOW: usize = 128;
+
+/// TCP client for JSON database.
+///
+/// Requests need exclusive access to the client: to share a connection
+/// across tasks, turn it into a `MultiplexedClient` with `multiplex`, or
+/// give each task a connection of its own from a `TcpClientPool`.
+pub struct TcpClient {
+    connection: ConnectionGuard,
+    hooks: Option<Arc<dyn ClientHooks>>,
+    topology: Option<ClusterTopology>,
+    /// Event frames received while waiting for responses
+    events: VecDeque<Response>,
+    /// Streamed request whose chunks are not all read
+    streaming: Option<PendingRequest>,
+    /// Set once a command changed the state of the connection or subscribed
+    /// it to pushes, so that a pool doesn't hand it out again
+    bound: bool,
+}
+
+/// A request written to the connection, reported to the client hooks once
+/// its response is read
+struct PendingRequest {
+    command: &'static str,
+    bytes_sent: usize,
+    /// Size of the chunks of a streamed result read so far
+    bytes_received: usize,
+    started_at: Instant,
+}
+
+impl TcpClient {
+    /// Connect to server
+    pub async fn connect(address: &str) -> Result<Self, String> {
+        let stream = TcpStream::connect(address)
+            .await
+            .map_err(|e| format!("Connection failed: {}", e))?;
+        info!("Connected to server {}", address);
+        Ok(Self::new(ClientStream::Plain(stream), address))
+    }
+
+    /// Connect to server over TLS, verifying its certificate with `tls`
+    #[cfg(feature = "tls")]
+    pub async fn connect_tls(address: &str, tls: &crate::tls::TlsConfig) -> Result<Self, String> {
+        let (connector, server_name) = tls.connector(address)?;
+        let stream = TcpStream::connect(address)
+            .await
+            .map_err(|e| format!("Connection failed: {}", e))?;
+        let stream = connector
+            .connect(server_name, stream)
+            .await
+            .map_err(|e| format!("TLS handshake failed: {}", e))?;
+        info!("Connected to server {} over TLS", address);
+        Ok(Self::new(ClientStream::Tls(Box::new(stream)), address))
+    }
+
+    fn new(stream: ClientStream, address: &str) -> Self {
+        Self {
+            connection: ConnectionGuard::new(stream, address.to_string()),
+            hooks: None,
+            topology: None,
+            events: VecDeque::new(),
+            streaming: None,
+            bound: false,
+        }
+    }
+
+    /// Install instrumentation hooks invoked around every request
+    pub fn with_hooks(mut self, hooks: Arc<dyn ClientHooks>) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    /// Tune the socket of the connection
+    pub fn with_socket_options(mut self, options: &SocketOptions) -> Result<Self, String> {
+        options.apply(self.connection.stream()?.get_ref().tcp())?;
+        Ok(self)
+    }
+
+    /// Negotiate the protocol version, encoding and compression of this
+    /// connection, offering everything this build supports
+    pub async fn hello(&mut self) -> Result<Handshake, String> {
+        self.handshake(false).await
+    }
+
+    /// Ask the server to push cluster topology changes on this connection.
+    ///
+    /// Returns false if the server has no topology to publish. Pushed changes
+    /// are picked up while waiting for responses and reported through
+    /// `ClientHooks::on_topology_changed` and `cluster_topology`.
+    pub async fn subscribe_topology(&mut self) -> Result<bool, String> {
+        Ok(self.handshake(true).await?.topology_updates)
+    }
+
+    async fn handshake(&mut self, topology_updates: bool) -> Result<Handshake, String> {
+        let offer = |supported: &[&str]| supported.iter().map(|s| s.to_string()).collect();
+        match self
+            .send_command(Command::Hello {
+                topology_updates,
+                protocol: Some(ProtocolVersion::current()),
+                encodings: offer(ENCODINGS),
+                compression: offer(COMPRESSIONS),
+                chunked_frames: true,
+            })
+            .await?
+        {
+            Response::Ok(Some(ack)) => {
+                let handshake: Handshake = serde_json::from_value(ack)
+                    .map_err(|e| format!("Invalid handshake response: {}", e))?;
+                let framing = Framing {
+                    compress: handshake.compression == "zstd",
+                    chunked: handshake.chunked_frames,
+                };
+                let codec = self.connection.stream()?.codec_mut();
+                codec.framing = framing;
+                codec.accepted = framing;
+                Ok(handshake)
+            }
+            Response::Error(e) => Err(e.to_string()),
+            other => Err(format!("Unexpected handshake response: {}", other)),
+        }
+    }
+
+    /// Authenticate the connection with a password or an API key, returning
+    /// the authenticated identity
+    pub async fn auth(&mut self, username: Option<&str>, password: &str) -> Result<String, String> {
+        match self
+            .send_command(Command::Auth {
+                username: username.map(str::to_string),
+                password: password.to_string(),
+            })
+            .await?
+        {
+            Response::Ok(Some(reply)) => {
+                Ok(reply["identity"].as_str().unwrap_or_default().to_string())
+            }
+            Response::Error(e) => Err(e.to_string()),
+            other => Err(format!("Unexpected response: {}", other)),
+        }
+    }
+
+    /// Switch this connection to a namespace
+    pub async fn select(&mut self, namespace: &str) -> Result<(), String> {
+        match self
+            .send_command(Command::Select {
+                namespace: namespace.to_string(),
+            })
+            .await?
+        {
+            Response::Error(e) => Err(e.to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fetch the key prefix to node assignments of the cluster
+    pub async fn routing_table(&mut self) -> Result<RoutingTable, String> {
+        match self.send_command(Command::RoutingTable).await? {
+            Response::Ok(Some(table)) => {
+                serde_json::from_value(table).map_err(|e| format!("Invalid routing table: {}", e))
+            }
+            Response::Error(e) => Err(e.to_string()),
+            other => Err(format!("Unexpected routing table response: {}", other)),
+        }
+    }
+
+    /// Ask the server to push an event for every change to a key of the
+    /// current namespace matching a glob; events are read with `next_event`
+    pub async fn subscribe_events(&mut self, pattern: &str) -> Result<(), String> {
+        match self
+            .send_command(Command::SubscribeEvents {
+                pattern: pattern.to_string(),
+            })
+            .await?
+        {
+            Response::Error(e) => Err(e.to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Watch a key, returning its current value; its new values are read with
+    /// `next_event`
+    pub async fn watch(&mut self, key: &str) -> Result<Option<Value>, String> {
+        match self
+            .send_command(Command::Watch {
+                key: key.to_string(),
+            })
+            .await?
+        {
+            Response::Ok(value) => Ok(value),
+            Response::Error(e) => Err(e.to_string()),
+            other => Err(format!("Unexpected watch response: {}", other)),
+        }
+    }
+
+    /// Publish a message on a channel, returning the number of subscribers
+    /// it was delivered to
+    pub async fn publish(&mut self, channel: &str, message: Value) -> Result<usize, String> {
+        match self
+            .send_command(Command::Publish {
+                channel: channel.to_string(),
+                message,
+            })
+            .await?
+        {
+            Response::Ok(Some(count)) => Ok(count.as_u64().unwrap_or(0) as usize),
+            Response::Error(e) => Err(e.to_string()),
+            other => Err(format!("Unexpected publish response: {}", other)),
+        }
+    }
+
+    /// Subscribe to channels; their messages are read with `next_event`
+    pub async fn subscribe(&mut self, channels: &[&str]) -> Result<(), String> {
+        match self
+            .send_command(Command::Subscribe {
+                channels: channels.iter().map(|c| c.to_string()).collect(),
+            })
+            .await?
+        {
+            Response::Error(e) => Err(e.to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Tail the change log of the current namespace after an offset; changes
+    /// are read with `next_event`
+    pub async fn changefeed(&mut self, from_offset: u64) -> Result<(), String> {
+        match self
+            .send_command(Command::Changefeed { from_offset })
+            .await?
+        {
+            Response::Error(e) => Err(e.to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Wait for the next event frame pushed by the server: a
+    /// `Response::KeyEvent`, `Response::KeyEventsDropped`, `Response::KeyUpdated`,
+    /// `Response::ChannelMessage` or `Response::Change`.
+    ///
+    /// Events received while waiting for responses are buffered until read.
+    pub async fn next_event(&mut self) -> Result<Response, String> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+        loop {
+            match self.receive_response().await? {
+                (Response::ClusterTopologyChanged(topology), _) => self.topology_changed(topology),
+                (
+                    event @ (Response::KeyEvent(_)
+                    | Response::KeyEventsDropped(_)
+                    | Response::KeyUpdated(_)
+                    | Response::ChannelMessage(_)
+                    | Response::Change(_)),
+                    _,
+                ) => return Ok(event),
+                (other, _) => return Err(format!("Unexpected frame: {}", other)),
+            }
+        }
+    }
+
+    /// Returns the events pushed by the server as a stream, as read by
+    /// `next_event`; the stream ends after the first error, such as the
+    /// connection closing
+    pub fn events(&mut self) -> impl futures_util::Stream<Item = Result<Event, String>> + '_ {
+        futures_util::stream::unfold(Some(self), |client| async move {
+            let client = client?;
+            match client.next_event().await {
+                Ok(response) => {
+                    let event = Event::try_from(response)
+                        .map_err(|other| format!("Unexpected frame: {}", other));
+                    Some((event, Some(client)))
+                }
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+
+    /// Returns the most recent topology pushed by the server
+    pub fn cluster_topology(&self) -> Option<&ClusterTopology> {
+        self.topology.as_ref()
+    }
+
+    /// Send a command and receive the response; streamed results are read
+    /// with `stream`
+    pub async fn send_command(&mut self, command: Command) -> Result<Response, String> {
+        debug!("Sending command: {}", command);
+        if matches!(command, Command::Stream { .. }) {
+            return Err("Streamed results are read with TcpClient::stream".to_string());
+        }
+        let message = Serialized::new(&command)?;
+        self.bound |= command.binds_connection();
+
+        let started_at = Instant::now();
+        // Left set when the exchange fails: the connection is out of sync
+        self.connection.in_flight = true;
+        let bytes_sent = self.write_frame(&message).await?;
+        let request = self.request_started(command.name(), bytes_sent, started_at);
+
+        let result = self.read_reply().await;
+        if result.is_ok() {
+            self.connection.in_flight = false;
+        }
+        self.request_ended(request, &result);
+
+        let (response, _) = result?;
+        debug!("Response received: {}", response);
+
+        Ok(response)
+    }
+
+    /// Run a QSCAN or an EXPORT with its results sent in chunks of at most
+    /// `chunk_size` results, read with `ResultStream::next_chunk`.
+    ///
+    /// Sending another request before the last chunk skips the chunks left.
+    pub async fn stream(
+        &mut self,
+        command: Command,
+        chunk_size: usize,
+    ) -> Result<ResultStream<'_>, String> {
+        let message = Serialized::new(&Command::Stream {
+            command: Box::new(command),
+            chunk_size: Some(chunk_size),
+        })?;
+        let started_at = Instant::now();
+        let bytes_sent = self.write_frame(&message).await?;
+        self.connection.in_flight = true;
+        self.streaming = Some(self.request_started("STREAM", bytes_sent, started_at));
+        Ok(ResultStream { client: self })
+    }
+
+    /// Read the next frame of a streamed result; the request is reported to
+    /// the hooks with its final response
+    async fn read_stream_reply(&mut self) -> Result<Response, String> {
+        let result = self.read_reply().await;
+        let Some(mut request) = self.streaming.take() else {
+            return result.map(|(response, _)| response);
+        };
+        match result {
+            Ok((Response::Chunk(results), size)) => {
+                request.bytes_received += size;
+                self.streaming = Some(request);
+                Ok(Response::Chunk(results))
+            }
+            result => {
+                self.request_ended(request, &result);
+                result.map(|(response, _)| response)
+            }
+        }
+    }
+
+    /// Read the final response of a streamed result, skipping its chunks
+    async fn finish_stream(&mut self) -> Result<Response, String> {
+        loop {
+            let response = self.read_stream_reply().await?;
+            if !matches!(response, Response::Chunk(_)) {
+                self.connection.in_flight = false;
+                return Ok(response);
+            }
+        }
+    }
+
+    /// Stream NDJSON documents (`{"key": ..., "value": ...}` per line, as
+    /// written by EXPORT) to the server in BULKLOAD frames of `chunk_size`
+    /// documents, keeping several frames in flight; returns the number of
+    /// loaded documents.
+    ///
+    /// Frames are applied in order; on a failed frame the ones already sent
+    /// after it may still be applied.
+    pub async fn bulk_load(
+        &mut self,
+        ndjson: impl std::io::BufRead,
+        chunk_size: usize,
+    ) -> Result<u64, String> {
+        let chunk_size = chunk_size.max(1);
+        let mut loaded = 0;
+        let mut in_flight = VecDeque::new();
+        let mut chunk = String::new();
+        let mut documents = 0;
+        let mut lines = ndjson.lines();
+        loop {
+            let line = lines
+                .next()
+                .transpose()
+                .map_err(|e| format!("Read error: {}", e))?;
+            let end = line.is_none();
+            if let Some(line) = line.filter(|line| !line.trim().is_empty()) {
+                chunk.push_str(&line);
+                chunk.push('\n');
+                documents += 1;
+            }
+            if documents == chunk_size || (end && documents > 0) {
+                let data = std::mem::take(&mut chunk);
+                let message = Serialized::new(&Command::BulkLoad { data })?;
+                let started_at = Instant::now();
+                let bytes_sent = self.write_frame(&message).await?;
+                in_flight.push_back(self.request_started("BULKLOAD", bytes_sent, started_at));
+                documents = 0;
+                self.connection.in_flight = true;
+            }
+            if in_flight.len() == BULK_LOAD_WINDOW || (end && !in_flight.is_empty()) {
+                let reply = self.read_pending_reply(&mut in_flight).await?;
+                self.connection.in_flight = !in_flight.is_empty();
+                match reply {
+                    Response::Ok(Some(reply)) => loaded += reply["loaded"].as_u64().unwrap_or(0),
+                    Response::Error(e) => {
+                        // Keep the connection usable: later chunks may still
+                        // have been applied, but their replies are discarded
+                        while !in_flight.is_empty() {
+                            self.read_pending_reply(&mut in_flight).await?;
+                        }
+                        self.connection.in_flight = false;
+                        return Err(format!(
+                            "Bulk load failed after {} documents: {}",
+                            loaded, e
+                        ));
+                    }
+                    other => return Err(format!("Unexpected response: {}", other)),
+                }
+            } else if end {
+                return Ok(loaded);
+            }
+        }
+    }
+
+    /// Send several commands in a pipeline, returning their responses in
+    /// order.
+    ///
+    /// Requests are written without waiting for the previous responses,
+    /// keeping up to 128 in flight, so the batch takes a few round trips
+    /// instead of one per command. Each command runs on its own: a failing
+    /// one is answered with an error response and doesn't stop the others.
+    pub async fn send_batch(&mut self, commands: Vec<Command>) -> Result<Vec<Response>, String> {
+        if commands
+            .iter()
+            .any(|command| matches!(command, Command::Stream { .. }))
+        {
+            return Err("STREAM cannot be sent in a batch".to_string());
+        }
+        if self.streaming.is_some() {
+            self.finish_stream().await?;
+        }
+        let total = commands.len();
+        let mut responses = Vec::with_capacity(total);
+        let mut in_flight = VecDeque::new();
+        for (sent, command) in commands.into_iter().enumerate() {
+            let message = Serialized::new(&command)?;
+            self.bound |= command.binds_connection();
+            self.connection.in_flight = true;
+            let started_at = Instant::now();
+            let bytes_sent = self.feed_frame(&message).await?;
+            in_flight.push_back(self.request_started(command.name(), bytes_sent, started_at));
+            // Keep the window full, and wait for everything after the last
+            if sent + 1 - responses.len() == BATCH_WINDOW || sent + 1 == total {
+                self.connection
+                    .stream()?
+                    .flush()
+                    .await
+                    .map_err(|e| format!("Flush error: {}", e))?;
+                let until = if sent + 1 == total {
+                    total
+                } else {
+                    responses.len() + 1
+                };
+                while responses.len() < until {
+                    responses.push(self.read_pending_reply(&mut in_flight).await?);
+                }
+            }
+        }
+        self.connection.in_flight = false;
+        debug!("Batch of {} commands completed", total);
+        Ok(responses)
+    }
+
+    /// Report a request written to the connection to the hooks
+    fn request_started(
+        &self,
+        command: &'static str,
+        bytes_sent: usize,
+        started_at: Instant,
+    ) -> PendingRequest {
+        if let Some(hooks) = &self.hooks {
+            hooks.on_request_start(&RequestStart {
+                command,
+                bytes_sent,
+            });
+        }
+        PendingRequest {
+            command,
+            bytes_sent,
+            bytes_received: 0,
+            started_at,
+        }
+    }
+
+    /// Report the outcome of a request to the hooks
+    fn request_ended(&self, request: PendingRequest, result: &Result<(Response, usize), String>) {
+        let Some(hooks) = &self.hooks else {
+            return;
+        };
+        let (bytes_received, outcome) = match result {
+            Ok((Response::Error(_), n)) => (*n, RequestOutcome::ServerError),
+            Ok((_, n)) => (*n, RequestOutcome::Success),
+            Err(_) => (0, RequestOutcome::TransportError),
+        };
+        hooks.on_request_end(&RequestEnd {
+            command: request.command,
+            bytes_sent: request.bytes_sent,
+            bytes_received: request.bytes_received + bytes_received,
+            latency: request.started_at.elapsed(),
+            outcome,
+        });
+    }
+
+    /// Read the response to the oldest of pipelined requests and report it
+    /// to the hooks; when the connection fails, every request left is
+    /// reported as failed
+    async fn read_pending_reply(
+        &mut self,
+        in_flight: &mut VecDeque<PendingRequest>,
+    ) -> Result<Response, String> {
+        let result = self.read_reply().await;
+        if let Some(request) = in_flight.pop_front() {
+            self.request_ended(request, &result);
+        }
+        if result.is_err() {
+            for request in in_flight.drain(..) {
+                self.request_ended(request, &result);
+            }
+        }
+        result.map(|(response, _)| response)
+    }
+
+    /// Buffer a serialized request without flushing it, returning the size
+    /// of its frames
+    async fn feed_frame(&mut self, message: &Serialized) -> Result<usize, String> {
+        let stream = self.connection.stream()?;
+        // Writes out a full buffer first, so that only this request is measured
+        futures_util::future::poll_fn(|cx| stream.poll_ready_unpin(cx))
+            .await
+            .map_err(|e| format!("Send error: {}", e))?;
+        let buffered = stream.write_buffer().len();
+        stream
+            .start_send_unpin(message.payload(None))
+            .map_err(|e| format!("Send error: {}", e))?;
+        Ok(stream.write_buffer().len() - buffered)
+    }
+
+    /// Write a serialized request, returning the size of its frames
+    async fn write_frame(&mut self, message: &Serialized) -> Result<usize, String> {
+        if self.streaming.is_some() {
+            self.finish_stream().await?;
+        }
+        let size = self.feed_frame(message).await?;
+        // The write buffer is flushed after every request
+        self.connection
+            .stream()?
+            .flush()
+            .await
+            .map_err(|e| format!("Flush error: {}", e))?;
+        Ok(size)
+    }
+
+    /// Read the response to the oldest request in flight with its frame
+    /// size, handling any frame pushed before it
+    async fn read_reply(&mut self) -> Result<(Response, usize), String> {
+        loop {
+            match self.receive_response().await? {
+                (Response::ClusterTopologyChanged(topology), _) => self.topology_changed(topology),
+                (
+                    event @ (Response::KeyEvent(_)
+                    | Response::KeyEventsDropped(_)
+                    | Response::KeyUpdated(_)
+                    | Response::ChannelMessage(_)
+                    | Response::Change(_)),
+                    _,
+                ) => self.events.push_back(event),
+                response => return Ok(response),
+            }
+        }
+    }
+
+    /// Record a topology change pushed by the server
+    fn topology_changed(&mut self, topology: ClusterTopology) {
+        debug!("Cluster topology changed: {:?}", topology);
+        if let Some(hooks) = &self.hooks {
+            hooks.on_topology_changed(&topology);
+        }
+        self.topology = Some(topology);
+    }
+
+    /// Receive a response from the server
+    async fn receive_response(&mut self) -> Result<(Response, usize), String> {
+        let message = self
+            .connection
+            .stream()?
+            .next()
+            .await
+            .ok_or_else(|| "Length read error: connection closed".to_string())?
+            .map_err(|e| format!("Read error: {}", e))?;
+        Ok((message.decode()?, message.size))
+    }
+
+    /// Turn this connection into a client sending concurrent requests,
+    /// keeping its hooks and what HELLO, AUTH and SELECT set up
+    pub fn multiplex(mut self) -> Result<MultiplexedClient, String> {
+        let stream = self
+            .connection
+            .stream
+            .take()
+            .ok_or_else(|| "Connection closed".to_string())?;
+        Ok(MultiplexedClient::new(stream, self.hooks.take()))
+    }
+
+    /// Close the connection.
+    ///
+    /// Dropping the client also shuts the connection down, but only `close`
+    /// reports shutdown errors.
+    pub async fn close(mut self) -> Result<(), String> {
+        self.connection.close().await
+    }
+
+    /// Returns true if the connection is open, awaits no response and keeps
+    /// the state it was opened with, so that another request can reuse it
+    fn is_reusable(&self) -> bool {
+        self.connection.stream.is_some()
+            && !self.connection.in_flight
+            && self.streaming.is_none()
+            && !self.bound
+    }
+}
+
+/// Idle connections of a pool pinged before being handed out, by default
+const DEFAULT_HEALTH_CHECK_AFTER: Duration = Duration::from_secs(30);
+
+/// Pool of connections to a server, shared by concurrent callers.
+///
+/// Each request checks a connection out, so that at most `size` requests
+/// run at the same time, each one on a connection of its own; callers wait
+/// while every connection is checked out. Connections are opened as needed
+/// and returned once the request completes, unless it failed; those idle
+/// for longer than the health check interval are pinged first, and
+/// replaced if they don't answer.
+///
+/// A connection on which a command changed the state of the session, such
+/// as SELECT or AUTH, or subscribed it to pushes, such as WATCH, is closed
+/// instead of being returned, so that the next caller gets a fresh one.
+#[derive(Clone)]
+pub struct TcpClientPool {
+    inner: Arc<PoolInner>,
+    health_check_after: Duration,
+}
+
+struct PoolInner {
+    address: String,
+    /// Connections not checked out, with the time they were returned
+    idle: Mutex<Vec<(TcpClient, Instant)>>,
+    /// One permit per connection that can be checked out
+    permits: Arc<Semaphore>,
+}
+
+impl TcpClientPool {
+    /// Create a pool of up to `size` connections to a server; no connection
+    /// is opened before the first request
+    pub fn new(address: &str, size: usize) -> Self {
+        Self {
+            inner: Arc::new(PoolInner {
+                address: address.to_string(),
+                idle: Mutex::new(Vec::new()),
+                permits: Arc::new(Semaphore::new(size.max(1))),
+            }),
+            health_check_after: DEFAULT_HEALTH_CHECK_AFTER,
+        }
+    }
+
+    /// Ping connections idle for longer than `idle` before handing them out
+    /// (every time with a zero duration)
+    pub fn with_health_check(mut self, idle: Duration) -> Self {
+        self.health_check_after = idle;
+        self
+    }
+
+    /// Check a connection out, waiting while every connection is in use
+    pub async fn get(&self) -> Result<PooledClient, String> {
+        let permit = Arc::clone(&self.inner.permits)
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        loop {
+            let idle = self.inner.idle.lock().unwrap().pop();
+            let Some((mut client, returned_at)) = idle else {
+                break;
+            };
+            if returned_at.elapsed() < self.health_check_after
+                || matches!(client.send_command(Command::Ping).await, Ok(Response::Pong))
+            {
+                return Ok(PooledClient {
+                    client: Some(client),
+                    pool: Arc::clone(&self.inner),
+                    _permit: permit,
+                });
+            }
+            debug!(
+                "Replacing unhealthy pooled connection to {}",
+                self.inner.address
+            );
+        }
+        let client = TcpClient::connect(&self.inner.address).await?;
+        Ok(PooledClient {
+            client: Some(client),
+            pool: Arc::clone(&self.inner),
+            _permit: permit,
+        })
+    }
+
+    /// Send a command on a pooled connection and receive the response
+    pub async fn send_command(&self, command: Command) -> Result<Response, String> {
+        self.get().await?.send_command(command).await
+    }
+
+    /// Number of open connections not checked out
+    pub fn idle_connections(&self) -> usize {
+        self.inner.idle.lock().unwrap().len()
+    }
+}
+
+/// Connection checked out of a `TcpClientPool`, returned to the pool when
+/// dropped
+pub struct PooledClient {
+    client: Option<TcpClient>,
+    pool: Arc<PoolInner>,
+    _permit: tokio::sync::OwnedSemaphorePermit,
+}
+
+impl std::ops::Deref for PooledClient {
+    type Target = TcpClient;
+
+    fn deref(&self) -> &TcpClient {
+        self.client.as_ref().expect("set until dropped")
+    }
+}
+
+impl std::ops::DerefMut for PooledClient {
+    fn deref_mut(&mut self) -> &mut TcpClient {
+        self.client.as_mut().expect("set until dropped")
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        // Returned before the permit, so that the next caller finds it
+        if let Some(client) = self.client.take().filter(TcpClient::is_reusable) {
+            self.pool
+                .idle
+                .lock()
+                .unwrap()
+                .push((client, Instant::now()));
+        }
+    }
+}
+
+/// Interval between two checks of the lag of a replica
+const LAG_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Client sending writes to a primary and spreading reads across replicas.
+///
+/// Write and admin commands go to the primary; read-only commands go to the
+/// replicas in turn, and to the primary when no replica can serve them. A
+/// replica that fails a request is skipped for that request. Each node is
+/// reached through a `TcpClientPool`, whose connections are shared between
+/// requests: commands changing the state of a connection, such as SELECT, or
+/// subscribing it to pushes, such as WATCH, are refused and need a
+/// `TcpClient` of their own.
+#[derive(Clone)]
+pub struct RoutingClient {
+    primary: TcpClientPool,
+    replicas: Arc<Vec<Replica>>,
+    next_replica: Arc<AtomicUsize>,
+    max_staleness: Option<Duration>,
+}
+
+struct Replica {
+    pool: TcpClientPool,
+    /// Time of the last lag check, with the lag found (None if unknown)
+    lag: Mutex<Option<(Instant, Option<Duration>)>>,
+}
+
+impl RoutingClient {
+    /// Create a client of a primary and its replicas, with pools of up to
+    /// `size` connections per node
+    pub fn new(primary: &str, replicas: Vec<String>, size: usize) -> Self {
+        Self {
+            primary: TcpClientPool::new(primary, size),
+            replicas: Arc::new(
+                replicas
+                    .iter()
+                    .map(|address| Replica {
+                        pool: TcpClientPool::new(address, size),
+                        lag: Mutex::new(None),
+                    })
+                    .collect(),
+            ),
+            next_replica: Arc::new(AtomicUsize::new(0)),
+            max_staleness: None,
+        }
+    }
+
+    /// Read only from replicas whose replication lag, as reported by INFO
+    /// and checked at most once a second, is within `max_staleness`.
+    ///
+    /// Standbys that never caught up with their primary, and nodes that
+    /// don't report a lag, are skipped; primaries are never stale.
+    pub fn with_max_staleness(mut self, max_staleness: Duration) -> Self {
+        self.max_staleness = Some(max_staleness);
+        self
+    }
+
+    /// Send a command to the node serving it and receive the response:
+    /// writes, admin commands and commands with side effects go to the
+    /// primary, reads to the replicas
+    pub async fn send_command(&self, command: Command) -> Result<Response, String> {
+        Self::check_routable(&command)?;
+        if command.is_write()
+            || command.is_admin()
+            || command.has_side_effects()
+            || self.replicas.is_empty()
+        {
+            return self.primary.send_command(command).await;
+        }
+        let first = self.next_replica.fetch_add(1, Ordering::Relaxed);
+        for i in 0..self.replicas.len() {
+            let replica = &self.replicas[(first + i) % self.replicas.len()];
+            if !self.is_fresh(replica).await {
+                continue;
+            }
+            match replica.pool.send_command(command.clone()).await {
+                Ok(response) => return Ok(response),
+                Err(e) => debug!("Read from replica failed, trying the next node: {}", e),
+            }
+        }
+        self.primary.send_command(command).await
+    }
+
+    /// Send a command to the primary, for reads that must see the latest
+    /// writes
+    pub async fn send_to_primary(&self, command: Command) -> Result<Response, String> {
+        Self::check_routable(&command)?;
+        self.primary.send_command(command).await
+    }
+
+    /// Fails for commands binding the connection they run on
+    fn check_routable(command: &Command) -> Result<(), String> {
+        if command.binds_connection() {
+            return Err(format!(
+                "{} needs a TcpClient of its own: it can't run on a routed connection",
+                command.name()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns true if a replica is within the staleness bound
+    async fn is_fresh(&self, replica: &Replica) -> bool {
+        let Some(max_staleness) = self.max_staleness else {
+            return true;
+        };
+        let checked = *replica.lag.lock().unwrap();
+        let lag = match checked {
+            Some((checked_at, lag)) if checked_at.elapsed() < LAG_CHECK_INTERVAL => lag,
+            _ => {
+                let lag = match replica.pool.send_command(Command::Info).await {
+                    Ok(Response::Ok(Some(info))) => Self::replication_lag(&info["replication"]),
+                    _ => None,
+                };
+                *replica.lag.lock().unwrap() = Some((Instant::now(), lag));
+                lag
+            }
+        };
+        lag.is_some_and(|lag| lag <= max_staleness)
+    }
+
+    /// Returns the lag reported in the replication section of INFO
+    fn replication_lag(replication: &Value) -> Option<Duration> {
+        match replication["role"].as_str() {
+            Some("primary" | "leader") => Some(Duration::ZERO),
+            _ => replication["lag_ms"].as_u64().map(Duration::from_millis),
+        }
+    }
+}
+
+/// Responses awaited by a multiplexed client, by request id; None once the
+/// connection is closed
+type PendingResponses = Arc<Mutex<Option<HashMap<u64, oneshot::Sender<(Response, usize)>>>>>;
+
+/// Client sending concurrent requests on one connection.
+///
+/// Every request is tagged with an id: the server executes tagged requests
+/// concurrently and answers each one as soon as it completes, and responses
+/// are matched to their requests by id. Requests in flight at the same time
+/// may be applied in any order, so await a write before sending a request
+/// that depends on it. Frames pushed by the server are discarded.
+///
+/// Clones share the connection, which is closed once all of them are dropped.
+#[derive(Clone)]
+pub struct MultiplexedClient {
+    writer: Arc<tokio::sync::Mutex<FramedWrite<WriteHalf<ClientStream>, FrameCodec>>>,
+    pending: PendingResponses,
+    next_id: Arc<AtomicU64>,
+    hooks: Option<Arc<dyn ClientHooks>>,
+}
+
+impl MultiplexedClient {
+    /// Connect to server
+    pub async fn connect(address: &str) -> Result<Self, String> {
+        TcpClient::connect(address).await?.multiplex()
+    }
+
+    /// Install instrumentation hooks invoked around every request
+    pub fn with_hooks(mut self, hooks: Arc<dyn ClientHooks>) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    fn new(stream: Framed<ClientStream, FrameCodec>, hooks: Option<Arc<dyn ClientHooks>>) -> Self {
+        let parts = stream.into_parts();
+        let (reader, writer) = tokio::io::split(parts.io);
+        let mut reader = FramedRead::new(reader, parts.codec.clone());
+        // Keep any frame already read past the last response
+        reader.read_buffer_mut().extend_from_slice(&parts.read_buf);
+        let pending: PendingResponses = Arc::new(Mutex::new(Some(HashMap::new())));
+        tokio::spawn(Self::dispatch_responses(reader, Arc::clone(&pending)));
+        Self {
+            writer: Arc::new(tokio::sync::Mutex::new(FramedWrite::new(
+                writer,
+                parts.codec,
+            ))),
+            pending,
+            next_id: Arc::new(AtomicU64::new(1)),
+            hooks,
+        }
+    }
+
+    /// Send a command and receive its response, while other commands are
+    /// in flight on the connection
+    pub async fn send_command(&self, command: Command) -> Result<Response, String> {
+        debug!("Sending command: {}", command);
+        if matches!(command, Command::Stream { .. }) {
+            return Err("Streamed results need a TcpClient of their own".to_string());
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let message = Serialized::new(&command)?;
+
+        let (tx, rx) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap()
+            .as_mut()
+            .ok_or_else(|| "Connection closed".to_string())?
+            .insert(id, tx);
+        let command_name = command.name();
+        let started_at = Instant::now();
+        let written = {
+            let mut writer = self.writer.lock().await;
+            match writer.feed(message.payload(Some(id))).await {
+                Ok(()) => {
+                    let size = writer.write_buffer().len();
+                    writer.flush().await.map(|_| size)
+                }
+                Err(e) => Err(e),
+            }
+        };
+        let bytes_sent = match written {
+            Ok(size) => size,
+            Err(e) => {
+                if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+                    pending.remove(&id);
+                }
+                return Err(format!("Send error: {}", e));
+            }
+        };
+        if let Some(hooks) = &self.hooks {
+            hooks.on_request_start(&RequestStart {
+                command: command_name,
+                bytes_sent,
+            });
+        }
+
+        let result = rx
+            .await
+            .map_err(|_| "Connection closed before the response was received".to_string());
+        if let Some(hooks) = &self.hooks {
+            let (bytes_received, outcome) = match &result {
+                Ok((Response::Error(_), n)) => (*n, RequestOutcome::ServerError),
+                Ok((_, n)) => (*n, RequestOutcome::Success),
+                Err(_) => (0, RequestOutcome::TransportError),
+            };
+            hooks.on_request_end(&RequestEnd {
+                command: command_name,
+                bytes_sent,
+                bytes_received,
+                latency: started_at.elapsed(),
+                outcome,
+            });
+        }
+        result.map(|(response, _)| response)
+    }
+
+    /// Hand every response to the request with its id until the connection
+    /// closes, then fail the requests still in flight
+    async fn dispatch_responses(
+        mut reader: FramedRead<ReadHalf<ClientStream>, FrameCodec>,
+        pending: PendingResponses,
+    ) {
+        loop {
+            let message = match reader.next().await {
+                Some(Ok(message)) => message,
+                Some(Err(e)) => {
+                    debug!("Multiplexed connection closed: {}", e);
+                    break;
+                }
+                None => {
+                    debug!("Multiplexed connection closed");
+                    break;
+                }
+            };
+            match (message.id, message.decode::<Response>()) {
+                (Some(id), Ok(response)) => {
+                    let tx = pending.lock().unwrap().as_mut().and_then(|p| p.remove(&id));
+                    match tx {
+                        Some(tx) => {
+                            let _ = tx.send((response, message.size));
+                        }
+                        None => warn!("Discarding response to unknown request {}", id),
+                    }
+                }
+                (None, Ok(response)) => debug!("Discarding pushed frame: {}", response),
+                (_, Err(e)) => {
+                    debug!("Multiplexed connection closed: {}", e);
+                    break;
+                }
+            }
+        }
+        pending.lock().unwrap().take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Command;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+    use tokio::time::sleep;
+
+    #[tokio::test]
+    async fn test_tcp_communication() {
+        // Start the server in background
+        let database = Arc::new(Database::new());
+        let _server = TcpServer::new(database, "127.0.0.1:0".to_string());
 
         // For this test, use a fixed address
         let database = Arc::new(Database::new());
-        let server = TcpServer::new(database, "127.0.0.1:8081".to_string());
+        let server = TcpServer::new(database, "127.0.0.1:8081".to_string());
+
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+
+        // Wait for server to start
+        sleep(Duration::from_millis(100)).await;
+
+        // Test the client
+        let mut client = TcpClient::connect("127.0.0.1:8081").await.unwrap();
+
+        // Test SET
+        let set_cmd = Command::Set {
+            key: "test".to_string(),
+            value: json!({"hello": "world"}),
+        };
+        let response = client.send_command(set_cmd).await.unwrap();
+        assert!(matches!(response, Response::Ok(None)));
+
+        // Test GET
+        let get_cmd = Command::Get {
+            key: "test".to_string(),
+        };
+        let response = client.send_command(get_cmd).await.unwrap();
+        assert!(matches!(response, Response::Ok(Some(v)) if v == json!({"hello": "world"})));
+
+        // Test PING
+        let ping_cmd = Command::Ping;
+        let response = client.send_command(ping_cmd).await.unwrap();
+        assert!(matches!(response, Response::Pong));
+
+        client.close().await.unwrap();
+    }
+
+    #[derive(Default)]
+    struct CountingHooks {
+        started: std::sync::atomic::AtomicUsize,
+        ended: std::sync::atomic::AtomicUsize,
+        errors: std::sync::atomic::AtomicUsize,
+    }
+
+    impl ClientHooks for CountingHooks {
+        fn on_request_start(&self, _request: &RequestStart) {
+            self.started
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+
+        fn on_request_end(&self, request: &RequestEnd) {
+            assert!(request.bytes_sent > 0);
+            assert!(request.bytes_received > 0);
+            self.ended
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if request.outcome == RequestOutcome::ServerError {
+                self.errors
+                    .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_hooks() {
+        let database = Arc::new(Database::new());
+        let server = TcpServer::new(database, "127.0.0.1:8082".to_string());
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
+
+        let hooks = Arc::new(CountingHooks::default());
+        let mut client = TcpClient::connect("127.0.0.1:8082")
+            .await
+            .unwrap()
+            .with_hooks(hooks.clone());
+
+        client.send_command(Command::Ping).await.unwrap();
+        client
+            .send_command(Command::Delete {
+                key: "missing".to_string(),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(hooks.started.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(hooks.errors.load(std::sync::atomic::Ordering::SeqCst), 1);
+        client.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_hooks_cover_pipelines() {
+        let database = Arc::new(Database::new());
+        let server = TcpServer::new(database, "127.0.0.1:8115".to_string());
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
+
+        let hooks = Arc::new(CountingHooks::default());
+        let mut client = TcpClient::connect("127.0.0.1:8115")
+            .await
+            .unwrap()
+            .with_hooks(hooks.clone());
+        let count = |counter: &std::sync::atomic::AtomicUsize| {
+            counter.load(std::sync::atomic::Ordering::SeqCst)
+        };
+
+        // Every command of a batch is reported
+        let mut commands: Vec<Command> = (0..200)
+            .map(|i| Command::Set {
+                key: format!("batch:{}", i),
+                value: json!(i),
+            })
+            .collect();
+        commands.push(Command::Delete {
+            key: "missing".to_string(),
+        });
+        client.send_batch(commands).await.unwrap();
+        assert_eq!(count(&hooks.started), 201);
+        assert_eq!(count(&hooks.ended), 201);
+        assert_eq!(count(&hooks.errors), 1);
+
+        // As is every BULKLOAD frame, and a streamed result once complete
+        let ndjson = (0..10)
+            .map(|i| format!("{{\"key\": \"bulk:{}\", \"value\": {}}}\n", i, i))
+            .collect::<String>();
+        client.bulk_load(ndjson.as_bytes(), 4).await.unwrap();
+        assert_eq!(count(&hooks.ended), 204);
+        let scan = Command::QScan {
+            key_pattern: "bulk:*".to_string(),
+            query: "$".to_string(),
+            limit: None,
+        };
+        let mut stream = client.stream(scan, 3).await.unwrap();
+        while stream.next_chunk().await.unwrap().is_some() {}
+        assert_eq!(count(&hooks.started), 205);
+        assert_eq!(count(&hooks.ended), 205);
+        client.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_drop_shuts_down_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let client = TcpClient::connect(&address).await.unwrap();
+        let (mut server_side, _) = listener.accept().await.unwrap();
+        drop(client);
+
+        // The background shutdown reaches the peer as end of stream
+        let mut buf = [0u8; 1];
+        let read = tokio::time::timeout(Duration::from_secs(2), server_side.read(&mut buf))
+            .await
+            .expect("connection was not shut down")
+            .unwrap();
+        assert_eq!(read, 0);
+    }
+
+    #[tokio::test]
+    async fn test_topology_push() {
+        let database = Arc::new(Database::new());
+        let (topology_tx, _) = broadcast::channel(4);
+        let initial = ClusterTopology {
+            term: 1,
+            leader: Some(1),
+            members: vec![1],
+            learners: vec![],
+        };
+        let server = TcpServer::new(database, "127.0.0.1:8083".to_string())
+            .with_topology(topology_tx.clone(), initial);
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
+
+        let mut client = TcpClient::connect("127.0.0.1:8083").await.unwrap();
+        assert!(client.subscribe_topology().await.unwrap());
+
+        let topology = ClusterTopology {
+            term: 3,
+            leader: Some(2),
+            members: vec![1, 2, 3],
+            learners: vec![4],
+        };
+        topology_tx.send(topology.clone()).unwrap();
+        sleep(Duration::from_millis(50)).await;
+
+        // The push is consumed ahead of the next response
+        let response = client.send_command(Command::Ping).await.unwrap();
+        assert!(matches!(response, Response::Pong));
+        assert_eq!(client.cluster_topology(), Some(&topology));
+
+        // Writes route to the current leader, reads to any member
+        let table = client.routing_table().await.unwrap();
+        let route = table.route("any:key").unwrap();
+        assert_eq!(table.term, 3);
+        assert_eq!(route.write, Some(2));
+        assert_eq!(route.read, vec![1, 2, 3]);
+        client.close().await.unwrap();
+    }
+
+    /// Send a command and read back its response with its size
+    async fn exchange(client: &mut TcpClient, command: &Command) -> (Response, usize) {
+        client
+            .write_frame(&Serialized::new(command).unwrap())
+            .await
+            .unwrap();
+        client.read_reply().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_frame_compression() {
+        let database = Arc::new(Database::new());
+        let server = TcpServer::new(database, "127.0.0.1:8093".to_string());
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
+
+        let value = json!(vec!["a repetitive document"; 4096]);
+        let mut compressed = TcpClient::connect("127.0.0.1:8093").await.unwrap();
+        let handshake = compressed.hello().await.unwrap();
+        assert_eq!(handshake.compression, "zstd");
+        let set = compressed
+            .send_command(Command::Set {
+                key: "big".to_string(),
+                value: value.clone(),
+            })
+            .await
+            .unwrap();
+        assert!(matches!(set, Response::Ok(_)));
+
+        // Only the connection that negotiated compression gets compressed frames
+        let get = Command::Get {
+            key: "big".to_string(),
+        };
+        let mut plain = TcpClient::connect("127.0.0.1:8093").await.unwrap();
+        let (response, plain_size) = exchange(&mut plain, &get).await;
+        assert!(matches!(response, Response::Ok(Some(v)) if v == value));
+        let (response, compressed_size) = exchange(&mut compressed, &get).await;
+        assert!(matches!(response, Response::Ok(Some(v)) if v == value));
+        assert!(compressed_size * 10 < plain_size);
+
+        plain.close().await.unwrap();
+        compressed.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_chunked_frames() {
+        let database = Arc::new(Database::new());
+        let server = TcpServer::new(database, "127.0.0.1:8101".to_string());
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
+
+        let value = json!("x".repeat(10 << 20));
+        let mut client = TcpClient::connect("127.0.0.1:8101").await.unwrap();
+        assert!(client.hello().await.unwrap().chunked_frames);
+        let set = Command::Set {
+            key: "huge".to_string(),
+            value: value.clone(),
+        };
+        // The value is uploaded, and downloaded, in pieces of 4 MiB
+        let response = client.send_command(set).await.unwrap();
+        assert!(matches!(response, Response::Ok(_)));
+        let get = Command::Get {
+            key: "huge".to_string(),
+        };
+        let response = client.send_command(get.clone()).await.unwrap();
+        assert!(matches!(response, Response::Ok(Some(v)) if v == value));
+
+        // Connections that didn't negotiate chunked frames get a single frame
+        let mut plain = TcpClient::connect("127.0.0.1:8101").await.unwrap();
+        assert!(!plain.connection.stream().unwrap().codec().framing.chunked);
+        let response = plain.send_command(get).await.unwrap();
+        assert!(matches!(response, Response::Ok(Some(v)) if v == value));
+
+        plain.close().await.unwrap();
+        client.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_requests() {
+        let database = Arc::new(Database::new());
+        let server = TcpServer::new(database, "127.0.0.1:8102".to_string());
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
+
+        // Requests written at once are answered in order, with their
+        // responses written together
+        let stream = TcpStream::connect("127.0.0.1:8102").await.unwrap();
+        let mut stream = Framed::new(stream, FrameCodec::default());
+        let mut requests = Vec::new();
+        for i in 0..100 {
+            requests.push(
+                Serialized::new(&Command::Set {
+                    key: format!("key:{}", i),
+                    value: json!(i),
+                })
+                .unwrap(),
+            );
+        }
+        for request in &requests {
+            stream.feed(request.payload(None)).await.unwrap();
+        }
+        stream.flush().await.unwrap();
+        for _ in 0..100 {
+            let message = stream.next().await.unwrap().unwrap();
+            assert!(matches!(message.decode(), Ok(Response::Ok(_))));
+        }
+        let get = Serialized::new(&Command::Get {
+            key: "key:99".to_string(),
+        })
+        .unwrap();
+        stream.send(get.payload(None)).await.unwrap();
+        let message = stream.next().await.unwrap().unwrap();
+        assert!(matches!(message.decode(), Ok(Response::Ok(Some(v))) if v == json!(99)));
+    }
 
+    #[tokio::test]
+    async fn test_info_report() {
+        let database = Arc::new(Database::new());
+        let server = TcpServer::new(database, "127.0.0.1:8103".to_string());
         tokio::spawn(async move {
             let _ = server.start().await;
         });
+        sleep(Duration::from_millis(100)).await;
 
-        // Wait for server to start
+        let mut client = TcpClient::connect("127.0.0.1:8103").await.unwrap();
+        let other = TcpClient::connect("127.0.0.1:8103").await.unwrap();
+        client
+            .send_command(Command::Set {
+                key: "a".to_string(),
+                value: json!(1),
+            })
+            .await
+            .unwrap();
+        let Response::Ok(Some(info)) = client.send_command(Command::Info).await.unwrap() else {
+            panic!("INFO failed");
+        };
+        assert_eq!(info["build"]["version"], json!(env!("CARGO_PKG_VERSION")));
+        assert_eq!(info["keyspace"]["keys"], json!(1));
+        assert_eq!(info["clients"]["connected"], json!(2));
+        assert_eq!(info["replication"]["role"], json!("primary"));
+
+        other.close().await.unwrap();
+        client.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_list_and_kill() {
+        let database = Arc::new(Database::new());
+        let server =
+            TcpServer::new(database, "127.0.0.1:8104".to_string()).with_admin_commands(true);
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
         sleep(Duration::from_millis(100)).await;
 
-        // Test the client
-        let mut client = TcpClient::connect("127.0.0.1:8081").await.unwrap();
+        let mut admin = TcpClient::connect("127.0.0.1:8104").await.unwrap();
+        let mut worker = TcpClient::connect("127.0.0.1:8104").await.unwrap();
+        let response = worker
+            .send_command(Command::ClientSetName {
+                name: "worker".to_string(),
+            })
+            .await
+            .unwrap();
+        assert!(matches!(response, Response::Ok(None)));
 
-        // Test SET
-        let set_cmd = Command::Set {
-            key: "test".to_string(),
-            value: json!({"hello": "world"}),
+        let Response::Ok(Some(clients)) = admin.send_command(Command::ClientList).await.unwrap()
+        else {
+            panic!("CLIENT LIST failed");
         };
-        let response = client.send_command(set_cmd).await.unwrap();
+        let clients = clients.as_array().unwrap();
+        assert_eq!(clients.len(), 2);
+        let named = clients
+            .iter()
+            .find(|client| client["name"] == json!("worker"))
+            .unwrap();
+        assert_eq!(named["commands"], json!(1));
+        assert!(named["bytes_in"].as_u64() > Some(0));
+        assert!(named["bytes_out"].as_u64() > Some(0));
+        assert_eq!(named["namespace"], json!("default"));
+
+        let id = named["id"].as_u64().unwrap();
+        let response = admin
+            .send_command(Command::ClientKill { id })
+            .await
+            .unwrap();
         assert!(matches!(response, Response::Ok(None)));
+        sleep(Duration::from_millis(100)).await;
+        assert!(worker.send_command(Command::Ping).await.is_err());
+        let response = admin
+            .send_command(Command::ClientKill { id })
+            .await
+            .unwrap();
+        assert!(matches!(response, Response::Error(e) if e.code == ErrorCode::InvalidArgument));
+    }
 
-        // Test GET
-        let get_cmd = Command::Get {
-            key: "test".to_string(),
+    #[tokio::test]
+    async fn test_client_pool() {
+        let database = Arc::new(Database::new());
+        let server =
+            TcpServer::new(database, "127.0.0.1:8105".to_string()).with_admin_commands(true);
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
+
+        let pool = TcpClientPool::new("127.0.0.1:8105", 2).with_health_check(Duration::ZERO);
+        let mut requests = tokio::task::JoinSet::new();
+        for i in 0..20 {
+            let pool = pool.clone();
+            requests.spawn(async move {
+                pool.send_command(Command::Set {
+                    key: format!("key-{}", i),
+                    value: json!(i),
+                })
+                .await
+            });
+        }
+        while let Some(response) = requests.join_next().await {
+            assert!(matches!(response.unwrap(), Ok(Response::Ok(None))));
+        }
+        assert!(pool.idle_connections() <= 2);
+
+        // Connections closed by the server are replaced
+        let mut admin = TcpClient::connect("127.0.0.1:8105").await.unwrap();
+        admin
+            .send_command(Command::ClientSetName {
+                name: "admin".to_string(),
+            })
+            .await
+            .unwrap();
+        let Response::Ok(Some(clients)) = admin.send_command(Command::ClientList).await.unwrap()
+        else {
+            panic!("CLIENT LIST failed");
         };
-        let response = client.send_command(get_cmd).await.unwrap();
-        assert!(matches!(response, Response::Ok(Some(_))));
+        for client in clients.as_array().unwrap() {
+            if client["name"] != json!("admin") {
+                let id = client["id"].as_u64().unwrap();
+                admin
+                    .send_command(Command::ClientKill { id })
+                    .await
+                    .unwrap();
+            }
+        }
+        sleep(Duration::from_millis(100)).await;
+        let response = pool
+            .send_command(Command::Get {
+                key: "key-7".to_string(),
+            })
+            .await;
+        assert!(matches!(response, Ok(Response::Ok(Some(v))) if v == json!(7)));
 
-        // Test PING
-        let ping_cmd = Command::Ping;
-        let response = client.send_command(ping_cmd).await.unwrap();
+        // A namespace selected on a checkout doesn't leak into the next one
+        let pool = TcpClientPool::new("127.0.0.1:8105", 1);
+        let mut client = pool.get().await.unwrap();
+        client.select("other").await.unwrap();
+        drop(client);
+        assert_eq!(pool.idle_connections(), 0);
+        let response = pool
+            .send_command(Command::Get {
+                key: "key-7".to_string(),
+            })
+            .await;
+        assert!(matches!(response, Ok(Response::Ok(Some(v))) if v == json!(7)));
+        assert_eq!(pool.idle_connections(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_multiplexed_client() {
+        let database = Arc::new(Database::new());
+        let server = TcpServer::new(database, "127.0.0.1:8094".to_string());
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
+
+        // Hooks of the connection are kept
+        let hooks = Arc::new(CountingHooks::default());
+        let client = TcpClient::connect("127.0.0.1:8094")
+            .await
+            .unwrap()
+            .with_hooks(hooks.clone())
+            .multiplex()
+            .unwrap();
+        let mut requests = tokio::task::JoinSet::new();
+        for i in 0..100 {
+            let client = client.clone();
+            requests.spawn(async move {
+                let key = format!("key-{}", i);
+                client
+                    .send_command(Command::Set {
+                        key: key.clone(),
+                        value: json!(i),
+                    })
+                    .await
+                    .unwrap();
+                (i, client.send_command(Command::Get { key }).await.unwrap())
+            });
+        }
+        while let Some(result) = requests.join_next().await {
+            let (i, response) = result.unwrap();
+            assert!(matches!(response, Response::Ok(Some(v)) if v == json!(i)));
+        }
+        assert_eq!(hooks.started.load(std::sync::atomic::Ordering::SeqCst), 200);
+
+        // Connection-scoped commands are answered with their request id too
+        let response = client
+            .send_command(Command::Select {
+                namespace: "not valid".to_string(),
+            })
+            .await
+            .unwrap();
+        assert!(matches!(response, Response::Error(e) if e.code == ErrorCode::InvalidArgument));
+        assert_eq!(hooks.errors.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let database = Arc::new(Database::new());
+        let server = TcpServer::new(database, "127.0.0.1:8097".to_string())
+            .with_idle_timeout(Duration::from_millis(300));
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
+
+        // Requests keep the connection open
+        let mut client = TcpClient::connect("127.0.0.1:8097").await.unwrap();
+        for _ in 0..3 {
+            sleep(Duration::from_millis(150)).await;
+            assert!(matches!(
+                client.send_command(Command::Ping).await,
+                Ok(Response::Pong)
+            ));
+        }
+
+        sleep(Duration::from_millis(500)).await;
+        assert!(client.send_command(Command::Ping).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_socket_options() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let options = SocketOptions::new()
+            .with_nodelay(true)
+            .with_keepalive(Duration::from_secs(30))
+            .with_recv_buffer_size(256 << 10)
+            .with_send_buffer_size(256 << 10);
+        let mut client = TcpClient::connect(&address)
+            .await
+            .unwrap()
+            .with_socket_options(&options)
+            .unwrap();
+
+        let socket = socket2::SockRef::from(client.connection.stream().unwrap().get_ref().tcp());
+        assert!(socket.nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        assert!(socket.recv_buffer_size().unwrap() >= 256 << 10);
+        assert!(socket.send_buffer_size().unwrap() >= 256 << 10);
+        drop(listener);
+    }
+
+    #[tokio::test]
+    async fn test_auth() {
+        let database = Arc::new(Database::new());
+        let server = TcpServer::new(database, "127.0.0.1:8098".to_string()).with_credentials(
+            Credentials::new()
+                .with_identity(crate::auth::DEFAULT_IDENTITY, "s3cret")
+                .with_identity("dashboard", "key-1"),
+        );
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
+
+        let mut client = TcpClient::connect("127.0.0.1:8098").await.unwrap();
+        assert!(client.hello().await.unwrap().auth_required);
+        assert!(matches!(
+            client.send_command(Command::Get { key: "k".to_string() }).await,
+            Ok(Response::Error(e)) if e.code == ErrorCode::Unauthenticated
+        ));
+        assert!(client.auth(None, "wrong").await.is_err());
+        assert!(client.auth(Some("default"), "key-1").await.is_err());
+        assert_eq!(client.auth(None, "key-1").await.unwrap(), "dashboard");
+        assert!(!client.hello().await.unwrap().auth_required);
+        assert!(matches!(
+            client
+                .send_command(Command::Get {
+                    key: "k".to_string()
+                })
+                .await,
+            Ok(Response::Ok(None))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tenant_isolation() {
+        let database = Arc::new(Database::new());
+        let server = TcpServer::new(database.clone(), "127.0.0.1:8099".to_string())
+            .with_credentials(
+                Credentials::new()
+                    .with_identity("ops", "key-1")
+                    .with_tenant("acme", "key-2", "acme"),
+            )
+            .with_admin_commands(true);
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
+
+        let mut tenant = TcpClient::connect("127.0.0.1:8099").await.unwrap();
+        assert_eq!(tenant.auth(None, "key-2").await.unwrap(), "acme");
+        tenant
+            .send_command(Command::Set {
+                key: "k".to_string(),
+                value: json!(1),
+            })
+            .await
+            .unwrap();
+        assert!(tenant.select("default").await.is_err());
+        tenant.select("acme").await.unwrap();
+        // GETs answered by the fast path count as reads, like USAGE itself
+        for _ in 0..2 {
+            assert!(matches!(
+                tenant.send_command(Command::Get { key: "k".to_string() }).await,
+                Ok(Response::Ok(Some(value))) if value == json!(1)
+            ));
+        }
+        match tenant.send_command(Command::Usage).await.unwrap() {
+            Response::Ok(Some(usage)) => {
+                assert_eq!(usage["namespace"], "acme");
+                assert_eq!(usage["keys"], 1);
+                assert_eq!(usage["writes"], 1);
+                assert_eq!(usage["reads"], 3);
+            }
+            other => panic!("unexpected response: {}", other),
+        }
+        assert_eq!(database.value("k"), None);
+
+        // Channels of a tenant are scoped to its namespace
+        tenant.subscribe(&["news"]).await.unwrap();
+        let mut ops = TcpClient::connect("127.0.0.1:8099").await.unwrap();
+        ops.auth(None, "key-1").await.unwrap();
+        assert_eq!(ops.publish("news", json!(1)).await.unwrap(), 0);
+        assert_eq!(ops.publish("acme/news", json!(2)).await.unwrap(), 1);
+        match tenant.next_event().await.unwrap() {
+            Response::ChannelMessage(message) => assert_eq!(message.channel, "news"),
+            other => panic!("unexpected event: {}", other),
+        }
+
+        // Tenants only see and kill their own connections, and cannot run
+        // server-wide commands
+        let mut other = TcpClient::connect("127.0.0.1:8099").await.unwrap();
+        other.auth(None, "key-2").await.unwrap();
+        let Response::Ok(Some(listed)) = other.send_command(Command::ClientList).await.unwrap()
+        else {
+            panic!("CLIENT LIST failed");
+        };
+        let listed = listed.as_array().unwrap();
+        assert_eq!(listed.len(), 2);
+        assert!(listed.iter().all(|client| client["identity"] == "acme"));
+        let Response::Ok(Some(all)) = ops.send_command(Command::ClientList).await.unwrap() else {
+            panic!("CLIENT LIST failed");
+        };
+        assert_eq!(all.as_array().unwrap().len(), 3);
+        let ops_id = all
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|client| client["identity"] == "ops")
+            .and_then(|client| client["id"].as_u64())
+            .unwrap();
+        assert!(matches!(
+            other.send_command(Command::ClientKill { id: ops_id }).await.unwrap(),
+            Response::Error(e) if e.code == ErrorCode::InvalidArgument
+        ));
+        for command in [
+            Command::Info,
+            Command::Stats,
+            Command::ConfigSet {
+                parameter: "maxmemory".to_string(),
+                value: "1".to_string(),
+            },
+            Command::Save { background: false },
+            Command::Promote,
+        ] {
+            assert!(matches!(
+                other.send_command(command).await.unwrap(),
+                Response::Error(e) if e.code == ErrorCode::Forbidden
+            ));
+        }
+        assert!(matches!(
+            ops.send_command(Command::Info).await.unwrap(),
+            Response::Ok(Some(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stream_results() {
+        let database = Arc::new(Database::new());
+        for i in 0..25 {
+            database
+                .execute_command(Command::Set {
+                    key: format!("user:{:02}", i),
+                    value: json!({ "id": i }),
+                })
+                .await;
+        }
+        let server = TcpServer::new(database, "127.0.0.1:8100".to_string());
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
+
+        let mut client = TcpClient::connect("127.0.0.1:8100").await.unwrap();
+        let qscan = |limit| Command::QScan {
+            key_pattern: "user:*".to_string(),
+            query: "$.id".to_string(),
+            limit,
+        };
+        let mut results = client.stream(qscan(None), 10).await.unwrap();
+        let mut sizes = Vec::new();
+        while let Some(chunk) = results.next_chunk().await.unwrap() {
+            sizes.push(chunk.len());
+        }
+        assert_eq!(sizes, vec![10, 10, 5]);
+
+        let mut records = client
+            .stream(
+                Command::Export {
+                    pattern: "user:0*".to_string(),
+                },
+                100,
+            )
+            .await
+            .unwrap();
+        let chunk = records.next_chunk().await.unwrap().unwrap();
+        assert_eq!(chunk.len(), 10);
+        assert_eq!(chunk[3]["key"], "user:03");
+        assert_eq!(chunk[3]["value"], json!({ "id": 3 }));
+        assert_eq!(records.next_chunk().await.unwrap(), None);
+
+        let mut unsupported = client.stream(Command::Stats, 10).await.unwrap();
+        assert!(unsupported.next_chunk().await.is_err());
+
+        // Chunks left unread are skipped by the next request
+        let mut results = client.stream(qscan(Some(15)), 5).await.unwrap();
+        assert_eq!(results.next_chunk().await.unwrap().unwrap().len(), 5);
+        assert!(matches!(
+            client.send_command(Command::Ping).await,
+            Ok(Response::Pong)
+        ));
+
+        // A streamed result is not read as a single response
+        let stream = Command::Stream {
+            command: Box::new(qscan(None)),
+            chunk_size: Some(10),
+        };
+        assert!(client.send_command(stream).await.is_err());
+        assert!(matches!(
+            client.send_command(Command::Ping).await,
+            Ok(Response::Pong)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_select_namespace() {
+        let database = Arc::new(Database::new());
+        let server = TcpServer::new(database.clone(), "127.0.0.1:8084".to_string());
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
+
+        let mut client = TcpClient::connect("127.0.0.1:8084").await.unwrap();
+        client.select("tenant-a").await.unwrap();
+        client
+            .send_command(Command::Set {
+                key: "k".to_string(),
+                value: json!(1),
+            })
+            .await
+            .unwrap();
+        assert!(client.select("not valid").await.is_err());
+        let response = client
+            .send_command(Command::Flush { prefix: None })
+            .await
+            .unwrap();
+        assert!(matches!(response, Response::Error(e) if e.code == ErrorCode::Forbidden));
+
+        assert!(database.is_empty());
+        assert_eq!(database.namespace("tenant-a").unwrap().len(), 1);
+        client.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_key_events() {
+        let database = Arc::new(Database::new());
+        let server = TcpServer::new(database, "127.0.0.1:8087".to_string());
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
+
+        let mut subscriber = TcpClient::connect("127.0.0.1:8087").await.unwrap();
+        subscriber.subscribe_events("user:*").await.unwrap();
+        let mut writer = TcpClient::connect("127.0.0.1:8087").await.unwrap();
+        for command in [
+            Command::Set {
+                key: "user:1".to_string(),
+                value: json!({"name": "a"}),
+            },
+            Command::Set {
+                key: "order:1".to_string(),
+                value: json!(1),
+            },
+            Command::Merge {
+                key: "user:1".to_string(),
+                value: json!({"age": 1}),
+            },
+            Command::Delete {
+                key: "user:1".to_string(),
+            },
+        ] {
+            writer.send_command(command).await.unwrap();
+        }
+
+        for expected in ["set", "merge", "delete"] {
+            let event = tokio::time::timeout(Duration::from_secs(1), subscriber.next_event())
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(
+                matches!(&event, Response::KeyEvent(e) if e.event == expected && e.key == "user:1"),
+                "{}",
+                event
+            );
+        }
+        subscriber.close().await.unwrap();
+        writer.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_watch_key() {
+        let database = Arc::new(Database::new());
+        let server = TcpServer::new(database.clone(), "127.0.0.1:8088".to_string());
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
+
+        let mut watcher = TcpClient::connect("127.0.0.1:8088").await.unwrap();
+        assert_eq!(watcher.watch("k").await.unwrap(), None);
+        let mut next_update = async || {
+            tokio::time::timeout(Duration::from_secs(1), watcher.next_event())
+                .await
+                .unwrap()
+                .unwrap()
+        };
+
+        database
+            .execute_command(Command::Set {
+                key: "k".to_string(),
+                value: json!(1),
+            })
+            .await;
+        let update = next_update().await;
+        assert!(
+            matches!(&update, Response::KeyUpdated(u) if u.key == "k" && u.value == Some(json!(1))),
+            "{}",
+            update
+        );
+
+        database
+            .execute_command(Command::Delete {
+                key: "k".to_string(),
+            })
+            .await;
+        let update = next_update().await;
+        assert!(
+            matches!(&update, Response::KeyUpdated(u) if u.value.is_none()),
+            "{}",
+            update
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pub_sub() {
+        let database = Arc::new(Database::new());
+        let server = TcpServer::new(database, "127.0.0.1:8089".to_string());
+        let pubsub = server.pubsub();
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
+
+        let mut subscriber = TcpClient::connect("127.0.0.1:8089").await.unwrap();
+        let mut publisher = TcpClient::connect("127.0.0.1:8089").await.unwrap();
+        assert_eq!(publisher.publish("news", json!("early")).await.unwrap(), 0);
+        subscriber.subscribe(&["news", "sports"]).await.unwrap();
+        assert_eq!(
+            publisher.publish("news", json!({"id": 1})).await.unwrap(),
+            1
+        );
+        pubsub.publish("sports", json!({"id": 2}));
+
+        for (channel, id) in [("news", 1), ("sports", 2)] {
+            let message = tokio::time::timeout(Duration::from_secs(1), subscriber.next_event())
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(
+                matches!(&message, Response::ChannelMessage(m) if m.channel == channel && m.message["id"] == id),
+                "{}",
+                message
+            );
+        }
+
+        // Events can be read as a stream
+        publisher.publish("news", json!({"id": 3})).await.unwrap();
+        let mut events = Box::pin(subscriber.events());
+        let event = tokio::time::timeout(Duration::from_secs(1), events.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(matches!(&event, Event::Message(m) if m.message["id"] == 3));
+        drop(events);
+        subscriber.close().await.unwrap();
+        publisher.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_changefeed() {
+        let database = Arc::new(Database::new());
+        database.set_change_log_capacity(100);
+        let server = TcpServer::new(database.clone(), "127.0.0.1:8090".to_string());
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
+
+        for key in ["a", "b"] {
+            database
+                .execute_command(Command::Set {
+                    key: key.to_string(),
+                    value: json!(1),
+                })
+                .await;
+        }
+        let mut client = TcpClient::connect("127.0.0.1:8090").await.unwrap();
+        assert!(client.changefeed(3).await.is_err());
+        client.changefeed(1).await.unwrap();
+        database
+            .execute_command(Command::Delete {
+                key: "a".to_string(),
+            })
+            .await;
+
+        // Retained changes are replayed before new ones
+        for (offset, key) in [(2, "b"), (3, "a")] {
+            let change = tokio::time::timeout(Duration::from_secs(1), client.next_event())
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(
+                matches!(&change, Response::Change(c) if c.offset == offset && c.key == key),
+                "{}",
+                change
+            );
+        }
+        client.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_routing_client() {
+        let primary = Arc::new(Database::new());
+        // Only the primary keeps a change log for consumer groups
+        primary.set_change_log_capacity(10);
+        let replica = Arc::new(Database::new());
+        let root = std::env::temp_dir().join(format!("jsonvault-routing-{}", uuid::Uuid::new_v4()));
+        let dir = crate::storage::layout::DataDir::open(&root, Some("node-3")).unwrap();
+        let stale = Database::new();
+        // A standby that never reaches its primary has no known lag
+        stale.set_standby(Arc::new(crate::storage::standby::Standby::new(
+            "127.0.0.1:1",
+            dir,
+            crate::storage::aof::FsyncPolicy::Always,
+        )));
+        for (database, port) in [
+            (primary.clone(), 8107),
+            (replica.clone(), 8108),
+            (Arc::new(stale), 8109),
+        ] {
+            let server = TcpServer::new(database, format!("127.0.0.1:{}", port));
+            tokio::spawn(async move {
+                let _ = server.start().await;
+            });
+        }
+        sleep(Duration::from_millis(100)).await;
+
+        let get = || Command::Get {
+            key: "k".to_string(),
+        };
+        let client = RoutingClient::new("127.0.0.1:8107", vec!["127.0.0.1:8108".to_string()], 2);
+        let response = client
+            .send_command(Command::Set {
+                key: "k".to_string(),
+                value: json!("primary"),
+            })
+            .await
+            .unwrap();
+        assert!(matches!(response, Response::Ok(None)));
+        assert_eq!(primary.len(), 1);
+        assert_eq!(replica.len(), 0);
+        replica
+            .execute_command(Command::Set {
+                key: "k".to_string(),
+                value: json!("replica"),
+            })
+            .await;
+        let response = client.send_command(get()).await.unwrap();
+        assert!(matches!(response, Response::Ok(Some(v)) if v == json!("replica")));
+        let response = client.send_to_primary(get()).await.unwrap();
+        assert!(matches!(response, Response::Ok(Some(v)) if v == json!("primary")));
+
+        // Commands with side effects on the node run on the primary
+        let mut subscriber = TcpClient::connect("127.0.0.1:8107").await.unwrap();
+        subscriber.subscribe(&["news"]).await.unwrap();
+        let response = client
+            .send_command(Command::Publish {
+                channel: "news".to_string(),
+                message: json!(1),
+            })
+            .await
+            .unwrap();
+        assert!(matches!(response, Response::Ok(Some(v)) if v == json!(1)));
+        let response = client
+            .send_command(Command::GroupRead {
+                group: "indexer".to_string(),
+                count: 10,
+            })
+            .await
+            .unwrap();
+        assert!(
+            matches!(response, Response::Ok(Some(changes)) if changes.as_array().unwrap().len() == 1)
+        );
+        // Commands binding their connection are refused
+        for command in [
+            Command::Select {
+                namespace: "other".to_string(),
+            },
+            Command::Watch {
+                key: "k".to_string(),
+            },
+        ] {
+            assert!(client.send_command(command.clone()).await.is_err());
+            assert!(client.send_to_primary(command).await.is_err());
+        }
+        let response = client.send_command(get()).await.unwrap();
+        assert!(matches!(response, Response::Ok(Some(v)) if v == json!("replica")));
+
+        // Stale and unreachable replicas are skipped
+        let client = RoutingClient::new(
+            "127.0.0.1:8107",
+            vec!["127.0.0.1:8109".to_string(), "127.0.0.1:1".to_string()],
+            2,
+        )
+        .with_max_staleness(Duration::from_secs(5));
+        for _ in 0..2 {
+            let response = client.send_command(get()).await.unwrap();
+            assert!(matches!(response, Response::Ok(Some(v)) if v == json!("primary")));
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_send_batch() {
+        let database = Arc::new(Database::new());
+        let server = TcpServer::new(database.clone(), "127.0.0.1:8106".to_string());
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
+
+        let mut client = TcpClient::connect("127.0.0.1:8106").await.unwrap();
+        let mut commands: Vec<Command> = (0..300)
+            .map(|i| Command::Set {
+                key: format!("batch:{}", i),
+                value: json!(i),
+            })
+            .collect();
+        commands.push(Command::QGet {
+            key: "missing".to_string(),
+            query: "$.n".to_string(),
+        });
+        commands.push(Command::Get {
+            key: "batch:299".to_string(),
+        });
+        let responses = client.send_batch(commands).await.unwrap();
+        assert_eq!(responses.len(), 302);
+        assert!(responses[..300]
+            .iter()
+            .all(|response| matches!(response, Response::Ok(None))));
+        // A failed command doesn't stop the ones after it
+        assert!(matches!(&responses[300], Response::Error(e) if e.code == ErrorCode::KeyNotFound));
+        assert!(matches!(&responses[301], Response::Ok(Some(value)) if value == &json!(299)));
+        assert_eq!(database.len(), 300);
+
+        // The connection is still usable
+        assert!(client.send_batch(Vec::new()).await.unwrap().is_empty());
+        let response = client.send_command(Command::Ping).await.unwrap();
         assert!(matches!(response, Response::Pong));
+        client.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bulk_load() {
+        let database = Arc::new(Database::new());
+        let server = TcpServer::new(database.clone(), "127.0.0.1:8091".to_string());
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
 
+        let ndjson: String = (0..2500)
+            .map(|i| format!("{{\"key\":\"doc:{}\",\"value\":{{\"n\":{}}}}}\n", i, i))
+            .collect();
+        let mut client = TcpClient::connect("127.0.0.1:8091").await.unwrap();
+        let loaded = client.bulk_load(ndjson.as_bytes(), 1000).await.unwrap();
+        assert_eq!(loaded, 2500);
+        assert_eq!(database.len(), 2500);
+
+        // A failing chunk reports how far the load got
+        let err = client
+            .bulk_load("{\"key\":\"ok\",\"value\":1}\nnot json\n".as_bytes(), 1)
+            .await
+            .unwrap_err();
+        assert!(
+            err.starts_with("Bulk load failed after 1 documents"),
+            "{}",
+            err
+        );
+        client.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_proxy_failover() {
+        let database = Arc::new(Database::new());
+        let server = TcpServer::new(database.clone(), "127.0.0.1:8085".to_string());
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        // The first backend is unreachable
+        let proxy = TcpProxy::new(
+            "127.0.0.1:8086".to_string(),
+            vec!["127.0.0.1:1".to_string(), "127.0.0.1:8085".to_string()],
+        )
+        .with_retries(2, Duration::from_millis(10));
+        tokio::spawn(async move {
+            let _ = proxy.start().await;
+        });
+        sleep(Duration::from_millis(100)).await;
+
+        let mut client = TcpClient::connect("127.0.0.1:8086").await.unwrap();
+        client.select("tenant-a").await.unwrap();
+        let response = client
+            .send_command(Command::Set {
+                key: "k".to_string(),
+                value: json!(1),
+            })
+            .await
+            .unwrap();
+        assert!(matches!(response, Response::Ok(None)));
+        assert_eq!(database.namespace("tenant-a").unwrap().len(), 1);
         client.close().await.unwrap();
     }
 }
@@ -0,0 +1,99 @@
+use crate::protocol::{ErrorCode, ErrorInfo, ProfileKind};
+use serde_json::Value;
+
+/// Maximum duration of a single profiling session
+pub const MAX_PROFILE_SECONDS: u64 = 300;
+
+/// Default sampling frequency for CPU profiles
+#[cfg(feature = "profiling")]
+const DEFAULT_PROFILE_FREQUENCY: i32 = 99;
+
+/// Capture a pprof-compatible profile for the given number of seconds.
+///
+/// The profile is returned as a JSON object holding the base64-encoded
+/// protobuf payload, ready to be decoded and fed to `go tool pprof`.
+#[cfg(feature = "profiling")]
+pub async fn capture(kind: ProfileKind, seconds: u64) -> Result<Value, ErrorInfo> {
+    use base64::Engine;
+    use pprof::protos::Message;
+
+    if kind == ProfileKind::Heap {
+        return Err(ErrorInfo::new(
+            ErrorCode::Unsupported,
+            "Heap profiling is not supported by this build",
+        ));
+    }
+    let seconds = validate_duration(seconds)?;
+
+    // The profiler guard is not Send, so the whole session runs on a blocking thread
+    let encoded = tokio::task::spawn_blocking(move || -> Result<Vec<u8>, String> {
+        let guard = pprof::ProfilerGuardBuilder::default()
+            .frequency(DEFAULT_PROFILE_FREQUENCY)
+            .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+            .build()
+            .map_err(|e| format!("Failed to start profiler: {}", e))?;
+        std::thread::sleep(std::time::Duration::from_secs(seconds));
+        let profile = guard
+            .report()
+            .build()
+            .and_then(|report| report.pprof())
+            .map_err(|e| format!("Failed to build profile: {}", e))?;
+        let mut buffer = Vec::new();
+        profile
+            .encode(&mut buffer)
+            .map_err(|e| format!("Failed to encode profile: {}", e))?;
+        Ok(buffer)
+    })
+    .await
+    .map_err(|e| ErrorInfo::new(ErrorCode::Internal, format!("Profiler task failed: {}", e)))?
+    .map_err(|e| ErrorInfo::new(ErrorCode::Internal, e))?;
+
+    Ok(serde_json::json!({
+        "kind": "cpu",
+        "format": "pprof",
+        "seconds": seconds,
+        "encoding": "base64",
+        "size": encoded.len(),
+        "data": base64::engine::general_purpose::STANDARD.encode(&encoded),
+    }))
+}
+
+/// Capture a profile (unavailable: the server was built without the `profiling` feature)
+#[cfg(not(feature = "profiling"))]
+pub async fn capture(_kind: ProfileKind, seconds: u64) -> Result<Value, ErrorInfo> {
+    validate_duration(seconds)?;
+    Err(ErrorInfo::new(
+        ErrorCode::Unsupported,
+        "Profiling is not enabled in this build (compile with --features profiling)",
+    ))
+}
+
+/// Validates the requested profiling duration
+fn validate_duration(seconds: u64) -> Result<u64, ErrorInfo> {
+    if seconds == 0 || seconds > MAX_PROFILE_SECONDS {
+        return Err(ErrorInfo::new(
+            ErrorCode::InvalidArgument,
+            format!(
+                "Profile duration must be between 1 and {} seconds",
+                MAX_PROFILE_SECONDS
+            ),
+        ));
+    }
+    Ok(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_capture_rejects_invalid_duration() {
+        let err = capture(ProfileKind::Cpu, 0).await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidArgument);
+
+        let err = capture(ProfileKind::Cpu, MAX_PROFILE_SECONDS + 1)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidArgument);
+    }
+}
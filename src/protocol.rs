@@ -1,7 +1,11 @@
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::fmt;
 
+use crate::acl::Grant;
+use crate::changes::Change;
+
 /// Commands supported by the protocol
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Command {
@@ -11,8 +15,26 @@ pub enum Command {
     Get { key: String },
     /// DELETE key - Delete a value for a key
     Delete { key: String },
+    /// SETBYTES key bytes - Set a binary value for a key
+    SetBytes { key: String, value: Vec<u8> },
+    /// GETBYTES key - Read the binary value of a key
+    GetBytes { key: String },
     /// QGET key query - Execute a JSONPath query on a value
     QGet { key: String, query: String },
+    /// QSCAN pattern query [limit] - Execute a JSONPath query on every key matching a glob
+    QScan {
+        key_pattern: String,
+        query: String,
+        limit: Option<usize>,
+    },
+    /// AGGREGATE pattern path op - Aggregate a numeric JSONPath across keys matching a glob
+    Aggregate {
+        key_pattern: String,
+        path: String,
+        op: AggregateOp,
+    },
+    /// JQGET key program - Execute a jq program on a value
+    JqGet { key: String, program: String },
     /// QSET key path value - Set a sub-property using JSONPath
     QSet {
         key: String,
@@ -21,19 +43,956 @@ pub enum Command {
     },
     /// MERGE key value - Merge a JSON value with an existing one
     Merge { key: String, value: Value },
+    /// QTYPE key path - Return the JSON type (and length) of the value at a JSONPath
+    QType { key: String, path: String },
+    /// QAPPEND key path value - Append a value to the array at a JSONPath
+    QAppend {
+        key: String,
+        path: String,
+        value: Value,
+    },
+    /// QINSERT key path index value - Insert a value into the array at a JSONPath
+    /// (negative indexes count from the end)
+    QInsert {
+        key: String,
+        path: String,
+        index: i64,
+        value: Value,
+    },
+    /// QPOP key path [index] - Remove and return an element of the array at a JSONPath
+    /// (the last one by default)
+    QPop {
+        key: String,
+        path: String,
+        index: Option<i64>,
+    },
+    /// OBJKEYS key path - Return the member names of the object at a JSONPath
+    ObjKeys { key: String, path: String },
+    /// ARRLEN key path - Return the length of the array at a JSONPath
+    ArrLen { key: String, path: String },
+    /// DIGEST [key] - Stable digest of a key or of the whole keyspace
+    Digest { key: Option<String> },
+    /// EXPLAIN command - Describe how a command would be executed without running it
+    Explain { command: Box<Command> },
+    /// STREAM command [chunk_size] - Send the results of a QSCAN or an EXPORT in
+    /// chunks of at most chunk_size results, one frame each, then the result count
+    Stream {
+        command: Box<Command>,
+        #[serde(default)]
+        chunk_size: Option<usize>,
+    },
+    /// EVAL script keys args - Run a WASM script atomically on the declared keys
+    /// (requires the scripting feature)
+    Eval {
+        script: String,
+        keys: Vec<String>,
+        #[serde(default)]
+        args: Vec<Value>,
+    },
+    /// PROFILE kind seconds - Capture a pprof-compatible profile (admin)
+    Profile { kind: ProfileKind, seconds: u64 },
+    /// INJECTLATENCY target millis seconds - Delay client responses or replication on
+    /// this node for a number of seconds (admin, a zero delay clears the injection)
+    InjectLatency {
+        target: LatencyTarget,
+        millis: u64,
+        seconds: u64,
+    },
+    /// READONLY enabled - Reject every write, or accept writes again (admin)
+    ReadOnly { enabled: bool },
+    /// SAVE / BGSAVE - Save a snapshot of every namespace to the data directory;
+    /// BGSAVE answers once the keyspace is copied and writes the snapshot afterwards (admin)
+    Save {
+        #[serde(default)]
+        background: bool,
+    },
+    /// VERIFYBACKUP [source] - Load a backup into a temporary in-memory instance and
+    /// report whether it restores: the node's data directory by default, another data
+    /// directory on the server, or `s3` for the configured bucket (admin)
+    VerifyBackup {
+        #[serde(default)]
+        source: Option<String>,
+    },
+    /// AOFFETCH sequence offset - Complete AOF records of a segment from a byte offset,
+    /// or the newest snapshot index once the segment is gone, for standby nodes (admin)
+    AofFetch { sequence: u64, offset: u64 },
+    /// SNAPSHOTFETCH index offset - Bytes of a snapshot file from an offset, for
+    /// standby nodes bootstrapping (admin)
+    SnapshotFetch { index: u64, offset: u64 },
+    /// PROMOTE - Stop following the primary and accept writes (standby nodes, admin)
+    Promote,
+    /// ACLSET identity grants - Replace the grants of an authenticated identity (admin)
+    AclSet {
+        identity: String,
+        grants: Vec<Grant>,
+    },
+    /// ACLDEL identity - Remove the grants of an identity, leaving it without permissions (admin)
+    AclDel { identity: String },
+    /// ACLLIST - Grants of every restricted identity (admin)
+    AclList,
+    /// CONFIG GET pattern - Runtime configuration parameters matching a glob (admin)
+    ConfigGet { parameter: String },
+    /// CONFIG SET parameter value - Change a runtime configuration parameter,
+    /// effective without a restart (admin)
+    ConfigSet { parameter: String, value: String },
+    /// AUDIT since [identity] [key_pattern] [limit] - Records of the audit log since a
+    /// time (milliseconds since the UNIX epoch), oldest first (admin, requires the audit log)
+    Audit {
+        since: u64,
+        #[serde(default)]
+        identity: Option<String>,
+        #[serde(default)]
+        key_pattern: Option<String>,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    /// MEMORY USAGE key - Estimate the serialized and in-memory size of a document
+    MemoryUsage { key: String },
+    /// META key - Creation time, last update time and version of a key
+    Meta { key: String },
+    /// FLUSH [prefix] - Delete every key of the namespace, or those starting with a prefix (admin)
+    Flush {
+        #[serde(default)]
+        prefix: Option<String>,
+    },
+    /// EXPIRE key seconds - Delete a key once a number of seconds has passed
+    Expire { key: String, seconds: u64 },
+    /// TTL key - Remaining time to live of a key in milliseconds
+    Ttl { key: String },
+    /// PERSIST key - Remove the expiry of a key
+    Persist { key: String },
+    /// RANDOMKEY - A random key of the namespace
+    RandomKey,
+    /// SAMPLE n - Up to n random keys with their values
+    Sample { n: usize },
+    /// TOMBSTONES since - Keys deleted since a time (milliseconds since the UNIX epoch)
+    Tombstones { since: u64 },
+    /// CHANGES since [limit] - Changes with an offset greater than since, oldest first
+    /// (requires the change log to be enabled)
+    Changes {
+        since: u64,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    /// GROUPREAD group count - Up to count changes not yet acknowledged by a consumer group
+    GroupRead { group: String, count: usize },
+    /// GROUPACK group offset - Acknowledge the changes of a consumer group up to an offset
+    GroupAck { group: String, offset: u64 },
+    /// HISTORY key - Last revisions of a key (requires history to be enabled)
+    History { key: String },
+    /// RESTORE key version - Write back the value a key had at a past version
+    Restore { key: String, version: u64 },
+    /// ROUTINGTABLE - Key prefix to node assignments
+    RoutingTable,
+    /// INFO - Build information, enabled features, limits and node identity,
+    /// with uptime, key count, memory, clients and replication role
+    Info,
+    /// USAGE - Resource usage and quota of the current namespace
+    Usage,
+    /// STATS - Operation counters, key count, uptime and memory estimates
+    Stats,
+    /// SHARDSTATS - Keys and lock waits of every shard of the current namespace
+    ShardStats,
+    /// COMMAND - Every supported command with its arguments, arity, flags and
+    /// the protocol version that introduced it
+    Commands,
+    /// EXPORT pattern - Keys matching a glob with their values and metadata, as NDJSON
+    Export { pattern: String },
+    /// IMPORT data - Write the keys of an NDJSON export, like SETs
+    Import { data: String },
+    /// BULKLOAD data - Write a chunk of NDJSON documents streamed by a bulk load
+    BulkLoad { data: String },
+    /// WATCH key - Return the value of a key and push its new value every time it changes
+    Watch { key: String },
+    /// CHANGEFEED from_offset - Push every change with an offset greater than
+    /// from_offset, then every new change (requires the change log to be enabled)
+    Changefeed { from_offset: u64 },
+    /// SUBSCRIBEEVENTS pattern - Push an event for every change to a key of the
+    /// current namespace matching a glob (replaces any previous subscription)
+    SubscribeEvents { pattern: String },
+    /// PUBLISH channel message - Send a message to the subscribers of a channel
+    Publish { channel: String, message: Value },
+    /// SUBSCRIBE channel... - Push the messages published on channels to this connection
+    Subscribe { channels: Vec<String> },
+    /// CLIENT SETNAME name - Name the connection, as listed by CLIENT LIST
+    ClientSetName { name: String },
+    /// CLIENT LIST - Open connections with their name, identity, namespace,
+    /// commands, bytes in and out and idle time (admin)
+    ClientList,
+    /// CLIENT KILL id - Close a connection once its current command completes (admin)
+    ClientKill { id: u64 },
+    /// SELECT namespace - Switch the connection to a namespace
+    Select { namespace: String },
+    /// AUTH [username] password - Authenticate the connection with a password or an API key
+    Auth {
+        #[serde(default)]
+        username: Option<String>,
+        password: String,
+    },
+    /// HELLO - Connection handshake: agree on the protocol version, payload encoding and
+    /// frame compression, optionally subscribing to cluster topology changes
+    Hello {
+        #[serde(default)]
+        topology_updates: bool,
+        /// Protocol version of the client (None for clients predating versioning)
+        #[serde(default)]
+        protocol: Option<ProtocolVersion>,
+        /// Payload encodings the client can use, preferred first (JSON when empty)
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        encodings: Vec<String>,
+        /// Frame compression the client can use, preferred first (none when empty)
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        compression: Vec<String>,
+        /// Whether the client reads messages split across frames
+        #[serde(default)]
+        chunked_frames: bool,
+    },
     /// PING - Health check
     Ping,
 }
 
+/// Version of the wire protocol spoken by this build
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest protocol version this build can talk to
+pub const MIN_COMPATIBLE_PROTOCOL_VERSION: u32 = 1;
+
+/// Protocol version advertised by clients and peers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolVersion {
+    /// Protocol version spoken
+    pub version: u32,
+    /// Oldest protocol version still understood
+    pub min_compatible: u32,
+}
+
+impl ProtocolVersion {
+    /// The protocol version of this build
+    pub fn current() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            min_compatible: MIN_COMPATIBLE_PROTOCOL_VERSION,
+        }
+    }
+
+    /// Checks that two sides can talk to each other: each one must speak a
+    /// version the other still understands
+    pub fn check_compatible(&self, peer: &ProtocolVersion) -> Result<(), String> {
+        if peer.min_compatible > self.version {
+            return Err(format!(
+                "Peer speaks protocol version {} and requires at least {}, this node speaks {}: upgrade this node first",
+                peer.version, peer.min_compatible, self.version
+            ));
+        }
+        if self.min_compatible > peer.version {
+            return Err(format!(
+                "Peer speaks protocol version {}, older than the minimum {} supported by this node",
+                peer.version, self.min_compatible
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for ProtocolVersion {
+    /// Version assumed for peers and clients that don't advertise one
+    fn default() -> Self {
+        Self {
+            version: 1,
+            min_compatible: 1,
+        }
+    }
+}
+
+/// Payload encodings supported by this build, preferred first
+pub const ENCODINGS: &[&str] = &["json"];
+
+/// Frame compression supported by this build, preferred first
+pub const COMPRESSIONS: &[&str] = &["zstd", "none"];
+
+/// Connection settings agreed on by HELLO, as sent back to the client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Handshake {
+    /// Protocol version of the server
+    pub protocol: ProtocolVersion,
+    /// Encoding of the payloads from now on
+    pub encoding: String,
+    /// Compression of the frames from now on
+    pub compression: String,
+    /// Whether commands are refused until the client authenticates
+    pub auth_required: bool,
+    /// Whether topology changes are pushed on the connection
+    pub topology_updates: bool,
+    /// Whether messages larger than `MAX_FRAME_PIECE` are split across frames
+    #[serde(default)]
+    pub chunked_frames: bool,
+}
+
+impl Handshake {
+    /// Agree on the settings of a connection with a client offering a
+    /// protocol version, encodings and compression. Clients that offer no
+    /// encoding or compression get JSON without compression, as before
+    /// negotiation existed.
+    pub fn negotiate(
+        protocol: Option<ProtocolVersion>,
+        encodings: &[String],
+        compression: &[String],
+    ) -> Result<Self, String> {
+        let server_protocol = ProtocolVersion::current();
+        server_protocol.check_compatible(&protocol.unwrap_or_default())?;
+        Ok(Self {
+            protocol: server_protocol,
+            encoding: Self::pick("encoding", encodings, ENCODINGS, "json")?,
+            compression: Self::pick("compression", compression, COMPRESSIONS, "none")?,
+            auth_required: false,
+            topology_updates: false,
+            chunked_frames: false,
+        })
+    }
+
+    /// Picks the first offered option this build supports, or `default`
+    /// when nothing is offered
+    fn pick(
+        what: &str,
+        offered: &[String],
+        supported: &[&str],
+        default: &str,
+    ) -> Result<String, String> {
+        if offered.is_empty() {
+            return Ok(default.to_string());
+        }
+        offered
+            .iter()
+            .find(|option| supported.contains(&option.as_str()))
+            .cloned()
+            .ok_or_else(|| {
+                format!(
+                    "No supported {} among {}; this node supports {}",
+                    what,
+                    offered.join(", "),
+                    supported.join(", ")
+                )
+            })
+    }
+}
+
+/// Aggregation computed by `Command::Aggregate`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AggregateOp {
+    /// Number of numeric values
+    Count,
+    /// Sum of the numeric values
+    Sum,
+    /// Arithmetic mean of the numeric values
+    Avg,
+    /// Smallest numeric value
+    Min,
+    /// Largest numeric value
+    Max,
+}
+
+impl AggregateOp {
+    /// Returns the protocol name of the operation
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AggregateOp::Count => "count",
+            AggregateOp::Sum => "sum",
+            AggregateOp::Avg => "avg",
+            AggregateOp::Min => "min",
+            AggregateOp::Max => "max",
+        }
+    }
+}
+
+impl std::str::FromStr for AggregateOp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "count" => Ok(AggregateOp::Count),
+            "sum" => Ok(AggregateOp::Sum),
+            "avg" => Ok(AggregateOp::Avg),
+            "min" => Ok(AggregateOp::Min),
+            "max" => Ok(AggregateOp::Max),
+            other => Err(format!("Unknown aggregate operation '{}'", other)),
+        }
+    }
+}
+
+/// Kind of profile captured by `Command::Profile`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileKind {
+    /// Sampled CPU profile
+    Cpu,
+    /// Heap allocation profile
+    Heap,
+}
+
+/// Where `Command::InjectLatency` adds delays
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LatencyTarget {
+    /// Responses to client commands
+    Responses,
+    /// Replication requests received from the leader
+    Replication,
+}
+
+impl LatencyTarget {
+    /// Returns the protocol name of the target
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LatencyTarget::Responses => "responses",
+            LatencyTarget::Replication => "replication",
+        }
+    }
+}
+
+/// Maximum length in bytes of an error message sent over the wire
+pub const MAX_ERROR_MESSAGE_LEN: usize = 1024;
+
+/// Maximum serialized length in bytes of error details sent over the wire
+pub const MAX_ERROR_DETAILS_LEN: usize = 4096;
+
 /// Server response
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Response {
     /// Operation completed successfully
     Ok(Option<Value>),
     /// Operation error
-    Error(String),
+    Error(ErrorInfo),
     /// Response to PING
     Pong,
+    /// Binary value returned by GETBYTES
+    Bytes(Vec<u8>),
+    /// Unsolicited notification pushed to clients subscribed at handshake
+    ClusterTopologyChanged(ClusterTopology),
+    /// Change to a key, pushed to clients subscribed with SUBSCRIBEEVENTS
+    KeyEvent(KeyEvent),
+    /// Number of events dropped because the subscriber fell behind
+    KeyEventsDropped(u64),
+    /// New value of a key, pushed to clients that sent WATCH
+    KeyUpdated(KeyUpdate),
+    /// Message of a channel, pushed to clients that sent SUBSCRIBE
+    ChannelMessage(ChannelMessage),
+    /// Entry of the change log, pushed to clients that sent CHANGEFEED
+    Change(Change),
+    /// Chunk of the results of a STREAM, before the final response
+    Chunk(Vec<Value>),
+}
+
+/// Message published on a channel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChannelMessage {
+    pub channel: String,
+    pub message: Value,
+}
+
+/// Current value of a watched key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyUpdate {
+    pub namespace: String,
+    pub key: String,
+    /// None once the key is deleted
+    pub value: Option<Value>,
+}
+
+/// Change to a key of a namespace
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyEvent {
+    pub namespace: String,
+    pub key: String,
+    /// Lowercase name of the write command (`set`, `merge`, `delete`...),
+    /// or `expired` when the key was deleted because its expiry passed
+    pub event: String,
+}
+
+/// Event pushed by the server to a client that subscribed to it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// Change to a key, after SUBSCRIBEEVENTS
+    Key(KeyEvent),
+    /// Number of key events dropped because the subscriber fell behind
+    Dropped(u64),
+    /// New value of a key, after WATCH
+    KeyUpdated(KeyUpdate),
+    /// Message of a channel, after SUBSCRIBE
+    Message(ChannelMessage),
+    /// Entry of the change log, after CHANGEFEED
+    Change(Change),
+}
+
+impl TryFrom<Response> for Event {
+    type Error = Response;
+
+    /// Returns the event of an event frame, or the frame if it is another
+    /// response
+    fn try_from(response: Response) -> Result<Self, Response> {
+        match response {
+            Response::KeyEvent(event) => Ok(Event::Key(event)),
+            Response::KeyEventsDropped(count) => Ok(Event::Dropped(count)),
+            Response::KeyUpdated(update) => Ok(Event::KeyUpdated(update)),
+            Response::ChannelMessage(message) => Ok(Event::Message(message)),
+            Response::Change(change) => Ok(Event::Change(change)),
+            other => Err(other),
+        }
+    }
+}
+
+/// Cluster leadership and membership, as pushed to subscribed clients
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClusterTopology {
+    /// Current Raft term
+    pub term: u64,
+    /// Current leader, if one is known
+    pub leader: Option<u64>,
+    /// Cluster member IDs
+    pub members: Vec<u64>,
+    /// IDs of the members receiving replication without voting
+    #[serde(default)]
+    pub learners: Vec<u64>,
+}
+
+/// Assignment of key prefixes to cluster nodes, so that clients can send
+/// each request to a node able to serve it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoutingTable {
+    /// Raft term the table was derived from
+    pub term: u64,
+    /// Routes by key prefix; the longest matching prefix applies
+    pub routes: Vec<Route>,
+}
+
+/// Nodes serving the keys starting with a prefix
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Route {
+    pub prefix: String,
+    /// Node accepting writes (None when unknown or when the server is standalone)
+    pub write: Option<u64>,
+    /// Nodes serving reads (empty when the server is standalone)
+    pub read: Vec<u64>,
+}
+
+impl RoutingTable {
+    /// Every node holds the whole keyspace: writes go to the leader, reads to any member
+    pub fn from_topology(topology: Option<&ClusterTopology>) -> Self {
+        Self {
+            term: topology.map_or(0, |t| t.term),
+            routes: vec![Route {
+                prefix: String::new(),
+                write: topology.and_then(|t| t.leader),
+                read: topology.map(|t| t.members.clone()).unwrap_or_default(),
+            }],
+        }
+    }
+
+    /// Returns the route of a key
+    pub fn route(&self, key: &str) -> Option<&Route> {
+        self.routes
+            .iter()
+            .filter(|route| key.starts_with(&route.prefix))
+            .max_by_key(|route| route.prefix.len())
+    }
+}
+
+/// Machine-readable error codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// The requested key does not exist
+    KeyNotFound,
+    /// The requested path does not exist inside the document
+    PathNotFound,
+    /// The value has a different JSON type than the command expects
+    WrongType,
+    /// A command argument is invalid
+    InvalidArgument,
+    /// A query expression could not be parsed or evaluated
+    InvalidQuery,
+    /// This node is not the leader and cannot accept writes
+    NotLeader,
+    /// The operation is not supported by this server build or configuration
+    Unsupported,
+    /// The server cannot serve the request right now (e.g. writes are fenced)
+    Unavailable,
+    /// The write would exceed a namespace quota
+    QuotaExceeded,
+    /// The server is in read-only mode and rejects writes
+    ReadOnly,
+    /// The connection is not allowed to run the command
+    Forbidden,
+    /// The connection must authenticate first, or presented invalid credentials
+    Unauthenticated,
+    /// Unexpected server-side failure
+    Internal,
+    /// Error code not known to this version (or legacy string error)
+    #[serde(other)]
+    Unknown,
+}
+
+impl ErrorCode {
+    /// Returns the wire name of the code
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::KeyNotFound => "KEY_NOT_FOUND",
+            ErrorCode::PathNotFound => "PATH_NOT_FOUND",
+            ErrorCode::WrongType => "WRONG_TYPE",
+            ErrorCode::InvalidArgument => "INVALID_ARGUMENT",
+            ErrorCode::InvalidQuery => "INVALID_QUERY",
+            ErrorCode::NotLeader => "NOT_LEADER",
+            ErrorCode::Unsupported => "UNSUPPORTED",
+            ErrorCode::Unavailable => "UNAVAILABLE",
+            ErrorCode::QuotaExceeded => "QUOTA_EXCEEDED",
+            ErrorCode::ReadOnly => "READ_ONLY",
+            ErrorCode::Forbidden => "FORBIDDEN",
+            ErrorCode::Unauthenticated => "UNAUTHENTICATED",
+            ErrorCode::Internal => "INTERNAL",
+            ErrorCode::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+/// Structured error carried by `Response::Error`
+///
+/// Deserialization also accepts the legacy plain-string form, which is mapped
+/// to `ErrorCode::Unknown`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ErrorRepr")]
+pub struct ErrorInfo {
+    /// Machine-readable error code
+    pub code: ErrorCode,
+    /// Human-readable message (bounded to `MAX_ERROR_MESSAGE_LEN` bytes)
+    pub message: String,
+    /// Optional structured details (bounded to `MAX_ERROR_DETAILS_LEN` bytes)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+    /// Whether retrying the same request may succeed
+    #[serde(default)]
+    pub retriable: bool,
+}
+
+/// Wire representations accepted for `ErrorInfo`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ErrorRepr {
+    Structured {
+        code: ErrorCode,
+        message: String,
+        #[serde(default)]
+        details: Option<Value>,
+        #[serde(default)]
+        retriable: bool,
+    },
+    Legacy(String),
+}
+
+impl From<ErrorRepr> for ErrorInfo {
+    fn from(repr: ErrorRepr) -> Self {
+        match repr {
+            ErrorRepr::Structured {
+                code,
+                message,
+                details,
+                retriable,
+            } => ErrorInfo {
+                code,
+                message,
+                details,
+                retriable,
+            },
+            ErrorRepr::Legacy(message) => ErrorInfo {
+                code: ErrorCode::Unknown,
+                message,
+                details: None,
+                retriable: false,
+            },
+        }
+    }
+}
+
+impl ErrorInfo {
+    /// Create a new error with a bounded message
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: truncate_message(message.into()),
+            details: None,
+            retriable: false,
+        }
+    }
+
+    /// Attach structured details, dropped if they exceed `MAX_ERROR_DETAILS_LEN`
+    pub fn with_details(mut self, details: Value) -> Self {
+        let size = serde_json::to_string(&details)
+            .map(|s| s.len())
+            .unwrap_or(usize::MAX);
+        self.details = if size <= MAX_ERROR_DETAILS_LEN {
+            Some(details)
+        } else {
+            Some(serde_json::json!({ "truncated": true, "size": size }))
+        };
+        self
+    }
+
+    /// Mark the error as retriable
+    pub fn retriable(mut self) -> Self {
+        self.retriable = true;
+        self
+    }
+}
+
+/// Truncate a message to `MAX_ERROR_MESSAGE_LEN` bytes on a char boundary
+fn truncate_message(mut message: String) -> String {
+    if message.len() > MAX_ERROR_MESSAGE_LEN {
+        let mut end = MAX_ERROR_MESSAGE_LEN - 3;
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+        message.truncate(end);
+        message.push_str("...");
+    }
+    message
+}
+
+impl Response {
+    /// Build an error response
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        Response::Error(ErrorInfo::new(code, message))
+    }
+}
+
+impl Command {
+    /// Returns the protocol name of the command
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Set { .. } => "SET",
+            Command::Get { .. } => "GET",
+            Command::SetBytes { .. } => "SETBYTES",
+            Command::GetBytes { .. } => "GETBYTES",
+            Command::Delete { .. } => "DELETE",
+            Command::QGet { .. } => "QGET",
+            Command::QScan { .. } => "QSCAN",
+            Command::Aggregate { .. } => "AGGREGATE",
+            Command::JqGet { .. } => "JQGET",
+            Command::QSet { .. } => "QSET",
+            Command::Merge { .. } => "MERGE",
+            Command::QType { .. } => "QTYPE",
+            Command::QAppend { .. } => "QAPPEND",
+            Command::QInsert { .. } => "QINSERT",
+            Command::QPop { .. } => "QPOP",
+            Command::ObjKeys { .. } => "OBJKEYS",
+            Command::ArrLen { .. } => "ARRLEN",
+            Command::Digest { .. } => "DIGEST",
+            Command::Explain { .. } => "EXPLAIN",
+            Command::Stream { .. } => "STREAM",
+            Command::Eval { .. } => "EVAL",
+            Command::Profile { .. } => "PROFILE",
+            Command::InjectLatency { .. } => "INJECTLATENCY",
+            Command::ReadOnly { .. } => "READONLY",
+            Command::Save { background: false } => "SAVE",
+            Command::Save { background: true } => "BGSAVE",
+            Command::VerifyBackup { .. } => "VERIFYBACKUP",
+            Command::AofFetch { .. } => "AOFFETCH",
+            Command::SnapshotFetch { .. } => "SNAPSHOTFETCH",
+            Command::Promote => "PROMOTE",
+            Command::AclSet { .. } => "ACLSET",
+            Command::AclDel { .. } => "ACLDEL",
+            Command::AclList => "ACLLIST",
+            Command::ConfigGet { .. } => "CONFIG GET",
+            Command::ConfigSet { .. } => "CONFIG SET",
+            Command::Audit { .. } => "AUDIT",
+            Command::MemoryUsage { .. } => "MEMORY USAGE",
+            Command::Meta { .. } => "META",
+            Command::Flush { .. } => "FLUSH",
+            Command::Expire { .. } => "EXPIRE",
+            Command::Ttl { .. } => "TTL",
+            Command::Persist { .. } => "PERSIST",
+            Command::RandomKey => "RANDOMKEY",
+            Command::Sample { .. } => "SAMPLE",
+            Command::Tombstones { .. } => "TOMBSTONES",
+            Command::Changes { .. } => "CHANGES",
+            Command::GroupRead { .. } => "GROUPREAD",
+            Command::GroupAck { .. } => "GROUPACK",
+            Command::History { .. } => "HISTORY",
+            Command::Restore { .. } => "RESTORE",
+            Command::RoutingTable => "ROUTINGTABLE",
+            Command::Info => "INFO",
+            Command::Usage => "USAGE",
+            Command::Stats => "STATS",
+            Command::ShardStats => "SHARDSTATS",
+            Command::Commands => "COMMAND",
+            Command::Export { .. } => "EXPORT",
+            Command::Import { .. } => "IMPORT",
+            Command::BulkLoad { .. } => "BULKLOAD",
+            Command::Watch { .. } => "WATCH",
+            Command::Changefeed { .. } => "CHANGEFEED",
+            Command::SubscribeEvents { .. } => "SUBSCRIBEEVENTS",
+            Command::Publish { .. } => "PUBLISH",
+            Command::Subscribe { .. } => "SUBSCRIBE",
+            Command::ClientSetName { .. } => "CLIENT SETNAME",
+            Command::ClientList => "CLIENT LIST",
+            Command::ClientKill { .. } => "CLIENT KILL",
+            Command::Select { .. } => "SELECT",
+            Command::Auth { .. } => "AUTH",
+            Command::Hello { .. } => "HELLO",
+            Command::Ping => "PING",
+        }
+    }
+
+    /// Returns true if the command modifies the keyspace
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Command::Set { .. }
+                | Command::SetBytes { .. }
+                | Command::Delete { .. }
+                | Command::QSet { .. }
+                | Command::Merge { .. }
+                | Command::QAppend { .. }
+                | Command::QInsert { .. }
+                | Command::QPop { .. }
+                | Command::Restore { .. }
+                | Command::Flush { .. }
+                | Command::Expire { .. }
+                | Command::Persist { .. }
+                | Command::Eval { .. }
+                | Command::Import { .. }
+                | Command::BulkLoad { .. }
+                | Command::GroupAck { .. }
+        )
+    }
+
+    /// Returns true for commands that change state of the node other than
+    /// the keyspace, such as consumer group progress or subscriptions, so
+    /// that they must run on the primary
+    pub fn has_side_effects(&self) -> bool {
+        matches!(
+            self,
+            Command::Publish { .. }
+                | Command::Subscribe { .. }
+                | Command::SubscribeEvents { .. }
+                | Command::Watch { .. }
+                | Command::Changefeed { .. }
+                | Command::GroupRead { .. }
+        )
+    }
+
+    /// Returns true for commands that change the state of their connection,
+    /// such as SELECT, or make the server push frames on it, such as WATCH,
+    /// so that they can't run on a connection shared with other requests
+    pub fn binds_connection(&self) -> bool {
+        self.spec().flags.contains(&"connection")
+            || matches!(
+                self,
+                Command::Watch { .. }
+                    | Command::SubscribeEvents { .. }
+                    | Command::Subscribe { .. }
+                    | Command::Changefeed { .. }
+                    | Command::Stream { .. }
+            )
+    }
+
+    /// Returns true for commands that clients may only run with admin permission
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            Command::Flush { .. }
+                | Command::InjectLatency { .. }
+                | Command::ReadOnly { .. }
+                | Command::Save { .. }
+                | Command::VerifyBackup { .. }
+                | Command::AofFetch { .. }
+                | Command::SnapshotFetch { .. }
+                | Command::Promote
+                | Command::AclSet { .. }
+                | Command::AclDel { .. }
+                | Command::AclList
+                | Command::ConfigGet { .. }
+                | Command::ConfigSet { .. }
+                | Command::ClientList
+                | Command::ClientKill { .. }
+                | Command::Audit { .. }
+                | Command::Profile { .. }
+        )
+    }
+
+    /// Returns the key targeted by the command, if any
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            Command::Set { key, .. }
+            | Command::Get { key }
+            | Command::SetBytes { key, .. }
+            | Command::GetBytes { key }
+            | Command::Delete { key }
+            | Command::QGet { key, .. }
+            | Command::JqGet { key, .. }
+            | Command::QSet { key, .. }
+            | Command::Merge { key, .. }
+            | Command::QType { key, .. }
+            | Command::QAppend { key, .. }
+            | Command::QInsert { key, .. }
+            | Command::QPop { key, .. }
+            | Command::ObjKeys { key, .. }
+            | Command::ArrLen { key, .. }
+            | Command::MemoryUsage { key }
+            | Command::Meta { key }
+            | Command::History { key }
+            | Command::Expire { key, .. }
+            | Command::Ttl { key }
+            | Command::Persist { key }
+            | Command::Watch { key }
+            | Command::Restore { key, .. } => Some(key),
+            Command::Digest { key } => key.as_deref(),
+            Command::QScan { .. }
+            | Command::Aggregate { .. }
+            | Command::Explain { .. }
+            | Command::Stream { .. }
+            | Command::Eval { .. }
+            | Command::Profile { .. }
+            | Command::InjectLatency { .. }
+            | Command::ReadOnly { .. }
+            | Command::Save { .. }
+            | Command::VerifyBackup { .. }
+            | Command::AofFetch { .. }
+            | Command::SnapshotFetch { .. }
+            | Command::Promote
+            | Command::AclSet { .. }
+            | Command::AclDel { .. }
+            | Command::AclList
+            | Command::ConfigGet { .. }
+            | Command::ConfigSet { .. }
+            | Command::Audit { .. }
+            | Command::Usage
+            | Command::Info
+            | Command::Flush { .. }
+            | Command::RandomKey
+            | Command::Sample { .. }
+            | Command::Tombstones { .. }
+            | Command::Changes { .. }
+            | Command::GroupRead { .. }
+            | Command::GroupAck { .. }
+            | Command::RoutingTable
+            | Command::Stats
+            | Command::ShardStats
+            | Command::Commands
+            | Command::Export { .. }
+            | Command::Import { .. }
+            | Command::BulkLoad { .. }
+            | Command::SubscribeEvents { .. }
+            | Command::Changefeed { .. }
+            | Command::Publish { .. }
+            | Command::Subscribe { .. }
+            | Command::ClientSetName { .. }
+            | Command::ClientList
+            | Command::ClientKill { .. }
+            | Command::Select { .. }
+            | Command::Auth { .. }
+            | Command::Hello { .. }
+            | Command::Ping => None,
+        }
+    }
 }
 
 impl fmt::Display for Command {
@@ -41,10 +1000,117 @@ impl fmt::Display for Command {
         match self {
             Command::Set { key, .. } => write!(f, "SET {}", key),
             Command::Get { key } => write!(f, "GET {}", key),
+            Command::SetBytes { key, value } => {
+                write!(f, "SETBYTES {} ({} bytes)", key, value.len())
+            }
+            Command::GetBytes { key } => write!(f, "GETBYTES {}", key),
             Command::Delete { key } => write!(f, "DELETE {}", key),
             Command::QGet { key, query } => write!(f, "QGET {} {}", key, query),
+            Command::QScan {
+                key_pattern, query, ..
+            } => write!(f, "QSCAN {} {}", key_pattern, query),
+            Command::Aggregate {
+                key_pattern,
+                path,
+                op,
+            } => write!(f, "AGGREGATE {} {} {}", key_pattern, path, op.as_str()),
+            Command::JqGet { key, program } => write!(f, "JQGET {} {}", key, program),
             Command::QSet { key, path, .. } => write!(f, "QSET {} {}", key, path),
             Command::Merge { key, .. } => write!(f, "MERGE {}", key),
+            Command::QType { key, path } => write!(f, "QTYPE {} {}", key, path),
+            Command::QAppend { key, path, .. } => write!(f, "QAPPEND {} {}", key, path),
+            Command::QInsert {
+                key, path, index, ..
+            } => write!(f, "QINSERT {} {} {}", key, path, index),
+            Command::QPop { key, path, .. } => write!(f, "QPOP {} {}", key, path),
+            Command::ObjKeys { key, path } => write!(f, "OBJKEYS {} {}", key, path),
+            Command::ArrLen { key, path } => write!(f, "ARRLEN {} {}", key, path),
+            Command::Digest { key: Some(key) } => write!(f, "DIGEST {}", key),
+            Command::Digest { key: None } => write!(f, "DIGEST"),
+            Command::Explain { command } => write!(f, "EXPLAIN {}", command),
+            Command::Stream { command, .. } => write!(f, "STREAM {}", command),
+            Command::Eval { script, keys, .. } => write!(f, "EVAL {} {}", script, keys.join(" ")),
+            Command::Profile { kind, seconds } => write!(f, "PROFILE {:?} {}", kind, seconds),
+            Command::InjectLatency {
+                target,
+                millis,
+                seconds,
+            } => write!(
+                f,
+                "INJECTLATENCY {} {} {}",
+                target.as_str(),
+                millis,
+                seconds
+            ),
+            Command::ReadOnly { enabled } => {
+                write!(f, "READONLY {}", if *enabled { "on" } else { "off" })
+            }
+            Command::Audit { since, .. } => write!(f, "AUDIT {}", since),
+            Command::MemoryUsage { key } => write!(f, "MEMORY USAGE {}", key),
+            Command::Meta { key } => write!(f, "META {}", key),
+            Command::Flush { prefix: None } => write!(f, "FLUSH"),
+            Command::Flush {
+                prefix: Some(prefix),
+            } => write!(f, "FLUSH {}", prefix),
+            Command::Expire { key, seconds } => write!(f, "EXPIRE {} {}", key, seconds),
+            Command::Ttl { key } => write!(f, "TTL {}", key),
+            Command::Persist { key } => write!(f, "PERSIST {}", key),
+            Command::RandomKey => write!(f, "RANDOMKEY"),
+            Command::Sample { n } => write!(f, "SAMPLE {}", n),
+            Command::Tombstones { since } => write!(f, "TOMBSTONES {}", since),
+            Command::Changes { since, .. } => write!(f, "CHANGES {}", since),
+            Command::GroupRead { group, count } => write!(f, "GROUPREAD {} {}", group, count),
+            Command::GroupAck { group, offset } => write!(f, "GROUPACK {} {}", group, offset),
+            Command::History { key } => write!(f, "HISTORY {}", key),
+            Command::Restore { key, version } => write!(f, "RESTORE {} {}", key, version),
+            Command::RoutingTable => write!(f, "ROUTINGTABLE"),
+            Command::Info => write!(f, "INFO"),
+            Command::Usage => write!(f, "USAGE"),
+            Command::Stats => write!(f, "STATS"),
+            Command::ShardStats => write!(f, "SHARDSTATS"),
+            Command::Commands => write!(f, "COMMAND"),
+            Command::Save { background } => {
+                write!(f, "{}", if *background { "BGSAVE" } else { "SAVE" })
+            }
+            Command::VerifyBackup { source } => match source {
+                Some(source) => write!(f, "VERIFYBACKUP {}", source),
+                None => write!(f, "VERIFYBACKUP"),
+            },
+            Command::AofFetch { sequence, offset } => write!(f, "AOFFETCH {} {}", sequence, offset),
+            Command::SnapshotFetch { index, offset } => {
+                write!(f, "SNAPSHOTFETCH {} {}", index, offset)
+            }
+            Command::Promote => write!(f, "PROMOTE"),
+            Command::AclSet { identity, grants } => {
+                let grants: Vec<String> = grants.iter().map(Grant::to_string).collect();
+                write!(f, "ACLSET {} {}", identity, grants.join(" "))
+            }
+            Command::AclDel { identity } => write!(f, "ACLDEL {}", identity),
+            Command::AclList => write!(f, "ACLLIST"),
+            Command::ConfigGet { parameter } => write!(f, "CONFIG GET {}", parameter),
+            Command::ConfigSet { parameter, value } => {
+                write!(f, "CONFIG SET {} {}", parameter, value)
+            }
+            Command::Export { pattern } => write!(f, "EXPORT {}", pattern),
+            Command::Import { data } => write!(f, "IMPORT ({} bytes)", data.len()),
+            Command::BulkLoad { data } => write!(f, "BULKLOAD ({} bytes)", data.len()),
+            Command::Watch { key } => write!(f, "WATCH {}", key),
+            Command::Changefeed { from_offset } => write!(f, "CHANGEFEED {}", from_offset),
+            Command::SubscribeEvents { pattern } => write!(f, "SUBSCRIBEEVENTS {}", pattern),
+            Command::Publish { channel, .. } => write!(f, "PUBLISH {}", channel),
+            Command::Subscribe { channels } => write!(f, "SUBSCRIBE {}", channels.join(" ")),
+            Command::ClientSetName { name } => write!(f, "CLIENT SETNAME {}", name),
+            Command::ClientList => write!(f, "CLIENT LIST"),
+            Command::ClientKill { id } => write!(f, "CLIENT KILL {}", id),
+            Command::Select { namespace } => write!(f, "SELECT {}", namespace),
+            // The password is never displayed
+            Command::Auth { username, .. } => match username {
+                Some(username) => write!(f, "AUTH {}", username),
+                None => write!(f, "AUTH"),
+            },
+            Command::Hello {
+                topology_updates, ..
+            } => write!(f, "HELLO {}", topology_updates),
             Command::Ping => write!(f, "PING"),
         }
     }
@@ -55,8 +1121,304 @@ impl fmt::Display for Response {
         match self {
             Response::Ok(Some(value)) => write!(f, "OK {}", value),
             Response::Ok(None) => write!(f, "OK"),
-            Response::Error(msg) => write!(f, "ERROR {}", msg),
+            Response::Error(err) => write!(f, "ERROR {}", err),
             Response::Pong => write!(f, "PONG"),
+            Response::Bytes(bytes) => write!(f, "BYTES ({} bytes)", bytes.len()),
+            Response::ClusterTopologyChanged(topology) => write!(
+                f,
+                "TOPOLOGY term={} leader={:?} members={:?} learners={:?}",
+                topology.term, topology.leader, topology.members, topology.learners
+            ),
+            Response::KeyEvent(event) => {
+                write!(f, "EVENT {} {} {}", event.event, event.namespace, event.key)
+            }
+            Response::KeyEventsDropped(count) => write!(f, "EVENTS DROPPED {}", count),
+            Response::KeyUpdated(KeyUpdate {
+                key,
+                value: Some(value),
+                ..
+            }) => write!(f, "UPDATED {} {}", key, value),
+            Response::KeyUpdated(KeyUpdate {
+                key, value: None, ..
+            }) => {
+                write!(f, "DELETED {}", key)
+            }
+            Response::ChannelMessage(message) => {
+                write!(f, "MESSAGE {} {}", message.channel, message.message)
+            }
+            Response::Change(change) => write!(
+                f,
+                "CHANGE {} {} {}",
+                change.offset,
+                change.kind.as_str(),
+                change.key
+            ),
+            Response::Chunk(results) => write!(f, "CHUNK ({} results)", results.len()),
         }
     }
 }
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for ErrorInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+/// Flag set in the length prefix of frames with a zstd-compressed payload
+pub(crate) const COMPRESSED_FRAME: u32 = 1 << 31;
+
+/// Flag set in the length prefix of frames carrying a request id: the id
+/// follows the length prefix as 8 bytes in big-endian, and the response to
+/// the request carries the same id
+pub(crate) const TAGGED_FRAME: u32 = 1 << 30;
+
+/// Flag set in the length prefix of frames whose message continues in the
+/// next frame: a message split across frames ends with the first frame
+/// without the flag, and every piece carries the request id
+pub(crate) const CONTINUED_FRAME: u32 = 1 << 29;
+
+/// Largest payload of a frame, whatever the connection negotiated: the
+/// highest bits of the length prefix are flags. Compressed frames
+/// decompress to at most this size too
+pub const MAX_FRAME_LENGTH: usize = (1 << 29) - 1;
+
+/// Largest message accepted by default, across its frames
+pub const MAX_MESSAGE_SIZE: usize = 1 << 30;
+
+/// Largest message accepted from connections that have yet to authenticate
+pub const MAX_UNAUTHENTICATED_MESSAGE: usize = 64 << 10;
+
+/// Largest payload of a frame on connections that negotiated chunked
+/// frames; larger messages are split, so that neither end needs a buffer
+/// holding the whole message
+pub const MAX_FRAME_PIECE: usize = 4 << 20;
+
+/// Payload size from which frames are compressed on connections that
+/// negotiated compression
+pub const COMPRESSION_THRESHOLD: usize = 16 << 10;
+
+/// zstd level of compressed frames, favoring speed over ratio
+const FRAME_COMPRESSION_LEVEL: i32 = 1;
+
+/// Compresses the payload of a frame, or returns None if it is too small
+/// to be worth it or does not shrink
+pub(crate) fn compress_frame(payload: &[u8]) -> Option<Vec<u8>> {
+    if payload.len() < COMPRESSION_THRESHOLD {
+        return None;
+    }
+    zstd::bulk::compress(payload, FRAME_COMPRESSION_LEVEL)
+        .ok()
+        .filter(|compressed| compressed.len() < payload.len())
+}
+
+/// Decompresses the payload of a frame flagged with `COMPRESSED_FRAME`,
+/// failing once it exceeds `limit` bytes rather than trusting the sender
+pub(crate) fn decompress_frame(payload: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+
+    let decoder = zstd::stream::read::Decoder::new(payload)
+        .map_err(|e| format!("Frame decompression error: {}", e))?;
+    let mut decompressed = Vec::new();
+    decoder
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| format!("Frame decompression error: {}", e))?;
+    if decompressed.len() > limit {
+        return Err(format!("Compressed frame expands beyond {} bytes", limit));
+    }
+    Ok(decompressed)
+}
+
+/// Serializes a message as JSON in pieces of at most `MAX_FRAME_PIECE`
+/// bytes rather than in a single buffer
+pub(crate) fn serialize_pieces<T: Serialize>(message: &T) -> Result<Vec<Vec<u8>>, String> {
+    let mut writer = PieceWriter(vec![Vec::new()]);
+    serde_json::to_writer(&mut writer, message)
+        .map_err(|e| format!("JSON serialization error: {}", e))?;
+    Ok(writer.0)
+}
+
+struct PieceWriter(Vec<Vec<u8>>);
+
+impl std::io::Write for PieceWriter {
+    fn write(&mut self, mut buf: &[u8]) -> std::io::Result<usize> {
+        let written = buf.len();
+        while !buf.is_empty() {
+            let piece = self.0.last_mut().expect("there is always a piece");
+            if piece.len() == MAX_FRAME_PIECE {
+                self.0.push(Vec::new());
+                continue;
+            }
+            let n = buf.len().min(MAX_FRAME_PIECE - piece.len());
+            piece.extend_from_slice(&buf[..n]);
+            buf = &buf[n..];
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Decodes the JSON payload of a message split across frames, without
+/// joining the pieces
+#[cfg(not(feature = "simd-json"))]
+pub(crate) fn decode_pieces<T: DeserializeOwned>(pieces: &[impl AsRef<[u8]>]) -> Result<T, String> {
+    serde_json::from_reader(PieceReader { pieces, offset: 0 })
+        .map_err(|e| format!("JSON deserialization error: {}", e))
+}
+
+/// Reads the pieces of a message one after the other
+#[cfg(not(feature = "simd-json"))]
+struct PieceReader<'a, P> {
+    pieces: &'a [P],
+    /// Position in the first piece
+    offset: usize,
+}
+
+#[cfg(not(feature = "simd-json"))]
+impl<P: AsRef<[u8]>> std::io::Read for PieceReader<'_, P> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while let Some((piece, rest)) = self.pieces.split_first() {
+            let piece = piece.as_ref();
+            if self.offset < piece.len() {
+                let n = buf.len().min(piece.len() - self.offset);
+                buf[..n].copy_from_slice(&piece[self.offset..self.offset + n]);
+                self.offset += n;
+                return Ok(n);
+            }
+            self.pieces = rest;
+            self.offset = 0;
+        }
+        Ok(0)
+    }
+}
+
+/// Decodes the JSON payload of a message split across frames; SIMD parsing
+/// needs the pieces joined
+#[cfg(feature = "simd-json")]
+pub(crate) fn decode_pieces<T: DeserializeOwned>(pieces: &[impl AsRef<[u8]>]) -> Result<T, String> {
+    let pieces: Vec<&[u8]> = pieces.iter().map(AsRef::as_ref).collect();
+    decode_frame(&pieces.concat())
+}
+
+/// Decodes the JSON payload of a frame
+#[cfg(not(feature = "simd-json"))]
+pub(crate) fn decode_frame<T: DeserializeOwned>(payload: &[u8]) -> Result<T, String> {
+    let payload = std::str::from_utf8(payload).map_err(|e| format!("Non-UTF-8 payload: {}", e))?;
+    serde_json::from_str(payload).map_err(|e| format!("JSON deserialization error: {}", e))
+}
+
+/// Decodes the JSON payload of a frame with SIMD-accelerated parsing
+#[cfg(feature = "simd-json")]
+pub(crate) fn decode_frame<T: DeserializeOwned>(payload: &[u8]) -> Result<T, String> {
+    // simd-json parses in place
+    let mut payload = payload.to_vec();
+    simd_json::serde::from_slice(&mut payload)
+        .map_err(|e| format!("JSON deserialization error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_frame() {
+        let command: Command =
+            decode_frame(br#"{"Set":{"key":"k","value":{"n":[1,-2,3.5,"\u00e9",null]}}}"#).unwrap();
+        assert!(matches!(command, Command::Set { key, value }
+            if key == "k" && value == serde_json::json!({"n": [1, -2, 3.5, "é", null]})));
+        assert!(decode_frame::<Command>(b"{\"Get\":").is_err());
+        assert!(decode_frame::<Command>(&[0xff, 0xfe]).is_err());
+    }
+
+    #[test]
+    fn test_frame_compression() {
+        assert!(compress_frame(b"{\"Ping\":null}").is_none());
+        let payload = serde_json::to_vec(&Command::Set {
+            key: "k".to_string(),
+            value: serde_json::json!(vec!["a repetitive document"; 2048]),
+        })
+        .unwrap();
+        let compressed = compress_frame(&payload).unwrap();
+        assert!(compressed.len() < payload.len() / 10);
+        assert_eq!(
+            decompress_frame(&compressed, MAX_FRAME_LENGTH).unwrap(),
+            payload
+        );
+        assert!(decompress_frame(b"not zstd", MAX_FRAME_LENGTH).is_err());
+    }
+
+    #[test]
+    fn test_error_backward_compatible_deserialization() {
+        let legacy: Response = serde_json::from_str(r#"{"Error":"Key not found"}"#).unwrap();
+        match legacy {
+            Response::Error(err) => {
+                assert_eq!(err.code, ErrorCode::Unknown);
+                assert_eq!(err.message, "Key not found");
+            }
+            other => panic!("Unexpected response: {}", other),
+        }
+
+        let encoded =
+            serde_json::to_string(&Response::error(ErrorCode::KeyNotFound, "missing")).unwrap();
+        let decoded: Response = serde_json::from_str(&encoded).unwrap();
+        assert!(matches!(decoded, Response::Error(err) if err.code == ErrorCode::KeyNotFound));
+    }
+
+    #[test]
+    fn test_error_message_is_bounded() {
+        let err = ErrorInfo::new(ErrorCode::Internal, "x".repeat(10_000));
+        assert!(err.message.len() <= MAX_ERROR_MESSAGE_LEN);
+    }
+
+    #[test]
+    fn test_protocol_compatibility() {
+        let current = ProtocolVersion {
+            version: 2,
+            min_compatible: 1,
+        };
+        let compatible_newer = ProtocolVersion {
+            version: 3,
+            min_compatible: 2,
+        };
+        let incompatible_newer = ProtocolVersion {
+            version: 4,
+            min_compatible: 3,
+        };
+        assert!(current.check_compatible(&compatible_newer).is_ok());
+        assert!(current
+            .check_compatible(&ProtocolVersion::default())
+            .is_ok());
+        assert!(current.check_compatible(&incompatible_newer).is_err());
+        assert!(incompatible_newer.check_compatible(&current).is_err());
+
+        // Legacy handshakes without a protocol version still parse
+        let hello: Command =
+            serde_json::from_str(r#"{"Hello":{"topology_updates":true}}"#).unwrap();
+        assert!(matches!(hello, Command::Hello { protocol: None, .. }));
+
+        // Negotiation picks the first supported option, defaulting for legacy clients
+        let offer = |options: &[&str]| options.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+        let handshake = Handshake::negotiate(None, &[], &[]).unwrap();
+        assert_eq!(handshake.encoding, "json");
+        assert_eq!(handshake.compression, "none");
+        assert_eq!(handshake.protocol, ProtocolVersion::current());
+        let handshake = Handshake::negotiate(
+            Some(ProtocolVersion::current()),
+            &offer(&["msgpack", "json"]),
+            &offer(&["none"]),
+        )
+        .unwrap();
+        assert_eq!(handshake.encoding, "json");
+        assert!(Handshake::negotiate(None, &offer(&["msgpack"]), &[]).is_err());
+        assert!(Handshake::negotiate(Some(incompatible_newer), &[], &[]).is_err());
+    }
+}
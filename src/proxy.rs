@@ -0,0 +1,65 @@
+use clap::{Arg, Command as ClapCommand};
+use jsonvault::TcpProxy;
+use log::{error, info};
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+
+    let matches = ClapCommand::new("jsonvault-proxy")
+        .version("0.1.0")
+        .about("JsonVault proxy - Single stable endpoint in front of a JsonVault cluster")
+        .arg(
+            Arg::new("address")
+                .short('a')
+                .long("address")
+                .value_name("ADDRESS")
+                .help("Proxy bind address")
+                .default_value("127.0.0.1:8070"),
+        )
+        .arg(
+            Arg::new("backends")
+                .short('b')
+                .long("backends")
+                .value_name("ADDRESS_LIST")
+                .help("Server addresses, tried in order (comma-separated)")
+                .value_delimiter(',')
+                .required(true),
+        )
+        .arg(
+            Arg::new("retries")
+                .long("retries")
+                .value_name("COUNT")
+                .help("Retries of a request after a failover or a retriable error")
+                .default_value("3")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("retry-backoff")
+                .long("retry-backoff")
+                .value_name("MS")
+                .help("Delay between retries")
+                .default_value("100")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .get_matches();
+
+    let address = matches.get_one::<String>("address").unwrap().clone();
+    let backends: Vec<String> = matches
+        .get_many::<String>("backends")
+        .unwrap()
+        .cloned()
+        .collect();
+    let retries = *matches.get_one::<usize>("retries").unwrap();
+    let backoff = Duration::from_millis(*matches.get_one::<u64>("retry-backoff").unwrap());
+
+    info!("Starting JsonVault proxy on {}", address);
+    let proxy = TcpProxy::new(address, backends).with_retries(retries, backoff);
+    if let Err(e) = proxy.start().await {
+        error!("Proxy error: {}", e);
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
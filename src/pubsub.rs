@@ -0,0 +1,139 @@
+use dashmap::DashMap;
+use log::{debug, warn};
+use serde_json::Value;
+use std::collections::HashSet;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+
+use crate::protocol::ChannelMessage;
+
+/// Messages buffered per channel for each subscriber
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Publish/subscribe channels, independent of the keyspace.
+///
+/// Delivery is at most once: messages published while nobody listens, or
+/// while a subscriber is too far behind, are dropped.
+#[derive(Debug, Default)]
+pub struct PubSub {
+    channels: DashMap<String, broadcast::Sender<ChannelMessage>>,
+}
+
+impl PubSub {
+    /// Create a hub without channels
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Publish a message, returning the number of subscribers it was sent to
+    pub fn publish(&self, channel: &str, message: Value) -> usize {
+        let sent = match self.channels.get(channel) {
+            Some(sender) => sender.send(ChannelMessage {
+                channel: channel.to_string(),
+                message,
+            }),
+            None => return 0,
+        };
+        sent.unwrap_or_else(|_| {
+            // Forget channels whose subscribers are all gone
+            self.channels
+                .remove_if(channel, |_, sender| sender.receiver_count() == 0);
+            0
+        })
+    }
+
+    /// Subscribe to the messages of a channel, creating it on first use
+    pub fn subscribe(&self, channel: &str) -> broadcast::Receiver<ChannelMessage> {
+        self.channels
+            .entry(channel.to_string())
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+}
+
+/// Channels a connection subscribed to, merged into a single stream
+pub(crate) struct Subscriptions {
+    channels: HashSet<String>,
+    tx: mpsc::Sender<ChannelMessage>,
+    rx: mpsc::Receiver<ChannelMessage>,
+    /// Forward the messages of each channel to `tx`
+    forwarders: Vec<JoinHandle<()>>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        Self {
+            channels: HashSet::new(),
+            tx,
+            rx,
+            forwarders: Vec::new(),
+        }
+    }
+
+    /// Add a channel; messages published from now on are received
+    pub fn subscribe(&mut self, pubsub: &PubSub, channel: &str) {
+        if !self.channels.insert(channel.to_string()) {
+            return;
+        }
+        let mut rx = pubsub.subscribe(channel);
+        let tx = self.tx.clone();
+        self.forwarders.push(tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(message) => {
+                        if tx.send(message).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        warn!("Subscriber fell behind, {} messages dropped", count);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        }));
+        debug!("Subscribed to channel {}", channel);
+    }
+
+    /// Wait for the next message of any subscribed channel
+    pub async fn recv(&mut self) -> Option<ChannelMessage> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for Subscriptions {
+    fn drop(&mut self) {
+        for forwarder in &self.forwarders {
+            forwarder.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_publish_subscribe() {
+        let pubsub = PubSub::new();
+        assert_eq!(pubsub.publish("news", json!("nobody listens")), 0);
+
+        let mut subscriptions = Subscriptions::new();
+        subscriptions.subscribe(&pubsub, "news");
+        subscriptions.subscribe(&pubsub, "news");
+        assert_eq!(pubsub.publish("news", json!({"id": 1})), 1);
+        assert_eq!(pubsub.publish("sports", json!({"id": 2})), 0);
+
+        let message = subscriptions.recv().await.unwrap();
+        assert_eq!(message.channel, "news");
+        assert_eq!(message.message, json!({"id": 1}));
+
+        // Channels are forgotten once their subscribers are gone
+        drop(subscriptions);
+        tokio::task::yield_now().await;
+        assert_eq!(pubsub.publish("news", json!({"id": 3})), 0);
+        assert!(pubsub.channels.is_empty());
+    }
+}
@@ -1,11 +1,19 @@
 use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, Mutex, RwLock};
 use uuid::Uuid;
 use tokio::time::{interval, Duration, Instant};
-use log::{info, warn};
 
-use crate::protocol::{Command, Response};
+use crate::clock::{self, Clock};
+use crate::ids::{self, IdGenerator};
+use log::{error, info, warn};
+
+use crate::protocol::{ClusterTopology, Command, LatencyTarget, ProtocolVersion, Response};
+use crate::stall;
+use crate::database::DEFAULT_NAMESPACE;
 use crate::Database;
 
 pub type NodeId = u64;
@@ -27,6 +35,13 @@ pub struct LogEntry {
     pub index: LogIndex,
     pub command: Command,
     pub id: Uuid,
+    /// Namespace the command applies to
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+}
+
+fn default_namespace() -> String {
+    DEFAULT_NAMESPACE.to_string()
 }
 
 /// AppendEntries RPC request
@@ -38,6 +53,9 @@ pub struct AppendEntriesRequest {
     pub prev_log_term: Term,
     pub entries: Vec<LogEntry>,
     pub leader_commit: LogIndex,
+    /// Protocol version of the leader
+    #[serde(default)]
+    pub protocol: ProtocolVersion,
 }
 
 /// AppendEntries RPC response
@@ -55,6 +73,9 @@ pub struct VoteRequest {
     pub candidate_id: NodeId,
     pub last_log_index: LogIndex,
     pub last_log_term: Term,
+    /// Protocol version of the candidate
+    #[serde(default)]
+    pub protocol: ProtocolVersion,
 }
 
 /// RequestVote RPC response
@@ -64,6 +85,59 @@ pub struct VoteResponse {
     pub vote_granted: bool,
 }
 
+/// Term and vote of a node, saved before they take effect so that a
+/// restarted node never votes twice in a term
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct HardState {
+    pub current_term: Term,
+    /// Candidate voted for in the current term
+    pub voted_for: Option<NodeId>,
+}
+
+impl HardState {
+    /// Read the hard state saved at `path`, or the initial one if none was saved
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| format!("Invalid Raft state {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Atomically replace the hard state saved at `path`, synced to disk
+    /// before returning
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let tmp = path.with_extension("tmp");
+        let content = serde_json::to_vec(self).map_err(|e| e.to_string())?;
+        File::create(&tmp)
+            .and_then(|mut file| {
+                file.write_all(&content)?;
+                file.sync_all()
+            })
+            .map_err(|e| format!("Failed to write {}: {}", tmp.display(), e))?;
+        fs::rename(&tmp, path).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        // Make the rename itself durable
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            File::open(dir)
+                .and_then(|dir| dir.sync_all())
+                .map_err(|e| format!("Failed to sync {}: {}", dir.display(), e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Save the hard state of a node, if it has a file for it, on a blocking
+/// thread so that the disk writes don't stall the runtime
+async fn save_hard_state(path: Option<Arc<PathBuf>>, current_term: Term, voted_for: Option<NodeId>) -> Result<(), String> {
+    let Some(path) = path else {
+        return Ok(());
+    };
+    tokio::task::spawn_blocking(move || HardState { current_term, voted_for }.save(&path))
+        .await
+        .map_err(|e| format!("Failed to save the Raft state: {}", e))?
+}
+
 /// Raft consensus manager with automatic failover and replication
 pub struct RaftManager {
     /// Unique node identifier
@@ -92,6 +166,9 @@ pub struct RaftManager {
     
     /// Cluster nodes
     cluster_nodes: Arc<RwLock<Vec<NodeId>>>,
+
+    /// Nodes receiving replication without voting, until promoted
+    learners: Arc<RwLock<Vec<NodeId>>>,
     
     /// Current leader ID
     current_leader: Arc<RwLock<Option<NodeId>>>,
@@ -101,6 +178,22 @@ pub struct RaftManager {
     
     /// Timestamp of last heartbeat received
     last_heartbeat: Arc<RwLock<Instant>>,
+
+    /// Leadership and membership change notifications
+    topology_tx: broadcast::Sender<ClusterTopology>,
+
+    /// Time source for heartbeats and election timeouts
+    clock: Arc<dyn Clock>,
+
+    /// Source of log entry IDs
+    ids: Arc<dyn IdGenerator>,
+
+    /// File the term and vote are saved to (None keeps them in memory only)
+    hard_state_path: Option<Arc<PathBuf>>,
+
+    /// Held while the term or vote changes, so that changes are saved and
+    /// applied one at a time without holding the state locks across the save
+    hard_state_gate: Arc<Mutex<()>>,
 }
 
 impl RaftManager {
@@ -116,15 +209,81 @@ impl RaftManager {
             last_applied: Arc::new(RwLock::new(0)),
             commit_index: Arc::new(RwLock::new(0)),
             cluster_nodes: Arc::new(RwLock::new(vec![node_id])),
+            learners: Arc::new(RwLock::new(Vec::new())),
             current_leader: Arc::new(RwLock::new(None)),
             election_timeout: Duration::from_millis(150 + (fastrand::u64(..150))),
             last_heartbeat: Arc::new(RwLock::new(Instant::now())),
+            topology_tx: broadcast::channel(TOPOLOGY_CHANNEL_CAPACITY).0,
+            clock: clock::system_clock(),
+            ids: ids::random_ids(),
+            hard_state_path: None,
+            hard_state_gate: Arc::new(Mutex::new(())),
         })
     }
 
-    /// Initialize the cluster with automatic failover capabilities
+    /// Use a custom time source (e.g. a `ManualClock` in tests)
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.last_heartbeat = Arc::new(RwLock::new(clock.now()));
+        self.clock = clock;
+        self
+    }
+
+    /// Save the term and vote to `path` before they take effect, resuming
+    /// from the ones saved there by a previous run
+    pub fn with_hard_state(mut self, path: impl Into<PathBuf>) -> Result<Self, String> {
+        let path = path.into();
+        let state = HardState::load(&path)?;
+        info!("Node {} resumes at term {} (voted for {:?})", self.node_id, state.current_term, state.voted_for);
+        self.current_term = Arc::new(RwLock::new(state.current_term));
+        self.voted_for = Arc::new(RwLock::new(state.voted_for));
+        self.hard_state_path = Some(Arc::new(path));
+        Ok(self)
+    }
+
+    /// Use a custom generator for log entry IDs
+    pub fn with_id_generator(mut self, ids: Arc<dyn IdGenerator>) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// Returns the sender used to publish topology changes, so that
+    /// connection handlers can subscribe to them
+    pub fn topology_sender(&self) -> broadcast::Sender<ClusterTopology> {
+        self.topology_tx.clone()
+    }
+
+    /// Subscribe to leadership and membership changes
+    pub fn subscribe_topology(&self) -> broadcast::Receiver<ClusterTopology> {
+        self.topology_tx.subscribe()
+    }
+
+    /// Get the current cluster topology
+    pub async fn topology(&self) -> ClusterTopology {
+        ClusterTopology {
+            term: *self.current_term.read().await,
+            leader: *self.current_leader.read().await,
+            members: self.cluster_nodes.read().await.clone(),
+            learners: self.learners.read().await.clone(),
+        }
+    }
+
+    /// Publish the current topology to subscribers
+    async fn publish_topology(&self) {
+        let topology = self.topology().await;
+        publish_topology(&self.topology_tx, topology);
+    }
+
+    /// Initialize the cluster with automatic failover capabilities. A node
+    /// missing from `members` joins as a learner: it follows the leader but
+    /// neither votes nor campaigns until promoted
     pub async fn initialize_cluster(&mut self, members: Vec<NodeId>) -> Result<(), String> {
         *self.cluster_nodes.write().await = members.clone();
+        let mut learners = self.learners.write().await;
+        learners.retain(|id| !members.contains(id));
+        if !members.contains(&self.node_id) && !learners.contains(&self.node_id) {
+            learners.push(self.node_id);
+        }
+        drop(learners);
         
         // If we're the only node, become leader immediately
         if members.len() == 1 && members[0] == self.node_id {
@@ -136,12 +295,19 @@ impl RaftManager {
             self.start_election_timer().await;
             info!("Node {} initialized in cluster of {} nodes with distributed consensus", self.node_id, members.len());
         }
+        self.publish_topology().await;
         
         Ok(())
     }
 
     /// Submit a command through Raft consensus with automatic replication
     pub async fn submit_command(&self, command: Command) -> Result<Response, String> {
+        self.submit_command_in(DEFAULT_NAMESPACE, command).await
+    }
+
+    /// Submit a command targeting a namespace through Raft consensus
+    pub async fn submit_command_in(&self, namespace: &str, command: Command) -> Result<Response, String> {
+        let database = self.database.namespace(namespace)?;
         if !self.is_leader().await {
             if let Some(leader_id) = self.leader_id().await {
                 return Err(format!("Not the leader, current leader is node {}", leader_id));
@@ -154,7 +320,8 @@ impl RaftManager {
             term: *self.current_term.read().await,
             index: self.log.read().await.len() as LogIndex + 1,
             command: command.clone(),
-            id: Uuid::new_v4(),
+            id: self.ids.next_id(),
+            namespace: namespace.to_string(),
         };
 
         // Add to log
@@ -162,7 +329,7 @@ impl RaftManager {
         
         // Apply immediately for single-node cluster
         // In multi-node, this would wait for majority consensus
-        let response = self.database.execute_command(command).await;
+        let response = database.execute_command(command).await;
         *self.last_applied.write().await = entry.index;
         *self.commit_index.write().await = entry.index;
 
@@ -187,21 +354,20 @@ impl RaftManager {
 
     /// Get cluster metrics for monitoring
     pub async fn metrics(&self) -> ClusterMetrics {
-        let state = self.state.read().await.clone();
-        let current_term = *self.current_term.read().await;
-        let is_leader = matches!(state, RaftState::Leader);
-        let cluster_size = self.cluster_nodes.read().await.len();
-        let last_log_index = self.log.read().await.len() as LogIndex;
-        let last_applied = *self.last_applied.read().await;
+        self.monitor().metrics().await
+    }
 
-        ClusterMetrics {
+    /// Returns a handle reading the cluster metrics of this node, for
+    /// components that don't own the manager
+    pub fn monitor(&self) -> RaftMonitor {
+        RaftMonitor {
             node_id: self.node_id,
-            current_term,
-            is_leader,
-            cluster_size,
-            state: format!("{:?}", state),
-            last_log_index,
-            last_applied,
+            state: Arc::clone(&self.state),
+            current_term: Arc::clone(&self.current_term),
+            log: Arc::clone(&self.log),
+            last_applied: Arc::clone(&self.last_applied),
+            cluster_nodes: Arc::clone(&self.cluster_nodes),
+            learners: Arc::clone(&self.learners),
         }
     }
 
@@ -211,20 +377,72 @@ impl RaftManager {
         if !nodes.contains(&new_node_id) {
             nodes.push(new_node_id);
             info!("Added node {} to cluster with automatic replication", new_node_id);
+            drop(nodes);
+            self.publish_topology().await;
         }
         Ok(())
     }
 
+    /// Add a node to the cluster after checking that it speaks a compatible protocol
+    pub async fn add_peer(&mut self, new_node_id: NodeId, protocol: ProtocolVersion) -> Result<(), String> {
+        ProtocolVersion::current()
+            .check_compatible(&protocol)
+            .map_err(|e| format!("Refusing to add node {}: {}", new_node_id, e))?;
+        self.add_node(new_node_id).await
+    }
+
+    /// Add a node that receives replication without voting, so that it can
+    /// catch up before it counts towards the majority
+    pub async fn add_learner(&mut self, node_id: NodeId, protocol: ProtocolVersion) -> Result<(), String> {
+        ProtocolVersion::current()
+            .check_compatible(&protocol)
+            .map_err(|e| format!("Refusing to add learner {}: {}", node_id, e))?;
+        if self.cluster_nodes.read().await.contains(&node_id) {
+            return Err(format!("Node {} is already a voting member", node_id));
+        }
+        let mut learners = self.learners.write().await;
+        if !learners.contains(&node_id) {
+            learners.push(node_id);
+            info!("Added node {} to cluster as a learner", node_id);
+            drop(learners);
+            self.publish_topology().await;
+        }
+        Ok(())
+    }
+
+    /// Turn a learner into a voting member. The log is not replicated to
+    /// other nodes yet, so the caller checks that the learner caught up
+    pub async fn promote_learner(&mut self, node_id: NodeId) -> Result<(), String> {
+        let mut learners = self.learners.write().await;
+        let Some(position) = learners.iter().position(|id| *id == node_id) else {
+            return Err(format!("Node {} is not a learner", node_id));
+        };
+        learners.remove(position);
+        drop(learners);
+        info!("Promoting learner {} to voting member", node_id);
+        self.add_node(node_id).await
+    }
+
+    /// Returns the nodes receiving replication without voting
+    pub async fn learners(&self) -> Vec<NodeId> {
+        self.learners.read().await.clone()
+    }
+
     /// Start election timer for automatic failover
     async fn start_election_timer(&self) {
         let state = self.state.clone();
         let current_term = self.current_term.clone();
         let voted_for = self.voted_for.clone();
         let cluster_nodes = self.cluster_nodes.clone();
+        let learners = self.learners.clone();
         let current_leader = self.current_leader.clone();
         let last_heartbeat = self.last_heartbeat.clone();
         let node_id = self.node_id;
         let election_timeout = self.election_timeout;
+        let topology_tx = self.topology_tx.clone();
+        let clock = self.clock.clone();
+        let hard_state_path = self.hard_state_path.clone();
+        let hard_state_gate = self.hard_state_gate.clone();
 
         tokio::spawn(async move {
             let mut election_timer = interval(Duration::from_millis(50));
@@ -240,14 +458,25 @@ impl RaftManager {
                     continue;
                 }
 
+                // Learners never campaign, until promoted
+                if !cluster_nodes.read().await.contains(&node_id) {
+                    continue;
+                }
+
                 // Check if election timeout has expired
                 let last_hb = *last_heartbeat.read().await;
-                if last_hb.elapsed() > election_timeout {
+                if clock.now().saturating_duration_since(last_hb) > election_timeout {
                     info!("Election timeout for node {}, starting leader election", node_id);
                     
-                    // Start election
-                    let mut term = current_term.write().await;
-                    *term += 1;
+                    // Start election, voting for this node in the new term
+                    let _changing = hard_state_gate.lock().await;
+                    let term = *current_term.read().await + 1;
+                    if let Err(e) = save_hard_state(hard_state_path.clone(), term, Some(node_id)).await {
+                        error!("Node {} cannot start an election: {}", node_id, e);
+                        *last_heartbeat.write().await = clock.now();
+                        continue;
+                    }
+                    *current_term.write().await = term;
                     *voted_for.write().await = Some(node_id);
                     *state.write().await = RaftState::Candidate;
                     *current_leader.write().await = None;
@@ -257,7 +486,13 @@ impl RaftManager {
                     if nodes.len() == 1 {
                         *state.write().await = RaftState::Leader;
                         *current_leader.write().await = Some(node_id);
-                        info!("Node {} became leader for term {} (automatic failover)", node_id, *term);
+                        info!("Node {} became leader for term {} (automatic failover)", node_id, term);
+                        publish_topology(&topology_tx, ClusterTopology {
+                            term,
+                            leader: Some(node_id),
+                            members: nodes,
+                            learners: learners.read().await.clone(),
+                        });
                     } else {
                         // For multi-node cluster, would send RequestVote RPCs
                         // Implementation placeholder for future multi-node support
@@ -265,42 +500,116 @@ impl RaftManager {
                         *state.write().await = RaftState::Follower;
                     }
 
-                    *last_heartbeat.write().await = Instant::now();
+                    *last_heartbeat.write().await = clock.now();
                 }
             }
         });
     }
 
+    /// Step down from leadership when the write pipeline stalls for longer than
+    /// `threshold`, so that the cluster fails over to a healthy node
+    pub fn start_stall_watchdog(&self, threshold: Duration) {
+        let database = self.database.clone();
+        let state = self.state.clone();
+        let current_term = self.current_term.clone();
+        let current_leader = self.current_leader.clone();
+        let cluster_nodes = self.cluster_nodes.clone();
+        let learners = self.learners.clone();
+        let last_heartbeat = self.last_heartbeat.clone();
+        let topology_tx = self.topology_tx.clone();
+        let clock = self.clock.clone();
+        let node_id = self.node_id;
+
+        tokio::spawn(async move {
+            let mut ticker = interval(stall::check_interval(threshold));
+            loop {
+                ticker.tick().await;
+                if !matches!(*state.read().await, RaftState::Leader)
+                    || !database.write_monitor().is_stalled(threshold)
+                {
+                    continue;
+                }
+
+                let members = cluster_nodes.read().await.clone();
+                if members.len() < 2 {
+                    // No other node to fail over to
+                    continue;
+                }
+
+                error!("Write pipeline stalled for more than {:?}, node {} stepping down", threshold, node_id);
+                *state.write().await = RaftState::Follower;
+                *current_leader.write().await = None;
+                *last_heartbeat.write().await = clock.now();
+                publish_topology(&topology_tx, ClusterTopology {
+                    term: *current_term.read().await,
+                    leader: None,
+                    members,
+                    learners: learners.read().await.clone(),
+                });
+            }
+        });
+    }
+
     /// Handle AppendEntries RPC for replication and heartbeat
     pub async fn handle_append_entries(&self, request: AppendEntriesRequest) -> AppendEntriesResponse {
-        let mut current_term = self.current_term.write().await;
+        // Simulates a slow follower during game days
+        if let Some(delay) = self.database.injected_latency(LatencyTarget::Replication) {
+            tokio::time::sleep(delay).await;
+        }
+        let _changing = self.hard_state_gate.lock().await;
+        let current_term = *self.current_term.read().await;
+
+        if let Err(e) = ProtocolVersion::current().check_compatible(&request.protocol) {
+            warn!("Rejecting entries from node {}: {}", request.leader_id, e);
+            return AppendEntriesResponse {
+                term: current_term,
+                success: false,
+                match_index: None,
+            };
+        }
         
         // If request term is older, reject
-        if request.term < *current_term {
+        if request.term < current_term {
             return AppendEntriesResponse {
-                term: *current_term,
+                term: current_term,
                 success: false,
                 match_index: None,
             };
         }
 
         // If request term is newer, update our term
-        if request.term > *current_term {
-            *current_term = request.term;
+        if request.term > current_term {
+            if let Err(e) = save_hard_state(self.hard_state_path.clone(), request.term, None).await {
+                error!("Rejecting entries from node {}: {}", request.leader_id, e);
+                return AppendEntriesResponse {
+                    term: current_term,
+                    success: false,
+                    match_index: None,
+                };
+            }
+            *self.current_term.write().await = request.term;
             *self.voted_for.write().await = None;
             *self.state.write().await = RaftState::Follower;
         }
 
         // Update leader and reset election timer
-        *self.current_leader.write().await = Some(request.leader_id);
-        *self.last_heartbeat.write().await = Instant::now();
+        let previous_leader = self.current_leader.write().await.replace(request.leader_id);
+        *self.last_heartbeat.write().await = self.clock.now();
+        if previous_leader != Some(request.leader_id) {
+            publish_topology(&self.topology_tx, ClusterTopology {
+                term: request.term,
+                leader: Some(request.leader_id),
+                members: self.cluster_nodes.read().await.clone(),
+                learners: self.learners.read().await.clone(),
+            });
+        }
 
         // Simplified log acceptance for single-node cluster
         // Multi-node implementation will include proper log consistency checks:
         // - Verify prev_log_index and prev_log_term match
         // - Check for conflicting entries and truncate if necessary
         AppendEntriesResponse {
-            term: *current_term,
+            term: request.term,
             success: true,
             match_index: Some(request.prev_log_index + request.entries.len() as LogIndex),
         }
@@ -308,34 +617,70 @@ impl RaftManager {
 
     /// Handle RequestVote RPC for leader election
     pub async fn handle_vote_request(&self, request: VoteRequest) -> VoteResponse {
-        let mut current_term = self.current_term.write().await;
-        let mut voted_for = self.voted_for.write().await;
+        let _changing = self.hard_state_gate.lock().await;
+        let current_term = *self.current_term.read().await;
+        let voted_for = *self.voted_for.read().await;
 
-        // If request term is older, reject
-        if request.term < *current_term {
+        // Never elect a node this one cannot follow
+        if let Err(e) = ProtocolVersion::current().check_compatible(&request.protocol) {
+            warn!("Rejecting vote request from node {}: {}", request.candidate_id, e);
             return VoteResponse {
-                term: *current_term,
+                term: current_term,
                 vote_granted: false,
             };
         }
 
-        // If request term is newer, update our term
-        if request.term > *current_term {
-            *current_term = request.term;
-            *voted_for = None;
-            *self.state.write().await = RaftState::Follower;
+        // Learners don't vote, and voters don't elect a learner
+        if !self.cluster_nodes.read().await.contains(&self.node_id)
+            || self.learners.read().await.contains(&request.candidate_id)
+        {
+            return VoteResponse {
+                term: current_term,
+                vote_granted: false,
+            };
         }
 
+        // If request term is older, reject
+        if request.term < current_term {
+            return VoteResponse {
+                term: current_term,
+                vote_granted: false,
+            };
+        }
+
+        // A newer term resets the vote
+        let (term, previous_vote) = if request.term > current_term {
+            (request.term, None)
+        } else {
+            (current_term, voted_for)
+        };
+
         // Vote if we haven't voted or voted for this candidate
-        let vote_granted = voted_for.is_none() || *voted_for == Some(request.candidate_id);
-        
+        let vote_granted = previous_vote.is_none() || previous_vote == Some(request.candidate_id);
+        let vote = if vote_granted { Some(request.candidate_id) } else { previous_vote };
+
+        // The term and vote are saved before the candidate learns about them
+        if (term, vote) != (current_term, voted_for) {
+            if let Err(e) = save_hard_state(self.hard_state_path.clone(), term, vote).await {
+                error!("Rejecting vote request from node {}: {}", request.candidate_id, e);
+                return VoteResponse {
+                    term: current_term,
+                    vote_granted: false,
+                };
+            }
+            if term > current_term {
+                *self.state.write().await = RaftState::Follower;
+            }
+            *self.current_term.write().await = term;
+            *self.voted_for.write().await = vote;
+        }
+
         if vote_granted {
-            *voted_for = Some(request.candidate_id);
-            info!("Granted vote to node {} for term {}", request.candidate_id, *current_term);
+            info!("Granted vote to node {} for term {}", request.candidate_id, term);
         }
 
         VoteResponse {
-            term: *current_term,
+            term,
             vote_granted,
         }
     }
@@ -347,13 +692,64 @@ impl RaftManager {
     }
 }
 
+/// Capacity of the topology notification channel; slow subscribers skip
+/// to the most recent change
+const TOPOLOGY_CHANNEL_CAPACITY: usize = 16;
+
+/// Send a topology change to subscribers, if any
+fn publish_topology(topology_tx: &broadcast::Sender<ClusterTopology>, topology: ClusterTopology) {
+    info!("Cluster topology changed: leader {:?}, term {}, members {:?}, learners {:?}", topology.leader, topology.term, topology.members, topology.learners);
+    // An error only means nobody is subscribed
+    let _ = topology_tx.send(topology);
+}
+
+/// Read-only view of the consensus state of a node
+#[derive(Clone)]
+pub struct RaftMonitor {
+    node_id: NodeId,
+    state: Arc<RwLock<RaftState>>,
+    current_term: Arc<RwLock<Term>>,
+    log: Arc<RwLock<Vec<LogEntry>>>,
+    last_applied: Arc<RwLock<LogIndex>>,
+    cluster_nodes: Arc<RwLock<Vec<NodeId>>>,
+    learners: Arc<RwLock<Vec<NodeId>>>,
+}
+
+impl RaftMonitor {
+    /// Get cluster metrics for monitoring
+    pub async fn metrics(&self) -> ClusterMetrics {
+        let state = self.state.read().await.clone();
+        let current_term = *self.current_term.read().await;
+        let is_leader = matches!(state, RaftState::Leader);
+        let cluster_size = self.cluster_nodes.read().await.len();
+        let learners = self.learners.read().await.len();
+        let last_log_index = self.log.read().await.len() as LogIndex;
+        let last_applied = *self.last_applied.read().await;
+
+        ClusterMetrics {
+            node_id: self.node_id,
+            current_term,
+            is_leader,
+            cluster_size,
+            learners,
+            state: format!("{:?}", state),
+            last_log_index,
+            last_applied,
+        }
+    }
+}
+
 /// Cluster metrics for monitoring distributed consensus
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ClusterMetrics {
     pub node_id: NodeId,
     pub current_term: Term,
     pub is_leader: bool,
+    /// Voting members
     pub cluster_size: usize,
+    /// Members receiving replication without voting
+    #[serde(default)]
+    pub learners: usize,
     pub state: String,
     pub last_log_index: LogIndex,
     pub last_applied: LogIndex,
@@ -362,6 +758,7 @@ pub struct ClusterMetrics {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::protocol::PROTOCOL_VERSION;
 
     #[tokio::test]
     async fn test_raft_manager_creation() {
@@ -423,4 +820,177 @@ mod tests {
         let result = manager.submit_command(command).await;
         assert!(result.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_topology_notifications() {
+        let database = Arc::new(Database::new());
+        let mut manager = RaftManager::new(1, database).await.unwrap();
+        let mut topology = manager.subscribe_topology();
+
+        manager.initialize_cluster(vec![1]).await.unwrap();
+        let event = topology.recv().await.unwrap();
+        assert_eq!(event.leader, Some(1));
+        assert_eq!(event.members, vec![1]);
+
+        manager.add_node(2).await.unwrap();
+        let event = topology.recv().await.unwrap();
+        assert_eq!(event.members, vec![1, 2]);
+
+        // Adding a known node is not a change
+        manager.add_node(2).await.unwrap();
+        assert!(topology.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_leader_steps_down_on_write_stall() {
+        let database = Arc::new(Database::new());
+        let manager = RaftManager::new(1, database.clone()).await.unwrap();
+        *manager.cluster_nodes.write().await = vec![1, 2, 3];
+        *manager.state.write().await = RaftState::Leader;
+        *manager.current_leader.write().await = Some(1);
+
+        let mut topology = manager.subscribe_topology();
+        manager.start_stall_watchdog(Duration::from_millis(20));
+
+        let _stalled = database.write_monitor().begin();
+        let event = tokio::time::timeout(Duration::from_secs(1), topology.recv())
+            .await
+            .expect("leader did not step down")
+            .unwrap();
+        assert_eq!(event.leader, None);
+        assert!(!manager.is_leader().await);
+    }
+
+    #[tokio::test]
+    async fn test_submit_command_in_namespace() {
+        let database = Arc::new(Database::new());
+        let mut manager = RaftManager::new(1, database.clone()).await.unwrap();
+        manager.initialize_cluster(vec![1]).await.unwrap();
+
+        let command = Command::Set {
+            key: "k".to_string(),
+            value: serde_json::json!(1),
+        };
+        manager.submit_command_in("tenant", command).await.unwrap();
+
+        assert!(database.is_empty());
+        assert_eq!(database.namespace("tenant").unwrap().len(), 1);
+        assert_eq!(manager.log.read().await[0].namespace, "tenant");
+    }
+
+    #[tokio::test]
+    async fn test_incompatible_peers_are_refused() {
+        let database = Arc::new(Database::new());
+        let mut manager = RaftManager::new(1, database).await.unwrap();
+        let newer = ProtocolVersion {
+            version: PROTOCOL_VERSION + 2,
+            min_compatible: PROTOCOL_VERSION + 1,
+        };
+
+        assert!(manager.add_peer(2, newer).await.is_err());
+        assert!(manager.add_peer(3, ProtocolVersion::current()).await.is_ok());
+        assert_eq!(manager.metrics().await.cluster_size, 2);
+
+        let response = manager
+            .handle_vote_request(VoteRequest {
+                term: 1,
+                candidate_id: 2,
+                last_log_index: 0,
+                last_log_term: 0,
+                protocol: newer,
+            })
+            .await;
+        assert!(!response.vote_granted);
+    }
+
+    // Paused, so that the election timer only ticks while the test sleeps
+    #[tokio::test(start_paused = true)]
+    async fn test_learner_promotion() {
+        let clock = Arc::new(clock::ManualClock::new());
+        let database = Arc::new(Database::new());
+        let mut manager = RaftManager::new(4, database)
+            .await
+            .unwrap()
+            .with_clock(clock.clone());
+        // Joining a cluster it isn't a member of, the node is a learner
+        manager.initialize_cluster(vec![1, 2, 3]).await.unwrap();
+        assert_eq!(manager.topology().await.learners, vec![4]);
+        let vote = VoteRequest {
+            term: 1,
+            candidate_id: 1,
+            last_log_index: 0,
+            last_log_term: 0,
+            protocol: ProtocolVersion::current(),
+        };
+        assert!(!manager.handle_vote_request(vote.clone()).await.vote_granted);
+        // The timer ticks past the election timeout without campaigning
+        clock.advance(Duration::from_secs(1));
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        assert_eq!(*manager.current_term.read().await, 0);
+
+        manager.add_learner(5, ProtocolVersion::current()).await.unwrap();
+        assert!(manager.add_learner(1, ProtocolVersion::current()).await.is_err());
+        assert!(manager.promote_learner(2).await.is_err());
+        manager.promote_learner(4).await.unwrap();
+        let topology = manager.topology().await;
+        assert_eq!(topology.members, vec![1, 2, 3, 4]);
+        assert_eq!(topology.learners, vec![5]);
+        let metrics = manager.metrics().await;
+        assert_eq!((metrics.cluster_size, metrics.learners), (4, 1));
+
+        // Once promoted, the same timer campaigns, for term 1
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        assert_eq!(*manager.current_term.read().await, 1);
+        assert_eq!(*manager.voted_for.read().await, Some(4));
+
+        // The node then votes in later terms, though not for a learner
+        let vote = VoteRequest { term: 2, ..vote };
+        let learner_vote = VoteRequest { candidate_id: 5, ..vote.clone() };
+        assert!(!manager.handle_vote_request(learner_vote).await.vote_granted);
+        assert!(manager.handle_vote_request(vote).await.vote_granted);
+    }
+
+    #[tokio::test]
+    async fn test_election_timeout_with_manual_clock() {
+        let clock = Arc::new(clock::ManualClock::new());
+        let database = Arc::new(Database::new());
+        let manager = RaftManager::new(1, database)
+            .await
+            .unwrap()
+            .with_clock(clock.clone());
+        manager.start_election_timer().await;
+
+        // Without the clock moving, no election ever starts
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        assert!(!manager.is_leader().await);
+
+        clock.advance(Duration::from_secs(1));
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        assert!(manager.is_leader().await);
+        assert_eq!(*manager.current_term.read().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_vote_survives_restart() {
+        let path = std::env::temp_dir().join(format!("jsonvault-raft-{}.json", Uuid::new_v4()));
+        let vote = |candidate_id| VoteRequest {
+            term: 5,
+            candidate_id,
+            last_log_index: 0,
+            last_log_term: 0,
+            protocol: ProtocolVersion::current(),
+        };
+
+        let manager = RaftManager::new(1, Arc::new(Database::new())).await.unwrap().with_hard_state(&path).unwrap();
+        assert!(manager.handle_vote_request(vote(2)).await.vote_granted);
+        drop(manager);
+
+        // After a restart the node still voted for node 2 in term 5
+        let manager = RaftManager::new(1, Arc::new(Database::new())).await.unwrap().with_hard_state(&path).unwrap();
+        assert_eq!(*manager.current_term.read().await, 5);
+        assert!(!manager.handle_vote_request(vote(3)).await.vote_granted);
+        assert!(manager.handle_vote_request(vote(2)).await.vote_granted);
+        assert_eq!(HardState::load(&path).unwrap(), HardState { current_term: 5, voted_for: Some(2) });
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,218 @@
+use crate::protocol::{ErrorCode, ErrorInfo};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Instructions budget of a single script run
+#[cfg(feature = "scripting")]
+pub const SCRIPT_FUEL: u64 = 100_000_000;
+
+/// Linear memory a script may grow to
+#[cfg(feature = "scripting")]
+pub const MAX_SCRIPT_MEMORY: usize = 64 * 1024 * 1024;
+
+/// Document passed to the `eval` export of a script
+#[derive(Debug, Serialize)]
+pub struct ScriptInput<'a> {
+    pub keys: &'a [String],
+    /// Current value of each key (null when missing)
+    pub values: Vec<Option<Value>>,
+    pub args: &'a [Value],
+}
+
+/// Document returned by the `eval` export of a script
+#[derive(Debug, Default, Deserialize)]
+pub struct ScriptOutput {
+    /// Value returned to the client
+    #[serde(default)]
+    pub result: Option<Value>,
+    /// New values of keys (null deletes the key)
+    #[serde(default)]
+    pub writes: BTreeMap<String, Option<Value>>,
+}
+
+/// WASM modules run by EVAL.
+///
+/// A module exports its `memory`, `alloc(len) -> ptr` and
+/// `eval(ptr, len) -> i64`: `eval` reads a JSON `ScriptInput` of `len` bytes
+/// at `ptr` and returns the location of a JSON `ScriptOutput` as
+/// `(ptr << 32) | len`. Modules cannot import host functions, and every run
+/// is bounded by `SCRIPT_FUEL` and `MAX_SCRIPT_MEMORY`.
+#[cfg(feature = "scripting")]
+pub struct Scripts {
+    engine: wasmtime::Engine,
+    modules: std::sync::RwLock<std::collections::HashMap<String, wasmtime::Module>>,
+}
+
+#[cfg(feature = "scripting")]
+impl Scripts {
+    /// Create a registry without scripts
+    pub fn new() -> Self {
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+        Self {
+            engine: wasmtime::Engine::new(&config).expect("valid WASM engine configuration"),
+            modules: Default::default(),
+        }
+    }
+
+    /// Compile a module (binary or text format) and register it under a name
+    pub fn load(&self, name: &str, module: &[u8]) -> Result<(), String> {
+        let module = wasmtime::Module::new(&self.engine, module)
+            .map_err(|e| format!("Invalid script '{}': {}", name, e))?;
+        self.modules
+            .write()
+            .unwrap()
+            .insert(name.to_string(), module);
+        Ok(())
+    }
+
+    /// Names of the registered scripts
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.modules.read().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Run a script on an input document, returning its output document
+    pub fn run(&self, name: &str, input: &[u8]) -> Result<Vec<u8>, ErrorInfo> {
+        use wasmtime::{Instance, Store, StoreLimits, StoreLimitsBuilder};
+
+        let module = self
+            .modules
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| {
+                ErrorInfo::new(
+                    ErrorCode::InvalidArgument,
+                    format!("Unknown script '{}'", name),
+                )
+            })?;
+        let failed = |e: wasmtime::Error| {
+            ErrorInfo::new(
+                ErrorCode::InvalidArgument,
+                format!("Script '{}' failed: {}", name, e),
+            )
+        };
+
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(MAX_SCRIPT_MEMORY)
+            .build();
+        let mut store: Store<StoreLimits> = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(SCRIPT_FUEL).map_err(failed)?;
+        let instance = Instance::new(&mut store, &module, &[]).map_err(failed)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| failed(wasmtime::Error::msg("no exported memory")))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&mut store, "alloc")
+            .map_err(failed)?;
+        let eval = instance
+            .get_typed_func::<(i32, i32), i64>(&mut store, "eval")
+            .map_err(failed)?;
+
+        let ptr = alloc.call(&mut store, input.len() as i32).map_err(failed)?;
+        memory
+            .write(&mut store, ptr as u32 as usize, input)
+            .map_err(|e| failed(e.into()))?;
+        let location = eval
+            .call(&mut store, (ptr, input.len() as i32))
+            .map_err(failed)? as u64;
+        let mut output = vec![0; (location & 0xffff_ffff) as usize];
+        memory
+            .read(&store, (location >> 32) as usize, &mut output)
+            .map_err(|e| failed(e.into()))?;
+        Ok(output)
+    }
+}
+
+/// WASM modules run by EVAL (unavailable: the server was built without the
+/// `scripting` feature)
+#[cfg(not(feature = "scripting"))]
+pub struct Scripts;
+
+#[cfg(not(feature = "scripting"))]
+impl Scripts {
+    /// Create a registry without scripts
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Register a script (always fails)
+    pub fn load(&self, _name: &str, _module: &[u8]) -> Result<(), String> {
+        Err(
+            "Scripting is not enabled in this build (compile with --features scripting)"
+                .to_string(),
+        )
+    }
+
+    /// Names of the registered scripts
+    pub fn names(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Run a script (always fails)
+    pub fn run(&self, _name: &str, _input: &[u8]) -> Result<Vec<u8>, ErrorInfo> {
+        Err(ErrorInfo::new(
+            ErrorCode::Unsupported,
+            "Scripting is not enabled in this build (compile with --features scripting)",
+        ))
+    }
+}
+
+impl Default for Scripts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for Scripts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Scripts")
+            .field("names", &self.names())
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "scripting"))]
+mod tests {
+    use super::*;
+
+    /// Ignores its input and writes a constant document
+    const CONSTANT_SCRIPT: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 0) "{\"result\":7,\"writes\":{\"k\":true}}")
+          (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "eval") (param i32 i32) (result i64) (i64.const 32)))
+    "#;
+
+    const LOOPING_SCRIPT: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "alloc") (param i32) (result i32) (i32.const 0))
+          (func (export "eval") (param i32 i32) (result i64) (loop (br 0)) (i64.const 0)))
+    "#;
+
+    #[test]
+    fn test_run_scripts() {
+        let scripts = Scripts::new();
+        scripts
+            .load("constant", CONSTANT_SCRIPT.as_bytes())
+            .unwrap();
+        scripts.load("loop", LOOPING_SCRIPT.as_bytes()).unwrap();
+        assert!(scripts.load("broken", b"(module").is_err());
+
+        let output = scripts.run("constant", b"{}").unwrap();
+        let output: ScriptOutput = serde_json::from_slice(&output).unwrap();
+        assert_eq!(output.result, Some(Value::from(7)));
+        assert_eq!(output.writes["k"], Some(Value::Bool(true)));
+
+        // Runaway scripts are stopped when their fuel runs out
+        assert!(scripts.run("loop", b"{}").is_err());
+        assert!(scripts.run("missing", b"{}").is_err());
+    }
+}
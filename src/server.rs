@@ -1,12 +1,31 @@
 use clap::{Arg, Command as ClapCommand};
+use jsonvault::capabilities::NodeInfo;
+use jsonvault::storage::aof::{spawn_aof_sync, AppendOnlyFile, FsyncPolicy};
+use jsonvault::storage::backup::{spawn_backups, Credentials, S3Backup, S3Bucket};
+use jsonvault::storage::layout::DataDir;
+use jsonvault::storage::snapshot::{spawn_snapshots, CheckedSnapshot, SnapshotStore};
+use jsonvault::storage::spill::SpillStore;
+use jsonvault::storage::standby::Standby;
+use jsonvault::{
+    spawn_active_expiry, spawn_fencing_watchdog, spawn_tombstone_purger, AuditLog, CdcExporter,
+    Database, JsonLinesSink, RaftManager, SocketOptions, TcpServer, TriggerSet, WebhookDispatcher,
+    WritePipeline,
+};
 use log::{error, info};
-use jsonvault::{Database, RaftManager, TcpServer};
 use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
+    // The logger accepts every level, so that CONFIG SET log-level can raise
+    // the level set by RUST_LOG at runtime
+    let initial_level = env_logger::Builder::from_default_env().build().filter();
+    env_logger::Builder::new()
+        .filter_level(log::LevelFilter::Trace)
+        .parse_default_env()
+        .init();
+    log::set_max_level(initial_level);
 
     let matches = ClapCommand::new("jsonvault-server")
         .version("0.1.0")
@@ -27,6 +46,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Cluster node IDs (comma-separated: 1,2,3)")
                 .value_delimiter(','),
         )
+        .arg(
+            Arg::new("learner")
+                .long("learner")
+                .help("Join the cluster nodes as a learner, replicating without voting until promoted")
+                .requires("cluster-nodes")
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("node-id")
                 .short('n')
@@ -35,21 +61,392 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Unique node identifier")
                 .default_value("auto-generated"),
         )
+        .arg(
+            Arg::new("write-transforms")
+                .long("write-transforms")
+                .value_name("FILE")
+                .help("JSON file with per-prefix write transformation rules"),
+        )
+        .arg(
+            Arg::new("triggers")
+                .long("triggers")
+                .value_name("FILE")
+                .help("JSON file with write triggers maintaining derived fields"),
+        )
+        .arg(
+            Arg::new("scripts")
+                .long("scripts")
+                .value_name("DIR")
+                .help("Directory of WASM scripts run by EVAL (requires the scripting feature)"),
+        )
+        .arg(
+            Arg::new("webhooks")
+                .long("webhooks")
+                .value_name("FILE")
+                .help("JSON file with rules posting key events to HTTP endpoints"),
+        )
+        .arg(
+            Arg::new("webhook-retries")
+                .long("webhook-retries")
+                .value_name("COUNT")
+                .help("Retries of a failed webhook delivery")
+                .default_value("5")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("webhook-backoff")
+                .long("webhook-backoff")
+                .value_name("MS")
+                .help("Delay before the first webhook retry, doubled on each following one")
+                .default_value("500")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("initial-capacity")
+                .long("initial-capacity")
+                .value_name("KEYS")
+                .help("Pre-size the keyspace for this many keys")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("shards")
+                .long("shards")
+                .value_name("COUNT")
+                .help("Number of keyspace shards (power of two, adaptive by default)")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("write-stall-timeout")
+                .long("write-stall-timeout")
+                .value_name("MS")
+                .help("Step down (cluster) or fence writes (standalone) when a write stalls this long")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("quotas")
+                .long("quotas")
+                .value_name("FILE")
+                .help("JSON file with per-namespace quotas (max_keys, max_bytes)"),
+        )
+        .arg(
+            Arg::new("max-memory")
+                .long("max-memory")
+                .value_name("BYTES")
+                .help("Limit the total size of keys and values, evicting keys of cache-tier namespaces beyond it")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("max-value-size")
+                .long("max-value-size")
+                .value_name("BYTES")
+                .help("Reject writes of values larger than this")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("eviction-policy")
+                .long("eviction-policy")
+                .value_name("POLICY")
+                .help("Keys evicted from cache-tier namespaces beyond --max-memory: oldest, random or none")
+                .value_parser(["oldest", "random", "none"]),
+        )
+        .arg(
+            Arg::new("spill")
+                .long("spill")
+                .help("Spill the least recently written documents to the data directory beyond --max-memory instead of failing writes")
+                .requires("data-dir")
+                .requires("max-memory")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("compress-threshold")
+                .long("compress-threshold")
+                .value_name("BYTES")
+                .help("Store documents of at least BYTES serialized bytes zstd-compressed in memory (disabled by default)")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("cache-namespaces")
+                .long("cache-namespaces")
+                .value_name("NAMESPACES")
+                .help("Namespaces whose keys may be evicted under memory pressure (comma-separated)")
+                .value_delimiter(','),
+        )
+        .arg(
+            Arg::new("history-depth")
+                .long("history-depth")
+                .value_name("REVISIONS")
+                .help("Keep the last REVISIONS values of every key for HISTORY and RESTORE (disabled by default)")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("tombstone-retention")
+                .long("tombstone-retention")
+                .value_name("SECONDS")
+                .help("Leave a tombstone for deleted keys, purged after SECONDS (disabled by default)")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("change-log-capacity")
+                .long("change-log-capacity")
+                .value_name("CHANGES")
+                .help("Keep the last CHANGES writes of every namespace for CHANGES and consumer groups (disabled by default)")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("cdc-file")
+                .long("cdc-file")
+                .value_name("FILE")
+                .help("Export the change log of every namespace to a JSON lines file (requires --change-log-capacity)"),
+        )
+        .arg(
+            Arg::new("cdc-interval")
+                .long("cdc-interval")
+                .value_name("MS")
+                .help("Interval between two exports of the change log")
+                .default_value("100")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("expiry-interval")
+                .long("expiry-interval")
+                .value_name("MS")
+                .help("Interval between active expiry cycles")
+                .default_value("100")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("expiry-batch")
+                .long("expiry-batch")
+                .value_name("KEYS")
+                .help("Expired keys deleted per namespace and batch by active expiry")
+                .default_value("200")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("inline-meta")
+                .long("inline-meta")
+                .help("Maintain _meta.created_at/updated_at/revision inside object documents")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("tcp-nodelay")
+                .long("tcp-nodelay")
+                .help("Disable Nagle's algorithm on client connections")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("tcp-keepalive")
+                .long("tcp-keepalive")
+                .value_name("SECONDS")
+                .help("Send keepalive probes on client connections silent for this long")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("tcp-recv-buffer")
+                .long("tcp-recv-buffer")
+                .value_name("BYTES")
+                .help("Kernel receive buffer size of client connections")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("tcp-send-buffer")
+                .long("tcp-send-buffer")
+                .value_name("BYTES")
+                .help("Kernel send buffer size of client connections")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("idle-timeout")
+                .long("idle-timeout")
+                .value_name("SECONDS")
+                .help("Close connections that send no request for this long")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("websocket")
+                .long("websocket")
+                .value_name("ADDRESS")
+                .help("Also accept WebSocket connections on this address, one command per message"),
+        )
+        .arg(
+            Arg::new("grpc")
+                .long("grpc")
+                .value_name("ADDRESS")
+                .help("Also serve the gRPC service on this address (requires the grpc feature)"),
+        )
+        .arg(
+            Arg::new("requirepass")
+                .long("requirepass")
+                .value_name("PASSWORD")
+                .help("Require clients to AUTH with this password before running commands"),
+        )
+        .arg(
+            Arg::new("api-keys")
+                .long("api-keys")
+                .value_name("FILE")
+                .help("JSON file mapping identity names to API keys accepted by AUTH"),
+        )
+        .arg(
+            Arg::new("acl")
+                .long("acl")
+                .value_name("FILE")
+                .help("JSON file with the grants of authenticated identities, denying the identities it omits (unrestricted when absent, until ACLSET)"),
+        )
+        .arg(
+            Arg::new("audit-log")
+                .long("audit-log")
+                .value_name("FILE")
+                .help("Append a record of every mutating command, with its identity and value hashes, to this file"),
+        )
+        .arg(
+            Arg::new("admin-commands")
+                .long("admin-commands")
+                .help("Allow clients to run admin commands such as FLUSH")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("read-only")
+                .long("read-only")
+                .help("Reject every write while serving reads (switch with the READONLY admin command)")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("self-test")
+                .long("self-test")
+                .value_name("MODE")
+                .help("Run a self-test against the local database instead of serving clients")
+                .value_parser(["soak"])
+                .hide(true),
+        )
+        .arg(
+            Arg::new("self-test-duration")
+                .long("self-test-duration")
+                .value_name("SECONDS")
+                .help("Stop the self-test after this many seconds (runs until stopped by default)")
+                .value_parser(clap::value_parser!(u64))
+                .hide(true),
+        )
+        .arg(
+            Arg::new("data-dir")
+                .long("data-dir")
+                .value_name("DIR")
+                .help("Data directory (stores the node identity, snapshots and AOF segments)"),
+        )
+        .arg(
+            Arg::new("aof")
+                .long("aof")
+                .help("Append every write to the AOF of the data directory and replay it on startup")
+                .requires("data-dir")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("aof-fsync")
+                .long("aof-fsync")
+                .value_name("POLICY")
+                .help("When appended writes are flushed to disk: always, everysec or no (left to the OS)")
+                .value_parser(["always", "everysec", "no"])
+                .default_value("everysec"),
+        )
+        .arg(
+            Arg::new("snapshot-interval")
+                .long("snapshot-interval")
+                .value_name("SECONDS")
+                .help("Save a snapshot of the dataset to the data directory periodically")
+                .requires("data-dir")
+                .value_parser(clap::value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("snapshot-retain")
+                .long("snapshot-retain")
+                .value_name("N")
+                .help("Number of snapshots kept in the data directory")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("3"),
+        )
+        .arg(
+            Arg::new("standby-of")
+                .long("standby-of")
+                .value_name("ADDRESS")
+                .help("Run as a read-only warm standby applying the AOF shipped by a primary, until PROMOTE")
+                .requires("data-dir")
+                .conflicts_with_all(["aof", "snapshot-interval", "cluster-nodes"]),
+        )
+        .arg(
+            Arg::new("backup-s3-endpoint")
+                .long("backup-s3-endpoint")
+                .value_name("URL")
+                .help("Back up snapshots and AOF segments to an S3-compatible endpoint (http:// or https://host[:port]); keys are read from AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY")
+                .requires("data-dir")
+                .requires("backup-s3-bucket"),
+        )
+        .arg(
+            Arg::new("backup-s3-bucket")
+                .long("backup-s3-bucket")
+                .value_name("BUCKET")
+                .help("Bucket receiving the backups")
+                .requires("backup-s3-endpoint"),
+        )
+        .arg(
+            Arg::new("backup-s3-region")
+                .long("backup-s3-region")
+                .value_name("REGION")
+                .help("Region the backup requests are signed for")
+                .default_value("us-east-1"),
+        )
+        .arg(
+            Arg::new("backup-s3-prefix")
+                .long("backup-s3-prefix")
+                .value_name("PREFIX")
+                .help("Key prefix of the backup objects, such as backups/node-1/")
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("backup-interval")
+                .long("backup-interval")
+                .value_name("SECONDS")
+                .help("Seconds between two incremental backups")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .default_value("300"),
+        )
+        .arg(
+            Arg::new("restore-from-s3")
+                .long("restore-from-s3")
+                .help("Download the latest backup into the (empty) data directory before loading it")
+                .requires("backup-s3-endpoint")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("canonical-json")
+                .long("canonical-json")
+                .help("Store and emit canonical JSON (normalized numbers, sorted keys)")
+                .action(clap::ArgAction::SetTrue),
+        )
         .get_matches();
 
     let address = matches.get_one::<String>("address").unwrap().clone();
     let node_id_arg = matches.get_one::<String>("node-id").unwrap();
-    let cluster_nodes: Option<Vec<String>> = matches.get_many::<String>("cluster-nodes")
+    let cluster_nodes: Option<Vec<String>> = matches
+        .get_many::<String>("cluster-nodes")
         .map(|values| values.cloned().collect());
-    
-    let node_id_str = if node_id_arg == "auto-generated" {
-        Uuid::new_v4().to_string()
-    } else {
-        node_id_arg.clone()
+
+    let explicit_node_id = (node_id_arg != "auto-generated").then_some(node_id_arg.as_str());
+    let data_dir = matches.get_one::<String>("data-dir").map(|path| {
+        DataDir::open(path, explicit_node_id).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        })
+    });
+
+    let node_id_str = match (&data_dir, explicit_node_id) {
+        // The data directory keeps the node identity stable across restarts
+        (Some(dir), _) => dir.node_id().to_string(),
+        (None, Some(node_id)) => node_id.to_string(),
+        (None, None) => Uuid::new_v4().to_string(),
     };
-    
+
     // Convert node_id to u64 for Raft
-    let node_id_numeric: u64 = node_id_str.chars()
+    let node_id_numeric: u64 = node_id_str
+        .chars()
         .take(8)
         .enumerate()
         .map(|(i, c)| (c as u64) << (i * 8))
@@ -59,9 +456,236 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("Starting JsonVault server with Raft consensus");
     info!("Node ID: {} (numeric: {})", node_id_str, node_id_numeric);
     info!("Address: {}", address);
+    if let Some(dir) = &data_dir {
+        info!(
+            "Data directory: {} (format version {})",
+            dir.root().display(),
+            dir.manifest().format_version
+        );
+    }
+
+    // The backup is restored before the keyspace is sized from its snapshot
+    let backup_bucket = match matches.get_one::<String>("backup-s3-endpoint") {
+        Some(endpoint) => {
+            let bucket = Credentials::from_env()
+                .and_then(|credentials| {
+                    S3Bucket::new(
+                        endpoint,
+                        matches.get_one::<String>("backup-s3-bucket").unwrap(),
+                        credentials,
+                    )
+                })
+                .unwrap_or_else(|e| {
+                    error!("{}", e);
+                    std::process::exit(1);
+                })
+                .with_region(matches.get_one::<String>("backup-s3-region").unwrap())
+                .with_prefix(matches.get_one::<String>("backup-s3-prefix").unwrap());
+            Some(bucket)
+        }
+        None => None,
+    };
+    if let (Some(bucket), Some(dir), true) = (
+        &backup_bucket,
+        &data_dir,
+        matches.get_flag("restore-from-s3"),
+    ) {
+        if let Err(e) = S3Backup::restore(bucket, dir).await {
+            error!("Restore failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // Create database, sized for the keys of the snapshot to load if it
+    // holds more than the configured capacity
+    let initial_capacity = matches.get_one::<usize>("initial-capacity").copied();
+    let mut newest_snapshot = match &data_dir {
+        Some(dir) => SnapshotStore::newest(dir).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        }),
+        None => None,
+    };
+    let expected_keys =
+        initial_capacity.max(newest_snapshot.as_ref().and_then(CheckedSnapshot::keys));
+    let database = match (expected_keys, matches.get_one::<usize>("shards")) {
+        (capacity, Some(shards)) => {
+            Database::with_capacity_and_shards(capacity.unwrap_or(0), *shards).unwrap_or_else(|e| {
+                error!("{}", e);
+                std::process::exit(1);
+            })
+        }
+        (Some(capacity), None) => Database::with_expected_keys(capacity),
+        (None, None) => Database::new(),
+    };
+    info!("Keyspace shards: {}", database.shard_count());
+    let database = Arc::new(database);
+
+    if let Some(path) = matches.get_one::<String>("write-transforms") {
+        let pipeline = WritePipeline::from_file(path).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
+        database.set_write_pipeline(pipeline);
+        info!("Loaded write transformation rules from {}", path);
+    }
+
+    if let Some(path) = matches.get_one::<String>("triggers") {
+        let triggers = TriggerSet::from_file(path).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
+        info!("Loaded {} write triggers from {}", triggers.len(), path);
+        database.set_triggers(triggers);
+    }
+
+    if let Some(dir) = matches.get_one::<String>("scripts") {
+        let loaded = database.load_scripts(dir).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
+        info!("Loaded {} scripts from {}", loaded, dir);
+    }
+
+    if let Some(path) = matches.get_one::<String>("webhooks") {
+        let retries = *matches.get_one::<usize>("webhook-retries").unwrap();
+        let backoff = Duration::from_millis(*matches.get_one::<u64>("webhook-backoff").unwrap());
+        let dispatcher = WebhookDispatcher::from_file(path).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
+        info!("Loaded {} webhook rules from {}", dispatcher.len(), path);
+        dispatcher
+            .with_retries(retries, backoff)
+            .spawn(Arc::clone(&database));
+    }
+
+    if let Some(path) = matches.get_one::<String>("quotas") {
+        database.load_quotas(path).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
+        info!("Loaded namespace quotas from {}", path);
+    }
+
+    if let Some(path) = matches.get_one::<String>("acl") {
+        database.access_control().load(path).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
+        info!("Loaded ACL grants from {}", path);
+    }
+
+    if let Some(path) = matches.get_one::<String>("audit-log") {
+        let audit = AuditLog::open(path).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
+        database.set_audit_log(Some(Arc::new(audit)));
+        info!("Mutating commands are audited to {}", path);
+    }
+
+    if let Some(max_bytes) = matches.get_one::<u64>("max-memory") {
+        database.set_max_memory(Some(*max_bytes));
+        info!("Memory limit: {} bytes", max_bytes);
+    }
+    if let Some(max_bytes) = matches.get_one::<u64>("max-value-size") {
+        database.config().set_max_value_size(Some(*max_bytes));
+        info!("Value size limit: {} bytes", max_bytes);
+    }
+    if let Some(policy) = matches.get_one::<String>("eviction-policy") {
+        database.config().set_eviction_policy(policy.parse()?);
+    }
+
+    if let Some(threshold) = matches.get_one::<usize>("compress-threshold") {
+        database.set_compression_threshold(*threshold);
+        info!(
+            "Documents of at least {} bytes are stored compressed",
+            threshold
+        );
+    }
+
+    if let Some(namespaces) = matches.get_many::<String>("cache-namespaces") {
+        for namespace in namespaces {
+            database.set_cache_tier(namespace, true);
+            info!("Namespace {} is cache tier", namespace);
+        }
+    }
+
+    if let Some(depth) = matches.get_one::<usize>("history-depth") {
+        database.set_history_depth(*depth);
+        info!("Keeping the last {} revisions of every key", depth);
+    }
 
-    // Create database
-    let database = Arc::new(Database::new());
+    if let Some(seconds) = matches.get_one::<u64>("tombstone-retention") {
+        let retention = Duration::from_secs(*seconds);
+        database.set_tombstone_retention(Some(retention));
+        spawn_tombstone_purger(
+            Arc::clone(&database),
+            (retention / 10).max(Duration::from_secs(1)),
+        );
+        info!("Deleted keys leave a tombstone for {:?}", retention);
+    }
+
+    if let Some(capacity) = matches.get_one::<usize>("change-log-capacity") {
+        database.set_change_log_capacity(*capacity);
+        info!("Keeping the last {} changes of every namespace", capacity);
+    }
+
+    if let Some(path) = matches.get_one::<String>("cdc-file") {
+        if matches
+            .get_one::<usize>("change-log-capacity")
+            .copied()
+            .unwrap_or(0)
+            == 0
+        {
+            error!("--cdc-file requires --change-log-capacity");
+            std::process::exit(1);
+        }
+        let sink = JsonLinesSink::open(path).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
+        let interval = Duration::from_millis(*matches.get_one::<u64>("cdc-interval").unwrap());
+        CdcExporter::new(Arc::new(sink))
+            .with_polling(interval, 100)
+            .spawn(Arc::clone(&database));
+        info!("Exporting changes to {}", path);
+    }
+
+    spawn_active_expiry(
+        Arc::clone(&database),
+        Duration::from_millis(*matches.get_one::<u64>("expiry-interval").unwrap()),
+        *matches.get_one::<usize>("expiry-batch").unwrap(),
+    );
+
+    if matches.get_flag("inline-meta") {
+        database.set_inline_meta(true);
+        info!("Inline document metadata enabled");
+    }
+
+    if matches.get_flag("canonical-json") {
+        database.set_canonical_json(true);
+        info!("Canonical JSON mode enabled");
+    }
+
+    if matches.get_one::<String>("self-test").is_some() {
+        let config = jsonvault::soak::SoakConfig {
+            duration: matches
+                .get_one::<u64>("self-test-duration")
+                .map(|s| Duration::from_secs(*s)),
+            ..Default::default()
+        };
+        info!("Starting soak self-test: {:?}", config);
+        let report = jsonvault::soak::run(Arc::clone(&database), config).await;
+        info!(
+            "Soak self-test finished: {} operations, {} verified reads, {} violations",
+            report.operations,
+            report.verifications,
+            report.violations.len()
+        );
+        std::process::exit(if report.violations.is_empty() { 0 } else { 1 });
+    }
 
     // Initialize Raft manager
     let mut raft_manager = RaftManager::new(node_id_numeric, Arc::clone(&database))
@@ -71,11 +695,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             std::process::exit(1);
         })
         .unwrap();
+    if let Some(dir) = &data_dir {
+        raft_manager = raft_manager
+            .with_hard_state(dir.raft_state_path())
+            .unwrap_or_else(|e| {
+                error!("{}", e);
+                std::process::exit(1);
+            });
+    }
 
-    // Parse cluster members
+    // Parse cluster members; a learner is not one of them
+    let learner = matches.get_flag("learner");
     let cluster_members = if let Some(nodes) = cluster_nodes {
-        let mut members = vec![node_id_numeric];
-        
+        let mut members = if learner {
+            Vec::new()
+        } else {
+            vec![node_id_numeric]
+        };
+
         for node_spec in nodes {
             if let Ok(parsed_id) = node_spec.parse::<u64>() {
                 members.push(parsed_id);
@@ -87,7 +724,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     // Initialize cluster with automatic failover
-    if let Err(e) = raft_manager.initialize_cluster(cluster_members.clone()).await {
+    if let Err(e) = raft_manager
+        .initialize_cluster(cluster_members.clone())
+        .await
+    {
         error!("Failed to initialize Raft cluster: {}", e);
         std::process::exit(1);
     }
@@ -96,25 +736,188 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let metrics = raft_manager.metrics().await;
     info!("Raft metrics: {:?}", metrics);
     info!("Cluster size: {} nodes", cluster_members.len());
-    
+    if learner {
+        info!("This node is a learner - replicating without voting until promoted");
+    }
+
     if metrics.is_leader {
         info!("This node is the leader - ready to accept writes");
     } else {
         info!("This node is a follower - will redirect writes to leader");
     }
 
+    if let Some(timeout_ms) = matches.get_one::<u64>("write-stall-timeout") {
+        let threshold = Duration::from_millis(*timeout_ms);
+        if cluster_members.len() > 1 || learner {
+            raft_manager.start_stall_watchdog(threshold);
+            info!("Leader steps down after write stalls of {:?}", threshold);
+        } else {
+            spawn_fencing_watchdog(Arc::clone(&database), threshold);
+            info!("Writes are fenced after write stalls of {:?}", threshold);
+        }
+    }
+
+    if matches.get_flag("read-only") {
+        database.set_read_only(true);
+        info!("Read-only mode: writes are rejected");
+    }
+
+    // Restore the dataset before serving: the newest snapshot, then the AOF
+    // written since; every write is then appended to the AOF
+    if let (Some(dir), true) = (&data_dir, matches.get_flag("spill")) {
+        let spill = SpillStore::open(dir.spill_dir()).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
+        database.set_spill(Some(Arc::new(spill)));
+    }
+
+    if let Some(dir) = &data_dir {
+        let snapshot = newest_snapshot
+            .take()
+            .map(|snapshot| snapshot.load(&database))
+            .transpose()
+            .unwrap_or_else(|e| {
+                error!("{}", e);
+                std::process::exit(1);
+            });
+        let fsync: FsyncPolicy = matches.get_one::<String>("aof-fsync").unwrap().parse()?;
+        if let Some(primary) = matches.get_one::<String>("standby-of") {
+            // The shipped segments are replayed like a local AOF, then
+            // shipping resumes from the end of the last one
+            if let Err(e) = AppendOnlyFile::replay(dir, &database, snapshot.unwrap_or(1)) {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+            database.set_standby(Arc::new(Standby::new(primary, dir.clone(), fsync)));
+            info!("Standby of {}: read-only until PROMOTE", primary);
+        } else if matches.get_flag("aof") {
+            let aof = AppendOnlyFile::replay(dir, &database, snapshot.unwrap_or(1))
+                .and_then(|_| AppendOnlyFile::open(dir, fsync))
+                .unwrap_or_else(|e| {
+                    error!("{}", e);
+                    std::process::exit(1);
+                });
+            let aof = Arc::new(aof);
+            if fsync == FsyncPolicy::EverySec {
+                spawn_aof_sync(Arc::clone(&aof), Duration::from_secs(1));
+            }
+            database.set_aof(Some(aof));
+            info!("AOF persistence enabled (fsync: {})", fsync);
+        }
+
+        let retain = *matches.get_one::<usize>("snapshot-retain").unwrap();
+        let store = Arc::new(SnapshotStore::new(dir.clone(), retain));
+        if let Some(seconds) = matches.get_one::<u64>("snapshot-interval") {
+            spawn_snapshots(
+                Arc::clone(&store),
+                Arc::clone(&database),
+                Duration::from_secs(*seconds),
+            );
+            info!(
+                "Snapshots are saved every {} seconds (keeping the last {})",
+                seconds, retain
+            );
+        }
+        database.set_snapshot_store(Some(store));
+
+        if let Some(bucket) = backup_bucket {
+            let seconds = *matches.get_one::<u64>("backup-interval").unwrap();
+            let backup = Arc::new(S3Backup::new(bucket, dir.clone()));
+            spawn_backups(Arc::clone(&backup), Duration::from_secs(seconds));
+            database.set_backup(Some(backup));
+            info!("Backups are uploaded every {} seconds", seconds);
+        }
+    }
+
+    // Report the node configuration at startup and through INFO
+    let mut capabilities = database.capabilities();
+    capabilities.features.raft = cluster_members.len() > 1 || learner;
+    capabilities.features.write_fencing = matches.contains_id("write-stall-timeout");
+    capabilities.features.admin_commands = matches.get_flag("admin-commands");
+    capabilities.features.webhooks = matches.contains_id("webhooks");
+    capabilities.features.websocket = matches.contains_id("websocket");
+    capabilities.features.grpc = matches.contains_id("grpc");
+    let require_auth = matches.contains_id("requirepass") || matches.contains_id("api-keys");
+    capabilities.features.auth = require_auth;
+    capabilities.features.cdc = matches.contains_id("cdc-file");
+    capabilities.features.persistence =
+        matches.get_flag("aof") || matches.contains_id("snapshot-interval");
+    capabilities.limits.initial_capacity = initial_capacity;
+    capabilities.limits.write_stall_timeout_ms =
+        matches.get_one::<u64>("write-stall-timeout").copied();
+    capabilities.limits.idle_timeout_secs = matches.get_one::<u64>("idle-timeout").copied();
+    capabilities.node = NodeInfo {
+        node_id: node_id_str.clone(),
+        address: address.clone(),
+        cluster_members: cluster_members.clone(),
+        data_dir: data_dir
+            .as_ref()
+            .map(|dir| dir.root().display().to_string()),
+    };
+    info!("Capabilities: {}", serde_json::to_string(&capabilities)?);
+    database.set_capabilities(capabilities);
+
     // Create TCP server
-    let server = TcpServer::new(Arc::clone(&database), address.clone());
+    let mut server = TcpServer::new(Arc::clone(&database), address.clone())
+        .with_topology(
+            raft_manager.topology_sender(),
+            raft_manager.topology().await,
+        )
+        .with_raft_monitor(raft_manager.monitor())
+        .with_admin_commands(matches.get_flag("admin-commands"));
+    let mut socket_options = SocketOptions::new().with_nodelay(matches.get_flag("tcp-nodelay"));
+    if let Some(seconds) = matches.get_one::<u64>("tcp-keepalive") {
+        socket_options = socket_options.with_keepalive(Duration::from_secs(*seconds));
+    }
+    if let Some(bytes) = matches.get_one::<usize>("tcp-recv-buffer") {
+        socket_options = socket_options.with_recv_buffer_size(*bytes);
+    }
+    if let Some(bytes) = matches.get_one::<usize>("tcp-send-buffer") {
+        socket_options = socket_options.with_send_buffer_size(*bytes);
+    }
+    server = server.with_socket_options(socket_options);
+    if let Some(seconds) = matches.get_one::<u64>("idle-timeout") {
+        server = server.with_idle_timeout(Duration::from_secs(*seconds));
+    }
+    if let Some(websocket_address) = matches.get_one::<String>("websocket") {
+        server = server.with_websocket(websocket_address.clone());
+    }
+    if let Some(grpc_address) = matches.get_one::<String>("grpc") {
+        #[cfg(feature = "grpc")]
+        {
+            server = server.with_grpc(grpc_address.clone());
+        }
+        #[cfg(not(feature = "grpc"))]
+        {
+            error!("--grpc {} requires the grpc feature", grpc_address);
+            std::process::exit(1);
+        }
+    }
+    if require_auth {
+        let mut credentials = match matches.get_one::<String>("api-keys") {
+            Some(path) => jsonvault::Credentials::from_file(path)?,
+            None => jsonvault::Credentials::new(),
+        };
+        if let Some(password) = matches.get_one::<String>("requirepass") {
+            credentials = credentials.with_identity(jsonvault::DEFAULT_IDENTITY, password);
+        }
+        info!(
+            "Clients must authenticate ({} identities)",
+            credentials.len()
+        );
+        server = server.with_credentials(credentials);
+    }
 
     info!("Server ready for connections with automatic failover");
 
     // Start server (this will block the main thread)
     if let Err(e) = server.start().await {
         error!("Server error: {}", e);
-        
+
         // Cleanup Raft
         let _ = raft_manager.shutdown().await;
-        
+
         std::process::exit(1);
     }
 
@@ -0,0 +1,279 @@
+use crate::protocol::{Command, Response};
+use crate::Database;
+use log::{error, info};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Settings of a soak run
+#[derive(Debug, Clone)]
+pub struct SoakConfig {
+    /// How long to run (None runs until the process is stopped)
+    pub duration: Option<Duration>,
+    /// Number of concurrent workers
+    pub workers: usize,
+    /// Number of keys owned by each worker
+    pub keys_per_worker: usize,
+    /// Maximum length of generated arrays
+    pub max_array_len: usize,
+    /// Interval between progress reports and global invariant checks
+    pub report_interval: Duration,
+}
+
+impl Default for SoakConfig {
+    fn default() -> Self {
+        Self {
+            duration: None,
+            workers: 8,
+            keys_per_worker: 1000,
+            max_array_len: 32,
+            report_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Outcome of a soak run
+#[derive(Debug, Clone, Default)]
+pub struct SoakReport {
+    /// Operations executed
+    pub operations: u64,
+    /// Reads checked against the expected state
+    pub verifications: u64,
+    /// Invariant violations (the run stops at the first one)
+    pub violations: Vec<String>,
+}
+
+/// Size accounted per generated document, used to bound the keyspace size
+const MAX_DOCUMENT_BYTES: u64 = 1024;
+
+/// Run randomized load against a database, checking that every acknowledged
+/// write is readable and that memory use stays bounded.
+///
+/// Each worker owns a disjoint set of keys and keeps the expected value of
+/// each of them, so any lost or corrupted write is detected on the next read.
+pub async fn run(database: Arc<Database>, config: SoakConfig) -> SoakReport {
+    let stop = Arc::new(AtomicBool::new(false));
+    let operations = Arc::new(AtomicU64::new(0));
+    let verifications = Arc::new(AtomicU64::new(0));
+    let started = Instant::now();
+
+    let workers: Vec<_> = (0..config.workers)
+        .map(|worker| {
+            let database = database.clone();
+            let config = config.clone();
+            let stop = stop.clone();
+            let operations = operations.clone();
+            let verifications = verifications.clone();
+            tokio::spawn(async move {
+                let result = run_worker(
+                    worker,
+                    &database,
+                    &config,
+                    &stop,
+                    &operations,
+                    &verifications,
+                )
+                .await;
+                if result.is_err() {
+                    stop.store(true, Ordering::Relaxed);
+                }
+                result
+            })
+        })
+        .collect();
+
+    let mut violations = Vec::new();
+    let max_keys = (config.workers * config.keys_per_worker) as u64;
+    let mut ticker = tokio::time::interval(config.report_interval);
+    ticker.tick().await;
+    while !stop.load(Ordering::Relaxed) {
+        let deadline = config.duration.map(|d| d.saturating_sub(started.elapsed()));
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = tokio::time::sleep(deadline.unwrap_or(Duration::MAX)), if deadline.is_some() => {
+                break;
+            }
+        }
+
+        if let Err(violation) = check_memory_bound(&database, max_keys).await {
+            violations.push(violation);
+            break;
+        }
+        info!(
+            "Soak: {:?} elapsed, {} operations, {} verified reads, {} keys",
+            started.elapsed(),
+            operations.load(Ordering::Relaxed),
+            verifications.load(Ordering::Relaxed),
+            database.len()
+        );
+    }
+    stop.store(true, Ordering::Relaxed);
+
+    for worker in workers {
+        match worker.await {
+            Ok(Ok(())) => {}
+            Ok(Err(violation)) => violations.push(violation),
+            Err(e) => violations.push(format!("Soak worker panicked: {}", e)),
+        }
+    }
+    if violations.is_empty() {
+        if let Err(violation) = check_memory_bound(&database, max_keys).await {
+            violations.push(violation);
+        }
+    }
+    for violation in &violations {
+        error!("Soak invariant violated: {}", violation);
+    }
+
+    SoakReport {
+        operations: operations.load(Ordering::Relaxed),
+        verifications: verifications.load(Ordering::Relaxed),
+        violations,
+    }
+}
+
+/// Apply random operations to the keys owned by one worker
+async fn run_worker(
+    worker: usize,
+    database: &Database,
+    config: &SoakConfig,
+    stop: &AtomicBool,
+    operations: &AtomicU64,
+    verifications: &AtomicU64,
+) -> Result<(), String> {
+    let mut expected: HashMap<String, Value> = HashMap::new();
+    let mut rng = fastrand::Rng::new();
+    let mut iteration = 0u64;
+
+    while !stop.load(Ordering::Relaxed) {
+        iteration += 1;
+        let key = format!("soak:{}:{}", worker, rng.usize(..config.keys_per_worker));
+        let command = match rng.u8(..10) {
+            0..=3 => Command::Set {
+                key: key.clone(),
+                value: json!({ "n": rng.u64(..), "tag": format!("w{}", worker) }),
+            },
+            4 => Command::Delete { key: key.clone() },
+            5 | 6 => match expected.get(&key) {
+                Some(Value::Array(arr)) if arr.len() >= config.max_array_len => Command::QPop {
+                    key: key.clone(),
+                    path: "$".to_string(),
+                    index: None,
+                },
+                None | Some(Value::Array(_)) => Command::QAppend {
+                    key: key.clone(),
+                    path: "$".to_string(),
+                    value: json!(rng.u32(..)),
+                },
+                Some(_) => Command::Delete { key: key.clone() },
+            },
+            _ => Command::Get { key: key.clone() },
+        };
+
+        let description = command.to_string();
+        let response = database.execute_command(command.clone()).await;
+        operations.fetch_add(1, Ordering::Relaxed);
+        let verified = apply_expected(&mut expected, command, &response)
+            .map_err(|e| format!("{} -> {}: {}", description, response, e))?;
+        if verified {
+            verifications.fetch_add(1, Ordering::Relaxed);
+        }
+
+        // Let other tasks run on single-threaded runtimes
+        if iteration.is_multiple_of(64) {
+            tokio::task::yield_now().await;
+        }
+    }
+    Ok(())
+}
+
+/// Update the expected state after an acknowledged command, returning true
+/// if the response was a read checked against the expected state
+fn apply_expected(
+    expected: &mut HashMap<String, Value>,
+    command: Command,
+    response: &Response,
+) -> Result<bool, String> {
+    match (command, response) {
+        (Command::Set { key, value }, Response::Ok(None)) => {
+            expected.insert(key, value);
+        }
+        (Command::Delete { key }, Response::Ok(None)) => {
+            if expected.remove(&key).is_none() {
+                return Err("deleted a key that should not exist".to_string());
+            }
+        }
+        (Command::Delete { key }, Response::Error(_)) => {
+            if expected.contains_key(&key) {
+                return Err("acknowledged key is missing".to_string());
+            }
+        }
+        (Command::QAppend { key, value, .. }, Response::Ok(Some(len))) => {
+            let arr = expected.entry(key).or_insert_with(|| json!([]));
+            if let Value::Array(arr) = arr {
+                arr.push(value);
+                if len.as_u64() != Some(arr.len() as u64) {
+                    return Err(format!("expected array length {}", arr.len()));
+                }
+            }
+        }
+        (Command::QPop { key, .. }, Response::Ok(popped)) => {
+            if let Some(Value::Array(arr)) = expected.get_mut(&key) {
+                if arr.pop().as_ref() != popped.as_ref() {
+                    return Err("popped an unexpected element".to_string());
+                }
+            }
+        }
+        (Command::Get { key }, Response::Ok(value)) => {
+            if value.as_ref() != expected.get(&key) {
+                return Err(format!("expected {:?}", expected.get(&key)));
+            }
+            return Ok(true);
+        }
+        (_, response) => return Err(format!("unexpected response {}", response)),
+    }
+    Ok(false)
+}
+
+/// Check that the keyspace stays within the bounds implied by the workload
+async fn check_memory_bound(database: &Database, max_keys: u64) -> Result<(), String> {
+    let usage = match database.execute_command(Command::Usage).await {
+        Response::Ok(Some(usage)) => usage,
+        other => return Err(format!("USAGE failed: {}", other)),
+    };
+    let keys = usage["keys"].as_u64().unwrap_or(0);
+    let bytes = usage["bytes"].as_u64().unwrap_or(0);
+    if keys > max_keys {
+        return Err(format!("{} keys, at most {} expected", keys, max_keys));
+    }
+    if bytes > max_keys * MAX_DOCUMENT_BYTES {
+        return Err(format!(
+            "{} bytes stored, at most {} expected",
+            bytes,
+            max_keys * MAX_DOCUMENT_BYTES
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_short_soak_run() {
+        let config = SoakConfig {
+            duration: Some(Duration::from_millis(300)),
+            workers: 4,
+            keys_per_worker: 50,
+            max_array_len: 8,
+            report_interval: Duration::from_millis(100),
+        };
+        let report = run(Arc::new(Database::new()), config).await;
+        assert!(report.violations.is_empty(), "{:?}", report.violations);
+        assert!(report.operations > 0);
+        assert!(report.verifications > 0);
+    }
+}
@@ -0,0 +1,130 @@
+use log::{error, info};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::clock::{self, Clock};
+use crate::Database;
+
+/// Tracks writes in progress so that a stalled write pipeline can be detected
+#[derive(Debug, Clone)]
+pub struct WriteStallMonitor {
+    /// Start time of every write in progress, by ticket id
+    in_flight: Arc<Mutex<BTreeMap<u64, Instant>>>,
+    next_id: Arc<AtomicU64>,
+    clock: Arc<dyn Clock>,
+}
+
+/// Marks a write as in progress until dropped
+#[derive(Debug)]
+pub struct WriteTicket {
+    monitor: WriteStallMonitor,
+    id: u64,
+}
+
+impl WriteStallMonitor {
+    /// Create a monitor with no writes in progress
+    pub fn new() -> Self {
+        Self::with_clock(clock::system_clock())
+    }
+
+    /// Create a monitor measuring time with a custom clock
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            in_flight: Arc::new(Mutex::new(BTreeMap::new())),
+            next_id: Arc::new(AtomicU64::new(0)),
+            clock,
+        }
+    }
+
+    /// Register a write; it counts as in progress until the ticket is dropped
+    pub fn begin(&self) -> WriteTicket {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let now = self.clock.now();
+        self.in_flight.lock().unwrap().insert(id, now);
+        WriteTicket {
+            monitor: self.clone(),
+            id,
+        }
+    }
+
+    /// Returns how long the oldest write in progress has been running
+    pub fn oldest_write_age(&self) -> Option<Duration> {
+        // Ticket ids grow with time, so the first entry is the oldest
+        let in_flight = self.in_flight.lock().unwrap();
+        let now = self.clock.now();
+        in_flight
+            .values()
+            .next()
+            .map(|started| now.saturating_duration_since(*started))
+    }
+
+    /// Returns true if a write has been in progress for longer than `threshold`
+    pub fn is_stalled(&self, threshold: Duration) -> bool {
+        self.oldest_write_age().is_some_and(|age| age > threshold)
+    }
+}
+
+impl Default for WriteStallMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for WriteTicket {
+    fn drop(&mut self) {
+        self.monitor.in_flight.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Interval between two stall checks for a given threshold
+pub(crate) fn check_interval(threshold: Duration) -> Duration {
+    (threshold / 4).max(Duration::from_millis(10))
+}
+
+/// Fence writes on a standalone node while its write pipeline is stalled.
+///
+/// Without a cluster to fail over to, rejecting writes quickly with a
+/// retriable error is better than letting every client time out.
+pub fn spawn_fencing_watchdog(database: Arc<Database>, threshold: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(check_interval(threshold));
+        loop {
+            ticker.tick().await;
+            let stalled = database.write_monitor().is_stalled(threshold);
+            if stalled != database.is_write_fenced() {
+                if stalled {
+                    error!(
+                        "Write pipeline stalled for more than {:?}, fencing writes",
+                        threshold
+                    );
+                } else {
+                    info!("Write pipeline recovered, accepting writes again");
+                }
+                database.set_write_fenced(stalled);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_stall_detection() {
+        let clock = Arc::new(clock::ManualClock::new());
+        let monitor = WriteStallMonitor::with_clock(clock.clone());
+        assert_eq!(monitor.oldest_write_age(), None);
+
+        let ticket = monitor.begin();
+        clock.advance(Duration::from_millis(20));
+        let _recent = monitor.begin();
+        assert!(monitor.is_stalled(Duration::from_millis(10)));
+
+        drop(ticket);
+        assert!(!monitor.is_stalled(Duration::from_millis(10)));
+    }
+}
@@ -0,0 +1,692 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use super::layout::DataDir;
+use crate::changes::ChangeLogState;
+use crate::database::{Database, KeyMeta};
+
+/// When appended records are flushed to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// After every record: no acknowledged write is lost
+    Always,
+    /// Once per second, in the background: up to a second of writes may be lost
+    EverySec,
+    /// When the operating system decides
+    No,
+}
+
+impl std::str::FromStr for FsyncPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(FsyncPolicy::Always),
+            "everysec" => Ok(FsyncPolicy::EverySec),
+            "no" => Ok(FsyncPolicy::No),
+            other => Err(format!("Unknown fsync policy '{}'", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for FsyncPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FsyncPolicy::Always => write!(f, "always"),
+            FsyncPolicy::EverySec => write!(f, "everysec"),
+            FsyncPolicy::No => write!(f, "no"),
+        }
+    }
+}
+
+/// A write to the keyspace, as recorded in the append-only file.
+///
+/// Records hold the resulting state of a key rather than the command that
+/// produced it, so replaying them does not run write transformations or
+/// triggers again and yields the same values.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum AofRecord<'a> {
+    /// A key was set to a JSON document
+    Set {
+        ns: Cow<'a, str>,
+        key: Cow<'a, str>,
+        value: Cow<'a, Value>,
+        /// Metadata of the key after the write; records written before it
+        /// was logged get new metadata on replay
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        meta: Option<KeyMeta>,
+    },
+    /// A chunk of documents was written by BULKLOAD; like SETs, the writes
+    /// remove the expiry of their keys
+    SetMany {
+        ns: Cow<'a, str>,
+        documents: Vec<BulkDocument<'a>>,
+    },
+    /// A key was set to a binary value
+    SetBytes {
+        ns: Cow<'a, str>,
+        key: Cow<'a, str>,
+        value: Cow<'a, [u8]>,
+    },
+    /// A key was deleted, expired or evicted
+    Delete { ns: Cow<'a, str>, key: Cow<'a, str> },
+    /// Every key of a namespace, or every key starting with a prefix, was
+    /// deleted by FLUSH
+    Flush {
+        ns: Cow<'a, str>,
+        prefix: Option<Cow<'a, str>>,
+    },
+    /// A key expires at a time, in milliseconds since the UNIX epoch
+    Expire {
+        ns: Cow<'a, str>,
+        key: Cow<'a, str>,
+        at: u64,
+    },
+    /// The expiry of a key was removed
+    Persist { ns: Cow<'a, str>, key: Cow<'a, str> },
+    /// A consumer group acknowledged the changes up to an offset
+    GroupAck {
+        ns: Cow<'a, str>,
+        group: Cow<'a, str>,
+        offset: u64,
+    },
+    /// Change log of a namespace, restored from a snapshot
+    ChangeLog {
+        ns: Cow<'a, str>,
+        state: Cow<'a, ChangeLogState>,
+    },
+}
+
+/// A document written by a `SetMany` record
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BulkDocument<'a> {
+    pub key: Cow<'a, str>,
+    pub value: Cow<'a, Value>,
+    pub meta: KeyMeta,
+}
+
+/// Complete records read from an AOF segment, shipped to standby nodes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AofChunk {
+    /// Sequence of the segment
+    pub sequence: u64,
+    /// Byte offset of the records in the segment
+    pub offset: u64,
+    /// Records, one JSON line each
+    pub data: String,
+    /// The segment is no longer appended to and `data` reaches its end
+    pub end_of_segment: bool,
+}
+
+/// Segment of the AOF that records are appended to
+#[derive(Debug)]
+struct Segment {
+    sequence: u64,
+    path: PathBuf,
+    writer: BufWriter<File>,
+    /// Number of records appended to the AOF, across segments
+    appended: u64,
+}
+
+impl Segment {
+    fn open(dir: &DataDir, sequence: u64, appended: u64) -> Result<Self, String> {
+        let path = dir.aof_segment_path(sequence);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to open AOF {}: {}", path.display(), e))?;
+        Ok(Self {
+            sequence,
+            path,
+            writer: BufWriter::new(file),
+            appended,
+        })
+    }
+
+    fn sync(&mut self) -> Result<(), String> {
+        self.writer
+            .flush()
+            .and_then(|_| self.writer.get_ref().sync_data())
+            .map_err(|e| format!("Failed to sync {}: {}", self.path.display(), e))
+    }
+}
+
+/// Progress of the fsyncs of the AOF
+#[derive(Debug, Default)]
+struct SyncState {
+    /// Number of appended records known to be on disk
+    synced: u64,
+    /// Whether a writer is running an fsync on behalf of the others
+    syncing: bool,
+}
+
+/// Appends the writes of every namespace to the current AOF segment of a
+/// data directory.
+///
+/// Appending only buffers a record: with `FsyncPolicy::Always`, writers wait
+/// for durability with `wait_durable` once they released their locks, and
+/// concurrent writers share fsyncs (group commit): one syncs everything
+/// appended so far while the others wait for an fsync that covers their
+/// records.
+///
+/// Once a write or an fsync fails, records may be missing from the segment,
+/// so every later append fails too: no write is acknowledged until the
+/// server restarts and replays the AOF.
+#[derive(Debug)]
+pub struct AppendOnlyFile {
+    dir: DataDir,
+    segment: Mutex<Segment>,
+    fsync: FsyncPolicy,
+    /// First write or fsync error, after which appends are refused
+    failed: Mutex<Option<String>>,
+    sync_state: Mutex<SyncState>,
+    synced: Condvar,
+    /// Number of fsyncs run, for observing group commit
+    syncs: AtomicU64,
+}
+
+impl AppendOnlyFile {
+    /// Open the last segment of a data directory for appending, creating the
+    /// first one if needed.
+    ///
+    /// Segments older than the newest snapshot are not replayed on top of it,
+    /// so appending starts at the snapshot's segment when there is no newer one.
+    pub fn open(dir: &DataDir, fsync: FsyncPolicy) -> Result<Self, String> {
+        let last_segment = dir
+            .list_aof_segments()?
+            .pop()
+            .map_or(1, |(sequence, _)| sequence);
+        let last_snapshot = dir.list_snapshots()?.pop().map_or(1, |(index, _)| index);
+        let segment = Segment::open(dir, last_segment.max(last_snapshot), 0)?;
+        Ok(Self {
+            dir: dir.clone(),
+            segment: Mutex::new(segment),
+            fsync,
+            failed: Mutex::new(None),
+            sync_state: Mutex::new(SyncState::default()),
+            synced: Condvar::new(),
+            syncs: AtomicU64::new(0),
+        })
+    }
+
+    /// Returns the fsync policy
+    pub fn fsync_policy(&self) -> FsyncPolicy {
+        self.fsync
+    }
+
+    /// Returns the data directory holding the segments
+    pub fn dir(&self) -> &DataDir {
+        &self.dir
+    }
+
+    /// Returns the number of fsyncs run so far
+    pub fn syncs(&self) -> u64 {
+        self.syncs.load(Ordering::Relaxed)
+    }
+
+    /// Append a record, returning the number of records appended so far,
+    /// this one included; the record may not be on disk yet
+    pub fn append(&self, record: &AofRecord) -> Result<u64, String> {
+        let mut line = serde_json::to_vec(record).expect("AOF records serialize");
+        line.push(b'\n');
+        let mut segment = self.segment.lock().unwrap();
+        self.check_healthy()?;
+        let written = segment
+            .writer
+            .write_all(&line)
+            .map_err(|e| format!("Failed to append to {}: {}", segment.path.display(), e));
+        self.record_failure(written)?;
+        segment.appended += 1;
+        Ok(segment.appended)
+    }
+
+    /// Returns the number of records appended so far
+    pub fn appended(&self) -> u64 {
+        self.segment.lock().unwrap().appended
+    }
+
+    /// With `FsyncPolicy::Always`, waits until the first `appended` records
+    /// are on disk; the fsync runs on the blocking thread pool, so callers
+    /// must not hold locks other writers need
+    pub async fn wait_durable(self: &Arc<Self>, appended: u64) -> Result<(), String> {
+        if self.fsync != FsyncPolicy::Always || self.sync_state.lock().unwrap().synced >= appended {
+            return Ok(());
+        }
+        let aof = Arc::clone(self);
+        tokio::task::spawn_blocking(move || aof.sync_through(appended))
+            .await
+            .map_err(|e| format!("Failed to sync the AOF: {}", e))?
+    }
+
+    /// Fails once a write or an fsync failed
+    fn check_healthy(&self) -> Result<(), String> {
+        match self.failed.lock().unwrap().as_ref() {
+            Some(e) => Err(format!("The AOF refuses writes after an error: {}", e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Remembers the first failure of a write or an fsync
+    fn record_failure<T>(&self, result: Result<T, String>) -> Result<T, String> {
+        if let Err(e) = &result {
+            self.failed.lock().unwrap().get_or_insert_with(|| e.clone());
+        }
+        result
+    }
+
+    /// Waits until the first `appended` records are on disk, running the
+    /// fsync unless another writer already is; blocks the calling thread
+    pub fn sync_through(&self, appended: u64) -> Result<(), String> {
+        let mut state = self.sync_state.lock().unwrap();
+        loop {
+            if state.synced >= appended {
+                return Ok(());
+            }
+            self.check_healthy()?;
+            if !state.syncing {
+                break;
+            }
+            state = self.synced.wait(state).unwrap();
+        }
+        state.syncing = true;
+        drop(state);
+
+        let synced = self.sync();
+        let mut state = self.sync_state.lock().unwrap();
+        state.syncing = false;
+        if let Ok(through) = synced {
+            state.synced = state.synced.max(through);
+        }
+        self.synced.notify_all();
+        synced.map(|_| ())
+    }
+
+    /// Flush the appended records to disk, returning how many are on disk
+    pub fn sync(&self) -> Result<u64, String> {
+        self.check_healthy()?;
+        let synced = self.sync_unchecked();
+        self.record_failure(synced)
+    }
+
+    fn sync_unchecked(&self) -> Result<u64, String> {
+        // The fsync runs without blocking appends
+        let (file, appended) = {
+            let mut segment = self.segment.lock().unwrap();
+            segment
+                .writer
+                .flush()
+                .and_then(|_| segment.writer.get_ref().try_clone())
+                .map(|file| (file, segment.appended))
+                .map_err(|e| format!("Failed to flush {}: {}", segment.path.display(), e))?
+        };
+        file.sync_data()
+            .map_err(|e| format!("Failed to sync the AOF: {}", e))?;
+        self.syncs.fetch_add(1, Ordering::Relaxed);
+        Ok(appended)
+    }
+
+    /// Flush the current segment and append to a new one from now on,
+    /// returning the sequence of the new segment
+    pub fn rotate(&self) -> Result<u64, String> {
+        let mut segment = self.segment.lock().unwrap();
+        self.check_healthy()?;
+        let synced = segment.sync();
+        self.record_failure(synced)?;
+        *segment = Segment::open(&self.dir, segment.sequence + 1, segment.appended)?;
+        Ok(segment.sequence)
+    }
+
+    /// Read the complete records of a segment from a byte offset, stopping
+    /// once at least `max_bytes` have been read; records still buffered in
+    /// the current segment are flushed first
+    pub fn read_chunk(
+        &self,
+        sequence: u64,
+        offset: u64,
+        max_bytes: u64,
+    ) -> Result<AofChunk, String> {
+        let current = {
+            let mut segment = self.segment.lock().unwrap();
+            if segment.sequence == sequence {
+                segment
+                    .writer
+                    .flush()
+                    .map_err(|e| format!("Failed to flush {}: {}", segment.path.display(), e))?;
+            }
+            segment.sequence
+        };
+        if sequence > current {
+            return Err(format!("AOF segment {} does not exist yet", sequence));
+        }
+        let path = self.dir.aof_segment_path(sequence);
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(format!("AOF segment {} was removed", sequence))
+            }
+            Err(e) => return Err(format!("Failed to read AOF {}: {}", path.display(), e)),
+        };
+        file.seek(SeekFrom::Start(offset))
+            .map_err(|e| format!("Failed to read AOF {}: {}", path.display(), e))?;
+
+        let mut reader = BufReader::new(file);
+        let mut data = Vec::new();
+        let mut end = false;
+        while (data.len() as u64) < max_bytes {
+            let start = data.len();
+            let read = reader
+                .read_until(b'\n', &mut data)
+                .map_err(|e| format!("Failed to read AOF {}: {}", path.display(), e))?;
+            if read == 0 || !data.ends_with(b"\n") {
+                // A record still being appended is read next time
+                data.truncate(start);
+                end = read == 0;
+                break;
+            }
+        }
+        if !end {
+            end = reader.fill_buf().is_ok_and(|rest| rest.is_empty());
+        }
+        let data = String::from_utf8(data)
+            .map_err(|e| format!("Corrupted AOF {}: {}", path.display(), e))?;
+        Ok(AofChunk {
+            sequence,
+            offset,
+            data,
+            end_of_segment: end && sequence < current,
+        })
+    }
+
+    /// Replay the AOF segments of a data directory from sequence `from` into
+    /// a database, returning the number of replayed records.
+    ///
+    /// A record cut short at the end of the last segment (a crash in the
+    /// middle of an append) is discarded and truncated away; any other
+    /// unreadable record fails the replay.
+    pub fn replay(dir: &DataDir, database: &Database, from: u64) -> Result<usize, String> {
+        Self::replay_segments(dir, database, from, true).map(|(_, replayed)| replayed)
+    }
+
+    /// Replay the AOF segments like `replay` without modifying them, as
+    /// backups are verified: a record cut short at the end of the last
+    /// segment may still be being appended and is left out, and a missing
+    /// segment fails the replay. Returns the number of segments and of
+    /// replayed records.
+    pub fn replay_readonly(
+        dir: &DataDir,
+        database: &Database,
+        from: u64,
+    ) -> Result<(usize, usize), String> {
+        Self::replay_segments(dir, database, from, false)
+    }
+
+    fn replay_segments(
+        dir: &DataDir,
+        database: &Database,
+        from: u64,
+        truncate: bool,
+    ) -> Result<(usize, usize), String> {
+        let mut segments = dir.list_aof_segments()?;
+        segments.retain(|(sequence, _)| *sequence >= from);
+        if !truncate {
+            // Segments are numbered without gaps from the first one needed
+            for (expected, (sequence, _)) in (from.max(1)..).zip(&segments) {
+                if *sequence != expected {
+                    return Err(format!("Missing AOF segment {}", expected));
+                }
+            }
+        }
+        let mut replayed = 0;
+        for (position, (_, path)) in segments.iter().enumerate() {
+            let is_last = position + 1 == segments.len();
+            let content = fs::read(path)
+                .map_err(|e| format!("Failed to read AOF {}: {}", path.display(), e))?;
+            let mut offset = 0;
+            for line in content.split_inclusive(|b| *b == b'\n') {
+                let record = match serde_json::from_slice::<AofRecord>(line) {
+                    Ok(record) => record,
+                    Err(_) if is_last && !line.ends_with(b"\n") && !truncate => break,
+                    Err(_) if is_last && !line.ends_with(b"\n") => {
+                        warn!(
+                            "Discarding a truncated record at the end of AOF {}",
+                            path.display()
+                        );
+                        let file = OpenOptions::new()
+                            .write(true)
+                            .open(path)
+                            .and_then(|file| file.set_len(offset as u64));
+                        file.map_err(|e| format!("Failed to truncate {}: {}", path.display(), e))?;
+                        break;
+                    }
+                    Err(e) => {
+                        return Err(format!(
+                            "Corrupted AOF {} at byte {}: {}",
+                            path.display(),
+                            offset,
+                            e
+                        ))
+                    }
+                };
+                database.apply_aof_record(record)?;
+                offset += line.len();
+                replayed += 1;
+            }
+        }
+        info!(
+            "Replayed {} AOF records from {} segments",
+            replayed,
+            segments.len()
+        );
+        Ok((segments.len(), replayed))
+    }
+
+    /// Delete the segments older than `sequence`, which are covered by a snapshot
+    pub fn remove_segments_before(dir: &DataDir, sequence: u64) -> Result<usize, String> {
+        let mut removed = 0;
+        for (existing, path) in dir.list_aof_segments()? {
+            if existing >= sequence {
+                break;
+            }
+            fs::remove_file(&path)
+                .map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+}
+
+/// Flush an AOF to disk every `interval`, for `FsyncPolicy::EverySec`
+pub fn spawn_aof_sync(aof: Arc<AppendOnlyFile>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let aof = Arc::clone(&aof);
+            let synced = tokio::task::spawn_blocking(move || aof.sync()).await;
+            if let Ok(Err(e)) = synced {
+                log::error!("{}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Command, Response};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_replay_after_restart() {
+        let root = std::env::temp_dir().join(format!("jsonvault-aof-{}", uuid::Uuid::new_v4()));
+        let dir = DataDir::open(&root, Some("node-1")).unwrap();
+
+        let database = Database::new();
+        let aof = Arc::new(AppendOnlyFile::open(&dir, FsyncPolicy::Always).unwrap());
+        database.set_aof(Some(Arc::clone(&aof)));
+        let app = database.namespace("app").unwrap();
+        let commands = [
+            Command::Set {
+                key: "a".to_string(),
+                value: json!({"n": 1}),
+            },
+            Command::QAppend {
+                key: "list".to_string(),
+                path: "$".to_string(),
+                value: json!("x"),
+            },
+            Command::Set {
+                key: "gone".to_string(),
+                value: json!(true),
+            },
+            Command::Delete {
+                key: "gone".to_string(),
+            },
+            Command::Set {
+                key: "tmp:1".to_string(),
+                value: json!(1),
+            },
+            Command::Set {
+                key: "tmp:2".to_string(),
+                value: json!(2),
+            },
+            Command::Flush {
+                prefix: Some("tmp:".to_string()),
+            },
+            Command::BulkLoad {
+                data: concat!(
+                    r#"{"key":"c","value":[0]}"#,
+                    "\n",
+                    r#"{"key":"a","value":{"n":1}}"#,
+                    "\n",
+                    r#"{"key":"c","value":[1]}"#,
+                )
+                .to_string(),
+            },
+            Command::Expire {
+                key: "a".to_string(),
+                seconds: 3600,
+            },
+        ];
+        for command in commands {
+            assert!(!matches!(
+                app.execute_command(command).await,
+                Response::Error(_)
+            ));
+        }
+        database
+            .execute_command(Command::SetBytes {
+                key: "b".to_string(),
+                value: vec![0, 1, 2],
+            })
+            .await;
+        drop(database);
+
+        // A crash in the middle of an append leaves a partial record
+        let segment = dir.aof_segment_path(1);
+        let mut file = OpenOptions::new().append(true).open(&segment).unwrap();
+        file.write_all(br#"{"op":"set","ns":"#).unwrap();
+        drop(file);
+
+        let restored = Database::new();
+        // FLUSH and a BULKLOAD chunk are single records
+        assert_eq!(AppendOnlyFile::replay(&dir, &restored, 1).unwrap(), 10);
+        let app = restored.namespace("app").unwrap();
+        assert_eq!(app.value("a"), Some(json!({"n": 1})));
+        assert_eq!(app.value("list"), Some(json!(["x"])));
+        assert_eq!(app.value("gone"), None);
+        assert_eq!(app.value("tmp:1"), None);
+        assert_eq!(app.value("c"), Some(json!([1])));
+        assert_eq!(app.matching_keys("*").len(), 3);
+        assert!(matches!(
+            app.execute_command(Command::Ttl { key: "a".to_string() }).await,
+            Response::Ok(Some(ttl)) if ttl.as_u64() > Some(3_500_000)
+        ));
+        assert!(matches!(
+            restored.execute_command(Command::GetBytes { key: "b".to_string() }).await,
+            Response::Bytes(value) if value == [0, 1, 2]
+        ));
+        assert!(fs::read(&segment).unwrap().ends_with(b"\n"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_aof_failure_fails_writes() {
+        let root = std::env::temp_dir().join(format!("jsonvault-aof-{}", uuid::Uuid::new_v4()));
+        let dir = DataDir::open(&root, Some("node-1")).unwrap();
+        // Every write to the segment fails as on a full disk
+        std::os::unix::fs::symlink("/dev/full", dir.aof_segment_path(1)).unwrap();
+
+        let database = Database::new();
+        let aof = Arc::new(AppendOnlyFile::open(&dir, FsyncPolicy::Always).unwrap());
+        database.set_aof(Some(Arc::clone(&aof)));
+        let set = |key: &str| Command::Set {
+            key: key.to_string(),
+            value: json!(1),
+        };
+        // The record is buffered, but its fsync fails
+        let response = database.execute_command(set("a")).await;
+        assert!(
+            matches!(response, Response::Error(e) if e.code == crate::protocol::ErrorCode::Internal)
+        );
+        // The failed record may still reach the disk later: later writes are
+        // neither applied nor acknowledged
+        let response = database.execute_command(set("b")).await;
+        assert!(matches!(response, Response::Error(e) if e.message.starts_with("The AOF refuses")));
+        assert_eq!(database.value("b"), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_group_commit() {
+        let root = std::env::temp_dir().join(format!("jsonvault-aof-{}", uuid::Uuid::new_v4()));
+        let dir = DataDir::open(&root, Some("node-1")).unwrap();
+        let aof = Arc::new(AppendOnlyFile::open(&dir, FsyncPolicy::Always).unwrap());
+        let barrier = Arc::new(std::sync::Barrier::new(8));
+
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let aof = Arc::clone(&aof);
+                let barrier = Arc::clone(&barrier);
+                std::thread::spawn(move || {
+                    for n in 0..25 {
+                        let key = format!("{}-{}", writer, n);
+                        let record = AofRecord::Delete {
+                            ns: "default".into(),
+                            key: key.into(),
+                        };
+                        barrier.wait();
+                        let appended = aof.append(&record).unwrap();
+                        // Every writer appended before any waits
+                        barrier.wait();
+                        aof.sync_through(appended).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        // The first writer of each round synced the records of all eight
+        assert_eq!(aof.syncs(), 25);
+        let content = fs::read(dir.aof_segment_path(1)).unwrap();
+        assert_eq!(content.split(|b| *b == b'\n').count(), 201);
+        assert_eq!(aof.sync_state.lock().unwrap().synced, 200);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,384 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::clock::{Clock, SystemClock};
+use crate::ids::{IdGenerator, RandomIdGenerator};
+
+/// Current on-disk format version
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Oldest format version able to read directories written by this build
+pub const MIN_READER_FORMAT_VERSION: u32 = 1;
+
+/// Name of the manifest file at the root of the data directory
+const MANIFEST_FILE: &str = "MANIFEST.json";
+/// Directory holding state machine snapshots
+const SNAPSHOTS_DIR: &str = "snapshots";
+/// Directory holding append-only file segments
+const AOF_DIR: &str = "aof";
+/// Directory holding documents spilled out of memory
+const SPILL_DIR: &str = "spill";
+/// File holding the Raft term and vote of the node
+const RAFT_STATE_FILE: &str = "raft-state.json";
+
+/// A migration upgrading a data directory by one format version
+type Migration = fn(&Path) -> Result<(), String>;
+
+/// Migrations indexed by the version they upgrade from
+const MIGRATIONS: &[Migration] = &[migrate_v0_to_v1];
+
+/// Describes the contents of a data directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Format version of the directory contents
+    pub format_version: u32,
+    /// Oldest format version able to read the directory
+    #[serde(default)]
+    pub min_reader_version: u32,
+    /// Version of the software that last wrote the manifest
+    #[serde(default)]
+    pub written_by: String,
+    /// Identity of the node owning the directory
+    pub node_id: String,
+    /// Creation time (RFC 3339)
+    pub created_at: String,
+}
+
+/// Owns the data directory of a node: manifest, node identity, snapshots and AOF segments
+#[derive(Debug, Clone)]
+pub struct DataDir {
+    root: PathBuf,
+    manifest: Manifest,
+}
+
+impl DataDir {
+    /// Open a data directory, creating it or migrating it to the current format.
+    ///
+    /// `node_id` is recorded when the directory is created; when opening an
+    /// existing directory it must match the stored identity. Passing None
+    /// adopts the stored identity (or generates one for a new directory).
+    pub fn open(root: impl AsRef<Path>, node_id: Option<&str>) -> Result<Self, String> {
+        Self::open_with(root, node_id, &SystemClock, &RandomIdGenerator)
+    }
+
+    /// Open a data directory, taking creation times from `clock` and
+    /// generated node identities from `ids`
+    pub fn open_with(
+        root: impl AsRef<Path>,
+        node_id: Option<&str>,
+        clock: &dyn Clock,
+        ids: &dyn IdGenerator,
+    ) -> Result<Self, String> {
+        let root = root.as_ref().to_path_buf();
+        let node_id_or_new = || {
+            node_id
+                .map(str::to_string)
+                .unwrap_or_else(|| ids.next_id().to_string())
+        };
+        let created_at = || {
+            chrono::DateTime::from_timestamp_millis(clock.unix_millis() as i64)
+                .unwrap_or_default()
+                .to_rfc3339()
+        };
+        fs::create_dir_all(&root)
+            .map_err(|e| format!("Failed to create data directory {}: {}", root.display(), e))?;
+
+        let manifest = match Self::read_manifest(&root)? {
+            Some(manifest) => manifest,
+            None if Self::is_empty_dir(&root)? => {
+                let manifest = Manifest {
+                    format_version: FORMAT_VERSION,
+                    min_reader_version: MIN_READER_FORMAT_VERSION,
+                    written_by: env!("CARGO_PKG_VERSION").to_string(),
+                    node_id: node_id_or_new(),
+                    created_at: created_at(),
+                };
+                Self::create_dirs(&root)?;
+                Self::write_manifest(&root, &manifest)?;
+                info!("Initialized data directory {}", root.display());
+                manifest
+            }
+            // Files written before the manifest existed
+            None => Manifest {
+                format_version: 0,
+                min_reader_version: 0,
+                written_by: String::new(),
+                node_id: node_id_or_new(),
+                created_at: created_at(),
+            },
+        };
+
+        if let Some(node_id) = node_id {
+            if manifest.node_id != node_id {
+                return Err(format!(
+                    "Data directory {} belongs to node '{}', not '{}'",
+                    root.display(),
+                    manifest.node_id,
+                    node_id
+                ));
+            }
+        }
+
+        let manifest = Self::migrate(&root, manifest)?;
+        Ok(Self { root, manifest })
+    }
+
+    /// Open an existing data directory as it is, without creating or
+    /// migrating anything, to inspect it
+    pub fn open_existing(root: impl AsRef<Path>) -> Result<Self, String> {
+        let root = root.as_ref().to_path_buf();
+        let manifest = Self::read_manifest(&root)?
+            .ok_or_else(|| format!("{} is not a data directory", root.display()))?;
+        Ok(Self { root, manifest })
+    }
+
+    /// Returns the root path of the data directory
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the directory manifest
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Returns the identity of the node owning the directory
+    pub fn node_id(&self) -> &str {
+        &self.manifest.node_id
+    }
+
+    /// Returns the directory holding snapshots
+    pub fn snapshots_dir(&self) -> PathBuf {
+        self.root.join(SNAPSHOTS_DIR)
+    }
+
+    /// Returns the directory holding AOF segments
+    pub fn aof_dir(&self) -> PathBuf {
+        self.root.join(AOF_DIR)
+    }
+
+    /// Returns the directory holding spilled documents, cleared on startup
+    pub fn spill_dir(&self) -> PathBuf {
+        self.root.join(SPILL_DIR)
+    }
+
+    /// Returns the path of the Raft term and vote of the node
+    pub fn raft_state_path(&self) -> PathBuf {
+        self.root.join(RAFT_STATE_FILE)
+    }
+
+    /// Returns the path of the snapshot taken at a log index
+    pub fn snapshot_path(&self, index: u64) -> PathBuf {
+        self.snapshots_dir()
+            .join(format!("snapshot-{:020}.snap", index))
+    }
+
+    /// Returns the path of an AOF segment
+    pub fn aof_segment_path(&self, sequence: u64) -> PathBuf {
+        self.aof_dir().join(format!("segment-{:020}.aof", sequence))
+    }
+
+    /// Lists snapshots as (log index, path), oldest first
+    pub fn list_snapshots(&self) -> Result<Vec<(u64, PathBuf)>, String> {
+        Self::list_numbered(&self.snapshots_dir(), "snapshot-", ".snap")
+    }
+
+    /// Lists AOF segments as (sequence, path), oldest first
+    pub fn list_aof_segments(&self) -> Result<Vec<(u64, PathBuf)>, String> {
+        Self::list_numbered(&self.aof_dir(), "segment-", ".aof")
+    }
+
+    /// Apply pending migrations, persisting the manifest after each step
+    fn migrate(root: &Path, mut manifest: Manifest) -> Result<Manifest, String> {
+        if manifest.format_version > FORMAT_VERSION {
+            // Newer formats may stay readable by older builds; never rewrite them
+            if manifest.min_reader_version > FORMAT_VERSION {
+                return Err(format!(
+                    "Data directory {} was written by jsonvault {} (format version {}, readable from version {}); \
+                     this build only supports format version {}, refusing to load it",
+                    root.display(),
+                    if manifest.written_by.is_empty() {
+                        "(unknown)"
+                    } else {
+                        &manifest.written_by
+                    },
+                    manifest.format_version,
+                    manifest.min_reader_version,
+                    FORMAT_VERSION
+                ));
+            }
+            info!(
+                "Data directory {} uses newer format version {}, compatible with this build",
+                root.display(),
+                manifest.format_version
+            );
+            return Ok(manifest);
+        }
+        while manifest.format_version < FORMAT_VERSION {
+            let from = manifest.format_version;
+            MIGRATIONS[from as usize](root)?;
+            manifest.format_version = from + 1;
+            manifest.min_reader_version = MIN_READER_FORMAT_VERSION.min(manifest.format_version);
+            manifest.written_by = env!("CARGO_PKG_VERSION").to_string();
+            Self::write_manifest(root, &manifest)?;
+            info!(
+                "Migrated data directory {} from format version {} to {}",
+                root.display(),
+                from,
+                from + 1
+            );
+        }
+        Ok(manifest)
+    }
+
+    /// Read the manifest, if present
+    fn read_manifest(root: &Path) -> Result<Option<Manifest>, String> {
+        let path = root.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|e| format!("Invalid manifest {}: {}", path.display(), e))
+    }
+
+    /// Atomically replace the manifest
+    fn write_manifest(root: &Path, manifest: &Manifest) -> Result<(), String> {
+        let path = root.join(MANIFEST_FILE);
+        let tmp = root.join(format!("{}.tmp", MANIFEST_FILE));
+        let content = serde_json::to_string_pretty(manifest)
+            .map_err(|e| format!("Failed to serialize manifest: {}", e))?;
+        fs::write(&tmp, content)
+            .map_err(|e| format!("Failed to write {}: {}", tmp.display(), e))?;
+        fs::rename(&tmp, &path).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// Create the directories of the current format
+    fn create_dirs(root: &Path) -> Result<(), String> {
+        for dir in [SNAPSHOTS_DIR, AOF_DIR] {
+            let path = root.join(dir);
+            fs::create_dir_all(&path)
+                .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if a directory has no entries
+    fn is_empty_dir(path: &Path) -> Result<bool, String> {
+        fs::read_dir(path)
+            .map(|mut entries| entries.next().is_none())
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+    }
+
+    /// Lists files named `{prefix}{number}{suffix}`, sorted by number
+    fn list_numbered(
+        dir: &Path,
+        prefix: &str,
+        suffix: &str,
+    ) -> Result<Vec<(u64, PathBuf)>, String> {
+        let entries =
+            fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+        let mut files: Vec<(u64, PathBuf)> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let number = name
+                    .strip_prefix(prefix)?
+                    .strip_suffix(suffix)?
+                    .parse()
+                    .ok()?;
+                Some((number, entry.path()))
+            })
+            .collect();
+        files.sort_by_key(|(number, _)| *number);
+        Ok(files)
+    }
+}
+
+/// Version 0 directories predate the manifest and kept everything at the root
+fn migrate_v0_to_v1(root: &Path) -> Result<(), String> {
+    DataDir::create_dirs(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("jsonvault-layout-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_create_and_reopen() {
+        let root = temp_dir();
+        let dir = DataDir::open(&root, Some("node-1")).unwrap();
+        assert_eq!(dir.manifest().format_version, FORMAT_VERSION);
+        assert!(dir.snapshots_dir().is_dir());
+
+        fs::write(dir.snapshot_path(20), "{}").unwrap();
+        fs::write(dir.snapshot_path(3), "{}").unwrap();
+        let indexes: Vec<u64> = dir
+            .list_snapshots()
+            .unwrap()
+            .into_iter()
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(indexes, vec![3, 20]);
+
+        // The stored identity is adopted, and a different one is rejected
+        assert_eq!(DataDir::open(&root, None).unwrap().node_id(), "node-1");
+        assert!(DataDir::open(&root, Some("node-2")).is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_deterministic_identity() {
+        let root = temp_dir();
+        let clock = crate::clock::ManualClock::new();
+        let ids = crate::ids::SequentialIdGenerator::new(1);
+        let dir = DataDir::open_with(&root, None, &clock, &ids).unwrap();
+        assert_eq!(dir.node_id(), uuid::Uuid::from_u64_pair(1, 1).to_string());
+        let created_at = chrono::DateTime::parse_from_rfc3339(&dir.manifest().created_at).unwrap();
+        assert_eq!(created_at.timestamp_millis() as u64, clock.unix_millis());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_migrates_legacy_directory() {
+        let root = temp_dir();
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("legacy.dat"), "").unwrap();
+
+        let dir = DataDir::open(&root, Some("node-1")).unwrap();
+        assert_eq!(dir.manifest().format_version, FORMAT_VERSION);
+        assert!(dir.aof_dir().is_dir());
+        assert!(root.join(MANIFEST_FILE).is_file());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_refuses_incompatible_newer_format() {
+        let root = temp_dir();
+        DataDir::open(&root, Some("node-1")).unwrap();
+
+        let mut manifest = DataDir::read_manifest(&root).unwrap().unwrap();
+        manifest.format_version = FORMAT_VERSION + 1;
+        DataDir::write_manifest(&root, &manifest).unwrap();
+        // Newer, but still readable by this build
+        assert!(DataDir::open(&root, None).is_ok());
+
+        manifest.min_reader_version = FORMAT_VERSION + 1;
+        DataDir::write_manifest(&root, &manifest).unwrap();
+        let err = DataDir::open(&root, None).unwrap_err();
+        assert!(err.contains("refusing to load"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
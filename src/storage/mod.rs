@@ -0,0 +1,6 @@
+pub mod aof;
+pub mod backup;
+pub mod layout;
+pub mod snapshot;
+pub mod spill;
+pub mod standby;
@@ -0,0 +1,631 @@
+use bytes::Bytes;
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::aof::{AofRecord, AppendOnlyFile};
+use super::backup::{self, BackupCheck};
+use super::layout::DataDir;
+use crate::changes::ChangeLogState;
+use crate::database::{Database, KeyMeta};
+use crate::document::Document;
+
+/// Leading bytes of every snapshot file
+const MAGIC: &[u8] = b"JVSNAP";
+
+/// Version of the snapshot encoding, written after the magic bytes; version
+/// 1 snapshots have no change logs, version 2 snapshots no key metadata and
+/// version 3 snapshots no key count in their header, and all are still read
+const SNAPSHOT_VERSION: u16 = 4;
+
+/// zstd level of snapshot files
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Smallest encoded key: a tag, the lengths of its namespace, key and value,
+/// its expiration time and its metadata
+const MIN_ENTRY_SIZE: u64 = 1 + 3 * 8 + 8 + 3 * 8;
+
+/// Entry tags of the snapshot encoding
+const TAG_END: u8 = 0;
+const TAG_JSON: u8 = 1;
+const TAG_BINARY: u8 = 2;
+const TAG_CHANGE_LOG: u8 = 3;
+
+/// A key copied out of the keyspace for a snapshot
+pub(crate) struct DumpEntry {
+    pub namespace: Arc<str>,
+    pub key: String,
+    pub value: DumpValue,
+    /// Expiration time in milliseconds since the UNIX epoch
+    pub expires_at: Option<u64>,
+    /// Metadata of a JSON document
+    pub meta: Option<KeyMeta>,
+}
+
+/// Keyspace copied out for a snapshot
+pub(crate) struct Dump {
+    /// Index of the snapshot
+    pub index: u64,
+    pub entries: Vec<DumpEntry>,
+    /// Change log of each namespace that has one
+    pub change_logs: Vec<(Arc<str>, ChangeLogState)>,
+}
+
+/// Value of a key copied for a snapshot
+pub(crate) enum DumpValue {
+    Json(Document),
+    Binary(Bytes),
+}
+
+/// Outcome of a snapshot
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotInfo {
+    /// Index of the snapshot: the first AOF segment it doesn't include
+    pub index: u64,
+    /// Number of keys saved
+    pub keys: usize,
+    /// Size of the snapshot file
+    pub bytes: u64,
+    /// Time taken to save the snapshot
+    pub duration_ms: u64,
+}
+
+/// Saves point-in-time snapshots of every namespace into a data directory,
+/// keeping the last `retain` ones
+#[derive(Debug)]
+pub struct SnapshotStore {
+    dir: DataDir,
+    retain: usize,
+    /// Held while saving, so that snapshots are taken one at a time
+    saving: Mutex<()>,
+}
+
+impl SnapshotStore {
+    /// Creates a store keeping the last `retain` snapshots (at least one)
+    pub fn new(dir: DataDir, retain: usize) -> Self {
+        Self {
+            dir,
+            retain: retain.max(1),
+            saving: Mutex::new(()),
+        }
+    }
+
+    /// Save a snapshot of a database, then drop the snapshots beyond the
+    /// retention and the AOF segments none of the remaining ones need
+    pub fn save(&self, database: &Database) -> Result<SnapshotInfo, String> {
+        self.save_with(database, |_, _| {})
+    }
+
+    /// Save a snapshot like `save`, calling `on_copied` with the snapshot
+    /// index and key count once the keyspace has been copied, before the
+    /// snapshot is written
+    pub fn save_with(
+        &self,
+        database: &Database,
+        on_copied: impl FnOnce(u64, usize),
+    ) -> Result<SnapshotInfo, String> {
+        let _saving = self.saving.lock().unwrap();
+        let started = Instant::now();
+        let last_snapshot = self
+            .dir
+            .list_snapshots()?
+            .pop()
+            .map_or(0, |(index, _)| index);
+        let last_segment = self
+            .dir
+            .list_aof_segments()?
+            .pop()
+            .map_or(0, |(sequence, _)| sequence);
+        let dump = database.dump(last_snapshot.max(last_segment) + 1)?;
+        let (index, keys) = (dump.index, dump.entries.len());
+        on_copied(index, keys);
+
+        let path = self.dir.snapshot_path(index);
+        let bytes = write_snapshot(&path, &dump)
+            .map_err(|e| format!("Failed to write snapshot {}: {}", path.display(), e))?;
+        self.prune()?;
+        info!("Saved snapshot {} ({} keys, {} bytes)", index, keys, bytes);
+        Ok(SnapshotInfo {
+            index,
+            keys,
+            bytes,
+            duration_ms: started.elapsed().as_millis() as u64,
+        })
+    }
+
+    /// Check the snapshots and AOF segments of the data directory, without
+    /// saving or pruning them meanwhile
+    pub fn verify(&self) -> Result<BackupCheck, String> {
+        let _saving = self.saving.lock().unwrap();
+        backup::verify(&self.dir)
+    }
+
+    /// Drop the snapshots beyond the retention and the AOF segments older
+    /// than the oldest remaining snapshot
+    fn prune(&self) -> Result<(), String> {
+        let snapshots = self.dir.list_snapshots()?;
+        let excess = snapshots.len().saturating_sub(self.retain);
+        for (_, path) in &snapshots[..excess] {
+            fs::remove_file(path)
+                .map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+        }
+        if let Some((oldest, _)) = snapshots.get(excess) {
+            AppendOnlyFile::remove_segments_before(&self.dir, *oldest)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the newest snapshot of a data directory that passes its
+    /// checks (None when there is no snapshot), to load once the keyspace is
+    /// sized for it.
+    ///
+    /// An unreadable snapshot is skipped in favor of the previous one.
+    pub fn newest(dir: &DataDir) -> Result<Option<CheckedSnapshot>, String> {
+        let snapshots = dir.list_snapshots()?;
+        for (index, path) in snapshots.iter().rev() {
+            // Checked before loading: records are applied as they are
+            // decoded, and a snapshot failing halfway would leave them behind
+            match check_snapshot(path) {
+                Ok((header, body)) => {
+                    return Ok(Some(CheckedSnapshot {
+                        index: *index,
+                        path: path.clone(),
+                        // The header is outside the checksum: the count is
+                        // bounded by the keys the body can hold
+                        keys: header
+                            .keys
+                            .map(|keys| keys.min(body / MIN_ENTRY_SIZE) as usize),
+                    }));
+                }
+                Err(e) => warn!("Skipping snapshot {}: {}", path.display(), e),
+            }
+        }
+        if snapshots.is_empty() {
+            Ok(None)
+        } else {
+            Err(format!(
+                "No readable snapshot in {}",
+                dir.snapshots_dir().display()
+            ))
+        }
+    }
+
+    /// Load the newest readable snapshot of a data directory into a database,
+    /// returning its index (None when there is no snapshot).
+    ///
+    /// An unreadable snapshot is skipped in favor of the previous one; the
+    /// AOF is then replayed from the returned index.
+    pub fn load(dir: &DataDir, database: &Database) -> Result<Option<u64>, String> {
+        Self::newest(dir)?
+            .map(|snapshot| snapshot.load(database))
+            .transpose()
+    }
+}
+
+/// A snapshot that passed its checks, not loaded yet
+#[derive(Debug)]
+pub struct CheckedSnapshot {
+    index: u64,
+    path: PathBuf,
+    /// Number of keys recorded in the header (None before version 4)
+    keys: Option<usize>,
+}
+
+impl CheckedSnapshot {
+    /// Index of the snapshot
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    /// Number of keys of the snapshot, to size the keyspace before loading it
+    pub fn keys(&self) -> Option<usize> {
+        self.keys
+    }
+
+    /// Load the snapshot into a database, returning its index
+    pub fn load(self, database: &Database) -> Result<u64, String> {
+        let keys = read_snapshot(&self.path, |record| database.apply_aof_record(record))
+            .map_err(|e| format!("Failed to load snapshot {}: {}", self.path.display(), e))?;
+        info!("Loaded snapshot {} ({} keys)", self.index, keys);
+        Ok(self.index)
+    }
+}
+
+/// Writes a snapshot next to its final path and renames it into place once
+/// it is on disk, returning its size
+fn write_snapshot(path: &Path, dump: &Dump) -> std::io::Result<u64> {
+    let entries = &dump.entries;
+    let partial = path.with_extension("snap.partial");
+    let mut file = BufWriter::new(File::create(&partial)?);
+    file.write_all(MAGIC)?;
+    file.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+    // Uncompressed, so that the keyspace can be sized before loading
+    file.write_all(&(entries.len() as u64).to_le_bytes())?;
+    let mut out = zstd::stream::write::Encoder::new(file, COMPRESSION_LEVEL)?;
+    // Checked as the snapshot is read back
+    out.include_checksum(true)?;
+    for entry in entries {
+        let (tag, value) = match &entry.value {
+            DumpValue::Json(document) => (
+                TAG_JSON,
+                document
+                    .serialized()
+                    .map_err(|e| std::io::Error::other(e.message))?,
+            ),
+            DumpValue::Binary(value) => (TAG_BINARY, value.clone()),
+        };
+        out.write_all(&[tag])?;
+        write_field(&mut out, entry.namespace.as_bytes())?;
+        write_field(&mut out, entry.key.as_bytes())?;
+        write_field(&mut out, &value)?;
+        out.write_all(&entry.expires_at.unwrap_or(0).to_le_bytes())?;
+        // Version 0 when the key has no metadata
+        let meta = entry.meta.unwrap_or(KeyMeta {
+            created_at: 0,
+            updated_at: 0,
+            version: 0,
+        });
+        out.write_all(&meta.created_at.to_le_bytes())?;
+        out.write_all(&meta.updated_at.to_le_bytes())?;
+        out.write_all(&meta.version.to_le_bytes())?;
+    }
+    // Change logs come after the keys, whose loading logs changes again
+    for (namespace, state) in &dump.change_logs {
+        out.write_all(&[TAG_CHANGE_LOG])?;
+        write_field(&mut out, namespace.as_bytes())?;
+        write_field(&mut out, &serde_json::to_vec(state)?)?;
+    }
+    out.write_all(&[TAG_END])?;
+    out.write_all(&(entries.len() as u64).to_le_bytes())?;
+    let file = out.finish()?.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    fs::rename(&partial, path)?;
+    if let Some(parent) = path.parent() {
+        File::open(parent)?.sync_all()?;
+    }
+    Ok(fs::metadata(path)?.len())
+}
+
+/// Writes a length-prefixed field
+fn write_field(out: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+    out.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    out.write_all(bytes)
+}
+
+/// Decoder of the compressed body of a snapshot file
+type Body = zstd::stream::read::Decoder<'static, BufReader<File>>;
+
+/// Uncompressed header of a snapshot file
+struct Header {
+    /// Encoding version
+    version: u16,
+    /// Number of keys (None before version 4)
+    keys: Option<u64>,
+}
+
+/// Opens a snapshot, checking its header, and returns the header with a
+/// reader of its decompressed body
+fn open_snapshot(path: &Path) -> Result<(Header, Reader<Body>), String> {
+    let mut file = Reader(File::open(path).map_err(|e| e.to_string())?);
+    let mut magic = [0u8; MAGIC.len()];
+    file.read(&mut magic)
+        .map_err(|_| "Not a snapshot file".to_string())?;
+    if magic != MAGIC {
+        return Err("Not a snapshot file".to_string());
+    }
+    let mut version = [0u8; 2];
+    file.read(&mut version)?;
+    let version = u16::from_le_bytes(version);
+    if !(1..=SNAPSHOT_VERSION).contains(&version) {
+        return Err(format!("Unsupported snapshot version {}", version));
+    }
+    let keys = if version >= 4 {
+        Some(file.u64()?)
+    } else {
+        None
+    };
+    let body = zstd::stream::read::Decoder::new(file.0).map_err(|e| e.to_string())?;
+    Ok((Header { version, keys }, Reader(body)))
+}
+
+/// Checks that a snapshot decompresses and matches its checksum, without
+/// decoding its entries, and returns its header with the size of its body
+fn check_snapshot(path: &Path) -> Result<(Header, u64), String> {
+    let (header, mut reader) = open_snapshot(path)?;
+    let body = std::io::copy(&mut reader.0, &mut std::io::sink()).map_err(|e| e.to_string())?;
+    Ok((header, body))
+}
+
+/// Reads a snapshot as the records that restore it, passing each one to
+/// `apply` as it is decoded, and returns its number of keys
+pub(super) fn read_snapshot(
+    path: &Path,
+    mut apply: impl FnMut(AofRecord<'static>) -> Result<(), String>,
+) -> Result<usize, String> {
+    let (header, mut reader) = open_snapshot(path)?;
+    let version = header.version;
+    let mut keys = 0;
+    loop {
+        let tag = reader.u8()?;
+        if tag == TAG_END {
+            break;
+        }
+        if tag == TAG_CHANGE_LOG {
+            let ns = Cow::Owned(reader.string()?);
+            let state = serde_json::from_slice(&reader.field()?).map_err(|e| e.to_string())?;
+            apply(AofRecord::ChangeLog {
+                ns,
+                state: Cow::Owned(state),
+            })?;
+            continue;
+        }
+        let ns: Cow<str> = Cow::Owned(reader.string()?);
+        let key: Cow<str> = Cow::Owned(reader.string()?);
+        let value = reader.field()?;
+        let expires_at = reader.u64()?;
+        let meta = if version >= 3 {
+            let meta = KeyMeta {
+                created_at: reader.u64()?,
+                updated_at: reader.u64()?,
+                version: reader.u64()?,
+            };
+            (meta.version > 0).then_some(meta)
+        } else {
+            None
+        };
+        apply(match tag {
+            TAG_JSON => AofRecord::Set {
+                ns: ns.clone(),
+                key: key.clone(),
+                value: Cow::Owned(
+                    serde_json::from_slice::<Value>(&value).map_err(|e| e.to_string())?,
+                ),
+                meta,
+            },
+            TAG_BINARY => AofRecord::SetBytes {
+                ns: ns.clone(),
+                key: key.clone(),
+                value: Cow::Owned(value),
+            },
+            other => return Err(format!("Unknown entry tag {}", other)),
+        })?;
+        if expires_at > 0 {
+            apply(AofRecord::Expire {
+                ns,
+                key,
+                at: expires_at,
+            })?;
+        }
+        keys += 1;
+    }
+    if reader.u64()? != keys as u64 || header.keys.is_some_and(|count| count != keys as u64) {
+        return Err("Entry count mismatch".to_string());
+    }
+    // Reading to the end of the body checks its checksum
+    let trailing = std::io::copy(&mut reader.0, &mut std::io::sink()).map_err(|e| e.to_string())?;
+    if trailing > 0 {
+        return Err("Trailing data after the snapshot entries".to_string());
+    }
+    Ok(keys)
+}
+
+/// Cursor over a snapshot file or its decompressed body
+struct Reader<R>(R);
+
+impl<R: Read> Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), String> {
+        self.0.read_exact(buf).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => "Truncated snapshot".to_string(),
+            _ => e.to_string(),
+        })
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        let mut byte = [0u8; 1];
+        self.read(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let mut bytes = [0u8; 8];
+        self.read(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn field(&mut self) -> Result<Vec<u8>, String> {
+        let len = self.u64()?;
+        // Read through `take`, so that a corrupt length doesn't allocate it upfront
+        let mut field = Vec::new();
+        (&mut self.0)
+            .take(len)
+            .read_to_end(&mut field)
+            .map_err(|e| e.to_string())?;
+        if field.len() as u64 != len {
+            return Err("Truncated snapshot".to_string());
+        }
+        Ok(field)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        String::from_utf8(self.field()?).map_err(|e| e.to_string())
+    }
+}
+
+/// Save a snapshot every `interval`
+pub fn spawn_snapshots(store: Arc<SnapshotStore>, database: Arc<Database>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick completes immediately
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let store = Arc::clone(&store);
+            let database = Arc::clone(&database);
+            let saved = tokio::task::spawn_blocking(move || store.save(&database)).await;
+            if let Ok(Err(e)) = saved {
+                log::error!("{}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::protocol::{Command, Response};
+    use crate::storage::aof::FsyncPolicy;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_snapshot_then_aof_tail() {
+        let root =
+            std::env::temp_dir().join(format!("jsonvault-snapshot-{}", uuid::Uuid::new_v4()));
+        let dir = DataDir::open(&root, Some("node-1")).unwrap();
+        let store = SnapshotStore::new(dir.clone(), 2);
+
+        let clock = Arc::new(ManualClock::new());
+        let database = Database::new().with_clock(clock.clone());
+        database.set_aof(Some(Arc::new(
+            AppendOnlyFile::open(&dir, FsyncPolicy::Always).unwrap(),
+        )));
+        let set = |key: &str, value| Command::Set {
+            key: key.to_string(),
+            value,
+        };
+        let app = database.namespace("app").unwrap();
+        app.execute_command(set("a", json!({"n": 1}))).await;
+        app.execute_command(Command::Expire {
+            key: "a".to_string(),
+            seconds: 3600,
+        })
+        .await;
+        database
+            .execute_command(Command::SetBytes {
+                key: "blob".to_string(),
+                value: vec![7; 3],
+            })
+            .await;
+        for _ in 0..3 {
+            store.save(&database).unwrap();
+        }
+        // Written after the last snapshot: only in the AOF
+        app.execute_command(set("b", json!(1))).await;
+        clock.advance(Duration::from_secs(1));
+        app.execute_command(set("b", json!(2))).await;
+        let meta = |database: &Database, key: &str| {
+            database.export_record(key).unwrap().unwrap().meta.unwrap()
+        };
+        let (meta_a, meta_b) = (meta(&app, "a"), meta(&app, "b"));
+        assert_eq!(meta_b.version, 2);
+
+        // Retention keeps two snapshots and the segments they need
+        let snapshots: Vec<u64> = dir.list_snapshots().unwrap().iter().map(|s| s.0).collect();
+        let segments: Vec<u64> = dir
+            .list_aof_segments()
+            .unwrap()
+            .iter()
+            .map(|s| s.0)
+            .collect();
+        assert_eq!(snapshots, vec![3, 4]);
+        assert_eq!(segments, vec![3, 4]);
+        // The header records the key count, to size the keyspace upfront
+        let checked = SnapshotStore::newest(&dir).unwrap().unwrap();
+        assert_eq!((checked.index(), checked.keys()), (4, Some(2)));
+        // A damaged count, outside the checksum, is bounded by the body
+        let newest = fs::read(dir.snapshot_path(4)).unwrap();
+        let mut damaged = newest.clone();
+        damaged[MAGIC.len() + 2..MAGIC.len() + 10].copy_from_slice(&u64::MAX.to_le_bytes());
+        fs::write(dir.snapshot_path(4), &damaged).unwrap();
+        let keys = SnapshotStore::newest(&dir)
+            .unwrap()
+            .unwrap()
+            .keys()
+            .unwrap();
+        assert!(keys < 10, "{} keys", keys);
+
+        // A damaged newest snapshot falls back to the previous one, even when
+        // it is only cut short after entries that decode
+        fs::write(dir.snapshot_path(4), &newest[..newest.len() - 4]).unwrap();
+        assert!(read_snapshot(&dir.snapshot_path(4), |_| Ok(())).is_err());
+        // Metadata is restored as it was, not minted at load time
+        let later = ManualClock::new();
+        later.advance(Duration::from_secs(60));
+        let restored = Database::new().with_clock(Arc::new(later));
+        let index = SnapshotStore::load(&dir, &restored).unwrap().unwrap();
+        assert_eq!(index, 3);
+        AppendOnlyFile::replay(&dir, &restored, index).unwrap();
+
+        let app = restored.namespace("app").unwrap();
+        assert_eq!(app.value("a"), Some(json!({"n": 1})));
+        assert_eq!(app.value("b"), Some(json!(2)));
+        assert_eq!(meta(&app, "a"), meta_a);
+        assert_eq!(meta(&app, "b"), meta_b);
+        assert!(matches!(
+            app.execute_command(Command::Ttl { key: "a".to_string() }).await,
+            Response::Ok(Some(ttl)) if ttl.as_u64() > Some(3_500_000)
+        ));
+        assert!(matches!(
+            restored.execute_command(Command::GetBytes { key: "blob".to_string() }).await,
+            Response::Bytes(value) if value == [7; 3]
+        ));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_change_log_survives_restart() {
+        let root = std::env::temp_dir().join(format!("jsonvault-changes-{}", uuid::Uuid::new_v4()));
+        let dir = DataDir::open(&root, Some("node-1")).unwrap();
+        let store = SnapshotStore::new(dir.clone(), 2);
+        let open = || {
+            let database = Database::new();
+            database.set_change_log_capacity(10);
+            database
+        };
+        let set = |key: &str| Command::Set {
+            key: key.to_string(),
+            value: json!(key),
+        };
+        let ack = |offset| Command::GroupAck {
+            group: "indexer".to_string(),
+            offset,
+        };
+
+        let database = open();
+        database.set_aof(Some(Arc::new(
+            AppendOnlyFile::open(&dir, FsyncPolicy::Always).unwrap(),
+        )));
+        database.execute_command(set("a")).await;
+        database.execute_command(set("b")).await;
+        database.execute_command(ack(1)).await;
+        store.save(&database).unwrap();
+        // Written after the snapshot: only in the AOF
+        database.execute_command(set("c")).await;
+        database.execute_command(ack(2)).await;
+
+        let restored = open();
+        let index = SnapshotStore::load(&dir, &restored).unwrap().unwrap();
+        AppendOnlyFile::replay(&dir, &restored, index).unwrap();
+        let read = restored
+            .execute_command(Command::GroupRead {
+                group: "indexer".to_string(),
+                count: 10,
+            })
+            .await;
+
+        assert!(matches!(read, Response::Ok(Some(changes))
+            if changes == json!([{"offset": 3, "kind": "set", "key": "c", "value": "c", "at": changes[0]["at"]}])));
+        assert_eq!(restored.change_log().last_offset(), 3);
+        restored.execute_command(set("d")).await;
+        assert_eq!(restored.change_log().last_offset(), 4);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+use log::{debug, info};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// zstd level of spilled documents, favoring speed over ratio
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Size from which the spill file is rotated to a new segment
+const SEGMENT_BYTES: u64 = 64 << 20;
+
+/// Keeps documents moved out of memory in append-only segment files.
+///
+/// A segment file is deleted once it is no longer written to and none of its
+/// documents is left spilled: documents are not moved between segments, so a
+/// few long-lived spilled documents can keep a mostly empty segment around.
+/// Spilled documents are only a cache of the keyspace and do not survive a
+/// restart.
+#[derive(Debug)]
+pub struct SpillStore {
+    dir: PathBuf,
+    active: Mutex<Arc<SpillSegment>>,
+    /// Serialized size of the documents spilled so far and not yet reloaded
+    spilled_bytes: Arc<AtomicU64>,
+    /// Number of documents spilled so far and not yet reloaded
+    spilled_documents: Arc<AtomicU64>,
+}
+
+impl SpillStore {
+    /// Open a spill directory, deleting the segments of a previous run
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, String> {
+        let dir = dir.as_ref().to_path_buf();
+        if dir.exists() {
+            fs::remove_dir_all(&dir)
+                .map_err(|e| format!("Failed to clear {}: {}", dir.display(), e))?;
+        }
+        fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let active = SpillSegment::create(&dir, 1)?;
+        info!("Spilling cold documents to {}", dir.display());
+        Ok(Self {
+            dir,
+            active: Mutex::new(Arc::new(active)),
+            spilled_bytes: Arc::default(),
+            spilled_documents: Arc::default(),
+        })
+    }
+
+    /// Serialized size of the spilled documents
+    pub fn spilled_bytes(&self) -> u64 {
+        self.spilled_bytes.load(Ordering::Acquire)
+    }
+
+    /// Number of spilled documents
+    pub fn spilled_documents(&self) -> u64 {
+        self.spilled_documents.load(Ordering::Acquire)
+    }
+
+    /// Write the JSON serialization of a document, returning where it is kept
+    pub(crate) fn spill(&self, serialized: &[u8]) -> Result<SpillSlot, String> {
+        let data = zstd::bulk::compress(serialized, COMPRESSION_LEVEL)
+            .map_err(|e| format!("Failed to compress a spilled document: {}", e))?;
+        let mut active = self.active.lock().unwrap();
+        if active.len.load(Ordering::Acquire) >= SEGMENT_BYTES {
+            let next = SpillSegment::create(&self.dir, active.id + 1)?;
+            debug!("Spilling to segment {}", next.id);
+            *active = Arc::new(next);
+        }
+        let offset = active.append(&data)?;
+        self.spilled_bytes
+            .fetch_add(serialized.len() as u64, Ordering::AcqRel);
+        self.spilled_documents.fetch_add(1, Ordering::AcqRel);
+        Ok(SpillSlot {
+            segment: Arc::clone(&active),
+            offset,
+            len: data.len(),
+            size: serialized.len() as u64,
+            spilled_bytes: Arc::clone(&self.spilled_bytes),
+            spilled_documents: Arc::clone(&self.spilled_documents),
+        })
+    }
+}
+
+/// A spill file, deleted once unused
+#[derive(Debug)]
+struct SpillSegment {
+    id: u64,
+    path: PathBuf,
+    file: Mutex<File>,
+    len: AtomicU64,
+}
+
+impl SpillSegment {
+    fn create(dir: &Path, id: u64) -> Result<Self, String> {
+        let path = dir.join(format!("spill-{:020}.dat", id));
+        let file = OpenOptions::new()
+            .create_new(true)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        Ok(Self {
+            id,
+            path,
+            file: Mutex::new(file),
+            len: AtomicU64::new(0),
+        })
+    }
+
+    fn append(&self, data: &[u8]) -> Result<u64, String> {
+        let mut file = self.file.lock().unwrap();
+        let offset = self.len.load(Ordering::Acquire);
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.write_all(data))
+            .map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))?;
+        self.len
+            .store(offset + data.len() as u64, Ordering::Release);
+        Ok(offset)
+    }
+
+    fn read(&self, offset: u64, len: usize) -> std::io::Result<Vec<u8>> {
+        let mut file = self.file.lock().unwrap();
+        let mut data = vec![0; len];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut data)?;
+        Ok(data)
+    }
+}
+
+impl Drop for SpillSegment {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Location of a spilled document; dropping it releases the document
+#[derive(Debug)]
+pub(crate) struct SpillSlot {
+    segment: Arc<SpillSegment>,
+    offset: u64,
+    len: usize,
+    /// Size of the uncompressed serialization
+    size: u64,
+    spilled_bytes: Arc<AtomicU64>,
+    spilled_documents: Arc<AtomicU64>,
+}
+
+impl SpillSlot {
+    /// Size of the JSON serialization of the document
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Reads back the JSON serialization of the document; blocking file
+    /// I/O, to keep out of shard locks and off the runtime when possible
+    pub fn read(&self) -> Result<Vec<u8>, String> {
+        let data = self
+            .segment
+            .read(self.offset, self.len)
+            .map_err(|e| format!("Failed to read {}: {}", self.segment.path.display(), e))?;
+        zstd::bulk::decompress(&data, self.size as usize)
+            .map_err(|e| format!("Failed to decompress a spilled document: {}", e))
+    }
+}
+
+impl Drop for SpillSlot {
+    fn drop(&mut self) {
+        self.spilled_bytes.fetch_sub(self.size, Ordering::AcqRel);
+        self.spilled_documents.fetch_sub(1, Ordering::AcqRel);
+    }
+}
@@ -0,0 +1,317 @@
+use log::{debug, info, warn};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::aof::{spawn_aof_sync, AofChunk, AofRecord, AppendOnlyFile, FsyncPolicy};
+use super::layout::DataDir;
+use super::snapshot::SnapshotStore;
+use crate::database::Database;
+use crate::network::TcpClient;
+use crate::protocol::{Command, Response};
+
+/// Delay between two fetches once the standby has caught up
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Delay before reconnecting to the primary after a failure
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Follows the AOF of a primary into a data directory, applying every
+/// shipped record to a read-only database.
+///
+/// The data directory mirrors the primary's: the segments are copied record
+/// by record under the same names, so a restarted standby loads its own
+/// snapshot and segments and resumes from the end of the last one. A standby
+/// is not a cluster member; it accepts writes only once promoted.
+#[derive(Debug)]
+pub struct Standby {
+    primary: String,
+    dir: DataDir,
+    fsync: FsyncPolicy,
+    promoted: AtomicBool,
+    /// Held while a chunk is applied, so that promotion waits for it
+    applying: tokio::sync::Mutex<()>,
+    /// Last time a fetch found nothing left to ship
+    caught_up_at: Mutex<Option<Instant>>,
+}
+
+impl Standby {
+    /// Create a standby of the server at `primary`, opening its own AOF with
+    /// `fsync` once promoted
+    pub fn new(primary: &str, dir: DataDir, fsync: FsyncPolicy) -> Self {
+        Self {
+            primary: primary.to_string(),
+            dir,
+            fsync,
+            promoted: AtomicBool::new(false),
+            applying: tokio::sync::Mutex::new(()),
+            caught_up_at: Mutex::new(None),
+        }
+    }
+
+    /// Returns the address of the primary
+    pub fn primary(&self) -> &str {
+        &self.primary
+    }
+
+    /// Returns how long ago the standby last had every record of the
+    /// primary applied, or None if it never caught up
+    pub fn lag(&self) -> Option<Duration> {
+        self.caught_up_at
+            .lock()
+            .unwrap()
+            .map(|caught_up_at| caught_up_at.elapsed())
+    }
+
+    /// Follow the primary in the background until promoted, reconnecting
+    /// after failures
+    pub fn spawn(self: &Arc<Self>, database: Database) {
+        let standby = Arc::clone(self);
+        tokio::spawn(async move {
+            while !standby.promoted.load(Ordering::Acquire) {
+                if let Err(e) = standby.follow(&database).await {
+                    warn!("Shipping from {} failed: {}", standby.primary, e);
+                    tokio::time::sleep(RETRY_INTERVAL).await;
+                }
+            }
+        });
+    }
+
+    /// Stop following the primary once the chunk being applied is done, and
+    /// open the AOF to append local writes from the end of the shipped log
+    pub async fn promote(&self) -> Result<Arc<AppendOnlyFile>, String> {
+        self.promoted.store(true, Ordering::Release);
+        let _applying = self.applying.lock().await;
+        let aof = Arc::new(AppendOnlyFile::open(&self.dir, self.fsync)?);
+        if self.fsync == FsyncPolicy::EverySec {
+            spawn_aof_sync(Arc::clone(&aof), Duration::from_secs(1));
+        }
+        info!("Promoted: no longer following {}", self.primary);
+        Ok(aof)
+    }
+
+    async fn follow(&self, database: &Database) -> Result<(), String> {
+        let mut client = TcpClient::connect(&self.primary).await?;
+        let (mut sequence, mut offset) = self.position()?;
+        info!(
+            "Following {} from AOF segment {} at byte {}",
+            self.primary, sequence, offset
+        );
+        loop {
+            let reply = client
+                .send_command(Command::AofFetch { sequence, offset })
+                .await?;
+            let reply = match reply {
+                Response::Ok(Some(reply)) => reply,
+                Response::Error(e) => return Err(e.to_string()),
+                other => return Err(format!("Unexpected response: {}", other)),
+            };
+            let _applying = self.applying.lock().await;
+            if self.promoted.load(Ordering::Acquire) {
+                return Ok(());
+            }
+            if let Some(index) = reply.get("snapshot").and_then(|index| index.as_u64()) {
+                self.bootstrap(&mut client, database, index).await?;
+                (sequence, offset) = (index, 0);
+                continue;
+            }
+            let chunk: AofChunk =
+                serde_json::from_value(reply).map_err(|e| format!("Invalid AOF chunk: {}", e))?;
+            if !chunk.data.is_empty() {
+                self.apply(database, &chunk)?;
+            }
+            if chunk.end_of_segment {
+                (sequence, offset) = (chunk.sequence + 1, 0);
+            } else {
+                (sequence, offset) = (chunk.sequence, chunk.offset + chunk.data.len() as u64);
+                if chunk.data.is_empty() {
+                    *self.caught_up_at.lock().unwrap() = Some(Instant::now());
+                    drop(_applying);
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    /// Returns where shipping resumes: the end of the last local segment, the
+    /// start of the segment of the last local snapshot, or nothing (0)
+    fn position(&self) -> Result<(u64, u64), String> {
+        if let Some((sequence, path)) = self.dir.list_aof_segments()?.pop() {
+            let length = fs::metadata(&path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+                .len();
+            return Ok((sequence, length));
+        }
+        Ok((
+            self.dir
+                .list_snapshots()?
+                .pop()
+                .map_or(0, |(index, _)| index),
+            0,
+        ))
+    }
+
+    /// Copy shipped records to the local segment, then apply them
+    fn apply(&self, database: &Database, chunk: &AofChunk) -> Result<(), String> {
+        let path = self.dir.aof_segment_path(chunk.sequence);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to open AOF {}: {}", path.display(), e))?;
+        let length = file
+            .metadata()
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+            .len();
+        if length != chunk.offset {
+            return Err(format!(
+                "AOF {} has {} bytes, but the chunk starts at byte {}",
+                path.display(),
+                length,
+                chunk.offset
+            ));
+        }
+        file.write_all(chunk.data.as_bytes())
+            .and_then(|_| file.sync_data())
+            .map_err(|e| format!("Failed to append to {}: {}", path.display(), e))?;
+
+        let mut applied = 0;
+        for line in chunk.data.lines() {
+            let record: AofRecord =
+                serde_json::from_str(line).map_err(|e| format!("Invalid shipped record: {}", e))?;
+            database.apply_aof_record(record)?;
+            applied += 1;
+        }
+        debug!(
+            "Applied {} records of AOF segment {} from byte {}",
+            applied, chunk.sequence, chunk.offset
+        );
+        Ok(())
+    }
+
+    /// Download a snapshot of the primary and load it into an empty standby
+    async fn bootstrap(
+        &self,
+        client: &mut TcpClient,
+        database: &Database,
+        index: u64,
+    ) -> Result<(), String> {
+        if !self.dir.list_aof_segments()?.is_empty() || !self.dir.list_snapshots()?.is_empty() {
+            return Err(
+                "The primary no longer has the AOF segments this standby needs; \
+                 restart it on an empty data directory"
+                    .to_string(),
+            );
+        }
+        let path = self.dir.snapshot_path(index);
+        let partial = path.with_extension("partial");
+        let mut file = fs::File::create(&partial)
+            .map_err(|e| format!("Failed to create {}: {}", partial.display(), e))?;
+        let mut offset = 0;
+        loop {
+            let bytes = match client
+                .send_command(Command::SnapshotFetch { index, offset })
+                .await?
+            {
+                Response::Bytes(bytes) => bytes,
+                Response::Error(e) => return Err(e.to_string()),
+                other => return Err(format!("Unexpected response: {}", other)),
+            };
+            if bytes.is_empty() {
+                break;
+            }
+            file.write_all(&bytes)
+                .map_err(|e| format!("Failed to write {}: {}", partial.display(), e))?;
+            offset += bytes.len() as u64;
+        }
+        file.sync_all()
+            .and_then(|_| fs::rename(&partial, &path))
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        SnapshotStore::load(&self.dir, database)?;
+        info!(
+            "Loaded snapshot {} of {} ({} bytes)",
+            index, self.primary, offset
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::TcpServer;
+    use crate::protocol::ErrorCode;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_ship_and_promote() {
+        let root = std::env::temp_dir().join(format!("jsonvault-standby-{}", uuid::Uuid::new_v4()));
+        let primary_dir = DataDir::open(root.join("primary"), Some("primary")).unwrap();
+        let primary = Arc::new(Database::new());
+        primary.set_aof(Some(Arc::new(
+            AppendOnlyFile::open(&primary_dir, FsyncPolicy::No).unwrap(),
+        )));
+        let set = |key: &str, value| Command::Set {
+            key: key.to_string(),
+            value,
+        };
+        primary.execute_command(set("a", json!(1))).await;
+        SnapshotStore::new(primary_dir.clone(), 2)
+            .save(&primary)
+            .unwrap();
+        primary.execute_command(set("b", json!(2))).await;
+        let server = TcpServer::new(Arc::clone(&primary), "127.0.0.1:8092".to_string())
+            .with_admin_commands(true);
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // The standby bootstraps from the snapshot, then follows the AOF
+        let standby_dir = DataDir::open(root.join("standby"), Some("standby")).unwrap();
+        let standby = Database::new();
+        standby.set_standby(Arc::new(Standby::new(
+            "127.0.0.1:8092",
+            standby_dir.clone(),
+            FsyncPolicy::Always,
+        )));
+        primary.execute_command(set("c", json!(3))).await;
+        for _ in 0..50 {
+            if standby.value("c").is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        for (key, value) in [("a", 1), ("b", 2), ("c", 3)] {
+            assert_eq!(standby.value(key), Some(json!(value)));
+        }
+        assert!(matches!(
+            standby.execute_command(set("d", json!(4))).await,
+            Response::Error(e) if e.code == ErrorCode::ReadOnly
+        ));
+
+        assert!(matches!(
+            standby.execute_command(Command::Promote).await,
+            Response::Ok(Some(_))
+        ));
+        assert!(matches!(
+            standby.execute_command(set("d", json!(4))).await,
+            Response::Ok(_)
+        ));
+        assert!(matches!(
+            standby.execute_command(Command::Promote).await,
+            Response::Error(e) if e.code == ErrorCode::Unsupported
+        ));
+
+        // The promoted node restarts from its own directory
+        let restarted = Database::new();
+        let index = SnapshotStore::load(&standby_dir, &restarted).unwrap();
+        AppendOnlyFile::replay(&standby_dir, &restarted, index.unwrap()).unwrap();
+        for (key, value) in [("a", 1), ("c", 3), ("d", 4)] {
+            assert_eq!(restarted.value(key), Some(json!(value)));
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
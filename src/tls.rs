@@ -0,0 +1,304 @@
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::client::WebPkiServerVerifier;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, Error, RootCertStore, SignatureScheme,
+};
+use tokio_rustls::TlsConnector;
+
+/// TLS settings of client connections: the trusted root certificates, an
+/// optional client certificate and how the server name is checked.
+///
+/// A new configuration trusts no root certificate: add the system roots,
+/// the CA certificates of an internal PKI, or both.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    roots: RootCertStore,
+    /// Certificate chain and private key presented to the server
+    client_cert: Option<(Vec<CertificateDer<'static>>, Arc<PrivateKeyDer<'static>>)>,
+    /// Name checked against the server certificate, instead of the host of
+    /// the address
+    server_name: Option<String>,
+    skip_hostname_verification: bool,
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        Self {
+            roots: RootCertStore::empty(),
+            client_cert: None,
+            server_name: None,
+            skip_hostname_verification: false,
+        }
+    }
+
+    /// Trust the root certificates of the operating system
+    pub fn with_system_roots(mut self) -> Result<Self, String> {
+        let native = rustls_native_certs::load_native_certs();
+        if native.certs.is_empty() {
+            if let Some(e) = native.errors.first() {
+                return Err(format!("Failed to load system root certificates: {}", e));
+            }
+        }
+        self.roots.add_parsable_certificates(native.certs);
+        Ok(self)
+    }
+
+    /// Trust the CA certificates of a PEM file
+    pub fn with_root_ca(mut self, path: impl AsRef<Path>) -> Result<Self, String> {
+        for cert in read_certs(path.as_ref())? {
+            self.roots
+                .add(cert)
+                .map_err(|e| format!("Invalid CA certificate: {}", e))?;
+        }
+        Ok(self)
+    }
+
+    /// Present a client certificate, with its chain, from PEM files
+    pub fn with_client_cert(
+        mut self,
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> Result<Self, String> {
+        let key_path = key_path.as_ref();
+        let key = PrivateKeyDer::from_pem_file(key_path)
+            .map_err(|e| format!("Failed to read key {}: {}", key_path.display(), e))?;
+        self.client_cert = Some((read_certs(cert_path.as_ref())?, Arc::new(key)));
+        Ok(self)
+    }
+
+    /// Check the server certificate against `name` rather than the host of
+    /// the address connected to
+    pub fn with_server_name(mut self, name: &str) -> Self {
+        self.server_name = Some(name.to_string());
+        self
+    }
+
+    /// Accept server certificates issued by a trusted root whatever name
+    /// they are issued to (verified by default)
+    pub fn with_hostname_verification(mut self, enabled: bool) -> Self {
+        self.skip_hostname_verification = !enabled;
+        self
+    }
+
+    /// Returns a connector for these settings and the name of the server
+    /// at `address`
+    pub(crate) fn connector(
+        &self,
+        address: &str,
+    ) -> Result<(TlsConnector, ServerName<'static>), String> {
+        let provider = Arc::new(ring::default_provider());
+        let verifier = WebPkiServerVerifier::builder_with_provider(
+            Arc::new(self.roots.clone()),
+            provider.clone(),
+        )
+        .build()
+        .map_err(|e| format!("Invalid TLS configuration: {}", e))?;
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| format!("Invalid TLS configuration: {}", e))?;
+        let builder = if self.skip_hostname_verification {
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(AnyNameVerifier(verifier)))
+        } else {
+            builder.with_webpki_verifier(verifier)
+        };
+        let config = match &self.client_cert {
+            Some((chain, key)) => builder
+                .with_client_auth_cert(chain.clone(), key.clone_key())
+                .map_err(|e| format!("Invalid client certificate: {}", e))?,
+            None => builder.with_no_client_auth(),
+        };
+
+        let host = match &self.server_name {
+            Some(name) => name.as_str(),
+            None => host(address),
+        };
+        let server_name = ServerName::try_from(host.to_string())
+            .map_err(|e| format!("Invalid server name {}: {}", host, e))?;
+        Ok((TlsConnector::from(Arc::new(config)), server_name))
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the host of a `host:port` address, without the brackets of IPv6
+/// addresses
+fn host(address: &str) -> &str {
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Failed to read certificates {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificate in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+/// Verifies the chain of server certificates but not the name they are
+/// issued to
+#[derive(Debug)]
+struct AnyNameVerifier(Arc<WebPkiServerVerifier>);
+
+impl ServerCertVerifier for AnyNameVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        // The name is checked once the chain is valid
+        match self
+            .0
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+        {
+            Err(Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) => Ok(ServerCertVerified::assertion()),
+            result => result,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_name() {
+        assert_eq!(host("db.internal:8080"), "db.internal");
+        assert_eq!(host("[::1]:8080"), "::1");
+        assert_eq!(host("localhost"), "localhost");
+    }
+
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/tls/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[tokio::test]
+    async fn test_tls_client() {
+        use crate::database::Database;
+        use crate::network::{TcpClient, TcpServer};
+        use crate::protocol::{Command, Response};
+        use tokio_rustls::rustls::server::WebPkiClientVerifier;
+        use tokio_rustls::rustls::ServerConfig;
+
+        let server = TcpServer::new(Arc::new(Database::new()), "127.0.0.1:8111".to_string());
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        // TLS is terminated in front of the server, as by a sidecar, and
+        // requires a client certificate issued by the test CA
+        let provider = Arc::new(ring::default_provider());
+        let mut roots = RootCertStore::empty();
+        roots.add_parsable_certificates(read_certs(Path::new(&fixture("ca.pem"))).unwrap());
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()
+                .unwrap();
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                read_certs(Path::new(&fixture("server.pem"))).unwrap(),
+                PrivateKeyDer::from_pem_file(fixture("server-key.pem")).unwrap(),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:8112")
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(mut stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    let mut server = tokio::net::TcpStream::connect("127.0.0.1:8111")
+                        .await
+                        .unwrap();
+                    let _ = tokio::io::copy_bidirectional(&mut stream, &mut server).await;
+                });
+            }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let tls = TlsConfig::new()
+            .with_root_ca(fixture("ca.pem"))
+            .unwrap()
+            .with_client_cert(fixture("client.pem"), fixture("client-key.pem"))
+            .unwrap();
+        // The certificate is issued to localhost, not to the address
+        let err = TcpClient::connect_tls("127.0.0.1:8112", &tls)
+            .await
+            .err()
+            .unwrap();
+        assert!(err.starts_with("TLS handshake failed"), "{}", err);
+        for tls in [
+            tls.clone().with_server_name("localhost"),
+            tls.clone().with_hostname_verification(false),
+        ] {
+            let mut client = TcpClient::connect_tls("127.0.0.1:8112", &tls)
+                .await
+                .unwrap();
+            let response = client.send_command(Command::Ping).await.unwrap();
+            assert!(matches!(response, Response::Pong));
+            client.close().await.unwrap();
+        }
+
+        // Untrusted servers are refused, whatever their name
+        let untrusted = TlsConfig::new().with_hostname_verification(false);
+        assert!(TcpClient::connect_tls("127.0.0.1:8112", &untrusted)
+            .await
+            .is_err());
+        // Without a client certificate the server drops the connection
+        let anonymous = TlsConfig::new()
+            .with_root_ca(fixture("ca.pem"))
+            .unwrap()
+            .with_server_name("localhost");
+        if let Ok(mut client) = TcpClient::connect_tls("127.0.0.1:8112", &anonymous).await {
+            assert!(client.send_command(Command::Ping).await.is_err());
+        }
+    }
+}
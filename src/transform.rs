@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A single transformation applied to a document before it is stored
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Transform {
+    /// Recursively remove object members whose value is null
+    StripNulls,
+    /// Remove the members matched by the given JSONPath expressions
+    RemoveFields { paths: Vec<String> },
+    /// Lowercase the string values matched by the given JSONPath expressions
+    Lowercase { paths: Vec<String> },
+    /// Trim surrounding whitespace from the string values matched by the given JSONPath expressions
+    Trim { paths: Vec<String> },
+}
+
+/// Transformations applied to every write on keys starting with a prefix
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TransformRule {
+    /// Key prefix the rule applies to (empty matches every key)
+    pub prefix: String,
+    /// Transformations applied in order
+    pub transforms: Vec<Transform>,
+}
+
+/// Ordered set of write transformation rules
+#[derive(Debug, Clone, Default)]
+pub struct WritePipeline {
+    rules: Vec<TransformRule>,
+}
+
+impl WritePipeline {
+    /// Create a pipeline from a list of rules
+    pub fn new(rules: Vec<TransformRule>) -> Self {
+        Self { rules }
+    }
+
+    /// Load a pipeline from a JSON file containing an array of rules
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read transform rules '{}': {}", path, e))?;
+        let rules: Vec<TransformRule> = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid transform rules '{}': {}", path, e))?;
+        Ok(Self::new(rules))
+    }
+
+    /// Returns true if no rule is configured
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Apply every rule matching the key, in configuration order
+    pub fn apply(&self, key: &str, value: Value) -> Result<Value, String> {
+        let mut value = value;
+        for rule in self.rules.iter().filter(|r| key.starts_with(&r.prefix)) {
+            for transform in &rule.transforms {
+                value = Self::apply_transform(transform, value)?;
+            }
+        }
+        Ok(value)
+    }
+
+    /// Apply a single transformation
+    fn apply_transform(transform: &Transform, value: Value) -> Result<Value, String> {
+        match transform {
+            Transform::StripNulls => Ok(Self::strip_nulls(value)),
+            Transform::RemoveFields { paths } => Self::replace_all(value, paths, &mut |_| None),
+            Transform::Lowercase { paths } => Self::replace_all(value, paths, &mut |v| match v {
+                Value::String(s) => Some(Value::String(s.to_lowercase())),
+                other => Some(other),
+            }),
+            Transform::Trim { paths } => Self::replace_all(value, paths, &mut |v| match v {
+                Value::String(s) => Some(Value::String(s.trim().to_string())),
+                other => Some(other),
+            }),
+        }
+    }
+
+    /// Replace every value matched by the given paths using a closure
+    fn replace_all<F>(value: Value, paths: &[String], fun: &mut F) -> Result<Value, String>
+    where
+        F: FnMut(Value) -> Option<Value>,
+    {
+        let mut value = value;
+        for path in paths {
+            value = jsonpath_lib::replace_with(value, path, fun)
+                .map_err(|e| format!("Transform path '{}' error: {}", path, e))?;
+        }
+        Ok(value)
+    }
+
+    /// Recursively remove null object members
+    fn strip_nulls(value: Value) -> Value {
+        match value {
+            Value::Object(map) => Value::Object(
+                map.into_iter()
+                    .filter(|(_, v)| !v.is_null())
+                    .map(|(k, v)| (k, Self::strip_nulls(v)))
+                    .collect(),
+            ),
+            Value::Array(arr) => Value::Array(arr.into_iter().map(Self::strip_nulls).collect()),
+            other => other,
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::database::Database;
+
+/// Condition on a write that fires a trigger
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "on", rename_all = "snake_case")]
+pub enum TriggerCondition {
+    /// The value at `path` becomes `value` (it was different or missing before)
+    Becomes { path: String, value: Value },
+    /// The value at `path` changes, appears or disappears
+    Changes { path: String },
+    /// The key is created
+    Created,
+}
+
+/// Change applied to a document when a trigger fires
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TriggerAction {
+    /// Set the value at `path`, creating missing objects along the way
+    Set { path: String, value: Value },
+    /// Set the value at `path` to the time of the write (milliseconds since the UNIX epoch)
+    SetNow { path: String },
+    /// Remove the object member at `path`
+    Remove { path: String },
+}
+
+/// Derived-field rule evaluated on every write to keys starting with a prefix
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Trigger {
+    /// Key prefix the trigger applies to (empty matches every key)
+    #[serde(default)]
+    pub prefix: String,
+    pub when: TriggerCondition,
+    /// Actions applied in order
+    pub then: Vec<TriggerAction>,
+}
+
+/// Ordered set of write triggers
+#[derive(Debug, Clone, Default)]
+pub struct TriggerSet {
+    triggers: Vec<Trigger>,
+}
+
+impl TriggerSet {
+    /// Create a set from a list of triggers
+    pub fn new(triggers: Vec<Trigger>) -> Self {
+        Self { triggers }
+    }
+
+    /// Load a set from a JSON file containing an array of triggers
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read triggers '{}': {}", path, e))?;
+        let triggers: Vec<Trigger> = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid triggers '{}': {}", path, e))?;
+        Ok(Self::new(triggers))
+    }
+
+    /// Number of configured triggers
+    pub fn len(&self) -> usize {
+        self.triggers.len()
+    }
+
+    /// Returns true if no trigger is configured
+    pub fn is_empty(&self) -> bool {
+        self.triggers.is_empty()
+    }
+
+    /// Fire the triggers of a key, in configuration order, on the value about
+    /// to replace `old`; `now` is the time of the write
+    pub fn apply(
+        &self,
+        key: &str,
+        old: Option<&Value>,
+        value: &mut Value,
+        now: u64,
+    ) -> Result<(), String> {
+        for trigger in self.triggers.iter().filter(|t| key.starts_with(&t.prefix)) {
+            if !Self::is_met(&trigger.when, old, value) {
+                continue;
+            }
+            for action in &trigger.then {
+                Self::apply_action(action, value, now)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_met(condition: &TriggerCondition, old: Option<&Value>, value: &Value) -> bool {
+        match condition {
+            TriggerCondition::Becomes {
+                path,
+                value: expected,
+            } => lookup(Some(value), path) == Some(expected) && lookup(old, path) != Some(expected),
+            TriggerCondition::Changes { path } => lookup(Some(value), path) != lookup(old, path),
+            TriggerCondition::Created => old.is_none(),
+        }
+    }
+
+    fn apply_action(action: &TriggerAction, value: &mut Value, now: u64) -> Result<(), String> {
+        match action {
+            TriggerAction::Set { path, value: new } => {
+                Database::set_nested_value(value, &Database::path_parts(path), new.clone())
+            }
+            TriggerAction::SetNow { path } => {
+                Database::set_nested_value(value, &Database::path_parts(path), now.into())
+            }
+            TriggerAction::Remove { path } => {
+                let parts = Database::path_parts(path);
+                let Some((member, parent)) = parts.split_last() else {
+                    return Err("The root of a document cannot be removed".to_string());
+                };
+                if let Some(Value::Object(map)) = Database::get_nested_mut(value, parent) {
+                    map.remove(*member);
+                }
+                Ok(())
+            }
+        }
+        .map_err(|e| format!("Trigger action {:?} failed: {}", action, e))
+    }
+}
+
+/// Returns the value at a simplified JSONPath of an optional document
+fn lookup<'a>(document: Option<&'a Value>, path: &str) -> Option<&'a Value> {
+    let mut current = document?;
+    for part in Database::path_parts(path) {
+        current = match current {
+            Value::Array(arr) => arr.get(part.parse::<usize>().ok()?)?,
+            Value::Object(map) => map.get(part)?,
+            _ => return None,
+        };
+    }
+    Some(current)
+}
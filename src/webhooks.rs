@@ -0,0 +1,271 @@
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+
+use crate::database::Database;
+use crate::glob;
+use crate::http;
+use crate::protocol::KeyEvent;
+
+/// Events waiting to be delivered to each endpoint
+const WEBHOOK_QUEUE_CAPACITY: usize = 1024;
+/// Upper bound of the delay between two delivery attempts
+const MAX_WEBHOOK_BACKOFF: Duration = Duration::from_secs(60);
+/// Time allowed to an endpoint to answer a request
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Posts the events of the keys matching a glob to an HTTP endpoint
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WebhookRule {
+    /// Glob the keys must match
+    pub pattern: String,
+    /// Namespace the rule applies to (every namespace when missing)
+    #[serde(default)]
+    pub namespace: Option<String>,
+    /// Endpoint receiving the events, as `http[s]://host[:port][/path]`
+    pub url: String,
+}
+
+impl WebhookRule {
+    fn matches(&self, event: &KeyEvent) -> bool {
+        self.namespace
+            .as_ref()
+            .is_none_or(|namespace| *namespace == event.namespace)
+            && glob::glob_match(&self.pattern, &event.key)
+    }
+}
+
+/// Delivers key events to the endpoints of the matching rules.
+///
+/// Each endpoint receives its events in order; failed deliveries are retried
+/// with exponential backoff and dropped once the retries are exhausted.
+#[derive(Debug, Clone)]
+pub struct WebhookDispatcher {
+    rules: Vec<WebhookRule>,
+    retries: usize,
+    backoff: Duration,
+}
+
+impl WebhookDispatcher {
+    /// Create a dispatcher from a list of rules
+    pub fn new(rules: Vec<WebhookRule>) -> Result<Self, String> {
+        for rule in &rules {
+            Endpoint::parse(&rule.url)?;
+        }
+        Ok(Self {
+            rules,
+            retries: 5,
+            backoff: Duration::from_millis(500),
+        })
+    }
+
+    /// Load a dispatcher from a JSON file containing an array of rules
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read webhook rules '{}': {}", path, e))?;
+        let rules: Vec<WebhookRule> = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid webhook rules '{}': {}", path, e))?;
+        Self::new(rules)
+    }
+
+    /// Retry a failed delivery up to `retries` times, waiting `backoff` before
+    /// the first retry and twice as long before each following one
+    pub fn with_retries(mut self, retries: usize, backoff: Duration) -> Self {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
+
+    /// Number of configured rules
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Returns true if no rule is configured
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Start delivering the events of a database in the background
+    pub fn spawn(self, database: Arc<Database>) {
+        let mut rx = database.subscribe_events();
+        let queues: Vec<(WebhookRule, mpsc::Sender<KeyEvent>)> = self
+            .rules
+            .into_iter()
+            .map(|rule| {
+                let (tx, queue) = mpsc::channel(WEBHOOK_QUEUE_CAPACITY);
+                let endpoint = Endpoint::parse(&rule.url).expect("validated by new");
+                tokio::spawn(deliver(endpoint, queue, self.retries, self.backoff));
+                (rule, tx)
+            })
+            .collect();
+
+        tokio::spawn(async move {
+            loop {
+                let event = match rx.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        warn!("Webhooks fell behind, {} key events dropped", count);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                for (rule, tx) in queues.iter().filter(|(rule, _)| rule.matches(&event)) {
+                    if tx.try_send(event.clone()).is_err() {
+                        warn!("Webhook queue of {} is full, event dropped", rule.url);
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Post the queued events to an endpoint, one at a time
+async fn deliver(
+    endpoint: Endpoint,
+    mut queue: mpsc::Receiver<KeyEvent>,
+    retries: usize,
+    backoff: Duration,
+) {
+    while let Some(event) = queue.recv().await {
+        let body = serde_json::to_vec(&event).expect("key events serialize");
+        let mut delay = backoff;
+        for attempt in 0..=retries {
+            match endpoint.post(&body).await {
+                Ok(()) => {
+                    debug!(
+                        "Delivered {} event of {} to {}",
+                        event.event, event.key, endpoint
+                    );
+                    break;
+                }
+                Err(e) if attempt < retries => {
+                    info!(
+                        "Webhook {} failed ({}), retrying in {:?}",
+                        endpoint, e, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_WEBHOOK_BACKOFF);
+                }
+                Err(e) => warn!(
+                    "Dropping {} event of {} after {} attempts to {}: {}",
+                    event.event,
+                    event.key,
+                    attempt + 1,
+                    endpoint,
+                    e
+                ),
+            }
+        }
+    }
+}
+
+/// HTTP or HTTPS endpoint
+#[derive(Debug, Clone)]
+struct Endpoint {
+    url: reqwest::Url,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> Result<Self, String> {
+        let url = http::parse_url(url).map_err(|e| format!("Invalid webhook URL: {}", e))?;
+        Ok(Self { url })
+    }
+
+    /// POST a JSON body, succeeding on a 2xx status
+    async fn post(&self, body: &[u8]) -> Result<(), String> {
+        let response = http::client()?
+            .post(self.url.clone())
+            .header("content-type", "application/json")
+            .body(body.to_vec())
+            .timeout(WEBHOOK_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| {
+                if e.is_timeout() {
+                    "timed out".to_string()
+                } else {
+                    format!("request error: {}", e)
+                }
+            })?;
+        // Only the status matters
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(format!("HTTP status {}", status.as_u16()))
+        }
+    }
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Command;
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_webhook_retries() {
+        assert!(WebhookDispatcher::new(vec![WebhookRule {
+            pattern: "*".to_string(),
+            namespace: None,
+            url: "ftp://example.com/hook".to_string(),
+        }])
+        .is_err());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hooks/users", listener.local_addr().unwrap());
+        let database = Arc::new(Database::new());
+        WebhookDispatcher::new(vec![WebhookRule {
+            pattern: "user:*".to_string(),
+            namespace: None,
+            url,
+        }])
+        .unwrap()
+        .with_retries(3, Duration::from_millis(10))
+        .spawn(Arc::clone(&database));
+
+        for key in ["order:1", "user:1"] {
+            database
+                .execute_command(Command::Set {
+                    key: key.to_string(),
+                    value: json!({"name": "Ada"}),
+                })
+                .await;
+        }
+
+        // The first attempt is rejected, the retry is accepted
+        let mut requests = Vec::new();
+        for status in ["500 Internal Server Error", "204 No Content"] {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"}") {
+                let mut chunk = [0u8; 1024];
+                let n = stream.read(&mut chunk).await.unwrap();
+                request.extend_from_slice(&chunk[..n]);
+            }
+            requests.push(String::from_utf8(request).unwrap());
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+        assert_eq!(requests[0], requests[1]);
+        assert!(requests[0].starts_with("POST /hooks/users HTTP/1.1\r\n"));
+        let body = requests[0].split("\r\n\r\n").nth(1).unwrap();
+        let event: KeyEvent = serde_json::from_str(body).unwrap();
+        assert_eq!(event.key, "user:1");
+        assert_eq!(event.event, "set");
+    }
+}
@@ -0,0 +1,181 @@
+use bytes::BytesMut;
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, WebSocketConfig};
+use tokio_tungstenite::tungstenite::{Error, Message};
+use tokio_tungstenite::WebSocketStream;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::codec::{FrameCodec, Payload};
+
+/// Size of the largest message accepted from a client
+const MAX_MESSAGE: usize = 64 << 20;
+
+/// Read the HTTP upgrade request of a WebSocket client and accept it
+pub(crate) async fn handshake(stream: TcpStream) -> Result<WebSocketStream<TcpStream>, String> {
+    let config = WebSocketConfig {
+        max_message_size: Some(MAX_MESSAGE),
+        max_frame_size: Some(MAX_MESSAGE),
+        ..WebSocketConfig::default()
+    };
+    tokio_tungstenite::accept_async_with_config(stream, Some(config))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Carry the messages of a WebSocket client to its session as protocol
+/// frames, and the frames of the session back as text messages, until either
+/// side closes. Pings are answered and fragmented messages reassembled by the
+/// WebSocket stream.
+pub(crate) async fn bridge(
+    mut socket: WebSocketStream<TcpStream>,
+    session: DuplexStream,
+) -> Result<(), String> {
+    let (mut session_reader, mut session_writer) = tokio::io::split(session);
+    // Frames waiting to be written to the session, and read from it
+    let mut requests = BytesMut::new();
+    let mut responses = BytesMut::with_capacity(4096);
+    // The session negotiates nothing at handshake for the bridge
+    let mut codec = FrameCodec::default();
+    loop {
+        // Requests are written as the session consumes them, so that a client
+        // sending faster than it reads cannot block both directions
+        tokio::select! {
+            written = session_writer.write_buf(&mut requests), if !requests.is_empty() => {
+                written.map_err(|e| format!("Session closed: {}", e))?;
+            }
+            message = socket.next(), if requests.len() < MAX_MESSAGE => {
+                let data = match message {
+                    None | Some(Ok(Message::Close(_))) => return Ok(()),
+                    Some(Ok(Message::Text(text))) => text.into_bytes(),
+                    Some(Ok(Message::Binary(data))) => data,
+                    Some(Ok(_)) => continue,
+                    Some(Err(Error::Capacity(e))) => {
+                        let _ = socket
+                            .close(Some(CloseFrame {
+                                code: CloseCode::Size,
+                                reason: "Message too big".into(),
+                            }))
+                            .await;
+                        return Err(e.to_string());
+                    }
+                    Some(Err(e)) => return Err(format!("Read error: {}", e)),
+                };
+                codec
+                    .encode(
+                        Payload {
+                            id: None,
+                            parts: vec![&data],
+                        },
+                        &mut requests,
+                    )
+                    .map_err(|e| format!("Frame error: {}", e))?;
+            }
+            read = session_reader.read_buf(&mut responses) => {
+                match read {
+                    Ok(0) => {
+                        let _ = socket.close(None).await;
+                        return Ok(());
+                    }
+                    Ok(_) => {}
+                    Err(e) => return Err(format!("Session read error: {}", e)),
+                }
+                while let Some(response) = codec
+                    .decode(&mut responses)
+                    .map_err(|e| format!("Session frame error: {}", e))?
+                {
+                    let text = String::from_utf8(response.into_payload().to_vec())
+                        .map_err(|e| format!("Session frame error: {}", e))?;
+                    socket
+                        .send(Message::Text(text))
+                        .await
+                        .map_err(|e| format!("Send error: {}", e))?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use crate::network::TcpServer;
+    use crate::protocol::{Command, Response};
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    type Client = WebSocketStream<TcpStream>;
+
+    async fn send_message(socket: &mut Client, message: serde_json::Value) {
+        socket
+            .send(Message::Text(message.to_string()))
+            .await
+            .unwrap();
+    }
+
+    async fn receive_message(socket: &mut Client) -> Response {
+        match socket.next().await.unwrap().unwrap() {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            message => panic!("Unexpected message {:?}", message),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_websocket_gateway() {
+        let database = Arc::new(Database::new());
+        let server = TcpServer::new(Arc::clone(&database), "127.0.0.1:8095".to_string())
+            .with_websocket("127.0.0.1:8096".to_string());
+        tokio::spawn(async move {
+            let _ = server.start().await;
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Requests that are not upgrades are refused
+        let mut plain = TcpStream::connect("127.0.0.1:8096").await.unwrap();
+        plain
+            .write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        let _ = plain.read_to_end(&mut response).await;
+        assert!(!response.starts_with(b"HTTP/1.1 101"));
+
+        let stream = TcpStream::connect("127.0.0.1:8096").await.unwrap();
+        let (mut socket, response) =
+            tokio_tungstenite::client_async("ws://127.0.0.1:8096/", stream)
+                .await
+                .unwrap();
+        assert_eq!(response.status(), 101);
+
+        send_message(&mut socket, json!({"Set": {"key": "a", "value": 1}})).await;
+        let response = receive_message(&mut socket).await;
+        assert!(matches!(response, Response::Ok(_)));
+        send_message(&mut socket, json!({"Get": {"key": "a"}})).await;
+        let response = receive_message(&mut socket).await;
+        assert!(matches!(response, Response::Ok(Some(v)) if v == json!(1)));
+
+        // Key changes are pushed as messages
+        send_message(&mut socket, json!({"Watch": {"key": "a"}})).await;
+        receive_message(&mut socket).await;
+        database
+            .execute_command(Command::Set {
+                key: "a".to_string(),
+                value: json!(2),
+            })
+            .await;
+        let response = receive_message(&mut socket).await;
+        assert!(matches!(response, Response::KeyUpdated(update) if update.value == Some(json!(2))));
+
+        // Messages above the limit close the connection
+        let oversized = "x".repeat(MAX_MESSAGE + 1);
+        let _ = socket.send(Message::Text(oversized)).await;
+        match socket.next().await {
+            Some(Ok(Message::Close(Some(frame)))) => assert_eq!(frame.code, CloseCode::Size),
+            other => panic!("Unexpected message {:?}", other),
+        }
+    }
+}